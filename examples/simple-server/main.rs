@@ -10,8 +10,8 @@ fn main() {
                 .required(false)
                 .short("b")
                 .long("backend")
-                .value_name("BACKEND")
-                .help("Sets the custom backend")
+                .value_name("URI")
+                .help("Sets the backend, e.g. s3://bucket/prefix, seaweedfs://filer:8888/bucket or file:///path")
                 .takes_value(true),
         )
         .arg(
@@ -22,13 +22,76 @@ fn main() {
                 .help("Enable data cache")
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("timeout")
+                .required(false)
+                .short("t")
+                .long("timeout")
+                .value_name("SECONDS")
+                .help("Sets the timeout of backend requests, 0 disables it")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fault")
+                .required(false)
+                .short("f")
+                .long("fault")
+                .value_name("SPEC")
+                .help("Injects a backend fault, <op>:<path glob>:<probability>:<action>")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("supervise")
+                .required(false)
+                .short("s")
+                .long("supervise")
+                .help("Mounts again with backoff when the fuse session dies")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("passthrough")
+                .required(false)
+                .long("passthrough")
+                .help("Reads opened files straight from a file:// backend")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("dump-dir")
+                .required(false)
+                .long("dump-dir")
+                .value_name("DIR")
+                .help("Lets the owner of the mount dump inode trees into this directory")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("allow-credential-process")
+                .required(false)
+                .long("allow-credential-process")
+                .help("Lets the backend uri name a command printing the credentials")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("bind")
+                .required(false)
+                .long("bind")
+                .value_name("PATH")
+                .help("Also mounts the filesystem read-only at this path, sharing its caches")
+                .takes_value(true)
+                .multiple(true)
+                .conflicts_with("supervise"),
+        )
         .get_matches();
     let backend: String = if let Some(backend) = matches.value_of("backend") {
         backend.to_owned()
     } else {
-        "".to_owned()
+        "seaweedfs://172.21.20.250:8888/server".to_owned()
     };
     let enable_cache: bool = matches.is_present("cache");
+    let timeout: u64 = matches
+        .value_of("timeout")
+        .map(|timeout| timeout.parse().expect("invalid timeout"))
+        .unwrap_or(30);
 
     env_logger::from_env(
         env_logger::Env::default()
@@ -40,10 +103,38 @@ fn main() {
     let mountpoint = env::var("MOUNT_POINT").unwrap_or(String::from("./mnt"));
     let rootpath = env::var("ROOT_PATH").unwrap_or(String::from("./root"));
 
-    let fs = ossfs::Fuse::new(
-        ossfs::SeaweedfsBackend::new("http://172.21.20.250:8888", "server"),
-        enable_cache,
-    );
+    let mut registry = ossfs::BackendRegistry::default();
+    if matches.is_present("allow-credential-process") {
+        registry.allow_credential_process();
+    }
+    let backend = match registry.build(&backend) {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("failed to connect to the backend. {}", e);
+            std::process::exit(1);
+        }
+    };
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    log::info!("fault injection seed: {}", seed);
+    let mut backend = ossfs::FaultInjectingBackend::new(backend, seed);
+    for spec in matches.values_of("fault").into_iter().flatten() {
+        backend = backend.with_fault(spec.parse().expect("invalid fault"));
+    }
+    let mut builder = ossfs::Fuse::builder(backend)
+        .enable_cache(enable_cache)
+        .passthrough(matches.is_present("passthrough"))
+        .watch_interval(Some(std::time::Duration::from_secs(10)))
+        .backend_timeout(if timeout == 0 {
+            None
+        } else {
+            Some(std::time::Duration::from_secs(timeout))
+        });
+    if let Some(dir) = matches.value_of("dump-dir") {
+        builder = builder.dump_dir(dir.into());
+    }
     // let fs = ossfs::Fuse::new(ossfs::SimpleBackend::new(rootpath), enable_cache);
     // let fs = super::Fuse::new(super::S3Backend::new(
     //     "http://172.21.20.134:9001",
@@ -51,9 +142,26 @@ fn main() {
     //     "admin",
     //     "password",
     // ));
-    let options = ["-o", "rw", "-o", "fsname=ossfs"]
+    let mut options = ossfs::default_mount_options("ossfs");
+    options.push("-o".into());
+    options.push("rw".into());
+    let options = options
         .iter()
         .map(|o| o.as_ref())
         .collect::<Vec<&std::ffi::OsStr>>();
-    fuse::mount(fs, &mountpoint, &options).unwrap();
+    let result = if matches.is_present("supervise") {
+        builder.supervise(&mountpoint, &options, &ossfs::Restart::default())
+    } else {
+        let binds: Vec<std::path::PathBuf> = matches
+            .values_of("bind")
+            .into_iter()
+            .flatten()
+            .map(std::path::PathBuf::from)
+            .collect();
+        builder.mount_with_binds(&mountpoint, &binds, &options)
+    };
+    if let Err(e) = result {
+        eprintln!("failed to mount {}. {}", mountpoint, e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,170 @@
+//! Python bindings of the vfs api, so data pipelines read a bucket through the caches
+//! of ossfs without a fuse mount, e.g. `ossfs.OssfsFileSystem("s3://bucket/prefix")`.
+
+use ossfs_core::{BackendRegistry, BoxedBackend, Config, Error, File, FileType, Vfs};
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Duration;
+
+#[pyclass]
+struct OssfsFileSystem {
+    vfs: Vfs<BoxedBackend>,
+}
+
+#[pyclass]
+struct OssfsFile {
+    // `None` once closed
+    file: Option<File<BoxedBackend>>,
+}
+
+#[pymethods]
+impl OssfsFileSystem {
+    /// Opens the backend at `uri`, e.g. s3://bucket/prefix,
+    /// seaweedfs://filer:8888/bucket or file:///path. `block_size` enables reads in
+    /// blocks of that many bytes, cached in up to `block_cache` bytes.
+    #[new]
+    #[args(block_size = "None", block_cache = "None")]
+    fn new(
+        obj: &PyRawObject,
+        uri: &str,
+        block_size: Option<u64>,
+        block_cache: Option<u64>,
+    ) -> PyResult<()> {
+        let backend = BackendRegistry::default().build(uri).map_err(to_py_err)?;
+        let defaults = Config::default();
+        // other clients of the bucket change the tree too
+        let config = Config {
+            watch_interval: Some(Duration::from_secs(10)),
+            block_size,
+            block_cache: block_cache.unwrap_or(defaults.block_cache),
+            ..defaults
+        };
+        obj.init(OssfsFileSystem {
+            vfs: Vfs::new(backend, &config),
+        });
+        Ok(())
+    }
+
+    /// The names of the entries of the directory at `path`.
+    fn listdir(&self, py: Python, path: &str) -> PyResult<Vec<String>> {
+        let vfs = &self.vfs;
+        let entries = py.allow_threads(|| vfs.read_dir(path)).map_err(to_py_err)?;
+        Ok(entries
+            .iter()
+            .map(|entry| entry.name().to_string_lossy().into_owned())
+            .collect())
+    }
+
+    /// Whether `path` is a directory, `False` if it does not exist.
+    fn isdir(&self, py: Python, path: &str) -> PyResult<bool> {
+        let vfs = &self.vfs;
+        match py.allow_threads(|| vfs.stat(path)) {
+            Ok(attr) => Ok(attr.kind == FileType::Directory),
+            Err(ref e) if e.errno(0) == libc::ENOENT => Ok(false),
+            Err(e) => Err(to_py_err(e)),
+        }
+    }
+
+    /// The size in bytes of the file at `path`.
+    fn size(&self, py: Python, path: &str) -> PyResult<u64> {
+        let vfs = &self.vfs;
+        let attr = py.allow_threads(|| vfs.stat(path)).map_err(to_py_err)?;
+        Ok(attr.size)
+    }
+
+    /// The whole content of the file at `path`.
+    fn read(&self, py: Python, path: &str) -> PyResult<PyObject> {
+        let vfs = &self.vfs;
+        let data = py.allow_threads(|| vfs.read(path)).map_err(to_py_err)?;
+        Ok(PyBytes::new(py, &data).to_object(py))
+    }
+
+    /// Opens the file at `path` for reading.
+    fn open(&self, py: Python, path: &str) -> PyResult<Py<OssfsFile>> {
+        let vfs = &self.vfs;
+        let file = py.allow_threads(|| vfs.open(path)).map_err(to_py_err)?;
+        Py::new(py, OssfsFile { file: Some(file) })
+    }
+}
+
+#[pymethods]
+impl OssfsFile {
+    /// Reads up to `size` bytes, the rest of the file if `size` is negative.
+    #[args(size = "-1")]
+    fn read(&mut self, py: Python, size: i64) -> PyResult<PyObject> {
+        let file = self.opened()?;
+        let data = py
+            .allow_threads(|| {
+                let mut data = vec![];
+                if size < 0 {
+                    file.read_to_end(&mut data)?;
+                } else {
+                    file.take(size as u64).read_to_end(&mut data)?;
+                }
+                Ok(data)
+            })
+            .map_err(|e: std::io::Error| to_py_err(Error::IO(e)))?;
+        Ok(PyBytes::new(py, &data).to_object(py))
+    }
+
+    /// Moves to `offset` relative to the start, the current position or the end of
+    /// the file for a `whence` of 0, 1 or 2, like `io.IOBase.seek`.
+    #[args(whence = "0")]
+    fn seek(&mut self, offset: i64, whence: u32) -> PyResult<u64> {
+        let position = match whence {
+            0 if offset >= 0 => SeekFrom::Start(offset as u64),
+            0 => return Err(exceptions::ValueError::py_err("negative seek position")),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(exceptions::ValueError::py_err("invalid whence")),
+        };
+        self.opened()?
+            .seek(position)
+            .map_err(|e| to_py_err(Error::IO(e)))
+    }
+
+    fn tell(&mut self) -> PyResult<u64> {
+        self.opened()?
+            .seek(SeekFrom::Current(0))
+            .map_err(|e| to_py_err(Error::IO(e)))
+    }
+
+    fn close(&mut self) {
+        self.file = None;
+    }
+}
+
+impl OssfsFile {
+    fn opened(&mut self) -> PyResult<&mut File<BoxedBackend>> {
+        match self.file {
+            Some(ref mut file) => Ok(file),
+            None => Err(exceptions::ValueError::py_err(
+                "I/O operation on closed file",
+            )),
+        }
+    }
+}
+
+// the OSError subclass python raises for the same errno
+fn to_py_err(e: Error) -> PyErr {
+    let errno = e.errno(libc::EIO);
+    let args = (errno, e.to_string());
+    match errno {
+        libc::ENOENT => exceptions::FileNotFoundError::py_err(args),
+        libc::EEXIST => exceptions::FileExistsError::py_err(args),
+        libc::ENOTDIR => exceptions::NotADirectoryError::py_err(args),
+        libc::EISDIR => exceptions::IsADirectoryError::py_err(args),
+        libc::EACCES | libc::EPERM => exceptions::PermissionError::py_err(args),
+        libc::ETIMEDOUT => exceptions::TimeoutError::py_err(args),
+        _ => exceptions::OSError::py_err(args),
+    }
+}
+
+#[pymodule]
+fn ossfs(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<OssfsFileSystem>()?;
+    m.add_class::<OssfsFile>()?;
+    Ok(())
+}
@@ -4,6 +4,14 @@ pub enum Error {
     Backend(String),
     IO(std::io::Error),
     Nix(nix::Error),
+    NotFound(String),
+    PermissionDenied(String),
+    // the backend asks us to slow down
+    Throttled(String),
+    Timeout(String),
+    // the entry already exists or was changed concurrently
+    Conflict(String),
+    Unavailable(String),
     Other(String),
 }
 
@@ -14,6 +22,12 @@ impl std::fmt::Display for Error {
             Error::Backend(message) => write!(f, "[backend] {}", message),
             Error::IO(io_error) => io_error.fmt(f),
             Error::Nix(e) => e.fmt(f),
+            Error::NotFound(message) => write!(f, "[not found] {}", message),
+            Error::PermissionDenied(message) => write!(f, "[permission denied] {}", message),
+            Error::Throttled(message) => write!(f, "[throttled] {}", message),
+            Error::Timeout(message) => write!(f, "[timeout] {}", message),
+            Error::Conflict(message) => write!(f, "[conflict] {}", message),
+            Error::Unavailable(message) => write!(f, "[unavailable] {}", message),
             Error::Other(e) => write!(f, "{}", e),
         }
     }
@@ -21,6 +35,83 @@ impl std::fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+/// `std::io::Error` can not be cloned, a clone of it keeps the os error code or
+/// else the kind and the message.
+impl Clone for Error {
+    fn clone(&self) -> Error {
+        match self {
+            Error::Fuse(code) => Error::Fuse(*code),
+            Error::Backend(message) => Error::Backend(message.clone()),
+            Error::IO(e) => Error::IO(match e.raw_os_error() {
+                Some(code) => std::io::Error::from_raw_os_error(code),
+                None => std::io::Error::new(e.kind(), e.to_string()),
+            }),
+            Error::Nix(e) => Error::Nix(e.clone()),
+            Error::NotFound(message) => Error::NotFound(message.clone()),
+            Error::PermissionDenied(message) => Error::PermissionDenied(message.clone()),
+            Error::Throttled(message) => Error::Throttled(message.clone()),
+            Error::Timeout(message) => Error::Timeout(message.clone()),
+            Error::Conflict(message) => Error::Conflict(message.clone()),
+            Error::Unavailable(message) => Error::Unavailable(message.clone()),
+            Error::Other(message) => Error::Other(message.clone()),
+        }
+    }
+}
+
+impl Error {
+    /// Maps the status of a failed http response onto the error taxonomy.
+    pub fn from_http_status(status: u16, message: String) -> Error {
+        match status {
+            404 => Error::NotFound(message),
+            401 | 403 => Error::PermissionDenied(message),
+            408 | 504 => Error::Timeout(message),
+            409 | 412 => Error::Conflict(message),
+            429 | 503 => Error::Throttled(message),
+            _ => Error::Backend(format!("status: {}, {}", status, message)),
+        }
+    }
+
+    /// errno replied to the kernel, `fallback` is used when the error carries no specific code.
+    pub fn errno(&self, fallback: libc::c_int) -> libc::c_int {
+        match self {
+            Error::Fuse(code) => *code,
+            Error::IO(e) => e.raw_os_error().unwrap_or_else(|| match e.kind() {
+                std::io::ErrorKind::NotFound => libc::ENOENT,
+                std::io::ErrorKind::PermissionDenied => libc::EACCES,
+                std::io::ErrorKind::AlreadyExists => libc::EEXIST,
+                std::io::ErrorKind::TimedOut => libc::ETIMEDOUT,
+                _ => fallback,
+            }),
+            Error::Nix(nix::Error::Sys(errno)) => *errno as libc::c_int,
+            Error::NotFound(_) => libc::ENOENT,
+            Error::PermissionDenied(_) => libc::EACCES,
+            Error::Throttled(_) => libc::EAGAIN,
+            Error::Timeout(_) => libc::ETIMEDOUT,
+            Error::Conflict(_) => libc::EEXIST,
+            Error::Unavailable(_) => libc::EIO,
+            _ => fallback,
+        }
+    }
+
+    /// Whether the same request may succeed when issued again later. Retryable
+    /// errors also tell that the backend itself is struggling.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Throttled(_) | Error::Timeout(_) | Error::Unavailable(_) => true,
+            Error::Backend(_) => true,
+            Error::IO(e) => match e.kind() {
+                std::io::ErrorKind::NotFound
+                | std::io::ErrorKind::PermissionDenied
+                | std::io::ErrorKind::AlreadyExists
+                | std::io::ErrorKind::InvalidInput
+                | std::io::ErrorKind::InvalidData => false,
+                _ => true,
+            },
+            _ => false,
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 impl From<std::io::Error> for Error {
@@ -34,12 +125,24 @@ where
     T: 'static + std::fmt::Display + std::error::Error,
 {
     fn from(e: rusoto_core::RusotoError<T>) -> Error {
-        Error::Backend(format!("{}", e))
+        match e {
+            rusoto_core::RusotoError::Unknown(response) => Error::from_http_status(
+                response.status.as_u16(),
+                String::from_utf8_lossy(&response.body).into_owned(),
+            ),
+            rusoto_core::RusotoError::HttpDispatch(e) => Error::Unavailable(format!("{}", e)),
+            rusoto_core::RusotoError::Credentials(e) => Error::PermissionDenied(format!("{}", e)),
+            e => Error::Backend(format!("{}", e)),
+        }
     }
 }
 
 impl From<hyper::Error> for Error {
     fn from(e: hyper::Error) -> Self {
-        Error::Backend(format!("hyper error: {:?}", e))
+        if e.is_connect() {
+            Error::Unavailable(format!("hyper error: {:?}", e))
+        } else {
+            Error::Backend(format!("hyper error: {:?}", e))
+        }
     }
 }
@@ -2,8 +2,62 @@ mod counter;
 mod error;
 mod ossfs_impl;
 
+pub use crate::ossfs_impl::attr::{FileAttr, FileType};
 pub use counter::Counter;
+pub use error::{Error, Result};
+pub use ossfs_impl::backend::conformance;
 pub use ossfs_impl::backend::{
-    s3::S3Backend, seaweedfs::SeaweedfsBackend, simple::SimpleBackend, Backend,
+    batching::BatchingBackend,
+    credentials::{Credentials, Keys, RefreshingProvider},
+    fault::{Fault, FaultAction, FaultError, FaultInjectingBackend},
+    mime::ContentTypes,
+    mock::MockBackend,
+    registry::BackendRegistry,
+    s3::S3Backend,
+    seaweedfs::{SeaweedfsBackend, SeaweedfsBuilder},
+    sharded::ShardedBackend,
+    simple::SimpleBackend,
+    tiered::TieredBackend,
+    tls::{TlsConfig, TlsVerify},
+    Backend, BoxedBackend, DynBackend,
 };
-pub use ossfs_impl::Fuse;
+pub use ossfs_impl::budget::{Prices, RequestCounts};
+pub use ossfs_impl::control::{Command, CONTROL_XATTR};
+pub use ossfs_impl::direct_io::DirectIo;
+#[cfg(all(windows, feature = "windows"))]
+pub use ossfs_impl::dokan::Dokan;
+#[cfg(unix)]
+pub use ossfs_impl::export;
+pub use ossfs_impl::filesystem::{
+    FileSystem, Inode, ETAG_XATTR, MD5_XATTR, ROOT_INODE, STORAGE_CLASS_XATTR, SYNC_XATTR,
+    TAG_XATTR_PREFIX,
+};
+pub use ossfs_impl::filter::Filter;
+#[cfg(feature = "gateway")]
+pub use ossfs_impl::gateway::Gateway;
+pub use ossfs_impl::health;
+pub use ossfs_impl::labels::Labels;
+pub use ossfs_impl::manifest;
+pub use ossfs_impl::node::Node;
+pub use ossfs_impl::object_store::{GetResult, ListResult, ObjectMeta, ObjectStore};
+pub use ossfs_impl::pins::RemoteChange;
+pub use ossfs_impl::platform::default_mount_options;
+pub use ossfs_impl::policy::{Access, Policy};
+pub use ossfs_impl::prefetch::{self, Warmed, WARM_XATTR};
+pub use ossfs_impl::prefetch_policy::{ColumnarPolicy, PrefetchPolicy};
+#[cfg(unix)]
+pub use ossfs_impl::shutdown;
+pub use ossfs_impl::simulate;
+pub use ossfs_impl::stat::{Stat, Usage};
+#[cfg(unix)]
+pub use ossfs_impl::supervisor::Restart;
+pub use ossfs_impl::trace;
+pub use ossfs_impl::transfer;
+pub use ossfs_impl::usage::{DU_BYTES_XATTR, DU_ENTRIES_XATTR};
+pub use ossfs_impl::vfs::{DirEntry, File, Vfs};
+pub use ossfs_impl::virtual_file::VirtualFile;
+pub use ossfs_impl::watch::Event;
+pub use ossfs_impl::writeback::{Pending, SyncState};
+pub use ossfs_impl::Config;
+#[cfg(unix)]
+pub use ossfs_impl::{Fuse, FuseBuilder};
@@ -0,0 +1,236 @@
+//! Reads adapting to how each open handle reads its file: whole, with a growing
+//! readahead window, or only the requested ranges.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// files at most this large are fetched whole at the first read of a handle
+const WHOLE_FILE_LIMIT: u64 = 1024 * 1024;
+// the readahead window grows up to it
+const MAX_WINDOW: u64 = 8 * 1024 * 1024;
+// consecutive sequential reads before reading ahead
+const STREAK: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// The requested range only.
+    Direct,
+    /// The requested range and as many bytes after it.
+    Readahead(u64),
+    /// The whole file.
+    WholeFile,
+}
+
+/// How a handle reads its file.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Pattern {
+    pub reads: u64,
+    // reads starting where the previous one ended
+    pub sequential: u64,
+    pub bytes: u64,
+    // where a sequential read starts
+    next: u64,
+    // consecutive sequential reads
+    streak: u32,
+    // readahead of the last fetch, 0 unless reading ahead
+    window: u64,
+}
+
+impl Pattern {
+    pub fn record(&mut self, offset: u64, size: u64) {
+        if self.reads > 0 && offset == self.next {
+            self.sequential += 1;
+            self.streak += 1;
+        } else {
+            self.streak = 0;
+            self.window = 0;
+        }
+        self.reads += 1;
+        self.bytes += size;
+        self.next = offset + size;
+    }
+
+    /// Average bytes of a read.
+    pub fn average(&self) -> u64 {
+        if self.reads == 0 {
+            return 0;
+        }
+        self.bytes / self.reads
+    }
+
+    /// How to fetch a range of a file of `file_size` bytes missing from the buffer,
+    /// growing the readahead window.
+    fn strategy(&mut self, file_size: u64) -> Strategy {
+        if self.reads == 1 && file_size <= WHOLE_FILE_LIMIT {
+            return Strategy::WholeFile;
+        }
+        if self.streak < STREAK {
+            return Strategy::Direct;
+        }
+        self.window = if self.window == 0 {
+            2 * self.average()
+        } else {
+            2 * self.window
+        };
+        self.window = std::cmp::min(self.window, MAX_WINDOW);
+        Strategy::Readahead(self.window)
+    }
+}
+
+/// What a read of a handle needs.
+#[derive(Debug, PartialEq)]
+pub enum Plan {
+    /// Served from what the handle fetched before.
+    Cached(Vec<u8>),
+    /// The range to fetch, which holds the requested one.
+    Fetch(u64, u64),
+}
+
+#[derive(Debug)]
+struct Handle {
+    ino: u64,
+    pattern: Pattern,
+    // offset and content of the last fetch larger than its read
+    buffer: Option<(u64, Arc<Vec<u8>>)>,
+}
+
+/// The handles open on the mount, numbered from 1.
+#[derive(Debug)]
+pub struct OpenHandles {
+    next: AtomicU64,
+    handles: Mutex<HashMap<u64, Handle>>,
+}
+
+impl OpenHandles {
+    pub fn new() -> OpenHandles {
+        OpenHandles {
+            next: AtomicU64::new(1),
+            handles: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn open(&self, ino: u64) -> u64 {
+        let fh = self.next.fetch_add(1, Ordering::Relaxed);
+        self.handles.lock().unwrap().insert(
+            fh,
+            Handle {
+                ino,
+                pattern: Pattern::default(),
+                buffer: None,
+            },
+        );
+        fh
+    }
+
+    /// Forgets the handle `fh`, returning how it was read.
+    pub fn close(&self, fh: u64) -> Option<Pattern> {
+        let handle = self.handles.lock().unwrap().remove(&fh)?;
+        Some(handle.pattern)
+    }
+
+    /// Records a read of `size` bytes at `offset` of the handle `fh` on a file of
+    /// `file_size` bytes and plans it. `None` for unknown handles.
+    pub fn plan(&self, fh: u64, offset: u64, size: u64, file_size: u64) -> Option<Plan> {
+        let mut handles = self.handles.lock().unwrap();
+        let handle = handles.get_mut(&fh)?;
+        handle.pattern.record(offset, size);
+        if let Some((start, ref data)) = handle.buffer {
+            let end = start + data.len() as u64;
+            if offset >= start && offset + size <= end {
+                let from = (offset - start) as usize;
+                return Some(Plan::Cached(data[from..from + size as usize].to_vec()));
+            }
+        }
+        let remaining = file_size.saturating_sub(offset);
+        Some(match handle.pattern.strategy(file_size) {
+            Strategy::Direct => Plan::Fetch(offset, size),
+            Strategy::Readahead(window) => {
+                Plan::Fetch(offset, std::cmp::min(size + window, remaining))
+            }
+            Strategy::WholeFile => Plan::Fetch(0, file_size),
+        })
+    }
+
+    /// Keeps `data` fetched at `offset` for the next reads of the handle `fh`.
+    pub fn fill(&self, fh: u64, offset: u64, data: Arc<Vec<u8>>) {
+        if let Some(handle) = self.handles.lock().unwrap().get_mut(&fh) {
+            handle.buffer = Some((offset, data));
+        }
+    }
+
+    /// Drops what the handles of the file `ino` fetched, once it is written.
+    pub fn forget(&self, ino: u64) {
+        for handle in self.handles.lock().unwrap().values_mut() {
+            if handle.ino == ino {
+                handle.buffer = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{OpenHandles, Plan};
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+    use std::ffi::OsStr;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_plan() {
+        let handles = OpenHandles::new();
+        let small = handles.open(2);
+        assert_eq!(handles.plan(small, 4, 4, 16), Some(Plan::Fetch(0, 16)));
+        assert_eq!(handles.plan(0, 0, 4, 16), None);
+
+        let mb = 1024 * 1024;
+        let large = handles.open(3);
+        assert_eq!(handles.plan(large, 0, 4, 64 * mb), Some(Plan::Fetch(0, 4)));
+        assert_eq!(handles.plan(large, 4, 4, 64 * mb), Some(Plan::Fetch(4, 4)));
+        assert_eq!(handles.plan(large, 8, 4, 64 * mb), Some(Plan::Fetch(8, 12)));
+        handles.fill(large, 8, Arc::new(vec![7; 12]));
+        assert_eq!(
+            handles.plan(large, 12, 4, 64 * mb),
+            Some(Plan::Cached(vec![7; 4]))
+        );
+        // a jump goes back to the requested ranges
+        assert_eq!(
+            handles.plan(large, 32 * mb, 4, 64 * mb),
+            Some(Plan::Fetch(32 * mb, 4))
+        );
+        let pattern = handles.close(large).unwrap();
+        assert_eq!((pattern.reads, pattern.sequential), (5, 3));
+        assert_eq!(pattern.average(), 4);
+    }
+
+    #[test]
+    fn test_adaptive_reads() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/a", b"0123456789");
+        let config = Config {
+            adaptive_reads: true,
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend.clone(), &config);
+        let a = fs.lookup(ROOT_INODE, OsStr::new("a")).unwrap().ino;
+        let fh = fs.open_handle(a);
+        assert_ne!(fh, 0);
+        let read = |offset: usize| {
+            let mut data = vec![];
+            fs.read(a, fh, false, offset, 2, |result| data = result.unwrap());
+            data
+        };
+        assert_eq!(read(0), b"01");
+        assert_eq!(read(8), b"89");
+        assert_eq!(read(4), b"45");
+        assert_eq!(backend.calls("read"), 1);
+
+        // written data replaces what the handle fetched
+        fs.write(a, 4, b"ab").unwrap();
+        fs.release(a).unwrap();
+        assert_eq!(read(4), b"ab");
+        fs.close_handle(fh);
+    }
+}
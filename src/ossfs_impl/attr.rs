@@ -0,0 +1,182 @@
+//! Attributes of nodes, built the same way by every backend from local metadata with
+//! `from_metadata` or from the size and modification time with `synthetic`.
+
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Kind of an entry.
+#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+pub enum FileType {
+    NamedPipe,
+    CharDevice,
+    BlockDevice,
+    Directory,
+    RegularFile,
+    Symlink,
+    Socket,
+}
+
+/// Attributes of an entry, the fields of a stat.
+#[derive(Clone, Copy, Debug)]
+pub struct FileAttr {
+    pub ino: u64,
+    pub size: u64,
+    /// Blocks of 512 bytes.
+    pub blocks: u64,
+    pub atime: SystemTime,
+    pub mtime: SystemTime,
+    pub ctime: SystemTime,
+    /// Creation time, macOS only.
+    pub crtime: SystemTime,
+    pub kind: FileType,
+    pub perm: u16,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u32,
+    /// Flags of the entry, macOS only, see chflags(2).
+    pub flags: u32,
+}
+
+/// Permissions of directories of stores without permissions.
+pub const DIR_PERM: u16 = 0o755;
+
+/// Permissions of files of stores without permissions.
+pub const FILE_PERM: u16 = 0o644;
+
+/// Attributes of an entry of a store which keeps no metadata but its kind, size
+/// and modification time, which stands for every time of the entry. Owned by
+/// root, with the default permissions of its kind.
+pub fn synthetic(kind: FileType, size: u64, mtime: SystemTime) -> FileAttr {
+    let dir = kind == FileType::Directory;
+    FileAttr {
+        ino: 0,
+        size,
+        blocks: blocks(size),
+        atime: mtime,
+        mtime,
+        ctime: mtime,
+        crtime: mtime,
+        kind,
+        perm: if dir { DIR_PERM } else { FILE_PERM },
+        nlink: if dir { 2 } else { 1 },
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        flags: 0,
+    }
+}
+
+/// Attributes of a local file of metadata `meta`, with the times to the nanosecond.
+#[cfg(unix)]
+pub fn from_metadata(meta: &std::fs::Metadata) -> FileAttr {
+    let ctime = system_time(meta.ctime(), meta.ctime_nsec());
+    FileAttr {
+        ino: 0,
+        size: meta.size(),
+        blocks: meta.blocks(),
+        atime: system_time(meta.atime(), meta.atime_nsec()),
+        mtime: system_time(meta.mtime(), meta.mtime_nsec()),
+        ctime,
+        // not every linux filesystem records a birth time
+        crtime: meta.created().unwrap_or(ctime),
+        kind: kind(meta.file_type()),
+        // the mode holds the kind in its upper bits
+        perm: (meta.mode() & 0o7777) as u16,
+        nlink: meta.nlink() as u32,
+        uid: meta.uid(),
+        gid: meta.gid(),
+        rdev: meta.rdev() as u32,
+        flags: flags(meta),
+    }
+}
+
+/// Kind of a local file of type `file_type`.
+#[cfg(unix)]
+pub fn kind(file_type: std::fs::FileType) -> FileType {
+    if file_type.is_dir() {
+        FileType::Directory
+    } else if file_type.is_symlink() {
+        FileType::Symlink
+    } else if file_type.is_fifo() {
+        FileType::NamedPipe
+    } else if file_type.is_socket() {
+        FileType::Socket
+    } else if file_type.is_block_device() {
+        FileType::BlockDevice
+    } else if file_type.is_char_device() {
+        FileType::CharDevice
+    } else {
+        FileType::RegularFile
+    }
+}
+
+/// Blocks of 512 bytes holding `size` bytes.
+pub fn blocks(size: u64) -> u64 {
+    (size + 511) / 512
+}
+
+/// Time of `seconds` and `nanoseconds` since the epoch, which may be before it.
+pub fn system_time(seconds: i64, nanoseconds: i64) -> SystemTime {
+    let nanoseconds = nanoseconds as u32;
+    if seconds >= 0 {
+        UNIX_EPOCH + Duration::new(seconds as u64, nanoseconds)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(seconds.wrapping_neg() as u64)
+            + Duration::new(0, nanoseconds)
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn flags(meta: &std::fs::Metadata) -> u32 {
+    use std::os::macos::fs::MetadataExt;
+    meta.st_flags()
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn flags(_meta: &std::fs::Metadata) -> u32 {
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use super::{from_metadata, synthetic, system_time, FileType, DIR_PERM};
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_synthetic() {
+        let mtime = UNIX_EPOCH + Duration::from_secs(1570000000);
+        let file = synthetic(FileType::RegularFile, 513, mtime);
+        assert_eq!((file.blocks, file.perm, file.nlink), (2, 0o644, 1));
+        assert_eq!((file.atime, file.crtime), (mtime, mtime));
+        let dir = synthetic(FileType::Directory, 0, mtime);
+        assert_eq!((dir.perm, dir.nlink), (DIR_PERM, 2));
+    }
+
+    #[test]
+    fn test_from_metadata() {
+        let dir = std::env::temp_dir().join(format!("ossfs-attr-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file");
+        std::fs::write(&path, b"hello").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o4640)).unwrap();
+        let meta = std::fs::metadata(&path).unwrap();
+        let attr = from_metadata(&meta);
+        assert_eq!(attr.kind, FileType::RegularFile);
+        assert_eq!(attr.perm, 0o4640);
+        assert_eq!(attr.size, 5);
+        assert_eq!(attr.mtime, meta.modified().unwrap());
+        assert_eq!(
+            from_metadata(&std::fs::metadata(&dir).unwrap()).kind,
+            FileType::Directory
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            system_time(-2, 500_000_000),
+            UNIX_EPOCH - Duration::from_millis(1500)
+        );
+    }
+}
@@ -0,0 +1,402 @@
+//! Batches the requests of bursty workloads: requests queued within a small window
+//! are sent at once, lookups as a listing, reads as a vectored read, removals together.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::attr::FileType;
+use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::node::Node;
+use crate::ossfs_impl::stat::{Stat, Usage};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// lookups of a directory from which it is listed instead
+const LIST_THRESHOLD: usize = 4;
+
+// requests waiting to be sent, by what they are batched on
+#[derive(Debug)]
+struct Queue<K, R, T> {
+    pending: Mutex<HashMap<K, Vec<(R, mpsc::Sender<Result<T>>)>>>,
+}
+
+impl<K: Hash + Eq + Clone, R, T> Queue<K, R, T> {
+    fn new() -> Queue<K, R, T> {
+        Queue {
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Queues `request` with the others of `key`. The first request of a batch
+    /// waits for `window`, then sends the whole batch with `send`, which answers
+    /// the requests in their order.
+    fn submit<F>(&self, key: K, request: R, window: Duration, send: F) -> Result<T>
+    where
+        F: FnOnce(Vec<R>) -> Vec<Result<T>>,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let first = {
+            let mut pending = self.pending.lock().unwrap();
+            let batch = pending.entry(key.clone()).or_insert_with(Vec::new);
+            batch.push((request, sender));
+            batch.len() == 1
+        };
+        if first {
+            std::thread::sleep(window);
+            let batch = self
+                .pending
+                .lock()
+                .unwrap()
+                .remove(&key)
+                .unwrap_or_default();
+            let (requests, senders): (Vec<R>, Vec<_>) = batch.into_iter().unzip();
+            for (sender, result) in senders.into_iter().zip(send(requests)) {
+                let _ = sender.send(result);
+            }
+        }
+        receiver
+            .recv()
+            .map_err(|_| Error::Other("the batch of the request was dropped".to_owned()))?
+    }
+}
+
+#[derive(Debug)]
+pub struct BatchingBackend<B> {
+    inner: B,
+    window: Duration,
+    // lookups by parent directory
+    lookups: Queue<PathBuf, PathBuf, Node>,
+    // reads by file
+    reads: Queue<PathBuf, Range<u64>, Vec<u8>>,
+    removals: Queue<(), PathBuf, ()>,
+}
+
+impl<B: Backend> BatchingBackend<B> {
+    pub fn new(inner: B) -> BatchingBackend<B> {
+        BatchingBackend {
+            inner,
+            window: Duration::from_millis(5),
+            lookups: Queue::new(),
+            reads: Queue::new(),
+            removals: Queue::new(),
+        }
+    }
+
+    /// Sets how long requests wait for others to be batched with, 5ms by default.
+    pub fn with_window(mut self, window: Duration) -> BatchingBackend<B> {
+        self.window = window;
+        self
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    // looks the files at `paths` of the directory `parent` up
+    fn lookup(&self, parent: &Path, paths: Vec<PathBuf>) -> Vec<Result<Node>> {
+        if paths.len() < LIST_THRESHOLD {
+            return paths.iter().map(|path| self.inner.get_node(path)).collect();
+        }
+        let children = match self.inner.get_children(parent) {
+            Ok(children) => children,
+            Err(e) => {
+                log::warn!(
+                    "failed to list {:?}, looking files up one by one. {}",
+                    parent,
+                    e
+                );
+                return paths.iter().map(|path| self.inner.get_node(path)).collect();
+            }
+        };
+        let children: HashMap<PathBuf, Node> = children
+            .into_iter()
+            .map(|child| (child.path(), child))
+            .collect();
+        paths
+            .iter()
+            .map(|path| match children.get(path) {
+                // a node of its own for each request, clones share their state
+                Some(child) => {
+                    Ok(
+                        Node::new(child.inode(), child.parent(), child.path(), child.attr())
+                            .with_etag(child.etag()),
+                    )
+                }
+                None => Err(Error::NotFound(format!("{:?}", path))),
+            })
+            .collect()
+    }
+
+    // reads the `ranges` of the file at `path`, those touching each other at once
+    fn read_ranges(&self, path: &Path, ranges: Vec<Range<u64>>) -> Vec<Result<Vec<u8>>> {
+        let mut order: Vec<usize> = (0..ranges.len()).collect();
+        order.sort_by_key(|&i| ranges[i].start);
+        let mut spans: Vec<Range<u64>> = vec![];
+        let mut span_of = vec![0; ranges.len()];
+        for i in order {
+            let range = &ranges[i];
+            match spans.last_mut() {
+                Some(span) if range.start <= span.end => {
+                    span.end = std::cmp::max(span.end, range.end)
+                }
+                _ => spans.push(range.clone()),
+            }
+            span_of[i] = spans.len() - 1;
+        }
+        let data = if spans.len() == 1 {
+            let size = (spans[0].end - spans[0].start) as usize;
+            vec![self.inner.read(path, spans[0].start, size)]
+        } else {
+            self.inner.read_vectored(path, &spans)
+        };
+        ranges
+            .iter()
+            .zip(span_of)
+            .map(|(range, span)| match &data[span] {
+                Ok(data) => {
+                    let start =
+                        std::cmp::min((range.start - spans[span].start) as usize, data.len());
+                    let end = std::cmp::min((range.end - spans[span].start) as usize, data.len());
+                    Ok(data[start..end].to_vec())
+                }
+                Err(e) => Err(e.clone()),
+            })
+            .collect()
+    }
+}
+
+impl<B: Backend> Backend for BatchingBackend<B> {
+    fn root(&self) -> Node {
+        self.inner.root()
+    }
+
+    fn check(&self) -> Result<()> {
+        self.inner.check()
+    }
+
+    fn probe(&self) -> Result<()> {
+        self.inner.probe()
+    }
+
+    fn get_children<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<Node>> {
+        self.inner.get_children(path)
+    }
+
+    fn for_each_children_page<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        page: &mut dyn FnMut(Vec<Node>),
+    ) -> Result<()> {
+        self.inner.for_each_children_page(path, page)
+    }
+
+    fn get_node<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Node> {
+        let path = path.as_ref();
+        let parent = match path.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => return self.inner.get_node(path),
+        };
+        self.lookups
+            .submit(parent.clone(), path.to_path_buf(), self.window, |paths| {
+                self.lookup(&parent, paths)
+            })
+    }
+
+    fn get_path<P: AsRef<Path> + Debug>(&self, path: P, depth: usize) -> Result<Option<Vec<Node>>> {
+        self.inner.get_path(path, depth)
+    }
+
+    fn list_below<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Option<Vec<Node>>> {
+        self.inner.list_below(path)
+    }
+
+    fn statfs<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Stat> {
+        self.inner.statfs(path)
+    }
+
+    fn usage<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Usage> {
+        self.inner.usage(path)
+    }
+
+    fn mknod<P: AsRef<Path> + Debug>(&self, path: P, filetype: FileType, mode: u32) -> Result<()> {
+        self.inner.mknod(path, filetype, mode)
+    }
+
+    fn read<P: AsRef<Path> + Debug>(&self, path: P, offset: u64, size: usize) -> Result<Vec<u8>> {
+        let path = path.as_ref();
+        let range = offset..offset + size as u64;
+        self.reads
+            .submit(path.to_path_buf(), range, self.window, |ranges| {
+                self.read_ranges(path, ranges)
+            })
+    }
+
+    fn read_vectored<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        ranges: &[Range<u64>],
+    ) -> Vec<Result<Vec<u8>>> {
+        self.inner.read_vectored(path, ranges)
+    }
+
+    fn read_if_match<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        offset: u64,
+        size: usize,
+        etag: &str,
+    ) -> Result<Vec<u8>> {
+        self.inner.read_if_match(path, offset, size, etag)
+    }
+
+    fn local_file<P: AsRef<Path> + Debug>(&self, path: P) -> Option<PathBuf> {
+        self.inner.local_file(path)
+    }
+
+    fn put<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        self.inner.put(path, data)
+    }
+
+    fn put_if_absent<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        self.inner.put_if_absent(path, data)
+    }
+
+    fn remove<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        self.removals
+            .submit((), path.as_ref().to_path_buf(), self.window, |paths| {
+                self.inner.remove_many(&paths)
+            })
+    }
+
+    fn remove_many(&self, paths: &[PathBuf]) -> Vec<Result<()>> {
+        self.inner.remove_many(paths)
+    }
+
+    fn remove_dir<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        self.inner.remove_dir(path)
+    }
+
+    fn remove_all<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        progress: &mut dyn FnMut(u64),
+    ) -> Result<u64> {
+        self.inner.remove_all(path, progress)
+    }
+
+    fn copy<P: AsRef<Path> + Debug>(&self, from: P, to: P) -> Result<()> {
+        self.inner.copy(from, to)
+    }
+
+    fn put_with_class<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+    ) -> Result<()> {
+        self.inner.put_with_class(path, data, class)
+    }
+
+    fn put_idempotent<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        self.inner.put_idempotent(path, data, class, token, retry)
+    }
+
+    fn put_streamed<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        len: u64,
+        body: &dyn Fn() -> Box<dyn Read + Send>,
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        self.inner
+            .put_streamed(path, len, body, class, token, retry)
+    }
+
+    fn set_storage_class<P: AsRef<Path> + Debug>(&self, path: P, class: &str) -> Result<()> {
+        self.inner.set_storage_class(path, class)
+    }
+
+    fn get_tags<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<(String, String)>> {
+        self.inner.get_tags(path)
+    }
+
+    fn set_tags<P: AsRef<Path> + Debug>(&self, path: P, tags: &[(String, String)]) -> Result<()> {
+        self.inner.set_tags(path, tags)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BatchingBackend;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::backend::Backend;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Barrier};
+    use std::time::Duration;
+
+    #[test]
+    fn test_batching() {
+        let mock = Arc::new(MockBackend::new());
+        for i in 0..8 {
+            mock.add_file(format!("/dir/{}", i), b"0123456789");
+        }
+        let backend =
+            Arc::new(BatchingBackend::new(mock.clone()).with_window(Duration::from_millis(100)));
+        // runs `f` on 8 threads starting together
+        let concurrently = |f: Arc<dyn Fn(usize) + Send + Sync>| {
+            let barrier = Arc::new(Barrier::new(8));
+            let threads: Vec<_> = (0..8)
+                .map(|i| {
+                    let (f, barrier) = (f.clone(), barrier.clone());
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        f(i)
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+        };
+
+        let b = backend.clone();
+        concurrently(Arc::new(move |i| {
+            let path = PathBuf::from(format!("/dir/{}", i));
+            assert_eq!(b.get_node(&path).unwrap().path(), path);
+        }));
+        assert!(mock.calls("get_children") + mock.calls("get_node") <= 2);
+        assert!(backend.get_node("/dir/missing").is_err());
+
+        // ranges of /dir/0 touching each other, read at once
+        let b = backend.clone();
+        concurrently(Arc::new(move |i| {
+            let offset = (i % 4) * 3;
+            let data = b.read("/dir/0", offset as u64, 3).unwrap();
+            let end = std::cmp::min(offset + 3, 10);
+            assert_eq!(data, &b"0123456789"[offset..end]);
+        }));
+        assert!(mock.calls("read") <= 2);
+
+        let b = backend.clone();
+        concurrently(Arc::new(move |i| {
+            b.remove(format!("/dir/{}", i)).unwrap();
+        }));
+        assert!(mock.calls("remove_many") <= 2);
+        assert_eq!(mock.calls("remove"), 0);
+        assert!(mock.content("/dir/7").is_none());
+    }
+}
@@ -0,0 +1,306 @@
+//! Semantic checks every `Backend` is expected to pass.
+
+use crate::error::Error;
+use crate::ossfs_impl::attr::FileType;
+use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::filesystem::ROOT_INODE;
+use crate::ossfs_impl::node::Node;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Barrier};
+
+/// Number of files used by `pagination`, large enough to span several pages of
+/// the object stores we know of.
+pub const PAGINATION_FILES: usize = 1100;
+
+pub const UNICODE_NAMES: &[&str] = &[
+    "中文",
+    "emoji 😀",
+    "space name",
+    "percent%20",
+    "plus+sign",
+    "query?hash#",
+    "e\u{301}",
+];
+
+/// Generates one `#[test]` per conformance check in a module called `$name`, each on
+/// a backend `$setup` creates with the given files below its root.
+#[macro_export]
+macro_rules! backend_conformance {
+    ($name:ident, $setup:expr) => {
+        mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            #[test]
+            fn root_attrs() {
+                $crate::conformance::root_attrs($setup);
+            }
+
+            #[test]
+            fn listing_completeness() {
+                $crate::conformance::listing_completeness($setup);
+            }
+
+            #[test]
+            fn pagination() {
+                $crate::conformance::pagination($setup);
+            }
+
+            #[test]
+            fn range_reads() {
+                $crate::conformance::range_reads($setup);
+            }
+
+            #[test]
+            fn vectored_reads() {
+                $crate::conformance::vectored_reads($setup);
+            }
+
+            #[test]
+            fn unicode_names() {
+                $crate::conformance::unicode_names($setup);
+            }
+
+            #[test]
+            fn error_mapping() {
+                $crate::conformance::error_mapping($setup);
+            }
+
+            #[test]
+            fn exclusive_create() {
+                $crate::conformance::exclusive_create($setup);
+            }
+        }
+    };
+}
+
+fn path_of<B: Backend>(backend: &B, relative: &str) -> PathBuf {
+    backend.root().path().join(relative)
+}
+
+fn list<B: Backend>(backend: &B, relative: &str) -> BTreeMap<String, Node> {
+    let path = path_of(backend, relative);
+    backend
+        .get_children(&path)
+        .unwrap_or_else(|e| panic!("get_children {:?}. error: {}", path, e))
+        .into_iter()
+        .map(|node| {
+            let name = node
+                .path()
+                .file_name()
+                .unwrap_or_else(|| panic!("child without name: {:?}", node.path()))
+                .to_string_lossy()
+                .into_owned();
+            (name, node)
+        })
+        .collect()
+}
+
+fn expect_errno(result: Result<(), Error>, what: &str) {
+    match result {
+        Ok(()) => panic!("{} succeeded", what),
+        Err(e) => assert_eq!(e.errno(0), libc::ENOENT, "{}. error: {}", what, e),
+    }
+}
+
+pub fn root_attrs<B: Backend, F: Fn(&[(&str, &[u8])]) -> B>(setup: F) {
+    let backend = setup(&[("file", &b"data"[..])]);
+    let root = backend.root();
+    assert_eq!(root.inode(), ROOT_INODE);
+    assert_eq!(root.attr().ino, ROOT_INODE);
+    assert_eq!(root.attr().kind, FileType::Directory);
+    let node = backend.get_node(root.path()).unwrap();
+    assert_eq!(node.attr().kind, FileType::Directory);
+}
+
+pub fn listing_completeness<B: Backend, F: Fn(&[(&str, &[u8])]) -> B>(setup: F) {
+    let backend = setup(&[
+        ("a", &b"1"[..]),
+        ("b", &b"22"[..]),
+        ("dir/c", &b"333"[..]),
+        ("dir/sub/d", &b""[..]),
+    ]);
+    let root = list(&backend, "");
+    assert_eq!(root.keys().collect::<Vec<_>>(), vec!["a", "b", "dir"]);
+    assert_eq!(root["a"].attr().kind, FileType::RegularFile);
+    assert_eq!(root["a"].attr().size, 1);
+    assert_eq!(root["b"].attr().size, 2);
+    assert_eq!(root["dir"].attr().kind, FileType::Directory);
+    assert_eq!(root["dir"].path(), path_of(&backend, "dir"));
+
+    let dir = list(&backend, "dir");
+    assert_eq!(dir.keys().collect::<Vec<_>>(), vec!["c", "sub"]);
+    assert_eq!(dir["c"].attr().size, 3);
+    assert_eq!(dir["sub"].attr().kind, FileType::Directory);
+
+    let node = backend.get_node(path_of(&backend, "dir/c")).unwrap();
+    assert_eq!(node.attr().kind, FileType::RegularFile);
+    assert_eq!(node.attr().size, 3);
+}
+
+pub fn pagination<B: Backend, F: Fn(&[(&str, &[u8])]) -> B>(setup: F) {
+    let names: Vec<String> = (0..PAGINATION_FILES)
+        .map(|i| format!("many/{:05}", i))
+        .collect();
+    let files: Vec<(&str, &[u8])> = names.iter().map(|name| (name.as_str(), &b""[..])).collect();
+    let backend = setup(&files);
+    let listed = list(&backend, "many");
+    assert_eq!(listed.len(), PAGINATION_FILES);
+    for name in names.iter() {
+        assert!(
+            listed.contains_key(&name["many/".len()..]),
+            "missing {}",
+            name
+        );
+    }
+}
+
+pub fn range_reads<B: Backend, F: Fn(&[(&str, &[u8])]) -> B>(setup: F) {
+    let data: Vec<u8> = (0..100u8).collect();
+    let backend = setup(&[("range", &data[..]), ("empty", &b""[..])]);
+    let path = path_of(&backend, "range");
+    let read = |offset: u64, size: usize| {
+        backend
+            .read(&path, offset, size)
+            .unwrap_or_else(|e| panic!("read {}+{}. error: {}", offset, size, e))
+    };
+    assert_eq!(read(0, 100), data);
+    assert_eq!(read(0, 10), &data[..10]);
+    assert_eq!(read(10, 10), &data[10..20]);
+    assert_eq!(read(90, 100), &data[90..]);
+    assert_eq!(read(99, 1), &data[99..]);
+    assert!(read(100, 10).is_empty());
+    assert!(read(150, 10).is_empty());
+    assert!(read(0, 0).is_empty());
+    let empty = path_of(&backend, "empty");
+    assert!(backend.read(&empty, 0, 10).unwrap().is_empty());
+}
+
+pub fn vectored_reads<B: Backend, F: Fn(&[(&str, &[u8])]) -> B>(setup: F) {
+    let data: Vec<u8> = (0..100u8).collect();
+    let backend = setup(&[("range", &data[..])]);
+    let path = path_of(&backend, "range");
+    let results: Vec<Vec<u8>> = backend
+        .read_vectored(&path, &[90..100, 0..10, 5..15, 40..40, 95..120, 150..160])
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|e| panic!("read_vectored. error: {}", e)))
+        .collect();
+    assert_eq!(results.len(), 6);
+    assert_eq!(results[0], &data[90..]);
+    assert_eq!(results[1], &data[..10]);
+    assert_eq!(results[2], &data[5..15]);
+    assert!(results[3].is_empty());
+    assert_eq!(results[4], &data[95..]);
+    assert!(results[5].is_empty());
+}
+
+pub fn unicode_names<B: Backend, F: Fn(&[(&str, &[u8])]) -> B>(setup: F) {
+    let files: Vec<(&str, &[u8])> = UNICODE_NAMES
+        .iter()
+        .map(|name| (*name, name.as_bytes()))
+        .collect();
+    let backend = setup(&files);
+    let listed = list(&backend, "");
+    for name in UNICODE_NAMES {
+        let node = listed
+            .get(*name)
+            .unwrap_or_else(|| panic!("missing {:?} in {:?}", name, listed.keys()));
+        assert_eq!(node.attr().size, name.len() as u64);
+        let path = path_of(&backend, name);
+        assert_eq!(node.path(), path);
+        backend
+            .get_node(&path)
+            .unwrap_or_else(|e| panic!("get_node {:?}. error: {}", path, e));
+        assert_eq!(backend.read(&path, 0, name.len()).unwrap(), name.as_bytes());
+    }
+}
+
+pub fn error_mapping<B: Backend, F: Fn(&[(&str, &[u8])]) -> B>(setup: F) {
+    let backend = setup(&[("file", &b"data"[..])]);
+    let missing = path_of(&backend, "missing");
+    expect_errno(backend.get_node(&missing).map(|_| ()), "get_node missing");
+    expect_errno(backend.read(&missing, 0, 10).map(|_| ()), "read missing");
+    expect_errno(
+        backend.get_children(&missing).and_then(|children| {
+            // object stores can not tell an empty prefix from a missing one
+            if children.is_empty() {
+                Err(Error::NotFound(format!("{:?}", missing)))
+            } else {
+                Ok(())
+            }
+        }),
+        "get_children missing",
+    );
+}
+
+/// Creating an existing file fails with EEXIST and leaves it alone, of several
+/// concurrent creates of a name exactly one succeeds. Lock files taken with
+/// `open(O_CREAT|O_EXCL)` rely on it.
+pub fn exclusive_create<B, F>(setup: F)
+where
+    B: Backend + Send + Sync + 'static,
+    F: Fn(&[(&str, &[u8])]) -> B,
+{
+    let backend = Arc::new(setup(&[("file", &b"data"[..])]));
+    let existing = path_of(&*backend, "file");
+    match backend.mknod(&existing, FileType::RegularFile, 0o644) {
+        Ok(()) => panic!("mknod of an existing file succeeded"),
+        Err(e) => assert_eq!(e.errno(0), libc::EEXIST, "mknod existing. error: {}", e),
+    }
+    assert_eq!(backend.read(&existing, 0, 4).unwrap(), b"data");
+
+    let threads = 4;
+    let barrier = Arc::new(Barrier::new(threads));
+    let created: usize = (0..threads)
+        .map(|_| {
+            let backend = backend.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                let lock = path_of(&*backend, "lock");
+                barrier.wait();
+                backend.mknod(&lock, FileType::RegularFile, 0o644).is_ok()
+            })
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .filter(|handle| handle.join().unwrap())
+        .count();
+    assert_eq!(created, 1);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::backend::simple::SimpleBackend;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_ROOT: AtomicUsize = AtomicUsize::new(0);
+
+    fn mock(files: &[(&str, &[u8])]) -> MockBackend {
+        let backend = MockBackend::new();
+        for (path, data) in files {
+            backend.add_file(Path::new("/").join(path), data);
+        }
+        backend
+    }
+
+    fn simple(files: &[(&str, &[u8])]) -> SimpleBackend {
+        let root = std::env::temp_dir().join(format!(
+            "ossfs-conformance-{}-{}",
+            std::process::id(),
+            NEXT_ROOT.fetch_add(1, Ordering::SeqCst)
+        ));
+        for (path, data) in files {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, data).unwrap();
+        }
+        std::fs::create_dir_all(&root).unwrap();
+        SimpleBackend::new(root.to_str().unwrap())
+    }
+
+    backend_conformance!(mock_backend, mock);
+    backend_conformance!(simple_backend, simple);
+}
@@ -0,0 +1,433 @@
+//! Where the keys of a backend come from, so they are not passed in plain text on
+//! the command line or in a config file.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::backend::sts;
+use futures01::future::FutureResult;
+use rusoto_core::credential::{AwsCredentials, CredentialsError, ProvideAwsCredentials};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+// temporary keys are renewed this long before they expire
+const RENEW_BEFORE_SECONDS: i64 = 5 * 60;
+
+/// Keys signing the requests, redacted when debugged.
+#[derive(Clone, PartialEq)]
+pub struct Keys {
+    pub access_key: String,
+    pub secret_key: String,
+    // set for temporary credentials
+    pub session_token: Option<String>,
+    pub expiration: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl std::fmt::Debug for Keys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "access_key: {}, expiration: {:?}",
+            self.access_key, self.expiration
+        )
+    }
+}
+
+impl Keys {
+    pub fn new<S: Into<String>>(access_key: S, secret_key: S) -> Keys {
+        Keys {
+            access_key: access_key.into(),
+            secret_key: secret_key.into(),
+            session_token: None,
+            expiration: None,
+        }
+    }
+}
+
+/// Parsed from `file:<path>`, `env:<access key var>:<secret var>`, `process:<command>`,
+/// `web-identity` or `assume-role:<role arn>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Credentials {
+    Static(Keys),
+    /// Names of the environment variables holding the access key and the secret,
+    /// a session token is taken from `AWS_SESSION_TOKEN`.
+    Env {
+        access_key: String,
+        secret_key: String,
+    },
+    File(PathBuf),
+    /// Shell command printing the keys.
+    Process(String),
+    /// Temporary keys of `role_arn` in exchange for the token in `token_file`.
+    WebIdentity {
+        role_arn: String,
+        token_file: PathBuf,
+        // of the security token service
+        endpoint: String,
+    },
+    /// Temporary keys of `role_arn`, assumed with the keys of `source`.
+    AssumeRole {
+        role_arn: String,
+        source: Box<Credentials>,
+        endpoint: String,
+    },
+}
+
+impl Default for Credentials {
+    fn default() -> Credentials {
+        Credentials::Env {
+            access_key: "AWS_ACCESS_KEY_ID".to_owned(),
+            secret_key: "AWS_SECRET_ACCESS_KEY".to_owned(),
+        }
+    }
+}
+
+impl FromStr for Credentials {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Credentials> {
+        let mut parts = source.splitn(2, ':');
+        let kind = parts.next().unwrap_or("");
+        let rest = parts.next().unwrap_or("");
+        match kind {
+            "file" if !rest.is_empty() => Ok(Credentials::File(PathBuf::from(rest))),
+            "process" if !rest.is_empty() => Ok(Credentials::Process(rest.to_owned())),
+            "env" if rest.is_empty() => Ok(Credentials::default()),
+            "web-identity" if rest.is_empty() => Credentials::web_identity(),
+            // the arn has colons of its own
+            "assume-role" if !rest.is_empty() => Ok(Credentials::AssumeRole {
+                role_arn: rest.to_owned(),
+                source: Box::new(Credentials::default()),
+                endpoint: sts::ENDPOINT.to_owned(),
+            }),
+            "env" => {
+                let mut names = rest.splitn(2, ':');
+                match (names.next(), names.next()) {
+                    (Some(access_key), Some(secret_key)) => Ok(Credentials::Env {
+                        access_key: access_key.to_owned(),
+                        secret_key: secret_key.to_owned(),
+                    }),
+                    _ => Err(invalid(source)),
+                }
+            }
+            _ => Err(invalid(source)),
+        }
+    }
+}
+
+fn invalid(source: &str) -> Error {
+    Error::Other(format!(
+        "invalid credentials {:?}, expected `file:<path>`, `env:<access key variable>:<secret variable>`, `process:<command>`, `web-identity` or `assume-role:<role arn>`",
+        source
+    ))
+}
+
+impl Credentials {
+    /// Exchanges the token of `AWS_WEB_IDENTITY_TOKEN_FILE` for keys of `AWS_ROLE_ARN`,
+    /// the variables Kubernetes sets for a service account bound to a role.
+    pub fn web_identity() -> Result<Credentials> {
+        let var = |name: &str| {
+            std::env::var(name).map_err(|_| Error::Other(format!("{} is not set", name)))
+        };
+        Ok(Credentials::WebIdentity {
+            role_arn: var("AWS_ROLE_ARN")?,
+            token_file: PathBuf::from(var("AWS_WEB_IDENTITY_TOKEN_FILE")?),
+            endpoint: sts::ENDPOINT.to_owned(),
+        })
+    }
+
+    /// Reads the keys, again on every call.
+    pub fn resolve(&self) -> Result<Keys> {
+        match self {
+            Credentials::Static(keys) => Ok(keys.clone()),
+            Credentials::Env {
+                access_key,
+                secret_key,
+            } => {
+                let var = |name: &str| {
+                    std::env::var(name).map_err(|_| Error::Other(format!("{} is not set", name)))
+                };
+                let mut keys = Keys::new(var(access_key)?, var(secret_key)?);
+                keys.session_token = std::env::var("AWS_SESSION_TOKEN").ok();
+                Ok(keys)
+            }
+            Credentials::File(path) => read_file(path),
+            Credentials::Process(command) => run_process(command),
+            Credentials::WebIdentity {
+                role_arn,
+                token_file,
+                endpoint,
+            } => {
+                // the token is rotated on disk, read it every time
+                let token = std::fs::read_to_string(token_file)?;
+                sts::assume_role_with_web_identity(endpoint, role_arn, token.trim())
+            }
+            Credentials::AssumeRole {
+                role_arn,
+                source,
+                endpoint,
+            } => sts::assume_role(endpoint, role_arn, &source.resolve()?),
+        }
+    }
+}
+
+/// Provides the keys of `Credentials` to rusoto, renewing temporary keys shortly
+/// before they expire. Clones share the keys.
+#[derive(Debug, Clone)]
+pub struct RefreshingProvider {
+    credentials: Credentials,
+    keys: Arc<Mutex<Option<Keys>>>,
+}
+
+impl RefreshingProvider {
+    /// Resolves the keys once already, so wrong credentials fail right away.
+    pub fn new(credentials: Credentials) -> Result<RefreshingProvider> {
+        let keys = credentials.resolve()?;
+        Ok(RefreshingProvider {
+            credentials,
+            keys: Arc::new(Mutex::new(Some(keys))),
+        })
+    }
+
+    /// The current keys, renewed when they are about to expire. Failing to renew
+    /// them keeps the current ones until they really expire, the next call tries
+    /// again.
+    pub fn keys(&self) -> Result<Keys> {
+        let mut keys = self.keys.lock().unwrap();
+        if let Some(current) = keys.as_ref() {
+            if !expires_within(current, RENEW_BEFORE_SECONDS) {
+                return Ok(current.clone());
+            }
+        }
+        match self.credentials.resolve() {
+            Ok(renewed) => {
+                log::debug!("renewed credentials. {:?}", renewed);
+                *keys = Some(renewed.clone());
+                Ok(renewed)
+            }
+            Err(e) => match keys.as_ref() {
+                Some(current) if !expires_within(current, 0) => {
+                    log::warn!(
+                        "failed to renew credentials, keeping the current ones. {}",
+                        e
+                    );
+                    Ok(current.clone())
+                }
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Renews the keys on the next call, e.g. when the backend rejected them as
+    /// expired before their expiration.
+    pub fn expire(&self) {
+        if let Some(current) = self.keys.lock().unwrap().as_mut() {
+            current.expiration = Some(chrono::Utc::now());
+        }
+    }
+
+    /// Whether the current keys expire soon or already did.
+    pub fn is_expiring(&self) -> bool {
+        match self.keys.lock().unwrap().as_ref() {
+            Some(current) => expires_within(current, RENEW_BEFORE_SECONDS),
+            None => true,
+        }
+    }
+}
+
+impl ProvideAwsCredentials for RefreshingProvider {
+    type Future = FutureResult<AwsCredentials, CredentialsError>;
+
+    fn credentials(&self) -> Self::Future {
+        futures01::future::result(
+            self.keys()
+                .map(|keys| {
+                    AwsCredentials::new(
+                        keys.access_key,
+                        keys.secret_key,
+                        keys.session_token,
+                        keys.expiration,
+                    )
+                })
+                .map_err(|e| CredentialsError::new(e.to_string())),
+        )
+    }
+}
+
+fn expires_within(keys: &Keys, seconds: i64) -> bool {
+    keys.expiration
+        .map(|expiration| expiration - chrono::Utc::now() <= chrono::Duration::seconds(seconds))
+        .unwrap_or(false)
+}
+
+fn read_file(path: &std::path::Path) -> Result<Keys> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = std::fs::metadata(path)?.permissions().mode();
+    if mode & 0o077 != 0 {
+        return Err(Error::PermissionDenied(format!(
+            "credentials file {:?} may be accessed by others (mode {:o}), restrict it to its owner",
+            path,
+            mode & 0o777
+        )));
+    }
+    let content = std::fs::read_to_string(path)?;
+    let mut section: Option<String> = None;
+    let (mut access_key, mut secret_key, mut session_token) = (None, None, None);
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = Some(line[1..line.len() - 1].trim().to_owned());
+            continue;
+        }
+        if section
+            .as_ref()
+            .map(|name| name != "default")
+            .unwrap_or(false)
+        {
+            continue;
+        }
+        let mut pair = line.splitn(2, '=');
+        let key = pair.next().unwrap_or("").trim();
+        let value = pair.next().unwrap_or("").trim().to_owned();
+        match key {
+            "access_key" | "aws_access_key_id" => access_key = Some(value),
+            "secret_key" | "aws_secret_access_key" => secret_key = Some(value),
+            "session_token" | "aws_session_token" => session_token = Some(value),
+            _ => {}
+        }
+    }
+    match (access_key, secret_key) {
+        (Some(access_key), Some(secret_key)) => Ok(Keys {
+            access_key,
+            secret_key,
+            session_token,
+            expiration: None,
+        }),
+        _ => Err(Error::Other(format!(
+            "credentials file {:?} misses the access key or the secret",
+            path
+        ))),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ProcessOutput {
+    #[serde(rename = "AccessKeyId")]
+    access_key_id: String,
+    #[serde(rename = "SecretAccessKey")]
+    secret_access_key: String,
+    #[serde(rename = "SessionToken", default)]
+    session_token: Option<String>,
+    #[serde(rename = "Expiration", default)]
+    expiration: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+fn run_process(command: &str) -> Result<Keys> {
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(std::process::Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::Other(format!(
+            "credential process {:?} failed with {}. {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    // never echo the output, it holds the secret
+    let parsed: ProcessOutput = serde_json::from_slice(&output.stdout).map_err(|e| {
+        Error::Other(format!(
+            "credential process {:?} printed invalid json. {}",
+            command, e
+        ))
+    })?;
+    Ok(Keys {
+        access_key: parsed.access_key_id,
+        secret_key: parsed.secret_access_key,
+        session_token: parsed.session_token,
+        expiration: parsed.expiration,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Credentials, Keys, RefreshingProvider};
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_credentials() {
+        let path = std::env::temp_dir().join(format!("ossfs-credentials-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "[other]\naws_access_key_id = no\n[default]\naws_access_key_id = AK\naws_secret_access_key = SK\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let credentials: Credentials = format!("file:{}", path.to_string_lossy()).parse().unwrap();
+        assert!(credentials.resolve().is_err());
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(credentials.resolve().unwrap(), Keys::new("AK", "SK"));
+        std::fs::remove_file(&path).unwrap();
+
+        let credentials: Credentials = "process:echo '{\"Version\": 1, \"AccessKeyId\": \"AK\", \"SecretAccessKey\": \"SK\", \"SessionToken\": \"T\"}'"
+            .parse()
+            .unwrap();
+        let keys = credentials.resolve().unwrap();
+        assert_eq!(keys.session_token, Some("T".to_owned()));
+        assert!(!format!("{:?}", keys).contains("SK"));
+        assert!("process:exit 1"
+            .parse::<Credentials>()
+            .unwrap()
+            .resolve()
+            .is_err());
+
+        std::env::set_var("OSSFS_TEST_ACCESS_KEY", "AK");
+        std::env::set_var("OSSFS_TEST_SECRET_KEY", "SK");
+        let credentials: Credentials = "env:OSSFS_TEST_ACCESS_KEY:OSSFS_TEST_SECRET_KEY"
+            .parse()
+            .unwrap();
+        assert_eq!(credentials.resolve().unwrap().secret_key, "SK");
+        assert!("vault:x".parse::<Credentials>().is_err());
+    }
+
+    #[test]
+    fn test_refreshing_provider() {
+        let path = std::env::temp_dir().join(format!("ossfs-keys-{}", std::process::id()));
+        let write = |access_key: &str, expires_in: i64| {
+            let expiration = chrono::Utc::now() + chrono::Duration::seconds(expires_in);
+            let keys = format!(
+                "{{\"AccessKeyId\": \"{}\", \"SecretAccessKey\": \"SK\", \"Expiration\": \"{}\"}}",
+                access_key,
+                expiration.to_rfc3339()
+            );
+            std::fs::write(&path, keys).unwrap();
+        };
+        write("AK1", 3600);
+        let provider = RefreshingProvider::new(Credentials::Process(format!(
+            "cat {}",
+            path.to_string_lossy()
+        )))
+        .unwrap();
+        write("AK2", 60);
+        assert_eq!(provider.keys().unwrap().access_key, "AK1");
+        provider.expire();
+        assert!(provider.is_expiring());
+        assert_eq!(provider.keys().unwrap().access_key, "AK2");
+        // failing to renew keeps keys which did not expire yet
+        std::fs::write(&path, "not json").unwrap();
+        assert_eq!(provider.keys().unwrap().access_key, "AK2");
+        // renewed before they expire
+        write("AK3", 3600);
+        assert_eq!(provider.keys().unwrap().access_key, "AK3");
+        assert!(!provider.is_expiring());
+        std::fs::write(&path, "not json").unwrap();
+        provider.expire();
+        assert!(provider.keys().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
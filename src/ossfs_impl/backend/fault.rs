@@ -0,0 +1,455 @@
+//! A decorator injecting latencies, errors and partial responses into another
+//! backend, drawn from a seeded generator so that a run can be reproduced.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::attr::FileType;
+use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::node::Node;
+use crate::ossfs_impl::stat::{Stat, Usage};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaultError {
+    NotFound,
+    PermissionDenied,
+    Throttled,
+    Timeout,
+    Conflict,
+    Unavailable,
+    Backend,
+}
+
+impl FaultError {
+    fn to_error(self, op: &str, path: &Path) -> Error {
+        let message = format!("injected fault. op: {}, path: {:?}", op, path);
+        match self {
+            FaultError::NotFound => Error::NotFound(message),
+            FaultError::PermissionDenied => Error::PermissionDenied(message),
+            FaultError::Throttled => Error::Throttled(message),
+            FaultError::Timeout => Error::Timeout(message),
+            FaultError::Conflict => Error::Conflict(message),
+            FaultError::Unavailable => Error::Unavailable(message),
+            FaultError::Backend => Error::Backend(message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FaultAction {
+    Latency(Duration),
+    Error(FaultError),
+    // returns the first half of the data or of the listing
+    Partial,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fault {
+    // `None` matches every operation
+    op: Option<String>,
+    pattern: String,
+    probability: f64,
+    action: FaultAction,
+}
+
+impl Fault {
+    pub fn new(action: FaultAction) -> Fault {
+        Fault {
+            op: None,
+            pattern: "*".to_owned(),
+            probability: 1.0,
+            action,
+        }
+    }
+
+    /// Only injects the fault into `op`, one of the `Backend` method names.
+    pub fn on<S: Into<String>>(mut self, op: S) -> Fault {
+        self.op = Some(op.into());
+        self
+    }
+
+    /// Only injects the fault for paths matching `pattern`, where `*` matches any
+    /// sequence of characters and `?` a single one.
+    pub fn matching<S: Into<String>>(mut self, pattern: S) -> Fault {
+        self.pattern = pattern.into();
+        self
+    }
+
+    pub fn with_probability(mut self, probability: f64) -> Fault {
+        self.probability = probability;
+        self
+    }
+
+    fn applies(&self, op: &str, path: &Path) -> bool {
+        if let Some(ref expected) = self.op {
+            if expected != op {
+                return false;
+            }
+        }
+        glob_match(self.pattern.as_bytes(), path.to_string_lossy().as_bytes())
+    }
+}
+
+/// Parses `<operation|*>:<path glob>:<probability>:<action>`, e.g.
+/// `read:*.bin:0.1:timeout` or `get_children:/photos/*:1:latency=200`.
+impl std::str::FromStr for Fault {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Fault> {
+        let invalid = |reason: &str| Error::Other(format!("invalid fault {:?}, {}", spec, reason));
+        let parts: Vec<&str> = spec.splitn(4, ':').collect();
+        if parts.len() != 4 {
+            return Err(invalid("expected <op>:<pattern>:<probability>:<action>"));
+        }
+        let probability: f64 = parts[2]
+            .parse()
+            .map_err(|_| invalid("probability is not a number"))?;
+        let action = match parts[3] {
+            "partial" => FaultAction::Partial,
+            "not_found" => FaultAction::Error(FaultError::NotFound),
+            "permission_denied" => FaultAction::Error(FaultError::PermissionDenied),
+            "throttled" => FaultAction::Error(FaultError::Throttled),
+            "timeout" => FaultAction::Error(FaultError::Timeout),
+            "conflict" => FaultAction::Error(FaultError::Conflict),
+            "unavailable" => FaultAction::Error(FaultError::Unavailable),
+            "backend" => FaultAction::Error(FaultError::Backend),
+            action if action.starts_with("latency=") => {
+                let millis: u64 = action["latency=".len()..]
+                    .parse()
+                    .map_err(|_| invalid("latency is not a number of milliseconds"))?;
+                FaultAction::Latency(Duration::from_millis(millis))
+            }
+            _ => return Err(invalid("unknown action")),
+        };
+        let mut fault = Fault::new(action)
+            .matching(parts[1])
+            .with_probability(probability);
+        if parts[0] != "*" {
+            fault = fault.on(parts[0]);
+        }
+        Ok(fault)
+    }
+}
+
+// iterative, on a mismatch only the last `*` is retried one byte further, so the
+// time stays linear in the length of `text` for each `*`
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` in `pattern` and of the text it was tried at
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, t));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[derive(Debug)]
+pub struct FaultInjectingBackend<B> {
+    inner: B,
+    faults: Vec<Fault>,
+    // xorshift state, never 0
+    state: Mutex<u64>,
+}
+
+impl<B: Backend> FaultInjectingBackend<B> {
+    pub fn new(inner: B, seed: u64) -> FaultInjectingBackend<B> {
+        FaultInjectingBackend {
+            inner,
+            faults: vec![],
+            state: Mutex::new(if seed == 0 {
+                0x9E37_79B9_7F4A_7C15
+            } else {
+                seed
+            }),
+        }
+    }
+
+    pub fn with_fault(mut self, fault: Fault) -> FaultInjectingBackend<B> {
+        self.faults.push(fault);
+        self
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    fn next_f64(&self) -> f64 {
+        let mut state = self.state.lock().unwrap();
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        (*state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Applies latencies and errors, returns whether the response should be cut short.
+    fn inject(&self, op: &str, path: &Path) -> Result<bool> {
+        let mut partial = false;
+        for fault in self.faults.iter() {
+            if !fault.applies(op, path) || self.next_f64() >= fault.probability {
+                continue;
+            }
+            log::warn!("injecting {:?} into {} {:?}", fault.action, op, path);
+            match fault.action {
+                FaultAction::Latency(latency) => std::thread::sleep(latency),
+                FaultAction::Error(error) => return Err(error.to_error(op, path)),
+                FaultAction::Partial => partial = true,
+            }
+        }
+        Ok(partial)
+    }
+}
+
+impl<B: Backend> Backend for FaultInjectingBackend<B> {
+    fn root(&self) -> Node {
+        self.inner.root()
+    }
+
+    fn check(&self) -> Result<()> {
+        self.inner.check()
+    }
+
+    fn probe(&self) -> Result<()> {
+        self.inject("probe", &self.inner.root().path())?;
+        self.inner.probe()
+    }
+
+    fn get_children<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<Node>> {
+        let partial = self.inject("get_children", path.as_ref())?;
+        let mut children = self.inner.get_children(path)?;
+        if partial {
+            let len = children.len() / 2;
+            children.truncate(len);
+        }
+        Ok(children)
+    }
+
+    fn get_node<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Node> {
+        self.inject("get_node", path.as_ref())?;
+        self.inner.get_node(path)
+    }
+
+    fn get_path<P: AsRef<Path> + Debug>(&self, path: P, depth: usize) -> Result<Option<Vec<Node>>> {
+        self.inject("get_path", path.as_ref())?;
+        self.inner.get_path(path, depth)
+    }
+
+    fn list_below<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Option<Vec<Node>>> {
+        self.inject("list_below", path.as_ref())?;
+        self.inner.list_below(path)
+    }
+
+    fn statfs<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Stat> {
+        self.inject("statfs", path.as_ref())?;
+        self.inner.statfs(path)
+    }
+
+    fn usage<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Usage> {
+        self.inject("usage", path.as_ref())?;
+        self.inner.usage(path)
+    }
+
+    fn mknod<P: AsRef<Path> + Debug>(&self, path: P, filetype: FileType, mode: u32) -> Result<()> {
+        self.inject("mknod", path.as_ref())?;
+        self.inner.mknod(path, filetype, mode)
+    }
+
+    fn read<P: AsRef<Path> + Debug>(&self, path: P, offset: u64, size: usize) -> Result<Vec<u8>> {
+        let partial = self.inject("read", path.as_ref())?;
+        let mut data = self.inner.read(path, offset, size)?;
+        if partial {
+            let len = data.len() / 2;
+            data.truncate(len);
+        }
+        Ok(data)
+    }
+
+    fn read_if_match<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        offset: u64,
+        size: usize,
+        etag: &str,
+    ) -> Result<Vec<u8>> {
+        let partial = self.inject("read", path.as_ref())?;
+        let mut data = self.inner.read_if_match(path, offset, size, etag)?;
+        if partial {
+            let len = data.len() / 2;
+            data.truncate(len);
+        }
+        Ok(data)
+    }
+
+    // reads which may fail are left to go through `read`
+    fn local_file<P: AsRef<Path> + Debug>(&self, path: P) -> Option<PathBuf> {
+        if self
+            .faults
+            .iter()
+            .any(|fault| fault.applies("read", path.as_ref()))
+        {
+            return None;
+        }
+        self.inner.local_file(path)
+    }
+
+    fn put<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        if self.inject("put", path.as_ref())? {
+            return self.inner.put(path, &data[..data.len() / 2]);
+        }
+        self.inner.put(path, data)
+    }
+
+    fn put_if_absent<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        self.inject("put_if_absent", path.as_ref())?;
+        self.inner.put_if_absent(path, data)
+    }
+
+    fn remove<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        self.inject("remove", path.as_ref())?;
+        self.inner.remove(path)
+    }
+
+    fn remove_many(&self, paths: &[PathBuf]) -> Vec<Result<()>> {
+        let injected: Vec<Option<Result<()>>> = paths
+            .iter()
+            .map(|path| self.inject("remove", path).err().map(Err))
+            .collect();
+        let passed: Vec<PathBuf> = paths
+            .iter()
+            .zip(&injected)
+            .filter(|(_, injected)| injected.is_none())
+            .map(|(path, _)| path.clone())
+            .collect();
+        let mut removed = self.inner.remove_many(&passed).into_iter();
+        injected
+            .into_iter()
+            .map(|injected| injected.unwrap_or_else(|| removed.next().unwrap()))
+            .collect()
+    }
+
+    fn remove_dir<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        self.inject("remove_dir", path.as_ref())?;
+        self.inner.remove_dir(path)
+    }
+
+    fn remove_all<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        progress: &mut dyn FnMut(u64),
+    ) -> Result<u64> {
+        self.inject("remove_all", path.as_ref())?;
+        self.inner.remove_all(path, progress)
+    }
+
+    fn copy<P: AsRef<Path> + Debug>(&self, from: P, to: P) -> Result<()> {
+        self.inject("copy", from.as_ref())?;
+        self.inner.copy(from, to)
+    }
+
+    fn put_with_class<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+    ) -> Result<()> {
+        if self.inject("put", path.as_ref())? {
+            return self
+                .inner
+                .put_with_class(path, &data[..data.len() / 2], class);
+        }
+        self.inner.put_with_class(path, data, class)
+    }
+
+    fn put_idempotent<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        if self.inject("put", path.as_ref())? {
+            return self
+                .inner
+                .put_idempotent(path, &data[..data.len() / 2], class, token, retry);
+        }
+        self.inner.put_idempotent(path, data, class, token, retry)
+    }
+
+    fn set_storage_class<P: AsRef<Path> + Debug>(&self, path: P, class: &str) -> Result<()> {
+        self.inject("set_storage_class", path.as_ref())?;
+        self.inner.set_storage_class(path, class)
+    }
+
+    fn get_tags<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<(String, String)>> {
+        self.inject("get_tags", path.as_ref())?;
+        self.inner.get_tags(path)
+    }
+
+    fn set_tags<P: AsRef<Path> + Debug>(&self, path: P, tags: &[(String, String)]) -> Result<()> {
+        self.inject("set_tags", path.as_ref())?;
+        self.inner.set_tags(path, tags)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+
+    fn backend(seed: u64) -> FaultInjectingBackend<MockBackend> {
+        let mock = MockBackend::new();
+        mock.add_file("/data/a.bin", &[1u8; 100]);
+        mock.add_file("/data/b.txt", &[2u8; 100]);
+        FaultInjectingBackend::new(mock, seed)
+            .with_fault("read:*.bin:0.5:timeout".parse().unwrap())
+            .with_fault("get_children:/data:1:partial".parse().unwrap())
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*.bin", b"/data/a.bin"));
+        assert!(glob_match(b"/data/?.*", b"/data/b.txt"));
+        assert!(glob_match(b"*", b""));
+        assert!(!glob_match(b"?", b""));
+        assert!(!glob_match(b"*.bin", b"/data/b.txt"));
+        // exponential with backtracking recursion
+        let pattern = "a*".repeat(30) + "b";
+        assert!(!glob_match(pattern.as_bytes(), &[b'a'; 100]));
+    }
+
+    #[test]
+    fn test_faults() {
+        let outcomes = |backend: &FaultInjectingBackend<MockBackend>| {
+            (0..100)
+                .map(|_| backend.read("/data/a.bin", 0, 100).is_ok())
+                .collect::<Vec<bool>>()
+        };
+        let first = outcomes(&backend(42));
+        // the same seed injects the same faults
+        assert_eq!(first, outcomes(&backend(42)));
+        assert!(first.iter().any(|ok| *ok) && first.iter().any(|ok| !*ok));
+
+        let backend = backend(42);
+        for _ in 0..100 {
+            assert_eq!(backend.read("/data/b.txt", 0, 100).unwrap().len(), 100);
+        }
+        assert_eq!(backend.get_children("/data").unwrap().len(), 1);
+        assert_eq!(backend.inner().calls("get_children"), 1);
+        assert!("read:*:x:timeout".parse::<Fault>().is_err());
+        assert!("read:*:1:explode".parse::<Fault>().is_err());
+    }
+}
@@ -0,0 +1,195 @@
+//! Conversions between local paths and object keys, escaping the bytes of names
+//! which are not valid UTF-8.
+
+use crate::error::{Error, Result};
+use std::ffi::OsString;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+// the bytes from 0x80 on stand for the private use characters from U+F780 on
+const ESCAPE_BASE: u32 = 0xF700;
+
+/// The key of `path`. Bytes which are not valid UTF-8 become the escape character
+/// standing for them, and so do the UTF-8 bytes of escape characters in names,
+/// so distinct paths never share a key.
+pub fn path_to_key<P: AsRef<Path>>(path: P) -> String {
+    let mut bytes = path.as_ref().as_os_str().as_bytes();
+    let mut key = String::with_capacity(bytes.len());
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(valid) => {
+                push_valid(&mut key, valid);
+                return key;
+            }
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                push_valid(&mut key, std::str::from_utf8(valid).unwrap());
+                let invalid = e.error_len().unwrap_or(rest.len());
+                push_escaped(&mut key, &rest[..invalid]);
+                bytes = &rest[invalid..];
+            }
+        }
+    }
+}
+
+/// The path of `key`, every escape character being the byte it stands for.
+pub fn key_to_path<K: AsRef<str>>(key: K) -> PathBuf {
+    let mut bytes = Vec::with_capacity(key.as_ref().len());
+    let mut buffer = [0u8; 4];
+    for c in key.as_ref().chars() {
+        if is_escape(c) {
+            bytes.push((c as u32 - ESCAPE_BASE) as u8);
+        } else {
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+        }
+    }
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+fn is_escape(c: char) -> bool {
+    let code = c as u32;
+    code >= ESCAPE_BASE + 0x80 && code <= ESCAPE_BASE + 0xFF
+}
+
+// escape characters of the name are escaped byte by byte, they are read back as
+// the character
+fn push_valid(key: &mut String, valid: &str) {
+    let mut buffer = [0u8; 4];
+    for c in valid.chars() {
+        if is_escape(c) {
+            push_escaped(key, c.encode_utf8(&mut buffer).as_bytes());
+        } else {
+            key.push(c);
+        }
+    }
+}
+
+fn push_escaped(key: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        key.push(std::char::from_u32(ESCAPE_BASE + *byte as u32).unwrap());
+    }
+}
+
+/// Drops `suffix` from the components of `key` ending with it, those of the
+/// directories exposed under another name than their prefix. Returns whether the
+/// last component had it.
+pub fn strip_directory_suffix(key: &str, suffix: &str) -> (String, bool) {
+    let mut last = false;
+    let components: Vec<&str> = key
+        .split('/')
+        .map(|component| {
+            last = component.len() > suffix.len() && component.ends_with(suffix);
+            if last {
+                &component[..component.len() - suffix.len()]
+            } else {
+                component
+            }
+        })
+        .collect();
+    (components.join("/"), last)
+}
+
+/// Percent-encodes a key for use as the path of an url, `/` is kept as separator.
+pub fn escape_url_path(key: &str) -> String {
+    let mut escaped = String::with_capacity(key.len());
+    for byte in key.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                escaped.push(byte as char)
+            }
+            _ => escaped.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    escaped
+}
+
+/// Reverses `escape_url_path`.
+pub fn unescape_url_path(escaped: &str) -> Result<String> {
+    let bytes = escaped.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let byte = escaped
+                .get(index + 1..index + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| Error::Backend(format!("invalid escape in {}", escaped)))?;
+            unescaped.push(byte);
+            index += 3;
+        } else {
+            unescaped.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(unescaped)
+        .map_err(|e| Error::Backend(format!("invalid utf-8 in {}. {}", escaped, e)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use quickcheck::QuickCheck;
+
+    const SPECIALS: &str = " %#?+&=;~'\"\\:@!$é中文😀\u{200D}";
+
+    // a name built from `seed`, mixing specials, plain letters, invalid utf-8,
+    // escape characters and separators
+    fn name(seed: &[u8]) -> Vec<u8> {
+        let specials: Vec<char> = SPECIALS.chars().collect();
+        let mut name = vec![];
+        let mut buffer = [0u8; 4];
+        for byte in seed.iter() {
+            match byte % 5 {
+                0 => {
+                    let c = specials[*byte as usize % specials.len()];
+                    name.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+                1 => name.push(b'a' + byte % 26),
+                // a lone continuation byte is never valid utf-8
+                2 => name.push(0x80 | (byte & 0x3f)),
+                3 => {
+                    let c = std::char::from_u32(ESCAPE_BASE + 0x80 + *byte as u32 % 0x80);
+                    name.extend_from_slice(c.unwrap().encode_utf8(&mut buffer).as_bytes());
+                }
+                _ => name.push(b'/'),
+            }
+        }
+        name
+    }
+
+    fn round_trip(seed: Vec<u8>) -> bool {
+        let path = PathBuf::from(OsString::from_vec(name(&seed)));
+        let key = path_to_key(&path);
+        key_to_path(&key) == path
+            && unescape_url_path(&escape_url_path(&key)).unwrap() == key
+            && escape_url_path(&key)
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-._~/%".contains(&b))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        QuickCheck::new()
+            .tests(10000)
+            .quickcheck(round_trip as fn(Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn test_escape_characters() {
+        // a name holding an escape character and one holding the byte it stands for
+        let escape = PathBuf::from("a\u{F7E9}");
+        let byte = PathBuf::from(OsString::from_vec(vec![b'a', 0xE9]));
+        assert_ne!(path_to_key(&escape), path_to_key(&byte));
+        assert_eq!(key_to_path(path_to_key(&escape)), escape);
+        assert_eq!(key_to_path(path_to_key(&byte)), byte);
+    }
+
+    #[test]
+    fn test_directory_suffix() {
+        let strip = |key| strip_directory_suffix(key, ".dir");
+        assert_eq!(strip("a/foo.dir"), ("a/foo".to_owned(), true));
+        assert_eq!(strip("a/foo.dir/b"), ("a/foo/b".to_owned(), false));
+        assert_eq!(strip("a/foo.dir/"), ("a/foo/".to_owned(), false));
+        assert_eq!(strip("a/.dir/foo"), ("a/.dir/foo".to_owned(), false));
+    }
+}
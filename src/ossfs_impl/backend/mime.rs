@@ -0,0 +1,103 @@
+//! Content types of the objects put by the backends, inferred from the extension.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+const BUILTIN: &[(&str, &str)] = &[
+    ("avi", "video/x-msvideo"),
+    ("bmp", "image/bmp"),
+    ("bz2", "application/x-bzip2"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "application/javascript"),
+    ("json", "application/json"),
+    ("md", "text/markdown"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("parquet", "application/vnd.apache.parquet"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("tar", "application/x-tar"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("tsv", "text/tab-separated-values"),
+    ("txt", "text/plain"),
+    ("wasm", "application/wasm"),
+    ("wav", "audio/wav"),
+    ("webm", "video/webm"),
+    ("webp", "image/webp"),
+    ("xml", "application/xml"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("zip", "application/zip"),
+];
+
+/// Maps file extensions, compared case-insensitively, to content types.
+#[derive(Debug, Clone)]
+pub struct ContentTypes {
+    types: HashMap<String, String>,
+}
+
+impl Default for ContentTypes {
+    /// The common types of the web and of datasets.
+    fn default() -> ContentTypes {
+        let mut types = ContentTypes::empty();
+        for (extension, content_type) in BUILTIN {
+            types = types.with(*extension, *content_type);
+        }
+        types
+    }
+}
+
+impl ContentTypes {
+    /// A mapping without any type, objects are put without one.
+    pub fn empty() -> ContentTypes {
+        ContentTypes {
+            types: HashMap::new(),
+        }
+    }
+
+    /// Puts the files ending with `.extension` with `content_type`, replacing the
+    /// type mapped before.
+    pub fn with<E, T>(mut self, extension: E, content_type: T) -> ContentTypes
+    where
+        E: AsRef<str>,
+        T: Into<String>,
+    {
+        let extension = extension.as_ref().trim_start_matches('.').to_lowercase();
+        self.types.insert(extension, content_type.into());
+        self
+    }
+
+    /// The content type of the file at `path`, `None` if its extension is unknown.
+    pub fn of(&self, path: &Path) -> Option<&str> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        self.types
+            .get(&extension)
+            .map(|content_type| content_type.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ContentTypes;
+    use std::path::Path;
+
+    #[test]
+    fn test_content_types() {
+        let types = ContentTypes::default().with(".npy", "application/x-npy");
+        assert_eq!(types.of(Path::new("/a/b.JPG")), Some("image/jpeg"));
+        assert_eq!(types.of(Path::new("/a/b.npy")), Some("application/x-npy"));
+        assert_eq!(types.of(Path::new("/a/b.unknown")), None);
+        assert_eq!(types.of(Path::new("/a/Makefile")), None);
+        assert_eq!(ContentTypes::empty().of(Path::new("b.json")), None);
+    }
+}
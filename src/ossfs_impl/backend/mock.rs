@@ -0,0 +1,545 @@
+//! An in-memory backend whose responses can be scripted, for testing the fuse
+//! layer without a real object store.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::attr;
+use crate::ossfs_impl::attr::{FileAttr, FileType};
+use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::filesystem::ROOT_INODE;
+use crate::ossfs_impl::node::Node;
+use crate::ossfs_impl::stat::Stat;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone)]
+struct Entry {
+    kind: FileType,
+    perm: u16,
+    data: Vec<u8>,
+    mtime: SystemTime,
+}
+
+#[derive(Debug, Default)]
+struct Script {
+    // errors returned by the next calls of an operation, consumed in order
+    errors: HashMap<&'static str, VecDeque<Error>>,
+    // errors returned by the next calls of an operation after it took effect, as if
+    // its response was lost
+    lost: HashMap<&'static str, VecDeque<Error>>,
+    latencies: HashMap<&'static str, Duration>,
+    calls: HashMap<&'static str, usize>,
+    // listings are handed over in pages of this many children, all at once if zero
+    page_size: usize,
+}
+
+/// Keeps the whole tree in memory, rooted at `/`.
+///
+/// Operations are named after the `Backend` methods: `get_children`, `get_node`,
+/// `statfs`, `mknod`, `read` and `put`. Paged listings also enter `list_page`
+/// before every page but the first.
+#[derive(Debug)]
+pub struct MockBackend {
+    entries: Mutex<BTreeMap<PathBuf, Entry>>,
+    // storage classes of the files put with one
+    classes: Mutex<HashMap<PathBuf, String>>,
+    tags: Mutex<HashMap<PathBuf, Vec<(String, String)>>>,
+    // tokens of the files put by `put_idempotent`
+    tokens: Mutex<HashMap<PathBuf, String>>,
+    script: Mutex<Script>,
+    uid: u32,
+    gid: u32,
+}
+
+impl MockBackend {
+    pub fn new() -> MockBackend {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            PathBuf::from("/"),
+            Entry {
+                kind: FileType::Directory,
+                perm: 0o755,
+                data: vec![],
+                mtime: SystemTime::now(),
+            },
+        );
+        MockBackend {
+            entries: Mutex::new(entries),
+            classes: Mutex::new(HashMap::new()),
+            tags: Mutex::new(HashMap::new()),
+            tokens: Mutex::new(HashMap::new()),
+            script: Mutex::new(Script::default()),
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        }
+    }
+
+    /// Adds a directory and all of its missing ancestors.
+    pub fn add_dir<P: AsRef<Path>>(&self, path: P) {
+        let mut entries = self.entries.lock().unwrap();
+        for ancestor in path.as_ref().ancestors() {
+            entries.entry(ancestor.to_path_buf()).or_insert(Entry {
+                kind: FileType::Directory,
+                perm: 0o755,
+                data: vec![],
+                mtime: SystemTime::now(),
+            });
+        }
+    }
+
+    /// Adds a regular file, replacing any existing content.
+    pub fn add_file<P: AsRef<Path>>(&self, path: P, data: &[u8]) {
+        if let Some(parent) = path.as_ref().parent() {
+            self.add_dir(parent);
+        }
+        self.entries.lock().unwrap().insert(
+            path.as_ref().to_path_buf(),
+            Entry {
+                kind: FileType::RegularFile,
+                perm: 0o644,
+                data: data.to_vec(),
+                mtime: SystemTime::now(),
+            },
+        );
+    }
+
+    pub fn remove<P: AsRef<Path>>(&self, path: P) {
+        let mut entries = self.entries.lock().unwrap();
+        let path = path.as_ref();
+        let removed: Vec<PathBuf> = entries
+            .keys()
+            .filter(|key| key.starts_with(path))
+            .cloned()
+            .collect();
+        for key in removed {
+            entries.remove(&key);
+        }
+    }
+
+    /// Content of a file, `None` if it does not exist or is a directory.
+    pub fn content<P: AsRef<Path>>(&self, path: P) -> Option<Vec<u8>> {
+        match self.entries.lock().unwrap().get(path.as_ref()) {
+            Some(entry) if entry.kind == FileType::RegularFile => Some(entry.data.clone()),
+            _ => None,
+        }
+    }
+
+    /// Storage class of a file, `None` for the default class.
+    pub fn storage_class<P: AsRef<Path>>(&self, path: P) -> Option<String> {
+        self.classes.lock().unwrap().get(path.as_ref()).cloned()
+    }
+
+    /// Tags of a file, as set by `Backend::set_tags`.
+    pub fn tags<P: AsRef<Path>>(&self, path: P) -> Vec<(String, String)> {
+        let tags = self.tags.lock().unwrap();
+        tags.get(path.as_ref()).cloned().unwrap_or_default()
+    }
+
+    /// Makes the next call of `op` fail with `error`, errors queue up in order.
+    pub fn fail_next(&self, op: &'static str, error: Error) {
+        let mut script = self.script.lock().unwrap();
+        script
+            .errors
+            .entry(op)
+            .or_insert_with(VecDeque::new)
+            .push_back(error);
+    }
+
+    /// Makes the next call of `op` take effect and then fail with `error`.
+    pub fn lose_next(&self, op: &'static str, error: Error) {
+        let mut script = self.script.lock().unwrap();
+        script
+            .lost
+            .entry(op)
+            .or_insert_with(VecDeque::new)
+            .push_back(error);
+    }
+
+    /// Delays every call of `op` by `latency`.
+    pub fn set_latency(&self, op: &'static str, latency: Duration) {
+        self.script.lock().unwrap().latencies.insert(op, latency);
+    }
+
+    /// Hands listings over in pages of `page_size` children.
+    pub fn set_page_size(&self, page_size: usize) {
+        self.script.lock().unwrap().page_size = page_size;
+    }
+
+    /// Number of times `op` has been called.
+    pub fn calls(&self, op: &'static str) -> usize {
+        let script = self.script.lock().unwrap();
+        script.calls.get(op).cloned().unwrap_or(0)
+    }
+
+    fn enter(&self, op: &'static str) -> Result<()> {
+        let (latency, error) = {
+            let mut script = self.script.lock().unwrap();
+            *script.calls.entry(op).or_insert(0) += 1;
+            let latency = script.latencies.get(op).cloned();
+            let error = script
+                .errors
+                .get_mut(op)
+                .and_then(|errors| errors.pop_front());
+            (latency, error)
+        };
+        if let Some(latency) = latency {
+            std::thread::sleep(latency);
+        }
+        match error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    fn leave(&self, op: &'static str) -> Result<()> {
+        let mut script = self.script.lock().unwrap();
+        match script
+            .lost
+            .get_mut(op)
+            .and_then(|errors| errors.pop_front())
+        {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    fn read_entry(&self, path: &Path, offset: u64, size: usize) -> Result<Vec<u8>> {
+        let entries = self.entries.lock().unwrap();
+        let entry = match entries.get(path) {
+            Some(entry) if entry.kind == FileType::RegularFile => entry,
+            Some(_) => return Err(Error::Fuse(libc::EISDIR)),
+            None => return Err(Error::NotFound(format!("{:?}", path))),
+        };
+        let len = entry.data.len() as u64;
+        if offset >= len {
+            return Ok(vec![]);
+        }
+        let end = std::cmp::min(len, offset + size as u64);
+        Ok(entry.data[offset as usize..end as usize].to_vec())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(path) {
+            Some(entry) if entry.kind == FileType::RegularFile => {}
+            Some(_) => return Err(Error::Fuse(libc::EISDIR)),
+            None => return Err(Error::NotFound(format!("{:?}", path))),
+        }
+        entries.remove(path);
+        Ok(())
+    }
+
+    fn node(&self, path: &Path, entry: &Entry) -> Node {
+        let inode = if path == Path::new("/") {
+            ROOT_INODE
+        } else {
+            0
+        };
+        Node::new(
+            inode,
+            inode,
+            path.to_path_buf(),
+            FileAttr {
+                ino: inode,
+                perm: entry.perm,
+                uid: self.uid,
+                gid: self.gid,
+                ..attr::synthetic(entry.kind, entry.data.len() as u64, entry.mtime)
+            },
+        )
+        .with_etag(if entry.kind == FileType::RegularFile {
+            Some(format!("{:x}", md5::compute(&entry.data)))
+        } else {
+            None
+        })
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> MockBackend {
+        MockBackend::new()
+    }
+}
+
+impl Backend for MockBackend {
+    fn root(&self) -> Node {
+        let entries = self.entries.lock().unwrap();
+        let root = Path::new("/");
+        self.node(root, &entries[root])
+    }
+
+    fn get_children<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<Node>> {
+        self.enter("get_children")?;
+        let entries = self.entries.lock().unwrap();
+        match entries.get(path.as_ref()) {
+            Some(entry) if entry.kind == FileType::Directory => {}
+            Some(_) => return Err(Error::Fuse(libc::ENOTDIR)),
+            None => return Err(Error::NotFound(format!("{:?}", path))),
+        }
+        Ok(entries
+            .iter()
+            .filter(|(key, _)| key.parent() == Some(path.as_ref()))
+            .map(|(key, entry)| self.node(key, entry))
+            .collect())
+    }
+
+    fn for_each_children_page<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        page: &mut dyn FnMut(Vec<Node>),
+    ) -> Result<()> {
+        let mut children = self.get_children(path)?;
+        let page_size = self.script.lock().unwrap().page_size;
+        if page_size == 0 {
+            page(children);
+            return Ok(());
+        }
+        let mut first = true;
+        while first || !children.is_empty() {
+            if !first {
+                self.enter("list_page")?;
+            }
+            first = false;
+            let rest = children.split_off(std::cmp::min(page_size, children.len()));
+            page(std::mem::replace(&mut children, rest));
+        }
+        Ok(())
+    }
+
+    fn get_node<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Node> {
+        self.enter("get_node")?;
+        let entries = self.entries.lock().unwrap();
+        match entries.get(path.as_ref()) {
+            Some(entry) => Ok(self.node(path.as_ref(), entry)),
+            None => Err(Error::NotFound(format!("{:?}", path))),
+        }
+    }
+
+    fn get_path<P: AsRef<Path> + Debug>(&self, path: P, depth: usize) -> Result<Option<Vec<Node>>> {
+        self.enter("get_path")?;
+        let entries = self.entries.lock().unwrap();
+        let mut nodes = vec![];
+        for path in path.as_ref().ancestors().take(depth) {
+            match entries.get(path) {
+                Some(entry) => nodes.push(self.node(path, entry)),
+                None => return Err(Error::NotFound(format!("{:?}", path))),
+            }
+        }
+        nodes.reverse();
+        Ok(Some(nodes))
+    }
+
+    fn list_below<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Option<Vec<Node>>> {
+        self.enter("list_below")?;
+        let entries = self.entries.lock().unwrap();
+        Ok(Some(
+            entries
+                .iter()
+                .filter(|(key, _)| *key != path.as_ref() && key.starts_with(path.as_ref()))
+                .map(|(key, entry)| self.node(key, entry))
+                .collect(),
+        ))
+    }
+
+    fn statfs<P: AsRef<Path> + Debug>(&self, _path: P) -> Result<Stat> {
+        self.enter("statfs")?;
+        let entries = self.entries.lock().unwrap();
+        let used: u64 = entries.values().map(|entry| entry.data.len() as u64).sum();
+        Ok(Stat {
+            blocks: std::cmp::max(used / 4096, 1),
+            blocks_free: 1 << 20,
+            blocks_available: 1 << 20,
+            files: entries.len() as u64,
+            files_free: 1 << 20,
+            block_size: 4096,
+            namelen: 255,
+            frsize: 4096,
+            known: true,
+        })
+    }
+
+    fn mknod<P: AsRef<Path> + Debug>(&self, path: P, filetype: FileType, mode: u32) -> Result<()> {
+        self.enter("mknod")?;
+        let mut entries = self.entries.lock().unwrap();
+        if entries.contains_key(path.as_ref()) {
+            return Err(Error::Conflict(format!("{:?}", path)));
+        }
+        match path
+            .as_ref()
+            .parent()
+            .and_then(|parent| entries.get(parent))
+        {
+            Some(parent) if parent.kind == FileType::Directory => {}
+            _ => return Err(Error::NotFound(format!("parent of {:?}", path))),
+        }
+        entries.insert(
+            path.as_ref().to_path_buf(),
+            Entry {
+                kind: filetype,
+                perm: (mode & 0o7777) as u16,
+                data: vec![],
+                mtime: SystemTime::now(),
+            },
+        );
+        Ok(())
+    }
+
+    fn read<P: AsRef<Path> + Debug>(&self, path: P, offset: u64, size: usize) -> Result<Vec<u8>> {
+        self.enter("read")?;
+        self.read_entry(path.as_ref(), offset, size)
+    }
+
+    fn read_if_match<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        offset: u64,
+        size: usize,
+        etag: &str,
+    ) -> Result<Vec<u8>> {
+        let matches = match self.entries.lock().unwrap().get(path.as_ref()) {
+            Some(entry) => format!("{:x}", md5::compute(&entry.data)) == etag,
+            None => false,
+        };
+        if !matches {
+            return Err(Error::Conflict(format!("{:?}", path)));
+        }
+        self.read(path, offset, size)
+    }
+
+    fn put<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        self.enter("put")?;
+        if let Some(entry) = self.entries.lock().unwrap().get(path.as_ref()) {
+            if entry.kind == FileType::Directory {
+                return Err(Error::Fuse(libc::EISDIR));
+            }
+        }
+        self.tokens.lock().unwrap().remove(path.as_ref());
+        self.add_file(path, data);
+        Ok(())
+    }
+
+    fn put_if_absent<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        self.enter("put_if_absent")?;
+        if let Some(parent) = path.as_ref().parent() {
+            self.add_dir(parent);
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.contains_key(path.as_ref()) {
+            return Err(Error::Conflict(format!("{:?}", path)));
+        }
+        entries.insert(
+            path.as_ref().to_path_buf(),
+            Entry {
+                kind: FileType::RegularFile,
+                perm: 0o644,
+                data: data.to_vec(),
+                mtime: SystemTime::now(),
+            },
+        );
+        Ok(())
+    }
+
+    fn remove<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        self.enter("remove")?;
+        self.remove_file(path.as_ref())
+    }
+
+    // a single call, however many paths
+    fn remove_many(&self, paths: &[PathBuf]) -> Vec<Result<()>> {
+        if let Err(e) = self.enter("remove_many") {
+            let errno = e.errno(libc::EIO);
+            return paths.iter().map(|_| Err(Error::Fuse(errno))).collect();
+        }
+        paths.iter().map(|path| self.remove_file(path)).collect()
+    }
+
+    fn remove_dir<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        self.enter("remove_dir")?;
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(path.as_ref()) {
+            Some(entry) if entry.kind == FileType::Directory => {}
+            Some(_) => return Err(Error::Fuse(libc::ENOTDIR)),
+            None => return Err(Error::NotFound(format!("{:?}", path))),
+        }
+        if entries
+            .keys()
+            .any(|key| key.parent() == Some(path.as_ref()))
+        {
+            return Err(Error::Fuse(libc::ENOTEMPTY));
+        }
+        entries.remove(path.as_ref());
+        Ok(())
+    }
+
+    fn put_with_class<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+    ) -> Result<()> {
+        self.put(&path, data)?;
+        let mut classes = self.classes.lock().unwrap();
+        match class {
+            Some(class) => classes.insert(path.as_ref().to_path_buf(), class.to_owned()),
+            None => classes.remove(path.as_ref()),
+        };
+        Ok(())
+    }
+
+    fn put_idempotent<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        if retry {
+            let tokens = self.tokens.lock().unwrap();
+            if tokens.get(path.as_ref()).map(|put| put.as_str()) == Some(token) {
+                return Ok(());
+            }
+        }
+        self.put_with_class(&path, data, class)?;
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(path.as_ref().to_path_buf(), token.to_owned());
+        self.leave("put")
+    }
+
+    fn set_storage_class<P: AsRef<Path> + Debug>(&self, path: P, class: &str) -> Result<()> {
+        self.enter("set_storage_class")?;
+        if self.content(&path).is_none() {
+            return Err(Error::NotFound(format!("{:?}", path)));
+        }
+        self.classes
+            .lock()
+            .unwrap()
+            .insert(path.as_ref().to_path_buf(), class.to_owned());
+        Ok(())
+    }
+
+    fn get_tags<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<(String, String)>> {
+        self.enter("get_tags")?;
+        if self.content(&path).is_none() {
+            return Err(Error::NotFound(format!("{:?}", path)));
+        }
+        let tags = self.tags.lock().unwrap();
+        Ok(tags.get(path.as_ref()).cloned().unwrap_or_default())
+    }
+
+    fn set_tags<P: AsRef<Path> + Debug>(&self, path: P, tags: &[(String, String)]) -> Result<()> {
+        self.enter("set_tags")?;
+        if self.content(&path).is_none() {
+            return Err(Error::NotFound(format!("{:?}", path)));
+        }
+        self.tags
+            .lock()
+            .unwrap()
+            .insert(path.as_ref().to_path_buf(), tags.to_vec());
+        Ok(())
+    }
+}
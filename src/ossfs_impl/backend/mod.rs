@@ -1,28 +1,813 @@
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::ossfs_impl::attr::{FileAttr, FileType};
+use crate::ossfs_impl::blocks;
 use crate::ossfs_impl::node::Node;
-use crate::ossfs_impl::stat::Stat;
-use fuse::FileType;
+use crate::ossfs_impl::stat::{Stat, Usage};
 use std::fmt::Debug;
 use std::future::Future;
-use std::path::Path;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
+pub mod batching;
+pub mod conformance;
+pub mod credentials;
+pub mod fault;
+pub mod key;
+pub mod mime;
+pub mod mock;
+pub mod read_only;
+pub mod registry;
 pub mod s3;
 pub mod seaweedfs;
+pub mod sharded;
 pub mod simple;
+pub mod sts;
+pub mod tiered;
+pub mod tls;
+
+// bytes read at once by the default `Backend::copy`
+const COPY_CHUNK: usize = 4 * 1024 * 1024;
 
 pub trait Backend {
     fn root(&self) -> Node;
+    /// Verifies the backend root is reachable and the credentials are accepted,
+    /// called once before mounting.
+    fn check(&self) -> Result<()> {
+        Ok(())
+    }
+    /// A request as light as possible telling whether the backend answers, sent
+    /// periodically by the health probes. Defaults to `check`.
+    fn probe(&self) -> Result<()> {
+        self.check()
+    }
     fn get_children<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<Node>>;
+    /// Lists the children of `path` like `get_children`, handing them to `page` as
+    /// soon as each page of the listing arrives. Backends listing in a single call
+    /// hand everything over at once.
+    fn for_each_children_page<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        page: &mut dyn FnMut(Vec<Node>),
+    ) -> Result<()> {
+        page(self.get_children(path)?);
+        Ok(())
+    }
     // fn get_node<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Node>;
     fn get_node<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Node>;
+    /// Nodes of the last `depth` components of `path`, the outermost first, fetched
+    /// in a single request, e.g. at the first access to a deep path. `None` if the
+    /// backend can not resolve whole paths, they are looked up a component at a
+    /// time then.
+    fn get_path<P: AsRef<Path> + Debug>(
+        &self,
+        _path: P,
+        _depth: usize,
+    ) -> Result<Option<Vec<Node>>> {
+        Ok(None)
+    }
+    /// Every entry below the directory at `path` at any depth, the directories
+    /// implied by them included, fetched with listings of the whole prefix instead
+    /// of a directory at a time. `None` if the backend lists a directory at a time.
+    fn list_below<P: AsRef<Path> + Debug>(&self, _path: P) -> Result<Option<Vec<Node>>> {
+        Ok(None)
+    }
     fn statfs<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Stat>;
+    /// Recursive usage of the directory at `path`. Unless the backend sums it up by
+    /// itself the tree below it is listed a directory at a time.
+    fn usage<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Usage> {
+        let mut usage = Usage::default();
+        for child in self.get_children(path)? {
+            let attr = child.attr();
+            if attr.kind != FileType::Directory {
+                usage.files += 1;
+                usage.bytes += attr.size;
+                continue;
+            }
+            let below = self.usage(child.path())?;
+            usage.bytes += below.bytes;
+            usage.files += below.files;
+            usage.dirs += below.dirs + 1;
+        }
+        Ok(usage)
+    }
     fn mknod<P: AsRef<Path> + Debug>(&self, path: P, filetype: FileType, mode: u32) -> Result<()>;
     fn read<P: AsRef<Path> + Debug>(&self, path: P, offset: u64, size: usize) -> Result<Vec<u8>>;
+    /// Reads each of `ranges` of the file at `path` like `read`, the results in the
+    /// order of the ranges, e.g. the footer and the column chunks of a parquet file.
+    /// Backends able to fetch several ranges at once do, the others read them in
+    /// turn.
+    fn read_vectored<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        ranges: &[Range<u64>],
+    ) -> Vec<Result<Vec<u8>>> {
+        ranges
+            .iter()
+            .map(|range| {
+                let size = range.end.saturating_sub(range.start) as usize;
+                self.read(&path, range.start, size)
+            })
+            .collect()
+    }
+    /// Reads like `read` while the file at `path` has the entity tag `etag`, fails
+    /// with `Error::Conflict` once it changed.
+    fn read_if_match<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        offset: u64,
+        size: usize,
+        etag: &str,
+    ) -> Result<Vec<u8>> {
+        log::error!(
+            "conditional reads are not supported. path: {:?}, offset: {}, size: {}, etag: {}",
+            path,
+            offset,
+            size,
+            etag
+        );
+        Err(Error::Fuse(libc::ENOSYS))
+    }
+    /// The file of the local filesystem holding the content at `path`, for backends
+    /// keeping their files in a local directory. Mounts with passthrough read opened
+    /// files from it directly, around the backend and the caches. `None` reads
+    /// through the backend.
+    fn local_file<P: AsRef<Path> + Debug>(&self, _path: P) -> Option<PathBuf> {
+        None
+    }
+    /// Replaces the whole content of the file at `path`, creating it and its
+    /// missing parents.
+    fn put<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        log::error!(
+            "put is not supported. path: {:?}, size: {}",
+            path,
+            data.len()
+        );
+        Err(Error::Fuse(libc::ENOSYS))
+    }
+    /// Creates the file at `path` holding `data` unless there is one already, which
+    /// fails with `Error::Conflict`. Checking and writing are a single step, so of
+    /// several callers, on other hosts too, exactly one succeeds.
+    fn put_if_absent<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        log::error!(
+            "put_if_absent is not supported. path: {:?}, size: {}",
+            path,
+            data.len()
+        );
+        Err(Error::Fuse(libc::ENOSYS))
+    }
+    /// Removes the file at `path`.
+    fn remove<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        log::error!("remove is not supported. path: {:?}", path);
+        Err(Error::Fuse(libc::ENOSYS))
+    }
+    /// Removes each of the files at `paths` like `remove`, the results in the order
+    /// of the paths. Backends able to delete several objects in a request do, the
+    /// others remove them in turn.
+    fn remove_many(&self, paths: &[PathBuf]) -> Vec<Result<()>> {
+        paths.iter().map(|path| self.remove(path)).collect()
+    }
+    /// Removes the empty directory at `path`.
+    fn remove_dir<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        log::error!("remove_dir is not supported. path: {:?}", path);
+        Err(Error::Fuse(libc::ENOSYS))
+    }
+    /// Removes the directory at `path` with everything below it, calling
+    /// `progress` with the number of entries removed so far as they go. Entries
+    /// failing to be removed are skipped, as are the directories holding them, and
+    /// reported once the rest is gone. Returns the number of entries removed.
+    fn remove_all<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        progress: &mut dyn FnMut(u64),
+    ) -> Result<u64> {
+        let mut removal = Removal::default();
+        remove_tree(self, path.as_ref(), &mut removal, progress);
+        removal.finish(path.as_ref())
+    }
+    /// Copies the file at `from` to `to`, replacing what is there. Unless the
+    /// backend copies by itself the content is read and put back whole.
+    fn copy<P: AsRef<Path> + Debug>(&self, from: P, to: P) -> Result<()> {
+        let mut data = vec![];
+        loop {
+            let chunk = self.read(&from, data.len() as u64, COPY_CHUNK)?;
+            if chunk.is_empty() {
+                break;
+            }
+            data.extend_from_slice(&chunk);
+        }
+        self.put(to, &data)
+    }
+    /// Puts `data` at `path` like `put`, in the storage class `class` of the store,
+    /// `None` for the default class of the backend. Backends without storage
+    /// classes ignore it.
+    fn put_with_class<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+    ) -> Result<()> {
+        let _ = class;
+        self.put(path, data)
+    }
+    /// Puts `data` at `path` like `put_with_class`, as an attempt of an upload that
+    /// is sent again after an ambiguous failure. Backends record `token` with the
+    /// object, and a `retry` finding the object put with the same token does not put
+    /// it again. Backends without a place for the token put on every attempt.
+    fn put_idempotent<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        let _ = (token, retry);
+        self.put_with_class(path, data, class)
+    }
+    /// Puts like `put_idempotent` the `len` bytes read from `body`, which returns a
+    /// new reader for each attempt, so content held in pieces is not joined first.
+    /// Backends that send a whole buffer read it into one.
+    fn put_streamed<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        len: u64,
+        body: &dyn Fn() -> Box<dyn Read + Send>,
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        let mut data = Vec::with_capacity(len as usize);
+        body().read_to_end(&mut data)?;
+        self.put_idempotent(path, &data, class, token, retry)
+    }
+    /// Moves the existing file at `path` to the storage class `class`.
+    fn set_storage_class<P: AsRef<Path> + Debug>(&self, path: P, class: &str) -> Result<()> {
+        log::error!(
+            "storage classes are not supported. path: {:?}, class: {}",
+            path,
+            class
+        );
+        Err(Error::Fuse(libc::ENOTSUP))
+    }
+    /// Tags of the file at `path` as pairs of key and value, e.g. for lifecycle
+    /// rules or cost allocation.
+    fn get_tags<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<(String, String)>> {
+        log::error!("tags are not supported. path: {:?}", path);
+        Err(Error::Fuse(libc::ENOTSUP))
+    }
+    /// Replaces all the tags of the file at `path` with `tags`.
+    fn set_tags<P: AsRef<Path> + Debug>(&self, path: P, tags: &[(String, String)]) -> Result<()> {
+        log::error!("tags are not supported. path: {:?}, tags: {:?}", path, tags);
+        Err(Error::Fuse(libc::ENOTSUP))
+    }
     // fn read<P: AsRef<Path> + Debug>(&self, path: P, offset: u64, size: usize) -> ReadFuture;
 }
 
+/// Lets a caller keep a handle on the backend after handing it over to `Fuse`.
+impl<B: Backend> Backend for Arc<B> {
+    fn root(&self) -> Node {
+        (**self).root()
+    }
+    fn check(&self) -> Result<()> {
+        (**self).check()
+    }
+    fn probe(&self) -> Result<()> {
+        (**self).probe()
+    }
+    fn get_children<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<Node>> {
+        (**self).get_children(path)
+    }
+    fn for_each_children_page<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        page: &mut dyn FnMut(Vec<Node>),
+    ) -> Result<()> {
+        (**self).for_each_children_page(path, page)
+    }
+    fn get_node<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Node> {
+        (**self).get_node(path)
+    }
+    fn get_path<P: AsRef<Path> + Debug>(&self, path: P, depth: usize) -> Result<Option<Vec<Node>>> {
+        (**self).get_path(path, depth)
+    }
+    fn list_below<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Option<Vec<Node>>> {
+        (**self).list_below(path)
+    }
+    fn statfs<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Stat> {
+        (**self).statfs(path)
+    }
+    fn usage<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Usage> {
+        (**self).usage(path)
+    }
+    fn mknod<P: AsRef<Path> + Debug>(&self, path: P, filetype: FileType, mode: u32) -> Result<()> {
+        (**self).mknod(path, filetype, mode)
+    }
+    fn read<P: AsRef<Path> + Debug>(&self, path: P, offset: u64, size: usize) -> Result<Vec<u8>> {
+        (**self).read(path, offset, size)
+    }
+    fn read_vectored<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        ranges: &[Range<u64>],
+    ) -> Vec<Result<Vec<u8>>> {
+        (**self).read_vectored(path, ranges)
+    }
+    fn read_if_match<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        offset: u64,
+        size: usize,
+        etag: &str,
+    ) -> Result<Vec<u8>> {
+        (**self).read_if_match(path, offset, size, etag)
+    }
+    fn local_file<P: AsRef<Path> + Debug>(&self, path: P) -> Option<PathBuf> {
+        (**self).local_file(path)
+    }
+    fn put<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        (**self).put(path, data)
+    }
+    fn put_if_absent<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        (**self).put_if_absent(path, data)
+    }
+    fn remove<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        (**self).remove(path)
+    }
+    fn remove_many(&self, paths: &[PathBuf]) -> Vec<Result<()>> {
+        (**self).remove_many(paths)
+    }
+    fn remove_dir<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        (**self).remove_dir(path)
+    }
+    fn remove_all<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        progress: &mut dyn FnMut(u64),
+    ) -> Result<u64> {
+        (**self).remove_all(path, progress)
+    }
+    fn copy<P: AsRef<Path> + Debug>(&self, from: P, to: P) -> Result<()> {
+        (**self).copy(from, to)
+    }
+    fn put_with_class<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+    ) -> Result<()> {
+        (**self).put_with_class(path, data, class)
+    }
+    fn put_idempotent<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        (**self).put_idempotent(path, data, class, token, retry)
+    }
+    fn put_streamed<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        len: u64,
+        body: &dyn Fn() -> Box<dyn Read + Send>,
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        (**self).put_streamed(path, len, body, class, token, retry)
+    }
+    fn set_storage_class<P: AsRef<Path> + Debug>(&self, path: P, class: &str) -> Result<()> {
+        (**self).set_storage_class(path, class)
+    }
+    fn get_tags<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<(String, String)>> {
+        (**self).get_tags(path)
+    }
+    fn set_tags<P: AsRef<Path> + Debug>(&self, path: P, tags: &[(String, String)]) -> Result<()> {
+        (**self).set_tags(path, tags)
+    }
+}
+
+/// Object safe counterpart of `Backend`, implemented for every backend. It lets
+/// backends chosen at runtime be stored as a `BoxedBackend`, which is a `Backend`
+/// itself.
+pub trait DynBackend: Debug + Send + Sync {
+    fn root(&self) -> Node;
+    fn check(&self) -> Result<()>;
+    fn probe(&self) -> Result<()>;
+    fn get_children(&self, path: &Path) -> Result<Vec<Node>>;
+    fn for_each_children_page(&self, path: &Path, page: &mut dyn FnMut(Vec<Node>)) -> Result<()>;
+    fn get_node(&self, path: &Path) -> Result<Node>;
+    fn get_path(&self, path: &Path, depth: usize) -> Result<Option<Vec<Node>>>;
+    fn list_below(&self, path: &Path) -> Result<Option<Vec<Node>>>;
+    fn statfs(&self, path: &Path) -> Result<Stat>;
+    fn usage(&self, path: &Path) -> Result<Usage>;
+    fn mknod(&self, path: &Path, filetype: FileType, mode: u32) -> Result<()>;
+    fn read(&self, path: &Path, offset: u64, size: usize) -> Result<Vec<u8>>;
+    fn read_vectored(&self, path: &Path, ranges: &[Range<u64>]) -> Vec<Result<Vec<u8>>>;
+    fn read_if_match(&self, path: &Path, offset: u64, size: usize, etag: &str) -> Result<Vec<u8>>;
+    fn local_file(&self, path: &Path) -> Option<PathBuf>;
+    fn put(&self, path: &Path, data: &[u8]) -> Result<()>;
+    fn put_if_absent(&self, path: &Path, data: &[u8]) -> Result<()>;
+    fn remove(&self, path: &Path) -> Result<()>;
+    fn remove_many(&self, paths: &[PathBuf]) -> Vec<Result<()>>;
+    fn remove_dir(&self, path: &Path) -> Result<()>;
+    fn remove_all(&self, path: &Path, progress: &mut dyn FnMut(u64)) -> Result<u64>;
+    fn copy(&self, from: &Path, to: &Path) -> Result<()>;
+    fn put_with_class(&self, path: &Path, data: &[u8], class: Option<&str>) -> Result<()>;
+    fn put_idempotent(
+        &self,
+        path: &Path,
+        data: &[u8],
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()>;
+    fn put_streamed(
+        &self,
+        path: &Path,
+        len: u64,
+        body: &dyn Fn() -> Box<dyn Read + Send>,
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()>;
+    fn set_storage_class(&self, path: &Path, class: &str) -> Result<()>;
+    fn get_tags(&self, path: &Path) -> Result<Vec<(String, String)>>;
+    fn set_tags(&self, path: &Path, tags: &[(String, String)]) -> Result<()>;
+}
+
+pub type BoxedBackend = Box<dyn DynBackend>;
+
+impl<B: Backend + Debug + Send + Sync> DynBackend for B {
+    fn root(&self) -> Node {
+        Backend::root(self)
+    }
+    fn check(&self) -> Result<()> {
+        Backend::check(self)
+    }
+    fn probe(&self) -> Result<()> {
+        Backend::probe(self)
+    }
+    fn get_children(&self, path: &Path) -> Result<Vec<Node>> {
+        Backend::get_children(self, path)
+    }
+    fn for_each_children_page(&self, path: &Path, page: &mut dyn FnMut(Vec<Node>)) -> Result<()> {
+        Backend::for_each_children_page(self, path, page)
+    }
+    fn get_node(&self, path: &Path) -> Result<Node> {
+        Backend::get_node(self, path)
+    }
+    fn get_path(&self, path: &Path, depth: usize) -> Result<Option<Vec<Node>>> {
+        Backend::get_path(self, path, depth)
+    }
+    fn list_below(&self, path: &Path) -> Result<Option<Vec<Node>>> {
+        Backend::list_below(self, path)
+    }
+    fn statfs(&self, path: &Path) -> Result<Stat> {
+        Backend::statfs(self, path)
+    }
+    fn usage(&self, path: &Path) -> Result<Usage> {
+        Backend::usage(self, path)
+    }
+    fn mknod(&self, path: &Path, filetype: FileType, mode: u32) -> Result<()> {
+        Backend::mknod(self, path, filetype, mode)
+    }
+    fn read(&self, path: &Path, offset: u64, size: usize) -> Result<Vec<u8>> {
+        Backend::read(self, path, offset, size)
+    }
+    fn read_vectored(&self, path: &Path, ranges: &[Range<u64>]) -> Vec<Result<Vec<u8>>> {
+        Backend::read_vectored(self, path, ranges)
+    }
+    fn read_if_match(&self, path: &Path, offset: u64, size: usize, etag: &str) -> Result<Vec<u8>> {
+        Backend::read_if_match(self, path, offset, size, etag)
+    }
+    fn local_file(&self, path: &Path) -> Option<PathBuf> {
+        Backend::local_file(self, path)
+    }
+    fn put(&self, path: &Path, data: &[u8]) -> Result<()> {
+        Backend::put(self, path, data)
+    }
+    fn put_if_absent(&self, path: &Path, data: &[u8]) -> Result<()> {
+        Backend::put_if_absent(self, path, data)
+    }
+    fn remove(&self, path: &Path) -> Result<()> {
+        Backend::remove(self, path)
+    }
+    fn remove_many(&self, paths: &[PathBuf]) -> Vec<Result<()>> {
+        Backend::remove_many(self, paths)
+    }
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        Backend::remove_dir(self, path)
+    }
+    fn remove_all(&self, path: &Path, progress: &mut dyn FnMut(u64)) -> Result<u64> {
+        Backend::remove_all(self, path, progress)
+    }
+    fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        Backend::copy(self, from, to)
+    }
+    fn put_with_class(&self, path: &Path, data: &[u8], class: Option<&str>) -> Result<()> {
+        Backend::put_with_class(self, path, data, class)
+    }
+    fn put_idempotent(
+        &self,
+        path: &Path,
+        data: &[u8],
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        Backend::put_idempotent(self, path, data, class, token, retry)
+    }
+    fn put_streamed(
+        &self,
+        path: &Path,
+        len: u64,
+        body: &dyn Fn() -> Box<dyn Read + Send>,
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        Backend::put_streamed(self, path, len, body, class, token, retry)
+    }
+    fn set_storage_class(&self, path: &Path, class: &str) -> Result<()> {
+        Backend::set_storage_class(self, path, class)
+    }
+    fn get_tags(&self, path: &Path) -> Result<Vec<(String, String)>> {
+        Backend::get_tags(self, path)
+    }
+    fn set_tags(&self, path: &Path, tags: &[(String, String)]) -> Result<()> {
+        Backend::set_tags(self, path, tags)
+    }
+}
+
+impl Backend for BoxedBackend {
+    fn root(&self) -> Node {
+        DynBackend::root(&**self)
+    }
+    fn check(&self) -> Result<()> {
+        DynBackend::check(&**self)
+    }
+    fn probe(&self) -> Result<()> {
+        DynBackend::probe(&**self)
+    }
+    fn get_children<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<Node>> {
+        DynBackend::get_children(&**self, path.as_ref())
+    }
+    fn for_each_children_page<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        page: &mut dyn FnMut(Vec<Node>),
+    ) -> Result<()> {
+        DynBackend::for_each_children_page(&**self, path.as_ref(), page)
+    }
+    fn get_node<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Node> {
+        DynBackend::get_node(&**self, path.as_ref())
+    }
+    fn get_path<P: AsRef<Path> + Debug>(&self, path: P, depth: usize) -> Result<Option<Vec<Node>>> {
+        DynBackend::get_path(&**self, path.as_ref(), depth)
+    }
+    fn list_below<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Option<Vec<Node>>> {
+        DynBackend::list_below(&**self, path.as_ref())
+    }
+    fn statfs<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Stat> {
+        DynBackend::statfs(&**self, path.as_ref())
+    }
+    fn usage<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Usage> {
+        DynBackend::usage(&**self, path.as_ref())
+    }
+    fn mknod<P: AsRef<Path> + Debug>(&self, path: P, filetype: FileType, mode: u32) -> Result<()> {
+        DynBackend::mknod(&**self, path.as_ref(), filetype, mode)
+    }
+    fn read<P: AsRef<Path> + Debug>(&self, path: P, offset: u64, size: usize) -> Result<Vec<u8>> {
+        DynBackend::read(&**self, path.as_ref(), offset, size)
+    }
+    fn read_vectored<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        ranges: &[Range<u64>],
+    ) -> Vec<Result<Vec<u8>>> {
+        DynBackend::read_vectored(&**self, path.as_ref(), ranges)
+    }
+    fn read_if_match<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        offset: u64,
+        size: usize,
+        etag: &str,
+    ) -> Result<Vec<u8>> {
+        DynBackend::read_if_match(&**self, path.as_ref(), offset, size, etag)
+    }
+    fn local_file<P: AsRef<Path> + Debug>(&self, path: P) -> Option<PathBuf> {
+        DynBackend::local_file(&**self, path.as_ref())
+    }
+    fn put<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        DynBackend::put(&**self, path.as_ref(), data)
+    }
+    fn put_if_absent<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        DynBackend::put_if_absent(&**self, path.as_ref(), data)
+    }
+    fn remove<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        DynBackend::remove(&**self, path.as_ref())
+    }
+    fn remove_many(&self, paths: &[PathBuf]) -> Vec<Result<()>> {
+        DynBackend::remove_many(&**self, paths)
+    }
+    fn remove_dir<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        DynBackend::remove_dir(&**self, path.as_ref())
+    }
+    fn remove_all<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        progress: &mut dyn FnMut(u64),
+    ) -> Result<u64> {
+        DynBackend::remove_all(&**self, path.as_ref(), progress)
+    }
+    fn copy<P: AsRef<Path> + Debug>(&self, from: P, to: P) -> Result<()> {
+        DynBackend::copy(&**self, from.as_ref(), to.as_ref())
+    }
+    fn put_with_class<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+    ) -> Result<()> {
+        DynBackend::put_with_class(&**self, path.as_ref(), data, class)
+    }
+    fn put_idempotent<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        DynBackend::put_idempotent(&**self, path.as_ref(), data, class, token, retry)
+    }
+    fn put_streamed<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        len: u64,
+        body: &dyn Fn() -> Box<dyn Read + Send>,
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        DynBackend::put_streamed(&**self, path.as_ref(), len, body, class, token, retry)
+    }
+    fn set_storage_class<P: AsRef<Path> + Debug>(&self, path: P, class: &str) -> Result<()> {
+        DynBackend::set_storage_class(&**self, path.as_ref(), class)
+    }
+    fn get_tags<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<(String, String)>> {
+        DynBackend::get_tags(&**self, path.as_ref())
+    }
+    fn set_tags<P: AsRef<Path> + Debug>(&self, path: P, tags: &[(String, String)]) -> Result<()> {
+        DynBackend::set_tags(&**self, path.as_ref(), tags)
+    }
+}
+
+/// Tally of a recursive removal, the failures are reported once everything else
+/// is removed.
+#[derive(Debug, Default)]
+pub(crate) struct Removal {
+    pub removed: u64,
+    pub failed: Vec<(String, Error)>,
+}
+
+impl Removal {
+    /// The number of entries removed below `path`, or an error naming the first of
+    /// the entries left.
+    pub fn finish(self, path: &Path) -> Result<u64> {
+        let (first, error) = match self.failed.first() {
+            Some(failed) => failed,
+            None => return Ok(self.removed),
+        };
+        log::error!(
+            "removed {} entries below {:?}, {} failed",
+            self.removed,
+            path,
+            self.failed.len()
+        );
+        Err(Error::Backend(format!(
+            "failed to remove {} entries below {:?}, the first of them {} with {}",
+            self.failed.len(),
+            path,
+            first,
+            error
+        )))
+    }
+}
+
+/// `node` preceded by its `depth - 1` closest ancestors with the attributes `dir`,
+/// for backends where an entry implies the directories leading to it.
+pub(crate) fn with_ancestors(node: Node, depth: usize, dir: FileAttr) -> Vec<Node> {
+    let path = node.path();
+    let mut nodes: Vec<Node> = path
+        .ancestors()
+        .skip(1)
+        .take(depth.saturating_sub(1))
+        .map(|ancestor| Node::new(0, 0, ancestor.to_path_buf(), dir))
+        .collect();
+    nodes.reverse();
+    nodes.push(node);
+    nodes
+}
+
+/// The spans covering `ranges` in the order of their offsets, ranges at most `gap`
+/// bytes apart are covered by the same span. Empty ranges are left out.
+pub(crate) fn coalesce(ranges: &[Range<u64>], gap: u64) -> Vec<Range<u64>> {
+    let mut sorted: Vec<Range<u64>> = ranges
+        .iter()
+        .filter(|range| range.start < range.end)
+        .cloned()
+        .collect();
+    sorted.sort_by_key(|range| range.start);
+    let mut spans: Vec<Range<u64>> = vec![];
+    for range in sorted {
+        if let Some(span) = spans.last_mut() {
+            if range.start <= span.end + gap {
+                span.end = std::cmp::max(span.end, range.end);
+                continue;
+            }
+        }
+        spans.push(range);
+    }
+    spans
+}
+
+/// The results of `ranges` cut out of the results of reading the `spans` covering
+/// them, as returned by `coalesce`.
+pub(crate) fn split(
+    ranges: &[Range<u64>],
+    spans: &[Range<u64>],
+    read: &[Result<Vec<u8>>],
+) -> Vec<Result<Vec<u8>>> {
+    ranges
+        .iter()
+        .map(|range| {
+            if range.start >= range.end {
+                return Ok(vec![]);
+            }
+            let index = spans
+                .iter()
+                .position(|span| span.start <= range.start && range.end <= span.end)
+                .expect("the spans cover the ranges");
+            let start = spans[index].start;
+            match read[index] {
+                Ok(ref data) => Ok(blocks::slice(data, range.start - start, range.end - start)),
+                Err(ref e) => Err(e.clone()),
+            }
+        })
+        .collect()
+}
+
+// removes the children of `path` depth first, then `path` itself unless some of
+// them are left
+fn remove_tree<B: Backend + ?Sized>(
+    backend: &B,
+    path: &Path,
+    removal: &mut Removal,
+    progress: &mut dyn FnMut(u64),
+) {
+    let children = match backend.get_children(path) {
+        Ok(children) => children,
+        Err(e) => {
+            removal.failed.push((format!("{:?}", path), e));
+            return;
+        }
+    };
+    let failed = removal.failed.len();
+    for child in children {
+        let child_path = child.path();
+        if child.attr().kind == FileType::Directory {
+            remove_tree(backend, &child_path, removal, progress);
+            continue;
+        }
+        match backend.remove(&child_path) {
+            Ok(()) => {
+                removal.removed += 1;
+                progress(removal.removed);
+            }
+            Err(e) => removal.failed.push((format!("{:?}", child_path), e)),
+        }
+    }
+    if removal.failed.len() > failed {
+        return;
+    }
+    match backend.remove_dir(path) {
+        Ok(()) => {
+            removal.removed += 1;
+            progress(removal.removed);
+        }
+        Err(e) => removal.failed.push((format!("{:?}", path), e)),
+    }
+}
+
 pub struct ReadFuture {
     inner: Pin<Box<dyn Future<Output = Result<Vec<u8>>> + Send>>,
 }
@@ -57,3 +842,25 @@ impl<T> BackendFuture<T> {
         Self { inner: fut.into() }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{coalesce, split};
+    use crate::error::Error;
+
+    #[test]
+    fn test_coalesce() {
+        let ranges = vec![100..110, 0..10, 15..20, 50..50, 5..12];
+        let spans = coalesce(&ranges, 4);
+        assert_eq!(spans, vec![0..20, 100..110]);
+
+        let data: Vec<u8> = (0..20).collect();
+        let read = vec![Ok(data), Err(Error::Timeout("read".to_owned()))];
+        let results = split(&ranges, &spans, &read);
+        assert!(results[0].is_err());
+        assert_eq!(results[1].as_ref().unwrap(), &(0..10).collect::<Vec<u8>>());
+        assert_eq!(results[2].as_ref().unwrap(), &(15..20).collect::<Vec<u8>>());
+        assert!(results[3].as_ref().unwrap().is_empty());
+        assert_eq!(results[4].as_ref().unwrap(), &(5..12).collect::<Vec<u8>>());
+    }
+}
@@ -0,0 +1,74 @@
+//! Read only clients of the store, refusing to send anything but GET, HEAD and OPTIONS
+//! requests whatever the code above the backend does.
+
+use crate::error::{Error, Result};
+use futures01::future::{Either, FutureResult};
+use rusoto_core::request::{DispatchSignedRequest, HttpDispatchError, HttpResponse};
+use rusoto_core::signature::SignedRequest;
+use std::time::Duration;
+
+/// Whether requests with `method` leave the store unchanged.
+pub fn is_safe(method: &str) -> bool {
+    match method.to_uppercase().as_str() {
+        "GET" | "HEAD" | "OPTIONS" => true,
+        _ => false,
+    }
+}
+
+/// Fails the request `method` on `target` of a read only client with EROFS.
+pub fn refuse(method: &str, target: &str) -> Error {
+    log::error!("read only client refused to send {} {}", method, target);
+    Error::Fuse(libc::EROFS)
+}
+
+/// Fails unless `method` is safe or the client may write.
+pub fn check(read_only: bool, method: &str, target: &str) -> Result<()> {
+    if read_only && !is_safe(method) {
+        return Err(refuse(method, target));
+    }
+    Ok(())
+}
+
+/// Dispatches the signed requests of rusoto which leave the store unchanged only.
+#[derive(Debug, Clone)]
+pub struct ReadOnlyDispatcher<D> {
+    inner: D,
+}
+
+impl<D> ReadOnlyDispatcher<D> {
+    pub fn new(inner: D) -> ReadOnlyDispatcher<D> {
+        ReadOnlyDispatcher { inner }
+    }
+}
+
+impl<D: DispatchSignedRequest> DispatchSignedRequest for ReadOnlyDispatcher<D> {
+    type Future = Either<D::Future, FutureResult<HttpResponse, HttpDispatchError>>;
+
+    fn dispatch(&self, request: SignedRequest, timeout: Option<Duration>) -> Self::Future {
+        if is_safe(request.method()) {
+            return Either::A(self.inner.dispatch(request, timeout));
+        }
+        refuse(request.method(), request.path());
+        Either::B(futures01::future::err(HttpDispatchError::new(format!(
+            "read only client refused to send {} {}",
+            request.method(),
+            request.path()
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, is_safe};
+
+    #[test]
+    fn test_read_only() {
+        assert!(is_safe("GET"));
+        assert!(is_safe("head"));
+        assert!(!is_safe("PUT"));
+        assert!(!is_safe("DELETE"));
+        assert!(check(false, "POST", "/bucket/key").is_ok());
+        let refused = check(true, "POST", "/bucket/key").unwrap_err();
+        assert_eq!(refused.errno(0), libc::EROFS);
+    }
+}
@@ -0,0 +1,289 @@
+//! Builds backends from connection strings, e.g. `s3://bucket/prefix?endpoint=..`,
+//! `seaweedfs://filer:8888/bucket` or `file:///path`, see `BackendRegistry::build`.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::backend::credentials::{Credentials, Keys};
+use crate::ossfs_impl::backend::s3::S3Backend;
+use crate::ossfs_impl::backend::seaweedfs::SeaweedfsBackend;
+use crate::ossfs_impl::backend::simple::SimpleBackend;
+use crate::ossfs_impl::backend::tls::TlsConfig;
+use crate::ossfs_impl::backend::BoxedBackend;
+use percent_encoding::percent_decode_str;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use url::Url;
+
+pub type Constructor = Box<dyn Fn(&Url) -> Result<BoxedBackend> + Send + Sync>;
+
+pub struct BackendRegistry {
+    constructors: BTreeMap<String, Constructor>,
+}
+
+impl std::fmt::Debug for BackendRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "schemes: {:?}", self.schemes())
+    }
+}
+
+impl Default for BackendRegistry {
+    fn default() -> BackendRegistry {
+        let mut registry = BackendRegistry::new();
+        registry.register("s3", |url: &Url| s3(url, false));
+        registry.register("seaweedfs", seaweedfs);
+        registry.register("seaweedfs+https", seaweedfs);
+        registry.register("file", file);
+        registry
+    }
+}
+
+impl BackendRegistry {
+    /// A registry without any scheme, use `default` for the builtin ones.
+    pub fn new() -> BackendRegistry {
+        BackendRegistry {
+            constructors: BTreeMap::new(),
+        }
+    }
+
+    /// Adds or replaces the constructor of `scheme`.
+    pub fn register<S, F>(&mut self, scheme: S, constructor: F)
+    where
+        S: Into<String>,
+        F: Fn(&Url) -> Result<BoxedBackend> + Send + Sync + 'static,
+    {
+        self.constructors
+            .insert(scheme.into().to_lowercase(), Box::new(constructor));
+    }
+
+    /// Lets the uris of the s3 scheme run the command of a `process:` credentials
+    /// source, only for uris from a trusted source such as the config file.
+    pub fn allow_credential_process(&mut self) {
+        self.register("s3", |url: &Url| s3(url, true));
+    }
+
+    pub fn schemes(&self) -> Vec<&str> {
+        self.constructors
+            .keys()
+            .map(|scheme| scheme.as_str())
+            .collect()
+    }
+
+    pub fn build(&self, uri: &str) -> Result<BoxedBackend> {
+        let url = Url::parse(uri)
+            .map_err(|e| Error::Other(format!("invalid backend uri {:?}. {}", uri, e)))?;
+        match self.constructors.get(url.scheme()) {
+            Some(constructor) => constructor(&url),
+            None => Err(Error::Other(format!(
+                "unknown backend scheme {:?} in {:?}, expected one of {:?}",
+                url.scheme(),
+                uri,
+                self.schemes()
+            ))),
+        }
+    }
+}
+
+fn query(url: &Url, name: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+fn setting(url: &Url, name: &str, env: &str) -> Result<String> {
+    query(url, name)
+        .or_else(|| std::env::var(env).ok())
+        .ok_or_else(|| {
+            Error::Other(format!(
+                "{} is required, set it with `?{}=` or {}",
+                name, name, env
+            ))
+        })
+}
+
+fn read_only(url: &Url) -> bool {
+    query(url, "read_only")
+        .map(|read_only| read_only == "true" || read_only == "1")
+        .unwrap_or(false)
+}
+
+fn number(url: &Url, name: &str, value: &str) -> Result<usize> {
+    value.parse().map_err(|_| {
+        Error::Other(format!(
+            "{} must be a number in backend uri {}, got {:?}",
+            name, url, value
+        ))
+    })
+}
+
+fn host(url: &Url) -> Result<String> {
+    match url.host_str() {
+        Some(host) if !host.is_empty() => Ok(host.to_owned()),
+        _ => Err(Error::Other(format!("missing host in backend uri {}", url))),
+    }
+}
+
+// the decoded path, `Url::path` keeps e.g. a space as `%20`
+fn path(url: &Url) -> Result<String> {
+    percent_decode_str(url.path())
+        .decode_utf8()
+        .map(|path| path.into_owned())
+        .map_err(|e| Error::Other(format!("invalid path in backend uri {}. {}", url, e)))
+}
+
+// `None` when no tls parameter is set
+fn tls(url: &Url) -> Result<Option<TlsConfig>> {
+    let mut tls = TlsConfig::new();
+    let mut set = false;
+    if let Some(ca) = query(url, "ca") {
+        tls = tls.ca_certificate(ca);
+        set = true;
+    }
+    match (query(url, "client_cert"), query(url, "client_key")) {
+        (Some(certificate), Some(key)) => {
+            tls = tls.client_identity(certificate, key);
+            set = true;
+        }
+        (None, None) => {}
+        _ => {
+            return Err(Error::Other(
+                "client_cert and client_key must be set together".to_owned(),
+            ))
+        }
+    }
+    if let Some(verify) = query(url, "tls_verify") {
+        tls = tls.verify(verify.parse()?);
+        set = true;
+    }
+    Ok(if set { Some(tls) } else { None })
+}
+
+fn s3(url: &Url, allow_process: bool) -> Result<BoxedBackend> {
+    let credentials = match query(url, "credentials") {
+        Some(source) => match source.parse()? {
+            Credentials::Process(_) if !allow_process => {
+                return Err(Error::PermissionDenied(format!(
+                    "credentials {:?} run a command, which is not allowed in a backend uri",
+                    source
+                )))
+            }
+            credentials => credentials,
+        },
+        None => Credentials::Static(Keys::new(
+            setting(url, "access_key", "AWS_ACCESS_KEY_ID")?,
+            setting(url, "secret_key", "AWS_SECRET_ACCESS_KEY")?,
+        )),
+    };
+    let mut backend = S3Backend::with_credentials(
+        setting(url, "endpoint", "S3_ENDPOINT")?,
+        host(url)?,
+        &credentials,
+    )?
+    .with_prefix(path(url)?);
+    if let Some(class) = query(url, "storage_class") {
+        backend = backend.with_storage_class(class);
+    }
+    if let Some(tls) = tls(url)? {
+        backend = backend.with_tls(&tls)?;
+    }
+    if read_only(url) {
+        backend = backend.with_read_only(true);
+    }
+    Ok(Box::new(backend))
+}
+
+fn seaweedfs(url: &Url) -> Result<BoxedBackend> {
+    let scheme = if url.scheme() == "seaweedfs+https" {
+        "https"
+    } else {
+        "http"
+    };
+    let filer = match url.port() {
+        Some(port) => format!("{}://{}:{}", scheme, host(url)?, port),
+        None => format!("{}://{}", scheme, host(url)?),
+    };
+    let path = path(url)?;
+    let bucket = path.trim_matches('/');
+    if bucket.is_empty() {
+        return Err(Error::Other(format!(
+            "missing bucket in backend uri {}",
+            url
+        )));
+    }
+    let mut builder = SeaweedfsBackend::builder(filer, bucket.to_owned());
+    if let Some(tls) = tls(url)? {
+        builder = builder.tls(tls);
+    }
+    if let Some(max) = query(url, "max_idle") {
+        builder = builder.max_idle_per_host(number(url, "max_idle", &max)?);
+    }
+    if let Some(seconds) = query(url, "idle_timeout") {
+        let seconds = number(url, "idle_timeout", &seconds)?;
+        builder = builder.idle_timeout(Some(Duration::from_secs(seconds as u64)));
+    }
+    if let Some(http2) = query(url, "http2") {
+        builder = builder.http2(http2 == "true" || http2 == "1");
+    }
+    Ok(Box::new(builder.read_only(read_only(url)).build()?))
+}
+
+fn file(url: &Url) -> Result<BoxedBackend> {
+    let path = url
+        .to_file_path()
+        .map_err(|_| Error::Other(format!("invalid path in backend uri {}", url)))?;
+    Ok(Box::new(SimpleBackend::try_new(
+        path.to_string_lossy().into_owned(),
+    )?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::BackendRegistry;
+    use crate::error::Error;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::backend::Backend;
+
+    #[test]
+    fn test_build() {
+        let mut registry = BackendRegistry::default();
+        registry.register("mock", |_: &url::Url| {
+            let mock = MockBackend::new();
+            mock.add_file("/a", b"hello");
+            Ok(Box::new(mock) as super::BoxedBackend)
+        });
+        let backend = registry.build("mock://anything").unwrap();
+        assert_eq!(backend.read("/a", 0, 5).unwrap(), b"hello");
+
+        let dir = std::env::temp_dir();
+        let backend = registry
+            .build(&format!("file://{}", dir.to_string_lossy()))
+            .unwrap();
+        backend.check().unwrap();
+
+        assert!(registry.build("ftp://host/path").is_err());
+        assert!(registry.build("not a uri").is_err());
+        assert!(registry.build("seaweedfs://filer:8888").is_err());
+    }
+
+    #[test]
+    fn test_decoded_path() {
+        let url = url::Url::parse("s3://bucket/my%20photos/%E6%97%A5").unwrap();
+        assert_eq!(super::path(&url).unwrap(), "/my photos/\u{65e5}");
+        let url = url::Url::parse("s3://bucket/%FF").unwrap();
+        assert!(super::path(&url).is_err());
+    }
+
+    #[test]
+    fn test_credential_process() {
+        let uri = "s3://bucket?endpoint=http://127.0.0.1:9000&credentials=process:exit%201";
+        let mut registry = BackendRegistry::default();
+        match registry.build(uri) {
+            Err(Error::PermissionDenied(_)) => {}
+            _ => panic!("a uri ran a credential process"),
+        }
+        registry.allow_credential_process();
+        match registry.build(uri) {
+            // the command ran and failed
+            Err(Error::PermissionDenied(_)) | Ok(_) => panic!("the process was not run"),
+            Err(_) => {}
+        }
+    }
+}
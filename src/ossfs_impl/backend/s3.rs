@@ -1,29 +1,105 @@
 use crate::error::{Error, Result};
-use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::attr;
+use crate::ossfs_impl::attr::{FileAttr, FileType};
+use crate::ossfs_impl::backend::credentials::{Credentials, Keys, RefreshingProvider};
+use crate::ossfs_impl::backend::mime::ContentTypes;
+use crate::ossfs_impl::backend::read_only::{self, ReadOnlyDispatcher};
+use crate::ossfs_impl::backend::tls::TlsConfig;
+use crate::ossfs_impl::backend::{self, key, with_ancestors, Backend, Removal};
 use crate::ossfs_impl::node::Node;
-use crate::ossfs_impl::stat::Stat;
-use fuse::{FileAttr, FileType};
-use rusoto_core::credential::StaticProvider;
-use rusoto_core::request::HttpClient;
-use rusoto_core::Region;
+use crate::ossfs_impl::request_id;
+use crate::ossfs_impl::stat::{Stat, Usage};
+use futures01::Future;
+use rusoto_core::request::{DispatchSignedRequest, HttpClient, HttpResponse};
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::{Client, Region, RusotoError};
 use rusoto_s3::{
-    CommonPrefix, HeadBucketRequest, HeadObjectRequest, ListObjectsV2Output, ListObjectsV2Request,
-    Object, S3Client, S3,
+    CommonPrefix, CopyObjectRequest, Delete, DeleteObjectRequest, DeleteObjectsRequest,
+    GetObjectError, GetObjectRequest, GetObjectTaggingRequest, HeadBucketRequest, HeadObjectError,
+    HeadObjectRequest, ListObjectsV2Output, ListObjectsV2Request, Object, ObjectIdentifier,
+    PutObjectError, PutObjectRequest, PutObjectTaggingRequest, S3Client, StreamingBody, Tag,
+    Tagging, S3,
 };
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::io::Read;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::time::UNIX_EPOCH;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::ossfs_impl::filesystem::ROOT_INODE;
 
+// keys deleted by a single request, the most the store accepts
+const DELETE_BATCH: i64 = 1000;
+
+// bytes between two ranges of a vectored read below which they are fetched by one
+// request, cheaper than a request of their own
+const COALESCE_GAP: u64 = 256 * 1024;
+
+// user metadata holding the token of an idempotent put, sent as x-amz-meta-ossfs-token
+const TOKEN_METADATA: &str = "ossfs-token";
+
+// bytes of a streamed body read at once
+const STREAM_CHUNK: usize = 1024 * 1024;
+
 pub struct S3Backend {
     client: S3Client,
+    // signs and sends the requests `rusoto_s3` has no fields for, like conditional puts
+    raw: Client,
+    // renews the keys `client` and `raw` sign with
+    provider: RefreshingProvider,
+    region: Region,
     bucket: String,
+    // key of the directory mounted as root, empty for the whole bucket
+    prefix: String,
+    // content types of the objects put, by extension
+    content_types: ContentTypes,
+    // storage class of the objects put, `None` for the default of the bucket
+    storage_class: Option<String>,
+    // of an endpoint with custom tls settings
+    connector: Option<hyper_tls_rusoto::HttpsConnector<hyper_rusoto::client::HttpConnector>>,
+    read_only: bool,
+    // appended to the names of the directories sharing theirs with a file, `None`
+    // hides those directories
+    directory_suffix: Option<String>,
     root: Option<Node>,
     uid: u32,
     gid: u32,
 }
 
+// sends the id of the operation a request is made for along with it, after the
+// signature: the header is not signed
+#[derive(Debug, Clone)]
+struct RequestIdDispatcher<D>(D);
+
+impl<D: DispatchSignedRequest> DispatchSignedRequest for RequestIdDispatcher<D> {
+    type Future = D::Future;
+
+    fn dispatch(&self, mut request: SignedRequest, timeout: Option<Duration>) -> Self::Future {
+        request.add_header(request_id::HEADER, &request_id::current_or_next());
+        self.0.dispatch(request, timeout)
+    }
+}
+
+// the chunks a streamed body is sent in
+struct Chunks(Box<dyn Read + Send>);
+
+impl Iterator for Chunks {
+    type Item = std::io::Result<bytes::Bytes>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chunk = vec![0; STREAM_CHUNK];
+        match self.0.read(&mut chunk) {
+            Ok(0) => None,
+            Ok(n) => {
+                chunk.truncate(n);
+                Some(Ok(chunk.into()))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
 impl std::fmt::Debug for S3Backend {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "bucket: {}, root: {:?}", self.bucket, self.root)
@@ -35,24 +111,146 @@ impl S3Backend {
     where
         S: Into<String>,
     {
-        let provider = StaticProvider::new_minimal(access_key.into(), secret_key.into());
-        // chain.set_timeout(Duration::from_millis(200));
-        let client = S3Client::new_with(
-            HttpClient::new().expect("failed to create request dispatcher"),
+        let keys = Keys::new(access_key.into(), secret_key.into());
+        let provider =
+            RefreshingProvider::new(Credentials::Static(keys)).expect("static keys always resolve");
+        S3Backend::with_provider(endpoint.into(), bucket.into(), provider)
+    }
+
+    /// Signs the requests with the keys `credentials` resolve to. Temporary keys
+    /// are renewed before they expire, and once more when the store rejects them
+    /// as expired.
+    pub fn with_credentials<S>(
+        endpoint: S,
+        bucket: S,
+        credentials: &Credentials,
+    ) -> Result<S3Backend>
+    where
+        S: Into<String>,
+    {
+        let provider = RefreshingProvider::new(credentials.clone())?;
+        Ok(S3Backend::with_provider(
+            endpoint.into(),
+            bucket.into(),
             provider,
-            Region::Custom {
-                name: "minio".to_owned(),
-                endpoint: endpoint.into(),
-            },
+        ))
+    }
+
+    fn with_provider(endpoint: String, bucket: String, provider: RefreshingProvider) -> S3Backend {
+        // chain.set_timeout(Duration::from_millis(200));
+        let raw = Client::new_with(
+            provider.clone(),
+            RequestIdDispatcher(HttpClient::new().expect("failed to create request dispatcher")),
         );
+        let region = Region::Custom {
+            name: "minio".to_owned(),
+            endpoint,
+        };
         S3Backend {
-            client,
-            bucket: bucket.into(),
+            client: S3Client::new_with_client(raw.clone(), region.clone()),
+            raw,
+            provider,
+            region,
+            bucket,
+            prefix: String::new(),
+            content_types: ContentTypes::default(),
+            storage_class: None,
+            connector: None,
+            read_only: false,
+            directory_suffix: None,
             root: None,
             uid: unsafe { libc::getuid() },
             gid: unsafe { libc::getgid() },
         }
     }
+
+    /// Connects to an `https://` endpoint with the certificates and verification of
+    /// `tls`.
+    pub fn with_tls(mut self, tls: &TlsConfig) -> Result<S3Backend> {
+        let mut http = hyper_rusoto::client::HttpConnector::new(4);
+        http.enforce_http(false);
+        self.connector = Some(hyper_tls_rusoto::HttpsConnector::from((
+            http,
+            tls.connector()?,
+        )));
+        self.reconnect();
+        Ok(self)
+    }
+
+    /// Refuses to send any request changing the bucket, whatever the mount asks for.
+    pub fn with_read_only(mut self, read_only: bool) -> S3Backend {
+        self.read_only = read_only;
+        self.reconnect();
+        self
+    }
+
+    /// Exposes the directory `foo/` as `foo` followed by `suffix` when a file `foo`
+    /// exists too, which keeps its name. Without a suffix such directories are
+    /// hidden. Components of paths ending with the suffix are read as such
+    /// directories, so it should not end the names of any key, e.g. `.ossfs-dir`.
+    pub fn with_directory_suffix<S: Into<String>>(mut self, suffix: S) -> S3Backend {
+        self.directory_suffix = Some(suffix.into());
+        self
+    }
+
+    // key of the object at `path`
+    fn key<P: AsRef<Path>>(&self, path: P) -> String {
+        let key = key::path_to_key(path.as_ref());
+        match self.directory_suffix {
+            Some(ref suffix) => key::strip_directory_suffix(&key, suffix).0,
+            None => key,
+        }
+    }
+
+    // whether `path` names a directory exposed with the suffix
+    fn is_suffixed(&self, path: &Path) -> bool {
+        match self.directory_suffix {
+            Some(ref suffix) => key::strip_directory_suffix(&key::path_to_key(path), suffix).1,
+            None => false,
+        }
+    }
+
+    // builds the clients again after their connector or mode changed
+    fn reconnect(&mut self) {
+        let dispatcher = match self.connector.clone() {
+            Some(connector) => HttpClient::from_connector(connector),
+            None => HttpClient::new().expect("failed to create request dispatcher"),
+        };
+        self.raw = if self.read_only {
+            Client::new_with(
+                self.provider.clone(),
+                RequestIdDispatcher(ReadOnlyDispatcher::new(dispatcher)),
+            )
+        } else {
+            Client::new_with(self.provider.clone(), RequestIdDispatcher(dispatcher))
+        };
+        self.client = S3Client::new_with_client(self.raw.clone(), self.region.clone());
+    }
+
+    // fails writes of a read only backend before they reach the dispatcher, which
+    // would refuse them as well
+    fn writable(&self, method: &str, key: &str) -> Result<()> {
+        read_only::check(self.read_only, method, key)
+    }
+
+    /// Mounts the objects under `prefix` instead of the whole bucket.
+    pub fn with_prefix<S: Into<String>>(mut self, prefix: S) -> S3Backend {
+        self.prefix = prefix.into().trim_matches('/').to_owned();
+        self
+    }
+
+    /// Puts objects with the content type `types` maps their extension to.
+    pub fn with_content_types(mut self, types: ContentTypes) -> S3Backend {
+        self.content_types = types;
+        self
+    }
+
+    /// Puts objects in the storage class `class`, e.g. `STANDARD_IA` or
+    /// `INTELLIGENT_TIERING`, unless a file asks for another one.
+    pub fn with_storage_class<S: Into<String>>(mut self, class: S) -> S3Backend {
+        self.storage_class = Some(class.into());
+        self
+    }
 }
 
 impl Backend for S3Backend {
@@ -60,214 +258,983 @@ impl Backend for S3Backend {
         if let Some(root) = &self.root {
             return root.clone();
         }
-        let resp_result = self
-            .client
-            .head_bucket(HeadBucketRequest {
-                bucket: self.bucket.clone(),
-                ..HeadBucketRequest::default()
-            })
-            .with_timeout(std::time::Duration::from_millis(1000))
-            .sync();
-        match resp_result {
-            Ok(_) => {
-                log::debug!("uid: {}, gid: {}", self.uid, self.gid);
-                Node::new(
-                    ROOT_INODE,
-                    ROOT_INODE,
-                    PathBuf::from(""),
-                    FileAttr {
-                        ino: ROOT_INODE,
-                        /// Size in bytes
-                        size: 4096,
-                        /// Size in blocks
-                        blocks: 1,
-                        /// Time of last access
-                        atime: UNIX_EPOCH,
-                        /// Time of last modification
-                        mtime: UNIX_EPOCH,
-                        /// Time of last change
-                        ctime: UNIX_EPOCH,
-                        /// Time of creation (macOS only)
-                        crtime: UNIX_EPOCH,
-                        /// Kind of file (directory, file, pipe, etc)
-                        kind: FileType::Directory,
-                        /// Permissions
-                        perm: 0o777,
-                        /// Number of hard links
-                        nlink: 2,
-                        /// User id
-                        uid: self.uid,
-                        /// Group id
-                        gid: self.gid,
-                        /// Rdev
-                        rdev: 0,
-                        /// Flags (macOS only, see chflags(2))
-                        flags: 0,
+        log::debug!("uid: {}, gid: {}", self.uid, self.gid);
+        Node::new(
+            ROOT_INODE,
+            ROOT_INODE,
+            PathBuf::from(&self.prefix),
+            FileAttr {
+                ino: ROOT_INODE,
+                perm: 0o777,
+                ..self.directory_attr()
+            },
+        )
+    }
+
+    fn check(&self) -> Result<()> {
+        self.renewing(|| {
+            self.client
+                .head_bucket(HeadBucketRequest {
+                    bucket: self.bucket.clone(),
+                    ..HeadBucketRequest::default()
+                })
+                .with_timeout(std::time::Duration::from_millis(1000))
+                .sync()
+        })?;
+        Ok(())
+    }
+
+    // the bucket answers a HEAD and lists its first key
+    fn probe(&self) -> Result<()> {
+        self.check()?;
+        self.renewing(|| {
+            self.client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: self.bucket.clone(),
+                    prefix: if self.prefix == "" {
+                        None
+                    } else {
+                        Some(self.prefix.clone() + "/")
                     },
+                    max_keys: Some(1),
+                    ..ListObjectsV2Request::default()
+                })
+                .with_timeout(std::time::Duration::from_millis(1000))
+                .sync()
+        })?;
+        Ok(())
+    }
+
+    fn get_children<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<Node>> {
+        let mut nodes = vec![];
+        self.for_each_children_page(path, &mut |page| nodes.extend(page))?;
+        Ok(nodes)
+    }
+
+    fn for_each_children_page<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        page: &mut dyn FnMut(Vec<Node>),
+    ) -> Result<()> {
+        let mut prefix = self.key(path.as_ref());
+        // list the content of the directory, not the directory itself
+        if prefix != "" && !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        // names of the files listed so far, a prefix is listed after the key
+        // naming a file alike
+        let mut files = HashSet::new();
+        let mut continuation_token = None;
+        loop {
+            let mut nodes = vec![];
+            let resp: ListObjectsV2Output = self.renewing(|| {
+                self.client
+                    .list_objects_v2(ListObjectsV2Request {
+                        bucket: self.bucket.clone(),
+                        prefix: if prefix == "" {
+                            None
+                        } else {
+                            Some(prefix.clone())
+                        },
+                        max_keys: Some(1000),
+                        delimiter: Some(String::from("/")),
+                        continuation_token: continuation_token.clone(),
+                        ..ListObjectsV2Request::default()
+                    })
+                    .sync()
+            })?;
+            log::debug!(
+                "{}:{} path: {:?}, prefixes: {:?}, objects: {:?}",
+                std::file!(),
+                std::line!(),
+                path,
+                resp.common_prefixes.as_ref().map(|p| p.len()),
+                resp.contents.as_ref().map(|c| c.len()),
+            );
+            // children keep the name `path` has, suffixed or not
+            let child = |key: &str| match key::key_to_path(key).file_name() {
+                Some(name) => path.as_ref().join(name),
+                None => key::key_to_path(key),
+            };
+            for object in resp.contents.unwrap_or_default() {
+                let object: Object = object;
+                match object.key {
+                    // the marker object of the directory being listed
+                    Some(ref key) if *key == prefix => {}
+                    Some(ref key) => {
+                        let path = child(key);
+                        files.insert(path.clone());
+                        nodes.push(
+                            Node::new(
+                                0,
+                                0,
+                                path,
+                                self.file_attr(
+                                    object.size.unwrap_or(0) as u64,
+                                    parse_time(&object.last_modified),
+                                ),
+                            )
+                            .with_etag(unquote(&object.e_tag)),
+                        )
+                    }
+                    None => {}
+                }
+            }
+            for prefix in resp.common_prefixes.unwrap_or_default() {
+                let prefix: CommonPrefix = prefix;
+                let mut path = match prefix.prefix {
+                    Some(ref prefix) => child(prefix),
+                    None => continue,
+                };
+                if files.contains(&path) {
+                    let suffix = match self.directory_suffix {
+                        Some(ref suffix) => suffix,
+                        None => {
+                            log::warn!(
+                                "{:?} is a file and a directory, hiding the directory",
+                                path
+                            );
+                            continue;
+                        }
+                    };
+                    let mut name = path.file_name().unwrap().to_owned();
+                    name.push(suffix);
+                    path.set_file_name(name);
+                }
+                nodes.push(Node::new(0, 0, path, self.directory_attr()));
+            }
+            page(nodes);
+            match resp.next_continuation_token {
+                Some(token) if resp.is_truncated == Some(true) => continuation_token = Some(token),
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn get_node<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Node> {
+        let key = self.key(path.as_ref());
+        if key == self.prefix {
+            return Ok(self.root());
+        }
+        // the file of the same name is not what a suffixed path looks for
+        let result = if self.is_suffixed(path.as_ref()) {
+            Err(RusotoError::Service(HeadObjectError::NoSuchKey(
+                key.clone(),
+            )))
+        } else {
+            self.renewing(|| {
+                self.client
+                    .head_object(HeadObjectRequest {
+                        bucket: self.bucket.clone(),
+                        key: key.clone(),
+                        ..HeadObjectRequest::default()
+                    })
+                    .sync()
+            })
+        };
+        match result {
+            Ok(output) => {
+                return Ok(Node::new(
+                    0,
+                    0,
+                    path.as_ref().to_path_buf(),
+                    self.file_attr(
+                        output.content_length.unwrap_or(0) as u64,
+                        parse_time(&output.last_modified),
+                    ),
                 )
+                .with_etag(unquote(&output.e_tag)))
+            }
+            Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => {}
+            Err(e) => match Error::from(e) {
+                Error::NotFound(_) => {}
+                e => return Err(e),
+            },
+        }
+        // directories only exist as a common prefix of their content
+        let resp = self.renewing(|| {
+            self.client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: self.bucket.clone(),
+                    prefix: Some(key.clone() + "/"),
+                    max_keys: Some(1),
+                    ..ListObjectsV2Request::default()
+                })
+                .sync()
+        })?;
+        if resp.contents.map(|c| c.is_empty()).unwrap_or(true) {
+            return Err(Error::NotFound(format!("{:?}", path)));
+        }
+        Ok(Node::new(
+            0,
+            0,
+            path.as_ref().to_path_buf(),
+            self.directory_attr(),
+        ))
+    }
+
+    fn get_path<P: AsRef<Path> + Debug>(&self, path: P, depth: usize) -> Result<Option<Vec<Node>>> {
+        // prefixes exist as long as something is below them
+        let node = self.get_node(path)?;
+        Ok(Some(with_ancestors(node, depth, self.directory_attr())))
+    }
+
+    /// Lists the keys below the prefix of `path` without a delimiter, a thousand of
+    /// them per request however deep they are.
+    fn list_below<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Option<Vec<Node>>> {
+        // suffixed directories are told apart from files a directory at a time
+        if self.directory_suffix.is_some() {
+            return Ok(None);
+        }
+        let path = path.as_ref();
+        let mut prefix = self.key(path);
+        if prefix != "" && !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        let mut nodes = vec![];
+        let mut files = HashSet::new();
+        let mut dirs = std::collections::BTreeSet::new();
+        let mut continuation_token = None;
+        loop {
+            let resp: ListObjectsV2Output = self.renewing(|| {
+                self.client
+                    .list_objects_v2(ListObjectsV2Request {
+                        bucket: self.bucket.clone(),
+                        prefix: if prefix == "" {
+                            None
+                        } else {
+                            Some(prefix.clone())
+                        },
+                        max_keys: Some(1000),
+                        continuation_token: continuation_token.clone(),
+                        ..ListObjectsV2Request::default()
+                    })
+                    .sync()
+            })?;
+            for object in resp.contents.unwrap_or_default() {
+                let object: Object = object;
+                let key = match object.key {
+                    Some(ref key) if key.len() > prefix.len() => &key[prefix.len()..],
+                    _ => continue,
+                };
+                // every prefix of the key below `path` is a directory
+                for (end, _) in key.match_indices('/') {
+                    dirs.insert(path.join(key::key_to_path(&key[..end])));
+                }
+                if key.ends_with('/') {
+                    continue;
+                }
+                let file = path.join(key::key_to_path(key));
+                files.insert(file.clone());
+                nodes.push(
+                    Node::new(
+                        0,
+                        0,
+                        file,
+                        self.file_attr(
+                            object.size.unwrap_or(0) as u64,
+                            parse_time(&object.last_modified),
+                        ),
+                    )
+                    .with_etag(unquote(&object.e_tag)),
+                );
             }
-            Err(e) => {
-                panic!(format!("failed to root node. error: {}", e));
+            match resp.next_continuation_token {
+                Some(token) if resp.is_truncated == Some(true) => continuation_token = Some(token),
+                _ => break,
             }
         }
+        // like in listings, a file hides the directory of the same name
+        for dir in dirs {
+            if !files.contains(&dir) {
+                nodes.push(Node::new(0, 0, dir, self.directory_attr()));
+            }
+        }
+        Ok(Some(nodes))
     }
 
-    fn get_children<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<Node>> {
-        let path_str = path
-            .as_ref()
-            .to_str()
-            .ok_or_else(|| {
+    fn statfs<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Stat> {
+        let key = self.key(path.as_ref());
+        self.renewing(|| {
+            self.client
+                .head_object(HeadObjectRequest {
+                    bucket: self.bucket.clone(),
+                    key: key.clone(),
+                    ..HeadObjectRequest::default()
+                })
+                .sync()
+        })?;
+        // buckets have no capacity, the filesystem estimates it
+        Ok(Stat::unknown(65535))
+    }
+    /// Lists every key below the prefix of `path` at once instead of a directory at
+    /// a time. Directories are counted by the prefixes of the keys and their marker
+    /// objects.
+    fn usage<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Usage> {
+        let mut prefix = self.key(path.as_ref());
+        if prefix != "" && !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        let mut usage = Usage::default();
+        let mut dirs = std::collections::HashSet::new();
+        let mut continuation_token = None;
+        loop {
+            let resp: ListObjectsV2Output = self.renewing(|| {
+                self.client
+                    .list_objects_v2(ListObjectsV2Request {
+                        bucket: self.bucket.clone(),
+                        prefix: if prefix == "" {
+                            None
+                        } else {
+                            Some(prefix.clone())
+                        },
+                        max_keys: Some(1000),
+                        continuation_token: continuation_token.clone(),
+                        ..ListObjectsV2Request::default()
+                    })
+                    .sync()
+            })?;
+            for object in resp.contents.unwrap_or_default() {
+                let key = match object.key {
+                    Some(ref key) if key.len() > prefix.len() => &key[prefix.len()..],
+                    _ => continue,
+                };
+                // every prefix of the key below `path` is a directory
+                for (end, _) in key.match_indices('/') {
+                    dirs.insert(key[..end].to_owned());
+                }
+                if !key.ends_with('/') {
+                    usage.files += 1;
+                    usage.bytes += object.size.unwrap_or(0) as u64;
+                }
+            }
+            match resp.next_continuation_token {
+                Some(token) if resp.is_truncated == Some(true) => continuation_token = Some(token),
+                _ => break,
+            }
+        }
+        usage.dirs = dirs.len() as u64;
+        Ok(usage)
+    }
+
+    fn mknod<P: AsRef<Path> + Debug>(&self, path: P, filetype: FileType, mode: u32) -> Result<()> {
+        let mut key = self.key(path.as_ref());
+        let mut content_type = None;
+        match filetype {
+            FileType::Directory => key.push('/'),
+            FileType::RegularFile => content_type = self.content_types.of(path.as_ref()),
+            _ => {
                 log::error!(
-                    "{}:{} failed to convert path to string. {:?}",
-                    std::file!(),
-                    std::line!(),
-                    path
+                    "unknown filetype. path: {:?}, type: {:?}, mode: {}",
+                    path,
+                    filetype,
+                    mode
                 );
-                Error::Backend(format!("parse path: {:?}", path))
-            })?
-            .to_owned();
-        let resp: ListObjectsV2Output = self
-            .client
-            .list_objects_v2(ListObjectsV2Request {
-                bucket: self.bucket.clone(),
-                prefix: if path_str == "" { None } else { Some(path_str) },
-                max_keys: Some(1000),
-                delimiter: Some(String::from("/")),
-                ..ListObjectsV2Request::default()
-            })
-            .sync()?;
-
-        let mut nodes1 = {
-            if let Some(common_prefix) = resp.common_prefixes {
-                let nodes: Vec<Node> = common_prefix
-                    .iter()
-                    .filter(|prefix| -> bool {
-                        let prefix: &CommonPrefix = prefix;
-                        prefix.prefix.is_some()
+                return Err(Error::Fuse(libc::ENOSYS));
+            }
+        }
+        self.put_key_if_absent(key, content_type, Vec::new())
+    }
+
+    fn read<P: AsRef<Path> + Debug>(&self, path: P, offset: u64, size: usize) -> Result<Vec<u8>> {
+        self.get_range(path.as_ref(), offset, size, None)
+    }
+
+    fn read_if_match<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        offset: u64,
+        size: usize,
+        etag: &str,
+    ) -> Result<Vec<u8>> {
+        self.get_range(path.as_ref(), offset, size, Some(etag))
+    }
+
+    /// Ranges at most `COALESCE_GAP` apart are fetched by one request, and the
+    /// requests are all sent at once. A request failing is sent again alone, with
+    /// renewed keys if they expired.
+    fn read_vectored<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        ranges: &[Range<u64>],
+    ) -> Vec<Result<Vec<u8>>> {
+        let spans = backend::coalesce(ranges, COALESCE_GAP);
+        let key = self.key(path.as_ref());
+        let requests: Vec<_> = spans
+            .iter()
+            .map(|span| {
+                self.client
+                    .get_object(GetObjectRequest {
+                        bucket: self.bucket.clone(),
+                        key: key.clone(),
+                        range: Some(format!("bytes={}-{}", span.start, span.end - 1)),
+                        ..GetObjectRequest::default()
                     })
-                    .filter(|prefix| -> bool {
-                        log::debug!("{}:{} prefix: {:?}", std::file!(), std::line!(), prefix);
-                        true
+                    .then(Ok::<_, ()>)
+            })
+            .collect();
+        let responses = futures01::future::join_all(requests)
+            .wait()
+            .expect("the responses are never errors");
+        let read: Vec<Result<Vec<u8>>> = spans
+            .iter()
+            .zip(responses)
+            .map(|(span, response)| {
+                let size = (span.end - span.start) as usize;
+                match response {
+                    Ok(output) => {
+                        let mut buffer = Vec::with_capacity(size);
+                        if let Some(body) = output.body {
+                            body.into_blocking_read()
+                                .take(size as u64)
+                                .read_to_end(&mut buffer)?;
+                        }
+                        Ok(buffer)
+                    }
+                    Err(_) => self.read(&path, span.start, size),
+                }
+            })
+            .collect();
+        backend::split(ranges, &spans, &read)
+    }
+
+    fn put<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        self.put_with_class(path, data, None)
+    }
+
+    fn put_with_class<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+    ) -> Result<()> {
+        self.put_object(path.as_ref(), data, class, None)
+    }
+
+    /// Tags the object with the token in its metadata. A retry heads the object
+    /// first and skips the put if an earlier attempt reached the store.
+    fn put_idempotent<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        if retry && self.put_before(path.as_ref(), token)? {
+            return Ok(());
+        }
+        self.put_object(path.as_ref(), data, class, Some(token))
+    }
+
+    fn put_streamed<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        len: u64,
+        body: &dyn Fn() -> Box<dyn Read + Send>,
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        if retry && self.put_before(path.as_ref(), token)? {
+            return Ok(());
+        }
+        let stream = || StreamingBody::new(futures01::stream::iter_result(Chunks(body())));
+        self.put_body(path.as_ref(), len, &stream, class, Some(token))
+    }
+
+    fn set_storage_class<P: AsRef<Path> + Debug>(&self, path: P, class: &str) -> Result<()> {
+        self.writable("PUT", &self.key(path.as_ref()))?;
+        // copying an object onto itself is how s3 changes its class
+        let key = self.key(path.as_ref());
+        let source = format!("{}/{}", self.bucket, key);
+        self.renewing(|| {
+            self.client
+                .copy_object(CopyObjectRequest {
+                    bucket: self.bucket.clone(),
+                    key: key.clone(),
+                    copy_source: key::escape_url_path(&source),
+                    metadata_directive: Some("COPY".to_owned()),
+                    storage_class: Some(class.to_owned()),
+                    ..CopyObjectRequest::default()
+                })
+                .sync()
+        })?;
+        Ok(())
+    }
+
+    fn get_tags<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<(String, String)>> {
+        let output = self.renewing(|| {
+            self.client
+                .get_object_tagging(GetObjectTaggingRequest {
+                    bucket: self.bucket.clone(),
+                    key: self.key(path.as_ref()),
+                    ..GetObjectTaggingRequest::default()
+                })
+                .sync()
+        })?;
+        Ok(output
+            .tag_set
+            .into_iter()
+            .map(|tag| (tag.key, tag.value))
+            .collect())
+    }
+
+    fn set_tags<P: AsRef<Path> + Debug>(&self, path: P, tags: &[(String, String)]) -> Result<()> {
+        self.writable("PUT", &self.key(path.as_ref()))?;
+        let tag_set: Vec<Tag> = tags
+            .iter()
+            .map(|(key, value)| Tag {
+                key: key.clone(),
+                value: value.clone(),
+            })
+            .collect();
+        self.renewing(|| {
+            self.client
+                .put_object_tagging(PutObjectTaggingRequest {
+                    bucket: self.bucket.clone(),
+                    key: self.key(path.as_ref()),
+                    tagging: Tagging {
+                        tag_set: tag_set.clone(),
+                    },
+                    ..PutObjectTaggingRequest::default()
+                })
+                .sync()
+        })?;
+        Ok(())
+    }
+
+    fn put_if_absent<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        self.put_key_if_absent(
+            self.key(path.as_ref()),
+            self.content_types.of(path.as_ref()),
+            data.to_vec(),
+        )
+    }
+
+    fn remove<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        self.writable("DELETE", &self.key(path.as_ref()))?;
+        self.renewing(|| {
+            self.client
+                .delete_object(DeleteObjectRequest {
+                    bucket: self.bucket.clone(),
+                    key: self.key(path.as_ref()),
+                    ..DeleteObjectRequest::default()
+                })
+                .sync()
+        })?;
+        Ok(())
+    }
+
+    fn remove_many(&self, paths: &[PathBuf]) -> Vec<Result<()>> {
+        let keys: Vec<String> = paths.iter().map(|path| self.key(path)).collect();
+        let mut results = Vec::with_capacity(keys.len());
+        for batch in keys.chunks(DELETE_BATCH as usize) {
+            if let Err(e) = self.writable("DELETE", &batch[0]) {
+                results.extend(batch.iter().map(|_| Err(Error::Fuse(e.errno(libc::EROFS)))));
+                continue;
+            }
+            let objects: Vec<ObjectIdentifier> = batch
+                .iter()
+                .map(|key| ObjectIdentifier {
+                    key: key.clone(),
+                    version_id: None,
+                })
+                .collect();
+            // quiet responses name the failed keys only
+            let output = self.renewing(|| {
+                self.client
+                    .delete_objects(DeleteObjectsRequest {
+                        bucket: self.bucket.clone(),
+                        delete: Delete {
+                            objects: objects.clone(),
+                            quiet: Some(true),
+                        },
+                        ..DeleteObjectsRequest::default()
                     })
-                    .map(|prefix| {
-                        let prefix: &CommonPrefix = prefix;
-                        log::debug!(
-                            "{}:{} parent: {:?}, prefix: {:?}",
-                            std::file!(),
-                            std::line!(),
-                            path,
-                            prefix
+                    .sync()
+            });
+            let errors: HashMap<String, String> = match output {
+                Ok(output) => output
+                    .errors
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|error| {
+                        let message = format!(
+                            "{}: {}",
+                            error.code.unwrap_or_default(),
+                            error.message.unwrap_or_default()
                         );
-                        Node::new(
-                            0,
-                            0,
-                            Path::new(&prefix.prefix.clone().unwrap()).to_path_buf(),
-                            FileAttr {
-                                ino: 0,
-                                size: 4096,
-                                blocks: 0,
-                                atime: UNIX_EPOCH,
-                                mtime: UNIX_EPOCH,
-                                ctime: UNIX_EPOCH,
-                                crtime: UNIX_EPOCH,
-                                kind: FileType::Directory,
-                                perm: 0o755,
-                                nlink: 2,
-                                uid: self.uid,
-                                gid: self.gid,
-                                rdev: 0,
-                                flags: 0,
-                            },
-                        )
+                        (error.key.unwrap_or_default(), message)
                     })
-                    .collect();
-                nodes
-            } else {
-                Vec::new()
-            }
-        };
-        let mut nodes2 = {
-            if let Some(contents) = resp.contents {
-                let nodes: Vec<Node> = contents
-                    .iter()
-                    .filter(|object| -> bool {
-                        let object: &Object = object;
-                        object.key.is_some()
+                    .collect(),
+                Err(e) => {
+                    let e = Error::from(e);
+                    log::error!("delete of {} objects failed. {}", batch.len(), e);
+                    results.extend(batch.iter().map(|_| Err(Error::Backend(e.to_string()))));
+                    continue;
+                }
+            };
+            results.extend(batch.iter().map(|key| match errors.get(key) {
+                Some(message) => Err(Error::Backend(message.clone())),
+                None => Ok(()),
+            }));
+        }
+        results
+    }
+
+    fn remove_dir<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        self.writable("DELETE", &self.key(path.as_ref()))?;
+        // only the marker object goes, a prefix with objects below it stays listed
+        let mut key = self.key(path.as_ref());
+        key.push('/');
+        self.renewing(|| {
+            self.client
+                .delete_object(DeleteObjectRequest {
+                    bucket: self.bucket.clone(),
+                    key: key.clone(),
+                    ..DeleteObjectRequest::default()
+                })
+                .sync()
+        })?;
+        Ok(())
+    }
+
+    /// Lists every key below the prefix of `path`, the marker objects of the
+    /// directories too, and deletes them a page at a time with a single request.
+    fn remove_all<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        progress: &mut dyn FnMut(u64),
+    ) -> Result<u64> {
+        let mut prefix = self.key(path.as_ref());
+        self.writable("DELETE", &prefix)?;
+        // never the whole bucket
+        if prefix == "" {
+            return Err(Error::Fuse(libc::EBUSY));
+        }
+        prefix.push('/');
+        let mut removal = Removal::default();
+        let mut continuation_token = None;
+        loop {
+            // without a delimiter the keys below every level are listed
+            let resp: ListObjectsV2Output = self.renewing(|| {
+                self.client
+                    .list_objects_v2(ListObjectsV2Request {
+                        bucket: self.bucket.clone(),
+                        prefix: Some(prefix.clone()),
+                        max_keys: Some(DELETE_BATCH),
+                        continuation_token: continuation_token.clone(),
+                        ..ListObjectsV2Request::default()
                     })
-                    .map(|object| {
-                        let object: &Object = object;
-                        Node::new(
-                            0,
-                            0,
-                            Path::new(&object.key.clone().unwrap()).to_path_buf(),
-                            FileAttr {
-                                ino: 0,
-                                size: object.size.unwrap() as u64,
-                                blocks: 0,
-                                atime: UNIX_EPOCH,
-                                mtime: UNIX_EPOCH,
-                                ctime: UNIX_EPOCH,
-                                crtime: UNIX_EPOCH,
-                                kind: FileType::RegularFile,
-                                perm: 0o644,
-                                nlink: 2,
-                                uid: 0,
-                                gid: 0,
-                                rdev: 0,
-                                flags: 0,
+                    .sync()
+            })?;
+            let objects: Vec<ObjectIdentifier> = resp
+                .contents
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|object| object.key)
+                .map(|key| ObjectIdentifier {
+                    key,
+                    version_id: None,
+                })
+                .collect();
+            if !objects.is_empty() {
+                let batch = objects.len();
+                // quiet responses name the failed keys only
+                let output = self.renewing(|| {
+                    self.client
+                        .delete_objects(DeleteObjectsRequest {
+                            bucket: self.bucket.clone(),
+                            delete: Delete {
+                                objects: objects.clone(),
+                                quiet: Some(true),
                             },
-                        )
-                    })
-                    .collect();
-                nodes
-            } else {
-                Vec::new()
+                            ..DeleteObjectsRequest::default()
+                        })
+                        .sync()
+                })?;
+                let errors = output.errors.unwrap_or_default();
+                removal.removed += (batch - errors.len()) as u64;
+                for error in errors {
+                    removal.failed.push((
+                        error.key.unwrap_or_default(),
+                        Error::Backend(format!(
+                            "{}: {}",
+                            error.code.unwrap_or_default(),
+                            error.message.unwrap_or_default()
+                        )),
+                    ));
+                }
+                progress(removal.removed);
+                log::debug!(
+                    "removed {} objects below {:?}, {} failed",
+                    removal.removed,
+                    prefix,
+                    removal.failed.len()
+                );
+            }
+            match resp.next_continuation_token {
+                Some(token) if resp.is_truncated == Some(true) => continuation_token = Some(token),
+                _ => break,
             }
+        }
+        removal.finish(path.as_ref())
+    }
+
+    fn copy<P: AsRef<Path> + Debug>(&self, from: P, to: P) -> Result<()> {
+        self.writable("PUT", &self.key(to.as_ref()))?;
+        let source = format!("{}/{}", self.bucket, self.key(from.as_ref()));
+        self.renewing(|| {
+            self.client
+                .copy_object(CopyObjectRequest {
+                    bucket: self.bucket.clone(),
+                    key: self.key(to.as_ref()),
+                    copy_source: key::escape_url_path(&source),
+                    ..CopyObjectRequest::default()
+                })
+                .sync()
+        })?;
+        Ok(())
+    }
+}
+
+impl S3Backend {
+    /// Puts `body` at `key` unless an object is there already, which fails with
+    /// `Error::Conflict`. The store decides, so creates racing from several mounts
+    /// of the bucket have a single winner.
+    // `size` bytes at `offset` of the object at `path`, on condition it has the
+    // entity tag `if_match`
+    fn get_range(
+        &self,
+        path: &Path,
+        offset: u64,
+        size: usize,
+        if_match: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        if size == 0 {
+            return Ok(vec![]);
+        }
+        let result = self.renewing(|| {
+            self.client
+                .get_object(GetObjectRequest {
+                    bucket: self.bucket.clone(),
+                    key: self.key(path),
+                    range: Some(format!("bytes={}-{}", offset, offset + size as u64 - 1)),
+                    // 412 once the object changed, mapped to a conflict
+                    if_match: if_match.map(|etag| format!("\"{}\"", etag)),
+                    ..GetObjectRequest::default()
+                })
+                .sync()
+        });
+        let output = match result {
+            Ok(output) => output,
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(key))) => {
+                return Err(Error::NotFound(key))
+            }
+            // the range starts past the end of the object
+            Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 416 => {
+                return Ok(vec![])
+            }
+            Err(e) => return Err(Error::from(e)),
         };
-        nodes1.append(&mut nodes2);
-        Ok(nodes1)
+        let mut buffer = Vec::with_capacity(size);
+        if let Some(body) = output.body {
+            body.into_blocking_read()
+                .take(size as u64)
+                .read_to_end(&mut buffer)?;
+        }
+        Ok(buffer)
     }
 
-    fn get_node<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Node> {
-        unimplemented!()
+    fn put_object(
+        &self,
+        path: &Path,
+        data: &[u8],
+        class: Option<&str>,
+        token: Option<&str>,
+    ) -> Result<()> {
+        let body = || data.to_vec().into();
+        self.put_body(path, data.len() as u64, &body, class, token)
     }
 
-    fn statfs<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Stat> {
-        let key = path
-            .as_ref()
-            .to_str()
-            .ok_or_else(|| {
-                log::error!(
-                    "{}:{} failed to convert path to string. {:?}",
-                    std::file!(),
-                    std::line!(),
-                    path
-                );
-                Error::Backend(format!("parse path: {:?}", path))
-            })?
-            .to_owned();
-        self.client
-            .head_object(HeadObjectRequest {
-                bucket: self.bucket.clone(),
-                key,
-                ..HeadObjectRequest::default()
-            })
-            .sync()?;
-        Ok(Stat {
-            blocks: 1,
-            blocks_free: 1,
-            blocks_available: 1,
-            files: 1,
-            files_free: 1,
-            block_size: 1,
-            namelen: 65535,
-            frsize: 1,
-        })
+    // `body` is called for each attempt, a streamed body can only be sent once
+    fn put_body(
+        &self,
+        path: &Path,
+        len: u64,
+        body: &dyn Fn() -> StreamingBody,
+        class: Option<&str>,
+        token: Option<&str>,
+    ) -> Result<()> {
+        self.writable("PUT", &self.key(path))?;
+        let metadata = token.map(|token| {
+            let mut metadata = HashMap::new();
+            metadata.insert(TOKEN_METADATA.to_owned(), token.to_owned());
+            metadata
+        });
+        self.renewing(|| {
+            self.client
+                .put_object(PutObjectRequest {
+                    bucket: self.bucket.clone(),
+                    key: self.key(path),
+                    body: Some(body()),
+                    content_length: Some(len as i64),
+                    content_type: self
+                        .content_types
+                        .of(path)
+                        .map(|content_type| content_type.to_owned()),
+                    storage_class: class
+                        .map(|class| class.to_owned())
+                        .or_else(|| self.storage_class.clone()),
+                    metadata: metadata.clone(),
+                    ..PutObjectRequest::default()
+                })
+                .sync()
+        })?;
+        Ok(())
     }
-    fn mknod<P: AsRef<Path> + Debug>(&self, path: P, filetype: FileType, mode: u32) -> Result<()> {
-        unimplemented!()
+
+    /// Whether an earlier attempt of the idempotent put `token` wrote the object.
+    fn put_before(&self, path: &Path, token: &str) -> Result<bool> {
+        if self.token_of(path)?.as_ref().map(|put| put.as_str()) != Some(token) {
+            return Ok(false);
+        }
+        log::info!(
+            "{:?} was put by an earlier attempt, not putting it again. token: {}",
+            path,
+            token
+        );
+        Ok(true)
     }
-    fn read<P: AsRef<Path> + Debug>(&self, path: P, offset: u64, size: usize) -> Result<Vec<u8>> {
-        unimplemented!()
+
+    /// The token of the idempotent put that wrote the object at `path`, `None` if
+    /// it does not exist or was put otherwise.
+    fn token_of(&self, path: &Path) -> Result<Option<String>> {
+        let result = self.renewing(|| {
+            self.client
+                .head_object(HeadObjectRequest {
+                    bucket: self.bucket.clone(),
+                    key: self.key(path),
+                    ..HeadObjectRequest::default()
+                })
+                .sync()
+        });
+        match result {
+            Ok(output) => Ok(output
+                .metadata
+                .and_then(|mut metadata| metadata.remove(TOKEN_METADATA))),
+            Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(None),
+            Err(e) => match Error::from(e) {
+                Error::NotFound(_) => Ok(None),
+                e => Err(e),
+            },
+        }
+    }
+
+    fn put_key_if_absent(
+        &self,
+        key: String,
+        content_type: Option<&str>,
+        body: Vec<u8>,
+    ) -> Result<()> {
+        self.writable("PUT", &key)?;
+        // the request is consumed by sending it, build it again for a retry
+        let request = || {
+            let mut request = SignedRequest::new(
+                "PUT",
+                "s3",
+                &self.region,
+                &format!("/{}/{}", self.bucket, key),
+            );
+            request.add_header("If-None-Match", "*");
+            if let Some(content_type) = content_type {
+                request.add_header("Content-Type", content_type);
+            }
+            if let Some(ref class) = self.storage_class {
+                request.add_header("x-amz-storage-class", class);
+            }
+            request.set_payload(Some(body.clone()));
+            request
+        };
+        self.renewing(|| {
+            self.raw
+                .sign_and_dispatch(request(), |response: HttpResponse| {
+                    if response.status.is_success() {
+                        Box::new(futures01::future::ok(()))
+                    } else {
+                        // 412 when the key exists, mapped to a conflict
+                        Box::new(response.buffer().from_err().and_then(|response| {
+                            Err(RusotoError::<PutObjectError>::Unknown(response))
+                        }))
+                    }
+                })
+                .sync()
+        })?;
+        Ok(())
+    }
+
+    /// Runs `call` once more with renewed keys when the store rejects the keys as
+    /// expired, as temporary keys may expire before their stated expiration.
+    fn renewing<T, E, F>(&self, mut call: F) -> std::result::Result<T, RusotoError<E>>
+    where
+        F: FnMut() -> std::result::Result<T, RusotoError<E>>,
+    {
+        let result = call();
+        let expired = match result {
+            // head responses have no body telling why
+            Err(RusotoError::Unknown(ref response)) => {
+                String::from_utf8_lossy(&response.body).contains("ExpiredToken")
+                    || (response.status.as_u16() == 400 || response.status.as_u16() == 403)
+                        && self.provider.is_expiring()
+            }
+            _ => false,
+        };
+        if !expired {
+            return result;
+        }
+        log::warn!("the credentials were rejected as expired, renewing them");
+        self.provider.expire();
+        call()
+    }
+
+    fn directory_attr(&self) -> FileAttr {
+        FileAttr {
+            uid: self.uid,
+            gid: self.gid,
+            ..attr::synthetic(FileType::Directory, 4096, UNIX_EPOCH)
+        }
+    }
+
+    fn file_attr(&self, size: u64, mtime: SystemTime) -> FileAttr {
+        FileAttr {
+            uid: self.uid,
+            gid: self.gid,
+            ..attr::synthetic(FileType::RegularFile, size, mtime)
+        }
+    }
+}
+
+// listings use rfc 3339 while head responses use the http date format
+// the store quotes entity tags
+fn unquote(etag: &Option<String>) -> Option<String> {
+    etag.as_ref()
+        .map(|etag| etag.trim_matches('"').to_owned())
+        .filter(|etag| !etag.is_empty())
+}
+
+fn parse_time(time: &Option<String>) -> SystemTime {
+    let time = match time {
+        Some(time) => time,
+        None => return UNIX_EPOCH,
+    };
+    let parsed = chrono::DateTime::parse_from_rfc3339(time)
+        .or_else(|_| chrono::DateTime::parse_from_rfc2822(time));
+    match parsed {
+        Ok(parsed) if parsed.timestamp() >= 0 => {
+            UNIX_EPOCH + Duration::new(parsed.timestamp() as u64, parsed.timestamp_subsec_nanos())
+        }
+        _ => UNIX_EPOCH,
     }
 }
@@ -1,19 +1,38 @@
 use crate::counter::Counter;
 use crate::error::{Error, Result};
+use crate::ossfs_impl::attr;
+use crate::ossfs_impl::attr::{FileAttr, FileType};
+use crate::ossfs_impl::backend::read_only;
+use crate::ossfs_impl::backend::tls::TlsConfig;
+use crate::ossfs_impl::backend::{key, with_ancestors};
 use crate::ossfs_impl::filesystem::ROOT_INODE;
 use crate::ossfs_impl::node::Node;
+use crate::ossfs_impl::request_id;
 use crate::ossfs_impl::stat::Stat;
 use crate::Backend;
-use fuse::{FileAttr, FileType};
 use futures_util::future::FutureExt;
 use futures_util::stream::StreamExt;
 use futures_util::try_future::TryFutureExt;
 use hyper::client::{connect::HttpConnector, Client};
 use hyper::{Body, Request, Response};
+use hyper_tls::HttpsConnector;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::ops::Add;
 use std::path::{Path, PathBuf};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// open responses kept for sequential readers, over all files
+const MAX_STREAMS: usize = 64;
+// a stream unused for this long is closed
+const STREAM_IDLE: Duration = Duration::from_secs(30);
+// reads skipping at most this far ahead, e.g. out of order readahead, consume the
+// stream instead of opening another one
+const STREAM_SKIP: u64 = 1024 * 1024;
+
+// speaks https to `https://` filers and plain http to the others
+type Connector = HttpsConnector<HttpConnector>;
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct Chunk {
@@ -49,13 +68,250 @@ pub struct ListObjectsResponse {
 
 #[derive(Debug)]
 pub struct SeaweedfsBackend {
-    client: Client<HttpConnector, Body>,
+    client: FilerClient,
     filer_url: String,
     bucket: String,
     root: Option<Node>,
     uid: u32,
     gid: u32,
     runtime: tokio::runtime::Runtime,
+    streams: Streams,
+}
+
+/// Sends the requests to the filer, refusing any but reads when read only.
+#[derive(Debug, Clone)]
+struct FilerClient {
+    client: Client<Connector, Body>,
+    read_only: bool,
+}
+
+impl FilerClient {
+    fn request(
+        &self,
+        request: Request<Body>,
+    ) -> impl std::future::Future<Output = Result<Response<Body>>> + 'static {
+        let client = self.client.clone();
+        let read_only = self.read_only;
+        let mut request = request;
+        if let Ok(id) = request_id::current_or_next().parse() {
+            request.headers_mut().insert(request_id::HEADER, id);
+        }
+        async move {
+            let target = request.uri().to_string();
+            read_only::check(read_only, request.method().as_str(), &target)?;
+            Ok(client.request(request).await?)
+        }
+    }
+}
+
+/// Settings of the connections to the filer. Reads of many small files issue
+/// thousands of short requests, kept idle connections spare them a new connection
+/// each, and HTTP/2 multiplexes them over a few connections.
+#[derive(Debug, Clone)]
+pub struct SeaweedfsBuilder {
+    filer_url: String,
+    bucket: String,
+    tls: TlsConfig,
+    // idle connections kept per host
+    max_idle_per_host: usize,
+    // idle connections are closed after this long, `None` keeps them
+    idle_timeout: Option<Duration>,
+    // interval of the tcp keepalive probes of the connections, `None` sends none
+    tcp_keepalive: Option<Duration>,
+    http2: bool,
+    read_only: bool,
+}
+
+impl SeaweedfsBuilder {
+    /// Connects to an `https://` filer with the certificates and verification of
+    /// `tls`.
+    pub fn tls(mut self, tls: TlsConfig) -> SeaweedfsBuilder {
+        self.tls = tls;
+        self
+    }
+
+    /// Keeps up to `max` idle connections per host for the next requests, 100 by
+    /// default. Zero closes every connection after its request.
+    pub fn max_idle_per_host(mut self, max: usize) -> SeaweedfsBuilder {
+        self.max_idle_per_host = max;
+        self
+    }
+
+    /// Closes connections idle for `timeout`, 90 seconds by default.
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> SeaweedfsBuilder {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Sends tcp keepalive probes every `interval`, 60 seconds by default, so
+    /// connections dropped by a middlebox are noticed before they are reused.
+    pub fn tcp_keepalive(mut self, interval: Option<Duration>) -> SeaweedfsBuilder {
+        self.tcp_keepalive = interval;
+        self
+    }
+
+    /// Speaks HTTP/2 without negotiating it, which the filer, or a proxy in front
+    /// of it, must accept. Off by default.
+    pub fn http2(mut self, http2: bool) -> SeaweedfsBuilder {
+        self.http2 = http2;
+        self
+    }
+
+    /// Refuses to send any request changing the filer, whatever the mount asks
+    /// for. Off by default.
+    pub fn read_only(mut self, read_only: bool) -> SeaweedfsBuilder {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Connects to the filer, failing if it can not be reached or the bucket does
+    /// not exist.
+    pub fn build(self) -> Result<SeaweedfsBackend> {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_nodelay(true);
+        http.set_keepalive(self.tcp_keepalive);
+        let tls = tokio_tls::TlsConnector::from(self.tls.connector()?);
+        let connector = HttpsConnector::from((http, tls));
+        let client = Client::builder()
+            .max_idle_per_host(self.max_idle_per_host)
+            .keep_alive(self.max_idle_per_host > 0)
+            .keep_alive_timeout(self.idle_timeout)
+            .http2_only(self.http2)
+            .build(connector);
+        let client = FilerClient {
+            client,
+            read_only: self.read_only,
+        };
+        SeaweedfsBackend::connect(self.filer_url, self.bucket, client)
+    }
+}
+
+/// The body of an open ended ranged GET. Sequential reads of a file are served
+/// from it instead of issuing a request per read.
+#[derive(Debug)]
+struct Stream {
+    body: Body,
+    // file offset of the first byte of `buffer`
+    position: u64,
+    // received but not yet read
+    buffer: Vec<u8>,
+    last_used: Instant,
+    // of the content the body is reading
+    version: Version,
+}
+
+/// The size and modification time of a version of a file, in seconds, `None` when
+/// the filer did not tell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Version {
+    size: u64,
+    mtime: Option<u64>,
+}
+
+impl Version {
+    fn of(attr: &FileAttr) -> Version {
+        Version {
+            size: attr.size,
+            mtime: seconds(attr.mtime),
+        }
+    }
+
+    fn matches(&self, other: &Version) -> bool {
+        let mtimes = match (self.mtime, other.mtime) {
+            (Some(mtime), Some(other)) => mtime == other,
+            _ => true,
+        };
+        self.size == other.size && mtimes
+    }
+}
+
+// `None` for the epoch, which stands for an unknown time
+fn seconds(time: SystemTime) -> Option<u64> {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) if since.as_secs() > 0 => Some(since.as_secs()),
+        _ => None,
+    }
+}
+
+// a `Last-Modified` header, an http date or seconds since the epoch
+fn http_time(value: &str) -> Option<SystemTime> {
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(UNIX_EPOCH.add(Duration::from_secs(seconds)));
+    }
+    chrono::DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|time| SystemTime::from(time.with_timezone(&chrono::Utc)))
+}
+
+/// Open streams by path, for sequential readers. A stream reads the version of the
+/// file it was opened on: once a listing or a lookup, those of the watcher
+/// included, finds another version the streams of the file are closed, so that
+/// reads do not go on serving the content the file had.
+#[derive(Debug, Default)]
+struct Streams {
+    open: Mutex<HashMap<PathBuf, Vec<Stream>>>,
+}
+
+impl Streams {
+    /// Removes a stream of `path` that can serve a read at `offset`, and closes the
+    /// streams idle for too long.
+    fn take(&self, path: &Path, offset: u64) -> Option<Stream> {
+        let mut streams = self.open.lock().unwrap();
+        for list in streams.values_mut() {
+            list.retain(|stream| stream.last_used.elapsed() < STREAM_IDLE);
+        }
+        streams.retain(|_, list| !list.is_empty());
+        let list = streams.get_mut(path)?;
+        let index = list.iter().position(|stream| {
+            stream.position <= offset && offset - stream.position <= STREAM_SKIP
+        })?;
+        Some(list.swap_remove(index))
+    }
+
+    fn put(&self, path: &Path, mut stream: Stream) {
+        stream.last_used = Instant::now();
+        let mut streams = self.open.lock().unwrap();
+        let total: usize = streams.values().map(|list| list.len()).sum();
+        if total >= MAX_STREAMS {
+            // close the least recently used one
+            let oldest = streams
+                .iter()
+                .flat_map(|(path, list)| {
+                    list.iter()
+                        .enumerate()
+                        .map(move |(index, stream)| (stream.last_used, path.clone(), index))
+                })
+                .min();
+            if let Some((_, path, index)) = oldest {
+                if let Some(list) = streams.get_mut(&path) {
+                    list.swap_remove(index);
+                }
+            }
+        }
+        streams
+            .entry(path.to_path_buf())
+            .or_insert_with(Vec::new)
+            .push(stream);
+    }
+
+    /// Closes the streams of `node` reading another version than it describes.
+    fn check(&self, node: &Node) {
+        let path = node.path();
+        let version = Version::of(&node.attr());
+        let mut streams = self.open.lock().unwrap();
+        let stale = match streams.get_mut(&path) {
+            Some(list) => {
+                list.retain(|stream| stream.version.matches(&version));
+                list.is_empty()
+            }
+            None => false,
+        };
+        if stale {
+            log::debug!("closing the streams of {:?}, it changed", path);
+            streams.remove(&path);
+        }
+    }
 }
 
 impl SeaweedfsBackend {
@@ -63,12 +319,40 @@ impl SeaweedfsBackend {
     where
         S: Into<String>,
     {
-        let bucket = bucket.into();
-        let client = Client::builder()
-            .max_idle_per_host(100)
-            .keep_alive(true)
-            .build_http();
-        let mut filer_url: String = filer_url.into();
+        match SeaweedfsBackend::try_new(filer_url, bucket) {
+            Ok(backend) => backend,
+            Err(e) => panic!("failed to create seaweedfs backend. error: {}", e),
+        }
+    }
+
+    /// Like `new`, but returns an error if the filer can not be reached or the
+    /// bucket does not exist.
+    pub fn try_new<S>(filer_url: S, bucket: S) -> Result<SeaweedfsBackend>
+    where
+        S: Into<String>,
+    {
+        SeaweedfsBackend::builder(filer_url, bucket).build()
+    }
+
+    /// Tunes the connections to the filer before connecting to it.
+    pub fn builder<S>(filer_url: S, bucket: S) -> SeaweedfsBuilder
+    where
+        S: Into<String>,
+    {
+        SeaweedfsBuilder {
+            filer_url: filer_url.into(),
+            bucket: bucket.into(),
+            tls: TlsConfig::default(),
+            max_idle_per_host: 100,
+            idle_timeout: Some(Duration::from_secs(90)),
+            tcp_keepalive: Some(Duration::from_secs(60)),
+            http2: false,
+            read_only: false,
+        }
+    }
+
+    fn connect(filer_url: String, bucket: String, client: FilerClient) -> Result<SeaweedfsBackend> {
+        let mut filer_url = filer_url;
         if !filer_url.ends_with("/") {
             filer_url += "/";
         }
@@ -79,34 +363,42 @@ impl SeaweedfsBackend {
             root: None,
             uid: 0,
             gid: 0,
-            runtime: tokio::runtime::Runtime::new().unwrap(),
+            runtime: tokio::runtime::Runtime::new()?,
+            streams: Streams::default(),
         };
-        let root_node = s
-            .get_node(bucket.clone())
-            .expect(&format!("get root attibute. root: {}", bucket));
+        let root_node = s.get_node(bucket.clone()).map_err(|e| {
+            log::error!(
+                "{}:{} root {} is not reachable on filer {}. error: {}",
+                std::file!(),
+                std::line!(),
+                bucket,
+                s.filer_url,
+                e
+            );
+            e
+        })?;
         s.root = Some(Node::new(
             ROOT_INODE,
             ROOT_INODE,
             bucket.into(),
             root_node.attr(),
         ));
-        s
+        Ok(s)
     }
 
-    fn escape(&self, key: &str, query_pairs: Option<&[(String, String)]>) -> hyper::Uri {
-        let key = if key.starts_with("/") { &key[1..] } else { key };
-        let u = self.filer_url.clone() + key;
+    fn escape(&self, path: &Path, query_pairs: Option<&[(String, String)]>) -> hyper::Uri {
+        let key = key::path_to_key(path);
+        let u = self.filer_url.clone() + &key::escape_url_path(key.trim_start_matches('/'));
         let mut u: url::Url = url::Url::parse(&u).expect(&format!("parse url: {:?}", u));
         if let Some(query_pairs) = query_pairs {
             u.query_pairs_mut().extend_pairs(query_pairs.into_iter());
         }
-        let u = u.as_str().replace("+", "%20");
         log::debug!("escape u: {}", u);
         u.as_str().parse().unwrap()
     }
 
     fn get(
-        client: Client<HttpConnector, Body>,
+        client: FilerClient,
         request: Request<Body>,
     ) -> impl std::future::Future<Output = Result<Vec<u8>>> + 'static {
         // let client = self.client.clone();
@@ -129,7 +421,7 @@ impl SeaweedfsBackend {
                     String::from_utf8(data)
                 );
                 log::error!("{}", error_message);
-                Err(Error::Backend(error_message))
+                Err(Error::from_http_status(status.as_u16(), error_message))
             } else {
                 Ok(data)
             }
@@ -137,7 +429,7 @@ impl SeaweedfsBackend {
     }
 
     fn get_page(
-        client: Client<HttpConnector, Body>,
+        client: FilerClient,
         request: Request<Body>,
         offset: usize,
         limit: usize,
@@ -146,7 +438,9 @@ impl SeaweedfsBackend {
             let mut request = request;
             request.headers_mut().append(
                 "Range",
-                format!("bytes={}-{}", offset, offset + limit - 1).parse().unwrap(),
+                format!("bytes={}-{}", offset, offset + limit - 1)
+                    .parse()
+                    .unwrap(),
             );
             let data = Self::get(client, request).await?;
             Ok(data)
@@ -159,18 +453,19 @@ impl SeaweedfsBackend {
     ) -> impl std::future::Future<Output = Result<FileAttr>> + 'static {
         let client = self.client.clone();
         let request_uri = std::sync::Arc::new(request.uri().clone().to_string());
+        let uri = request_uri.clone();
         log::debug!("{}:{}", std::file!(), std::line!());
         client
             .request(request)
-            .map(|res| match res {
+            .map(move |res| match res {
                 Ok(res) => {
                     log::debug!("{}:{}", std::file!(), std::line!());
                     let response: Response<Body> = res;
                     if !response.status().is_success() {
-                        return Err(Error::Backend(format!(
-                            "status code: {}",
-                            response.status()
-                        )));
+                        return Err(Error::from_http_status(
+                            response.status().as_u16(),
+                            format!("head {}", uri),
+                        ));
                     }
                     let header = response.headers();
                     log::debug!("{}:{} header: {:?}", std::file!(), std::line!(), header);
@@ -180,12 +475,11 @@ impl SeaweedfsBackend {
                     } else {
                         0u64
                     };
-                    let last_modified = if header.contains_key("Last-Modified") {
-                        let value: &hyper::header::HeaderValue = &header["Last-Modified"];
-                        value.to_str().unwrap_or("0").parse::<usize>().unwrap_or(0)
-                    } else {
-                        0usize
-                    };
+                    let mtime = header
+                        .get("Last-Modified")
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(http_time)
+                        .unwrap_or(UNIX_EPOCH);
                     let is_dir = if header.contains_key("X-Filer-Isdir") {
                         let value: &hyper::header::HeaderValue = &header["X-Filer-Isdir"];
                         value
@@ -196,25 +490,14 @@ impl SeaweedfsBackend {
                     } else {
                         true
                     };
+                    let kind = if is_dir {
+                        FileType::Directory
+                    } else {
+                        FileType::RegularFile
+                    };
                     Ok(FileAttr {
-                        ino: 0,
-                        size,
-                        blocks: 1,
                         atime: std::time::SystemTime::now(),
-                        mtime: UNIX_EPOCH.add(Duration::from_secs(last_modified as u64)),
-                        ctime: UNIX_EPOCH,
-                        crtime: UNIX_EPOCH,
-                        kind: if is_dir {
-                            FileType::Directory
-                        } else {
-                            FileType::RegularFile
-                        },
-                        perm: if is_dir { 0o755 } else { 0o644 } as u16,
-                        nlink: 1,
-                        uid: 0,
-                        gid: 0,
-                        rdev: 0,
-                        flags: 0,
+                        ..attr::synthetic(kind, size, mtime)
                     })
                 }
                 Err(err) => {
@@ -236,15 +519,73 @@ impl SeaweedfsBackend {
     }
 }
 
+impl SeaweedfsBackend {
+    /// Requests the content of `path` from `offset` to the end. Returns `None` if
+    /// the filer does not honor the range.
+    fn open_stream(&self, path: &Path, offset: u64) -> Result<Option<Stream>> {
+        let u = self.escape(path, None);
+        let mut request = Request::get(u).body(Body::empty()).unwrap();
+        request
+            .headers_mut()
+            .append("Range", format!("bytes={}-", offset).parse().unwrap());
+        let client = self.client.clone();
+        let uri = request.uri().to_string();
+        let response: Response<Body> = self.runtime.block_on(client.request(request))?;
+        let status = response.status();
+        let headers = response.headers();
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let version = Version {
+            // `bytes <range>/<size>` of a 206 or a 416, the length of the whole of a 200
+            size: header("Content-Range")
+                .and_then(|range| range.rsplit('/').next())
+                .or_else(|| header("Content-Length"))
+                .and_then(|size| size.parse().ok())
+                .unwrap_or(0),
+            mtime: header("Last-Modified")
+                .and_then(http_time)
+                .and_then(seconds),
+        };
+        if status.as_u16() == 416 {
+            // at or past the end of the file
+            return Ok(Some(Stream {
+                body: Body::empty(),
+                position: offset,
+                buffer: vec![],
+                last_used: Instant::now(),
+                version,
+            }));
+        }
+        if !status.is_success() {
+            let message = format!("get {}, status: {}", uri, status);
+            log::error!("{}", message);
+            return Err(Error::from_http_status(status.as_u16(), message));
+        }
+        if status.as_u16() != 206 && offset > 0 {
+            return Ok(None);
+        }
+        Ok(Some(Stream {
+            body: response.into_body(),
+            position: offset,
+            buffer: vec![],
+            last_used: Instant::now(),
+            version,
+        }))
+    }
+}
+
 impl Backend for SeaweedfsBackend {
     fn root(&self) -> Node {
         self.root.as_ref().unwrap().clone()
     }
 
+    fn check(&self) -> Result<()> {
+        self.get_node(&self.bucket).map(|_| ())
+    }
+
     fn get_children<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<Node>> {
         let query_pairs = [("limit".to_owned(), 100000.to_string())];
         let query_pairs = Some(&query_pairs[..]);
-        let u = self.escape(path.as_ref().to_str().unwrap(), query_pairs);
+        let u = self.escape(path.as_ref(), query_pairs);
         let request = {
             let mut request = Request::get(u).body(Body::empty()).unwrap();
             request
@@ -268,97 +609,72 @@ impl Backend for SeaweedfsBackend {
             return s;
         }
 
-        Ok(response
+        let children: Vec<Node> = response
             .entries
             .iter()
             .map(|entry| {
                 let entry: &Entry = entry;
                 let true_path = trim_prefix(&entry.fullpath, &self.filer_url);
                 let size = entry.chunks.iter().fold(0, |acc, x| acc + x.size);
+                let kind = if entry.chunks.len() == 0 {
+                    FileType::Directory
+                } else {
+                    FileType::RegularFile
+                };
                 Node::new(
                     0,
                     0,
-                    PathBuf::from(true_path),
+                    key::key_to_path(true_path),
                     FileAttr {
-                        ino: 0,
-                        size,
-                        blocks: 1,
                         atime: std::time::SystemTime::now(),
-                        mtime: SystemTime::from(entry.mtime),
-                        ctime: SystemTime::from(entry.crtime),
                         crtime: SystemTime::from(entry.crtime),
-                        kind: if entry.chunks.len() == 0 {
-                            FileType::Directory
-                        } else {
-                            FileType::RegularFile
-                        },
-                        perm: if entry.chunks.len() == 0 {
-                            0o755
-                        } else {
-                            0o644
-                        } as u16,
-                        nlink: 1,
-                        uid: 0,
-                        gid: 0,
-                        rdev: 0,
-                        flags: 0,
+                        ..attr::synthetic(kind, size, SystemTime::from(entry.mtime))
                     },
                 )
             })
-            .collect())
+            .collect();
+        for child in children.iter() {
+            self.streams.check(child);
+        }
+        Ok(children)
     }
 
     fn get_node<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Node> {
-        let u = self.escape(
-            path.as_ref()
-                .to_str()
-                .expect(&format!("parse path to string. {:?}", path)),
-            None,
-        );
+        let u = self.escape(path.as_ref(), None);
         let request = Request::head(u)
             .body(Body::empty())
             .expect(&format!("head {:?}", path.as_ref()));
         log::debug!("befor get attribute");
         // let attr =
         //     futures::executor::block_on(self.get_attibute(request)).expect("block on failed");
-        let attr = self
-            .runtime
-            .block_on(self.get_attibute(request))
-            .expect("block on failed");
+        let attr = self.runtime.block_on(self.get_attibute(request))?;
         log::debug!("after get attribute");
-        Ok(Node::new(0, 0, path.as_ref().to_path_buf(), attr))
-    }
-
-    fn statfs<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Stat> {
-        #[cfg(not(any(target_os = "ios", target_os = "macos",)))]
-        {
-            Ok(Stat {
-                blocks: 1,
-                blocks_free: 1,
-                blocks_available: 1,
-                files: 1,
-                files_free: 1,
-                block_size: 1u32,
-                namelen: 65535,
-                frsize: 4096,
-            })
-        }
-        #[cfg(any(target_os = "ios", target_os = "macos",))]
-        {
-            Ok(Stat {
-                blocks: 1,
-                blocks_free: 1,
-                blocks_available: 1,
-                files: 1,
-                files_free: 1,
-                block_size: 1u32,
-                namelen: 65535,
-                frsize: 4096,
-            })
-        }
+        let node = Node::new(0, 0, path.as_ref().to_path_buf(), attr);
+        self.streams.check(&node);
+        Ok(node)
+    }
+
+    fn get_path<P: AsRef<Path> + Debug>(&self, path: P, depth: usize) -> Result<Option<Vec<Node>>> {
+        // the filer stats full paths, their directories exist if they do
+        let node = self.get_node(path)?;
+        let dir = attr::synthetic(FileType::Directory, 0, node.attr().mtime);
+        Ok(Some(with_ancestors(node, depth, dir)))
+    }
+
+    /// The filer reports no capacity, the filesystem estimates it.
+    fn statfs<P: AsRef<Path> + Debug>(&self, _path: P) -> Result<Stat> {
+        Ok(Stat::unknown(65535))
     }
     fn mknod<P: AsRef<Path> + Debug>(&self, path: P, filetype: FileType, mode: u32) -> Result<()> {
-        unimplemented!()
+        // the filer overwrites on upload and has no conditional create, creating here
+        // could not fail for a file another mount created meanwhile
+        log::error!(
+            "mknod is not supported by the filer. path: {:?}, type: {:?}, mode: {}",
+            path,
+            filetype,
+            mode
+        );
+        Err(Error::Fuse(libc::ENOSYS))
     }
     // fn read<P: AsRef<Path> + Debug>(&self, path: P, offset: u64, size: usize) -> super::ReadFuture {
     //     let u = self.escape(path.as_ref().to_str().unwrap(), None);
@@ -373,16 +689,108 @@ impl Backend for SeaweedfsBackend {
     // }
 
     fn read<P: AsRef<Path> + Debug>(&self, path: P, offset: u64, size: usize) -> Result<Vec<u8>> {
-        let u = self.escape(path.as_ref().to_str().unwrap(), None);
-        let request = Request::get(u).body(Body::empty()).unwrap();
-        let client = self.client.clone();
-        // super::ReadFuture::new(Box::new(Self::get_page(
-        //     client,
-        //     request,
-        //     offset as usize,
-        //     size,
-        // )))
-        self.runtime
-            .block_on(Self::get_page(client, request, offset as usize, size))
+        let mut stream = match self.streams.take(path.as_ref(), offset) {
+            Some(stream) => stream,
+            None => match self.open_stream(path.as_ref(), offset)? {
+                Some(stream) => stream,
+                // the filer ignored the range, fall back to a bounded request
+                None => {
+                    let u = self.escape(path.as_ref(), None);
+                    let request = Request::get(u).body(Body::empty()).unwrap();
+                    let client = self.client.clone();
+                    return self.runtime.block_on(Self::get_page(
+                        client,
+                        request,
+                        offset as usize,
+                        size,
+                    ));
+                }
+            },
+        };
+        let skip = (offset - stream.position) as usize;
+        let wanted = skip + size;
+        let finished = self.runtime.block_on(async {
+            while stream.buffer.len() < wanted {
+                match stream.body.next().await {
+                    Some(chunk) => stream.buffer.extend_from_slice(&chunk?),
+                    None => return Ok(true),
+                }
+            }
+            Ok::<bool, Error>(false)
+        })?;
+        let end = std::cmp::min(wanted, stream.buffer.len());
+        let data = stream.buffer[std::cmp::min(skip, end)..end].to_vec();
+        stream.buffer.drain(..end);
+        stream.position += end as u64;
+        if !finished || !stream.buffer.is_empty() {
+            self.streams.put(path.as_ref(), stream);
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{http_time, Stream, Streams, Version};
+    use crate::ossfs_impl::attr::{self, FileType};
+    use crate::ossfs_impl::node::Node;
+    use hyper::Body;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    fn stream(position: u64, size: u64, mtime: Option<u64>) -> Stream {
+        Stream {
+            body: Body::empty(),
+            position,
+            buffer: vec![],
+            last_used: Instant::now(),
+            version: Version { size, mtime },
+        }
+    }
+
+    fn node(path: &str, size: u64, mtime: u64) -> Node {
+        let mtime = UNIX_EPOCH + Duration::from_secs(mtime);
+        Node::new(
+            0,
+            0,
+            PathBuf::from(path),
+            attr::synthetic(FileType::RegularFile, size, mtime),
+        )
+    }
+
+    #[test]
+    fn test_streams() {
+        let streams = Streams::default();
+        let a = Path::new("/bucket/a");
+        streams.put(a, stream(10, 100, Some(1000)));
+        assert!(streams.take(a, 5).is_none());
+        assert_eq!(streams.take(a, 20).unwrap().position, 10);
+        assert!(streams.take(a, 20).is_none());
+
+        // the same version keeps the stream
+        streams.put(a, stream(10, 100, Some(1000)));
+        streams.check(&node("/bucket/a", 100, 1000));
+        assert!(streams.take(a, 10).is_some());
+
+        // rewritten with the same size
+        streams.put(a, stream(10, 100, Some(1000)));
+        streams.check(&node("/bucket/a", 100, 1060));
+        assert!(streams.take(a, 10).is_none());
+
+        // an unknown time only compares the sizes
+        streams.put(a, stream(10, 100, None));
+        streams.check(&node("/bucket/a", 100, 1060));
+        assert!(streams.take(a, 10).is_some());
+        streams.put(a, stream(10, 100, None));
+        streams.check(&node("/bucket/a", 120, 1060));
+        assert!(streams.take(a, 10).is_none());
+    }
+
+    #[test]
+    fn test_http_time() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_887_151);
+        assert_eq!(http_time("Tue, 15 Nov 1994 08:12:31 GMT"), Some(time));
+        assert_eq!(http_time("784887151"), Some(time));
+        assert_eq!(http_time("yesterday"), None);
     }
 }
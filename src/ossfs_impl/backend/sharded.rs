@@ -0,0 +1,394 @@
+//! A decorator spreading the entries of busy directories over hashed prefixes,
+//! `dir/file` being stored as `dir/ab/cd/file` while the mount shows a flat `dir`.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::attr::FileType;
+use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::node::Node;
+use crate::ossfs_impl::stat::Stat;
+use std::ffi::OsStr;
+use std::fmt::Debug;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct ShardedBackend<B> {
+    inner: B,
+    // sharded directories, as paths of the backend
+    dirs: Vec<PathBuf>,
+    // shard prefixes between a sharded directory and its entries
+    levels: usize,
+}
+
+impl<B: Backend> ShardedBackend<B> {
+    pub fn new(inner: B) -> ShardedBackend<B> {
+        ShardedBackend {
+            inner,
+            dirs: vec![],
+            levels: 2,
+        }
+    }
+
+    /// Shards the entries of the directory at `path`, relative to the root. Entries
+    /// written directly below it before are not listed anymore.
+    pub fn shard<P: AsRef<Path>>(mut self, path: P) -> ShardedBackend<B> {
+        let relative = path
+            .as_ref()
+            .strip_prefix("/")
+            .unwrap_or_else(|_| path.as_ref());
+        self.dirs.push(self.inner.root().path().join(relative));
+        self
+    }
+
+    /// Stores entries `levels` prefixes of two hex digits below their sharded
+    /// directory, 2 by default. Changing it hides the entries written before.
+    /// Backends without `list_below` list a sharded directory a prefix at a time,
+    /// 1 + 256 + 65536 requests with two levels.
+    pub fn with_levels(mut self, levels: usize) -> ShardedBackend<B> {
+        self.levels = std::cmp::max(levels, 1);
+        self
+    }
+
+    // the sharded directory `path` is in, with the part of `path` below it
+    fn split<'a>(&self, path: &'a Path) -> Option<(&Path, &'a Path)> {
+        self.dirs
+            .iter()
+            .find_map(|dir| match path.strip_prefix(dir) {
+                Ok(rest) if rest.components().next().is_some() => Some((dir.as_path(), rest)),
+                _ => None,
+            })
+    }
+
+    // the shard prefixes of the entry `name`
+    fn shards(&self, name: &Path) -> PathBuf {
+        let digest = format!("{:x}", md5::compute(name.to_string_lossy().as_bytes()));
+        (0..self.levels)
+            .map(|level| &digest[level * 2..level * 2 + 2])
+            .collect()
+    }
+
+    /// Where the backend stores `path`.
+    fn stored<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let path = path.as_ref();
+        match self.split(path) {
+            Some((dir, rest)) => {
+                let name = Path::new(rest.components().next().unwrap().as_os_str());
+                dir.join(self.shards(name)).join(rest)
+            }
+            None => path.to_path_buf(),
+        }
+    }
+
+    // the entries of the shards of the sharded directory `dir`, from a single
+    // listing of everything below it when the backend has one
+    fn list_shards(&self, dir: &Path) -> Result<Vec<Node>> {
+        let below = match self.inner.list_below(dir)? {
+            Some(below) => below,
+            None => return self.list_level(dir, 0, dir),
+        };
+        Ok(below
+            .iter()
+            .filter_map(|node| {
+                let path = node.path();
+                let rest = path.strip_prefix(dir).ok()?;
+                let components: Vec<&OsStr> =
+                    rest.components().map(|name| name.as_os_str()).collect();
+                if components.len() != self.levels + 1
+                    || !components[..self.levels]
+                        .iter()
+                        .all(|name| is_shard_name(name))
+                {
+                    return None;
+                }
+                Some(relabel(node, dir.join(components[self.levels])))
+            })
+            .collect())
+    }
+
+    // the entries of the shards below the backend's `prefix`, `depth` levels of
+    // shards down, relabeled as children of `dir`, a listing per shard
+    fn list_level(&self, prefix: &Path, depth: usize, dir: &Path) -> Result<Vec<Node>> {
+        let mut children = vec![];
+        for child in self.inner.get_children(prefix)? {
+            let path = child.path();
+            let name = match path.file_name() {
+                Some(name) => name,
+                None => continue,
+            };
+            if depth == self.levels {
+                children.push(relabel(&child, dir.join(name)));
+            } else if is_shard(&child) {
+                children.extend(self.list_level(&path, depth + 1, dir)?);
+            }
+        }
+        Ok(children)
+    }
+
+    // creates the shard prefixes leading to the stored `path`
+    fn create_shards(&self, path: &Path) -> Result<()> {
+        let (dir, rest) = match self.split(path) {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        let name = Path::new(rest.components().next().unwrap().as_os_str());
+        let mut shard = dir.to_path_buf();
+        for component in self.shards(name).components() {
+            shard.push(component);
+            match self.inner.mknod(&shard, FileType::Directory, 0o755) {
+                Ok(()) | Err(Error::Conflict(_)) => {}
+                Err(ref e) if e.errno(0) == libc::EEXIST => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_shard(node: &Node) -> bool {
+    node.attr().kind == FileType::Directory
+        && node.path().file_name().map(is_shard_name).unwrap_or(false)
+}
+
+fn is_shard_name(name: &OsStr) -> bool {
+    name.to_str()
+        .map(|name| name.len() == 2 && name.chars().all(|c| c.is_ascii_hexdigit()))
+        .unwrap_or(false)
+}
+
+fn relabel(node: &Node, path: PathBuf) -> Node {
+    Node::new(0, 0, path, node.attr()).with_etag(node.etag())
+}
+
+fn is_missing(e: &Error) -> bool {
+    match e {
+        Error::NotFound(_) => true,
+        e => e.errno(0) == libc::ENOENT,
+    }
+}
+
+impl<B: Backend> Backend for ShardedBackend<B> {
+    fn root(&self) -> Node {
+        self.inner.root()
+    }
+
+    fn check(&self) -> Result<()> {
+        self.inner.check()
+    }
+
+    fn probe(&self) -> Result<()> {
+        self.inner.probe()
+    }
+
+    fn get_children<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<Node>> {
+        let path = path.as_ref();
+        if self.dirs.iter().any(|dir| dir == path) {
+            return match self.list_shards(path) {
+                // nothing written yet
+                Err(ref e) if is_missing(e) && self.inner.get_node(path).is_ok() => Ok(vec![]),
+                listed => listed,
+            };
+        }
+        let stored = self.stored(path);
+        if stored == path {
+            return self.inner.get_children(path);
+        }
+        Ok(self
+            .inner
+            .get_children(&stored)?
+            .iter()
+            .filter(|child| !child.name().is_empty())
+            .map(|child| relabel(child, path.join(&*child.name())))
+            .collect())
+    }
+
+    fn get_node<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Node> {
+        let stored = self.stored(&path);
+        let node = self.inner.get_node(&stored)?;
+        if stored == path.as_ref() {
+            return Ok(node);
+        }
+        Ok(relabel(&node, path.as_ref().to_path_buf()))
+    }
+
+    fn statfs<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Stat> {
+        self.inner.statfs(self.stored(path))
+    }
+
+    fn mknod<P: AsRef<Path> + Debug>(&self, path: P, filetype: FileType, mode: u32) -> Result<()> {
+        let stored = self.stored(&path);
+        match self.inner.mknod(&stored, filetype, mode) {
+            Err(ref e) if is_missing(e) && stored != path.as_ref() => {
+                self.create_shards(path.as_ref())?;
+                self.inner.mknod(&stored, filetype, mode)
+            }
+            created => created,
+        }
+    }
+
+    fn read<P: AsRef<Path> + Debug>(&self, path: P, offset: u64, size: usize) -> Result<Vec<u8>> {
+        self.inner.read(self.stored(path), offset, size)
+    }
+
+    fn read_vectored<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        ranges: &[Range<u64>],
+    ) -> Vec<Result<Vec<u8>>> {
+        self.inner.read_vectored(self.stored(path), ranges)
+    }
+
+    fn read_if_match<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        offset: u64,
+        size: usize,
+        etag: &str,
+    ) -> Result<Vec<u8>> {
+        self.inner
+            .read_if_match(self.stored(path), offset, size, etag)
+    }
+
+    fn local_file<P: AsRef<Path> + Debug>(&self, path: P) -> Option<PathBuf> {
+        self.inner.local_file(self.stored(path))
+    }
+
+    fn put<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        self.inner.put(self.stored(path), data)
+    }
+
+    fn put_if_absent<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        self.inner.put_if_absent(self.stored(path), data)
+    }
+
+    fn remove<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        self.inner.remove(self.stored(path))
+    }
+
+    fn remove_many(&self, paths: &[PathBuf]) -> Vec<Result<()>> {
+        let stored: Vec<PathBuf> = paths.iter().map(|path| self.stored(path)).collect();
+        self.inner.remove_many(&stored)
+    }
+
+    fn remove_dir<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        self.inner.remove_dir(self.stored(path))
+    }
+
+    fn copy<P: AsRef<Path> + Debug>(&self, from: P, to: P) -> Result<()> {
+        self.inner.copy(self.stored(from), self.stored(to))
+    }
+
+    fn put_with_class<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+    ) -> Result<()> {
+        self.inner.put_with_class(self.stored(path), data, class)
+    }
+
+    fn put_idempotent<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        self.inner
+            .put_idempotent(self.stored(path), data, class, token, retry)
+    }
+
+    fn put_streamed<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        len: u64,
+        body: &dyn Fn() -> Box<dyn Read + Send>,
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        self.inner
+            .put_streamed(self.stored(path), len, body, class, token, retry)
+    }
+
+    fn set_storage_class<P: AsRef<Path> + Debug>(&self, path: P, class: &str) -> Result<()> {
+        self.inner.set_storage_class(self.stored(path), class)
+    }
+
+    fn get_tags<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<(String, String)>> {
+        self.inner.get_tags(self.stored(path))
+    }
+
+    fn set_tags<P: AsRef<Path> + Debug>(&self, path: P, tags: &[(String, String)]) -> Result<()> {
+        self.inner.set_tags(self.stored(path), tags)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ShardedBackend;
+    use crate::ossfs_impl::attr::FileType;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::backend::Backend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+    use std::ffi::OsStr;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_sharded() {
+        let mock = Arc::new(MockBackend::new());
+        mock.add_dir("/ingest");
+        let backend = ShardedBackend::new(mock.clone()).shard("ingest");
+        assert_eq!(
+            backend.stored("/ingest/file"),
+            Path::new("/ingest/8c/7d/file")
+        );
+        assert_eq!(backend.stored("/other/file"), Path::new("/other/file"));
+
+        let config = Config::default();
+        let fs = FileSystem::new(backend, &config);
+        let ingest = fs.lookup(ROOT_INODE, OsStr::new("ingest")).unwrap().ino;
+        for name in ["a", "b", "file"].iter() {
+            let created = fs
+                .mknod(
+                    ingest,
+                    OsStr::new(name),
+                    FileType::RegularFile,
+                    0o644,
+                    0,
+                    0,
+                    0,
+                )
+                .unwrap();
+            fs.write(created.inode(), 0, name.as_bytes()).unwrap();
+            fs.release(created.inode()).unwrap();
+        }
+        assert_eq!(mock.content("/ingest/8c/7d/file").unwrap(), b"file");
+        assert!(mock.content("/ingest/file").is_none());
+        assert!(mock.get_children("/ingest").unwrap().len() >= 2);
+
+        let fresh = FileSystem::new(ShardedBackend::new(mock.clone()).shard("/ingest"), &config);
+        let ingest = fresh.lookup(ROOT_INODE, OsStr::new("ingest")).unwrap().ino;
+        // a single listing of the whole directory, not one per shard
+        let listed = mock.calls("list_below");
+        let names: Vec<String> = fresh
+            .readdir(ingest, 0, 0)
+            .unwrap()
+            .iter()
+            .map(|node| {
+                node.path()
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        assert_eq!(names, vec!["a", "b", "file"]);
+        assert_eq!(mock.calls("list_below"), listed + 1);
+        let file = fresh.lookup(ingest, OsStr::new("file")).unwrap();
+        assert_eq!(file.size, 4);
+    }
+}
@@ -1,17 +1,15 @@
 use crate::counter::Counter;
 use crate::error::{Error, Result};
+use crate::ossfs_impl::attr;
+use crate::ossfs_impl::attr::{FileAttr, FileType};
 use crate::ossfs_impl::filesystem::ROOT_INODE;
 use crate::ossfs_impl::node::Node;
 use crate::ossfs_impl::stat::Stat;
-use fuse::{FileAttr, FileType};
 use std::fmt::Debug;
 use std::io::Read;
 use std::io::Seek;
-use std::ops::Add;
-use std::os::unix::fs::MetadataExt;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
-use std::time::UNIX_EPOCH;
 
 #[derive(Debug)]
 pub struct SimpleBackend {
@@ -22,52 +20,33 @@ pub struct SimpleBackend {
 
 impl SimpleBackend {
     pub fn new<R>(root: R) -> SimpleBackend
+    where
+        R: Into<String>,
+    {
+        match SimpleBackend::try_new(root) {
+            Ok(backend) => backend,
+            Err(e) => panic!("failed to create simple backend. error: {}", e),
+        }
+    }
+
+    /// Like `new`, but returns an error if `root` is not an accessible directory.
+    pub fn try_new<R>(root: R) -> Result<SimpleBackend>
     where
         R: Into<String>,
     {
         let root = root.into();
-        let meta: std::fs::Metadata = std::fs::metadata(&root).unwrap();
-        SimpleBackend {
+        let meta: std::fs::Metadata = std::fs::metadata(&root)?;
+        if !meta.is_dir() {
+            return Err(Error::Other(format!("root {} is not a directory", root)));
+        }
+        Ok(SimpleBackend {
             root,
             root_attr: FileAttr {
                 ino: ROOT_INODE,
-                /// Size in bytes
-                size: 4096,
-                /// Size in blocks
-                blocks: meta.blocks(),
-                /// Time of last access
-                atime: UNIX_EPOCH
-                    .clone()
-                    .add(Duration::from_secs(meta.atime() as u64)),
-                /// Time of last modification
-                mtime: UNIX_EPOCH
-                    .clone()
-                    .add(Duration::from_secs(meta.mtime() as u64)),
-                /// Time of last change
-                ctime: UNIX_EPOCH
-                    .clone()
-                    .add(Duration::from_secs(meta.ctime() as u64)),
-                /// Time of creation (macOS only)
-                crtime: UNIX_EPOCH
-                    .clone()
-                    .add(Duration::from_secs(meta.atime_nsec() as u64)),
-                /// Kind of file (directory, file, pipe, etc)
-                kind: FileType::Directory,
-                /// Permissions
-                perm: meta.mode() as u16,
-                /// Number of hard links
-                nlink: 2,
-                /// User id
-                uid: meta.uid(),
-                /// Group id
-                gid: meta.gid(),
-                /// Rdev
-                rdev: meta.rdev() as u32,
-                /// Flags (macOS only, see chflags(2))
-                flags: 0,
+                ..attr::from_metadata(&meta)
             },
             counter: Counter::new(1),
-        }
+        })
     }
 }
 
@@ -81,10 +60,15 @@ impl super::Backend for SimpleBackend {
         )
     }
 
+    fn check(&self) -> Result<()> {
+        std::fs::read_dir(&self.root)?;
+        Ok(())
+    }
+
     fn get_children<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<Node>> {
         let list: std::fs::ReadDir = match std::fs::read_dir(path.as_ref()) {
             Ok(dir) => dir,
-            Err(e) => return Err(Error::Backend(format!("{}", e))),
+            Err(e) => return Err(Error::from(e)),
         };
 
         Ok(list
@@ -95,34 +79,7 @@ impl super::Backend for SimpleBackend {
                     0,
                     0,
                     PathBuf::from(entry.path()),
-                    FileAttr {
-                        ino: 0,
-                        size: meta.size(),
-                        blocks: meta.blocks(),
-                        atime: std::time::UNIX_EPOCH
-                            .clone()
-                            .add(std::time::Duration::from_secs(meta.atime() as u64)),
-                        mtime: std::time::UNIX_EPOCH
-                            .clone()
-                            .add(std::time::Duration::from_secs(meta.mtime() as u64)),
-                        ctime: std::time::UNIX_EPOCH
-                            .clone()
-                            .add(std::time::Duration::from_secs(meta.ctime() as u64)),
-                        crtime: std::time::UNIX_EPOCH
-                            .clone()
-                            .add(std::time::Duration::from_secs(meta.atime_nsec() as u64)),
-                        kind: if meta.is_dir() {
-                            FileType::Directory
-                        } else {
-                            FileType::RegularFile
-                        },
-                        perm: meta.mode() as u16,
-                        nlink: meta.nlink() as u32,
-                        uid: meta.uid(),
-                        gid: meta.gid(),
-                        rdev: meta.rdev() as u32,
-                        flags: 0,
-                    },
+                    attr::from_metadata(&meta),
                 )
             })
             .collect::<Vec<Node>>())
@@ -134,34 +91,7 @@ impl super::Backend for SimpleBackend {
             0,
             0,
             path.as_ref().to_path_buf(),
-            FileAttr {
-                ino: 0,
-                size: meta.size(),
-                blocks: meta.blocks(),
-                atime: std::time::UNIX_EPOCH
-                    .clone()
-                    .add(std::time::Duration::from_secs(meta.atime() as u64)),
-                mtime: std::time::UNIX_EPOCH
-                    .clone()
-                    .add(std::time::Duration::from_secs(meta.mtime() as u64)),
-                ctime: std::time::UNIX_EPOCH
-                    .clone()
-                    .add(std::time::Duration::from_secs(meta.ctime() as u64)),
-                crtime: std::time::UNIX_EPOCH
-                    .clone()
-                    .add(std::time::Duration::from_secs(meta.atime_nsec() as u64)),
-                kind: if meta.is_dir() {
-                    FileType::Directory
-                } else {
-                    FileType::RegularFile
-                },
-                perm: meta.mode() as u16,
-                nlink: meta.nlink() as u32,
-                uid: meta.uid(),
-                gid: meta.gid(),
-                rdev: meta.rdev() as u32,
-                flags: 0,
-            },
+            attr::from_metadata(&meta),
         ))
     }
 
@@ -179,6 +109,7 @@ impl super::Backend for SimpleBackend {
                         block_size: stat.block_size() as u32,
                         namelen: stat.maximum_name_length() as u32,
                         frsize: 4096,
+                        known: true,
                     }
                 }
                 #[cfg(any(target_os = "ios", target_os = "macos",))]
@@ -192,6 +123,7 @@ impl super::Backend for SimpleBackend {
                         block_size: stat.block_size(),
                         namelen: 65535,
                         frsize: 4096,
+                        known: true,
                     }
                 }
             })
@@ -204,30 +136,26 @@ impl super::Backend for SimpleBackend {
     fn mknod<P: AsRef<Path> + Debug>(&self, path: P, filetype: FileType, mode: u32) -> Result<()> {
         Ok(match filetype {
             FileType::Directory => {
-                std::fs::create_dir_all(path.as_ref())?;
-                #[cfg(any(target_os = "unix", target_os = "macos"))]
+                std::fs::create_dir(path.as_ref())?;
+                #[cfg(unix)]
                 {
                     use std::os::unix::fs::PermissionsExt;
-                    let perm = std::fs::Permissions::from_mode(mode);
+                    let perm = std::fs::Permissions::from_mode(mode & 0o7777);
                     std::fs::set_permissions(path.as_ref(), perm)?;
                 }
-                #[cfg(any(target_os = "macos"))]
-                {
-                    // let meta = std::fs::metadata(path.as_ref())?;
-                }
             }
             FileType::RegularFile => {
-                let _ = std::fs::File::create(path.as_ref())?;
-                #[cfg(any(target_os = "unix", target_os = "macos"))]
+                // fails with EEXIST like the object stores do, never truncates
+                let _ = std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(path.as_ref())?;
+                #[cfg(unix)]
                 {
                     use std::os::unix::fs::PermissionsExt;
-                    let perm = std::fs::Permissions::from_mode(mode);
+                    let perm = std::fs::Permissions::from_mode(mode & 0o7777);
                     std::fs::set_permissions(path.as_ref(), perm)?;
                 }
-                #[cfg(any(target_os = "macos"))]
-                {
-                    // let meta = std::fs::metadata(path.as_ref())?;
-                }
             }
             _ => log::error!(
                 "unknown filetype. path: {:?}, type: {:?}, mode: {}",
@@ -249,6 +177,54 @@ impl super::Backend for SimpleBackend {
 
         self.synchronized_read_from_file(path, offset, size)
     }
+
+    // the paths of the nodes are those of the files below the root already
+    fn local_file<P: AsRef<Path> + Debug>(&self, path: P) -> Option<PathBuf> {
+        Some(path.as_ref().to_path_buf())
+    }
+
+    fn put<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        let _start = self.counter.start("backend::put".to_owned());
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path.as_ref(), data)?;
+        Ok(())
+    }
+
+    fn put_if_absent<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        let _start = self.counter.start("backend::put_if_absent".to_owned());
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path.as_ref())?;
+        file.write_all(data)?;
+        Ok(())
+    }
+
+    fn remove<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        let _start = self.counter.start("backend::remove".to_owned());
+        std::fs::remove_file(path.as_ref())?;
+        Ok(())
+    }
+
+    fn remove_dir<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        let _start = self.counter.start("backend::remove_dir".to_owned());
+        std::fs::remove_dir(path.as_ref())?;
+        Ok(())
+    }
+
+    fn copy<P: AsRef<Path> + Debug>(&self, from: P, to: P) -> Result<()> {
+        let _start = self.counter.start("backend::copy".to_owned());
+        if let Some(parent) = to.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(from.as_ref(), to.as_ref())?;
+        Ok(())
+    }
 }
 
 impl SimpleBackend {
@@ -340,24 +316,11 @@ impl SimpleBackend {
             let len = metadata.len();
             len
         };
-        if offset == len {
+        // the file may have been truncated by another writer, reading past its end
+        // is a short read rather than an error
+        if offset >= len {
             return Ok(vec![]);
         }
-        if offset > len {
-            log::error!(
-                "{}:{} path: {}, len: {}, offset: {}, size: {}",
-                std::file!(),
-                std::line!(),
-                path,
-                len,
-                offset,
-                size
-            );
-            return Err(Error::Backend(format!(
-                "path: {}, len: {}, offset: {}, size: {}",
-                path, len, offset, size
-            )));
-        }
         let size = if offset + size as u64 > len {
             len - offset
         } else {
@@ -368,9 +331,9 @@ impl SimpleBackend {
             return Err(Error::from(err));
         }
 
-        let mut buffer: Vec<u8> = vec![0u8; size];
+        let mut buffer: Vec<u8> = Vec::with_capacity(size);
 
-        match file.read_exact(&mut buffer) {
+        match file.take(size as u64).read_to_end(&mut buffer) {
             Ok(_) => Ok(buffer),
             Err(err) => Err(Error::from(err)),
         }
@@ -0,0 +1,137 @@
+//! Temporary keys from the AWS security token service, or a compatible one like the
+//! STS of MinIO, for `Credentials::WebIdentity` and `Credentials::AssumeRole`.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::backend::credentials::Keys;
+use futures01::Future;
+use rusoto_core::credential::StaticProvider;
+use rusoto_core::request::{HttpClient, HttpResponse};
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::{Client, Region, RusotoError};
+use std::time::Duration;
+
+pub const ENDPOINT: &str = "https://sts.amazonaws.com";
+
+const VERSION: &str = "2011-06-15";
+// names the sessions in the audit logs of the store
+const SESSION_NAME: &str = "ossfs";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn assume_role_with_web_identity(endpoint: &str, role_arn: &str, token: &str) -> Result<Keys> {
+    call(
+        endpoint,
+        &[
+            ("Action", "AssumeRoleWithWebIdentity"),
+            ("RoleArn", role_arn),
+            ("RoleSessionName", SESSION_NAME),
+            ("WebIdentityToken", token),
+            ("Version", VERSION),
+        ],
+        None,
+    )
+}
+
+pub fn assume_role(endpoint: &str, role_arn: &str, keys: &Keys) -> Result<Keys> {
+    call(
+        endpoint,
+        &[
+            ("Action", "AssumeRole"),
+            ("RoleArn", role_arn),
+            ("RoleSessionName", SESSION_NAME),
+            ("Version", VERSION),
+        ],
+        Some(keys),
+    )
+}
+
+// posts the form `params`, signed with `signer` unless the action needs no keys
+fn call(endpoint: &str, params: &[(&str, &str)], signer: Option<&Keys>) -> Result<Keys> {
+    let region = Region::Custom {
+        name: "us-east-1".to_owned(),
+        endpoint: endpoint.to_owned(),
+    };
+    let mut request = SignedRequest::new("POST", "sts", &region, "/");
+    let form = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    request.add_header("Content-Type", "application/x-www-form-urlencoded");
+    request.set_payload(Some(form.into_bytes()));
+    let dispatcher = HttpClient::new()
+        .map_err(|e| Error::Other(format!("failed to create request dispatcher. {}", e)))?;
+    let client = match signer {
+        Some(keys) => Client::new_with(
+            StaticProvider::new(
+                keys.access_key.clone(),
+                keys.secret_key.clone(),
+                keys.session_token.clone(),
+                None,
+            ),
+            dispatcher,
+        ),
+        None => Client::new_not_signing(dispatcher),
+    };
+    // failures are not parsed into service errors, `io::Error` only fills the type
+    let body = client
+        .sign_and_dispatch(request, |response: HttpResponse| {
+            Box::new(response.buffer().from_err().and_then(|response| {
+                if response.status.is_success() {
+                    Ok(response.body.to_vec())
+                } else {
+                    Err(RusotoError::<std::io::Error>::Unknown(response))
+                }
+            }))
+        })
+        .with_timeout(TIMEOUT)
+        .sync()?;
+    parse(&String::from_utf8_lossy(&body))
+}
+
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", name))?;
+    Some(xml[start..end].trim())
+}
+
+fn parse(xml: &str) -> Result<Keys> {
+    let required = |name: &str| {
+        element(xml, name)
+            .map(|value| value.to_owned())
+            .ok_or_else(|| Error::Backend(format!("sts response misses {}", name)))
+    };
+    Ok(Keys {
+        access_key: required("AccessKeyId")?,
+        secret_key: required("SecretAccessKey")?,
+        session_token: element(xml, "SessionToken").map(|token| token.to_owned()),
+        expiration: element(xml, "Expiration")
+            .and_then(|expiration| chrono::DateTime::parse_from_rfc3339(expiration).ok())
+            .map(|expiration| expiration.with_timezone(&chrono::Utc)),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+
+    #[test]
+    fn test_parse() {
+        let keys = parse(
+            "<AssumeRoleWithWebIdentityResponse><AssumeRoleWithWebIdentityResult>
+              <Credentials>
+                <SessionToken>token</SessionToken>
+                <SecretAccessKey>secret</SecretAccessKey>
+                <Expiration>2026-10-17T08:00:00Z</Expiration>
+                <AccessKeyId>ASIA</AccessKeyId>
+              </Credentials>
+            </AssumeRoleWithWebIdentityResult></AssumeRoleWithWebIdentityResponse>",
+        )
+        .unwrap();
+        assert_eq!(keys.access_key, "ASIA");
+        assert_eq!(keys.secret_key, "secret");
+        assert_eq!(keys.session_token, Some("token".to_owned()));
+        assert_eq!(
+            keys.expiration.unwrap().to_rfc3339(),
+            "2026-10-17T08:00:00+00:00"
+        );
+        assert!(parse("<Error><Code>AccessDenied</Code></Error>").is_err());
+    }
+}
@@ -0,0 +1,776 @@
+//! A decorator serving reads from a fast tier, like a `SimpleBackend` on a local
+//! nvme disk, in front of a slow object tier.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::attr::{FileAttr, FileType};
+use crate::ossfs_impl::backend::{Backend, COPY_CHUNK};
+use crate::ossfs_impl::node::Node;
+use crate::ossfs_impl::stat::{Stat, Usage};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// interval between writes of the index of a changed fast tier
+const INDEX_INTERVAL: Duration = Duration::from_secs(10);
+
+// header: magic, number of files, clock. a record per file: size, mtime seconds,
+// last read, path offset, path length (u32), mtime nanoseconds (u32). then the
+// utf-8 paths, every number little endian
+const INDEX_MAGIC: &[u8; 8] = b"OSSFSTI1";
+const INDEX_HEADER: usize = 24;
+const INDEX_RECORD: usize = 40;
+
+#[derive(Debug)]
+struct Promoted {
+    size: u64,
+    // of the file in the slow tier when it was promoted
+    mtime: SystemTime,
+    // value of `Tier::clock` at the last read
+    last_read: u64,
+}
+
+#[derive(Debug, Default)]
+struct Tier {
+    // by path of the slow tier
+    files: HashMap<PathBuf, Promoted>,
+    // bytes of the promoted files
+    used: u64,
+    // counts the reads, orders the promoted files by recency
+    clock: u64,
+    // the fast tier may differ from the index on disk, which is marked stale
+    stale: bool,
+    // changes of the fast tier begun, and those in progress
+    changes: u64,
+    changing: usize,
+}
+
+impl Tier {
+    // drops the least recently read files until `capacity` holds the others
+    fn overflow(&mut self, capacity: u64) -> Vec<PathBuf> {
+        let mut demoted = vec![];
+        while self.used > capacity {
+            let coldest = match self.files.iter().min_by_key(|(_, file)| file.last_read) {
+                Some((coldest, _)) => coldest.clone(),
+                None => break,
+            };
+            let file = self.files.remove(&coldest).unwrap();
+            self.used -= file.size;
+            demoted.push(coldest);
+        }
+        demoted
+    }
+}
+
+#[derive(Debug)]
+pub struct TieredBackend<F, S> {
+    fast: F,
+    slow: S,
+    fast_root: PathBuf,
+    slow_root: PathBuf,
+    // bytes of promoted files the fast tier holds at most
+    capacity: u64,
+    tier: Arc<Mutex<Tier>>,
+    // where the promoted files are listed, `None` forgets them when dropped
+    index: Option<PathBuf>,
+}
+
+// a change of the fast tier in progress, the index is stale at least until it ends
+struct Change<'a>(&'a Mutex<Tier>);
+
+impl<'a> Drop for Change<'a> {
+    fn drop(&mut self) {
+        let mut tier = self.0.lock().unwrap();
+        tier.changing -= 1;
+        tier.changes += 1;
+    }
+}
+
+impl<F: Backend, S: Backend> TieredBackend<F, S> {
+    /// Promotes the files read from `slow` to `fast`, keeping at most `capacity`
+    /// bytes there. Files larger than `capacity` are always read from `slow`.
+    pub fn new(fast: F, slow: S, capacity: u64) -> TieredBackend<F, S> {
+        TieredBackend {
+            fast_root: fast.root().path(),
+            slow_root: slow.root().path(),
+            fast,
+            slow,
+            capacity,
+            tier: Arc::new(Mutex::new(Tier::default())),
+            index: None,
+        }
+    }
+
+    /// Keeps the list of the promoted files in the index at `path`, so that they
+    /// are served again after a restart. The index is written every
+    /// `INDEX_INTERVAL` while the fast tier changes, and marked stale meanwhile: a
+    /// missing or stale index is rebuilt from the files of the fast tier.
+    pub fn with_index<P: Into<PathBuf>>(mut self, path: P) -> TieredBackend<F, S> {
+        let path = path.into();
+        let loaded = if stale_marker(&path).exists() {
+            Err(Error::Other("it is marked stale".to_owned()))
+        } else {
+            std::fs::read(&path)
+                .map_err(Error::from)
+                .and_then(|data| decode_index(&data))
+        };
+        let mut tier = match loaded {
+            Ok(tier) => tier,
+            Err(e) => {
+                log::warn!("rebuilding the index {:?} from the fast tier. {}", path, e);
+                let mut tier = self.scan();
+                // the rebuilt index replaces the one on disk right away
+                tier.stale = true;
+                tier.changes = 1;
+                tier
+            }
+        };
+        let demoted = tier.overflow(self.capacity);
+        log::info!(
+            "serving {} files of {} bytes from the fast tier, index {:?}",
+            tier.files.len(),
+            tier.used,
+            path
+        );
+        self.tier = Arc::new(Mutex::new(tier));
+        self.index = Some(path.clone());
+        {
+            let _change = self.begin_change();
+            for demoted in demoted {
+                self.discard(&demoted);
+            }
+        }
+        save_index(&self.tier, &path, false);
+        spawn_index_writer(Arc::downgrade(&self.tier), path);
+        self
+    }
+
+    // the promoted files of an index rebuilt from the fast tier: its copies of the
+    // files the slow tier has in the same size and did not change since
+    fn scan(&self) -> Tier {
+        let mut tier = Tier::default();
+        let mut directories = vec![self.fast_root.clone()];
+        while let Some(directory) = directories.pop() {
+            let children = match self.fast.get_children(&directory) {
+                Ok(children) => children,
+                Err(e) => {
+                    log::warn!("failed to list {:?} of the fast tier. {}", directory, e);
+                    continue;
+                }
+            };
+            for child in children {
+                let fast_path = child.path();
+                let copy = child.attr();
+                if copy.kind == FileType::Directory {
+                    directories.push(fast_path);
+                    continue;
+                }
+                let relative = fast_path
+                    .strip_prefix(&self.fast_root)
+                    .unwrap_or(&fast_path);
+                let relative = relative.strip_prefix("/").unwrap_or(relative);
+                let path = self.slow_root.join(relative);
+                let mtime = match self.slow.get_node(&path) {
+                    Ok(node) => {
+                        let attr = node.attr();
+                        let same = attr.kind == FileType::RegularFile
+                            && attr.size == copy.size
+                            && attr.mtime <= copy.mtime
+                            && indexable(&path, attr.mtime);
+                        if same {
+                            Some(attr.mtime)
+                        } else {
+                            None
+                        }
+                    }
+                    Err(_) => None,
+                };
+                let mtime = match mtime {
+                    Some(mtime) => mtime,
+                    None => {
+                        if let Err(e) = self.fast.remove(&fast_path) {
+                            log::warn!(
+                                "failed to remove {:?} from the fast tier. {}",
+                                fast_path,
+                                e
+                            );
+                        }
+                        continue;
+                    }
+                };
+                tier.used += copy.size;
+                tier.files.insert(
+                    path,
+                    Promoted {
+                        size: copy.size,
+                        mtime,
+                        last_read: 0,
+                    },
+                );
+            }
+        }
+        tier
+    }
+
+    // marks the index stale before the fast tier changes
+    fn begin_change(&self) -> Change<'_> {
+        let mut tier = self.tier.lock().unwrap();
+        if let Some(ref index) = self.index {
+            if !tier.stale {
+                if let Err(e) = std::fs::write(stale_marker(index), b"") {
+                    log::error!("failed to mark the index {:?} stale. {}", index, e);
+                }
+                tier.stale = true;
+            }
+        }
+        tier.changing += 1;
+        Change(&self.tier)
+    }
+
+    /// Bytes of the files promoted to the fast tier.
+    pub fn used(&self) -> u64 {
+        self.tier.lock().unwrap().used
+    }
+
+    /// Where the fast tier keeps the file at `path` of the slow tier.
+    fn fast_path(&self, path: &Path) -> PathBuf {
+        let relative = path.strip_prefix(&self.slow_root).unwrap_or(path);
+        let relative = relative.strip_prefix("/").unwrap_or(relative);
+        self.fast_root.join(relative)
+    }
+
+    // whether `path` is promoted, counting a read of it if it is
+    fn touch(&self, path: &Path) -> bool {
+        let mut tier = self.tier.lock().unwrap();
+        tier.clock += 1;
+        let clock = tier.clock;
+        match tier.files.get_mut(path) {
+            Some(promoted) => {
+                promoted.last_read = clock;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Copies the file at `path` to the fast tier. `head` is the start of its
+    /// content, read already, which saves reading small files twice.
+    fn promote(&self, path: &Path, head: &[u8]) -> Result<()> {
+        let attr = self.slow.get_node(path)?.attr();
+        if attr.kind != FileType::RegularFile || attr.size > self.capacity {
+            return Ok(());
+        }
+        // the index could not list it
+        if self.index.is_some() && !indexable(path, attr.mtime) {
+            return Ok(());
+        }
+        let mut data = head.to_vec();
+        while (data.len() as u64) < attr.size {
+            let chunk = self.slow.read(path, data.len() as u64, COPY_CHUNK)?;
+            if chunk.is_empty() {
+                break;
+            }
+            data.extend_from_slice(&chunk);
+        }
+        let _change = self.begin_change();
+        self.fast.put(self.fast_path(path), &data)?;
+        let demoted = {
+            let mut tier = self.tier.lock().unwrap();
+            tier.clock += 1;
+            let promoted = Promoted {
+                size: data.len() as u64,
+                mtime: attr.mtime,
+                last_read: tier.clock,
+            };
+            tier.used += promoted.size;
+            if let Some(replaced) = tier.files.insert(path.to_path_buf(), promoted) {
+                tier.used -= replaced.size;
+            }
+            tier.overflow(self.capacity)
+        };
+        log::debug!("promoted {:?}, demoted {:?}", path, demoted);
+        for path in demoted {
+            self.discard(&path);
+        }
+        Ok(())
+    }
+
+    /// Drops the files at `path` and below it from the fast tier.
+    fn demote(&self, path: &Path) {
+        let promoted = self
+            .tier
+            .lock()
+            .unwrap()
+            .files
+            .keys()
+            .any(|promoted| promoted.starts_with(path));
+        if !promoted {
+            return;
+        }
+        let _change = self.begin_change();
+        let demoted: Vec<PathBuf> = {
+            let mut tier = self.tier.lock().unwrap();
+            let demoted: Vec<PathBuf> = tier
+                .files
+                .keys()
+                .filter(|promoted| promoted.starts_with(path))
+                .cloned()
+                .collect();
+            for promoted in demoted.iter() {
+                let file = tier.files.remove(promoted).unwrap();
+                tier.used -= file.size;
+            }
+            demoted
+        };
+        for path in demoted {
+            self.discard(&path);
+        }
+    }
+
+    fn discard(&self, path: &Path) {
+        if let Err(e) = self.fast.remove(self.fast_path(path)) {
+            log::warn!("failed to remove {:?} from the fast tier. {}", path, e);
+        }
+    }
+
+    // demotes the file at `path` if the slow tier holds another version of it
+    fn check_fresh(&self, path: &Path, attr: &FileAttr) {
+        let stale = match self.tier.lock().unwrap().files.get(path) {
+            Some(promoted) => promoted.size != attr.size || promoted.mtime != attr.mtime,
+            None => false,
+        };
+        if stale {
+            log::debug!("{:?} changed in the slow tier, demoting it", path);
+            self.demote(path);
+        }
+    }
+}
+
+impl<F, S> Drop for TieredBackend<F, S> {
+    fn drop(&mut self) {
+        if let Some(ref index) = self.index {
+            // the recency of the files too
+            save_index(&self.tier, index, true);
+        }
+    }
+}
+
+// `path` with `suffix` appended to its file name
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// exists while the fast tier may hold files the index at `index` does not list
+fn stale_marker(index: &Path) -> PathBuf {
+    sibling(index, ".stale")
+}
+
+// whether the index can list the file at `path`
+fn indexable(path: &Path, mtime: SystemTime) -> bool {
+    path.to_str().is_some() && mtime.duration_since(UNIX_EPOCH).is_ok()
+}
+
+// writes the index of a stale tier, or of any with `always`, and drops the stale
+// mark unless the fast tier changed meanwhile
+fn save_index(tier: &Mutex<Tier>, index: &Path, always: bool) {
+    let (data, changes) = {
+        let tier = tier.lock().unwrap();
+        if !tier.stale && !always {
+            return;
+        }
+        (encode_index(&tier), tier.changes)
+    };
+    // renamed into place, the previous index stays whole until it is replaced
+    let temporary = sibling(index, ".tmp");
+    let result = std::fs::write(&temporary, &data).and_then(|_| std::fs::rename(&temporary, index));
+    if let Err(e) = result {
+        log::error!("failed to write the index {:?}. {}", index, e);
+        return;
+    }
+    let mut tier = tier.lock().unwrap();
+    if tier.changes != changes || tier.changing > 0 {
+        return;
+    }
+    match std::fs::remove_file(stale_marker(index)) {
+        Ok(()) => tier.stale = false,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => tier.stale = false,
+        Err(e) => log::error!("failed to mark the index {:?} fresh. {}", index, e),
+    }
+}
+
+// writes the index every `INDEX_INTERVAL` until the backend is dropped
+fn spawn_index_writer(tier: Weak<Mutex<Tier>>, index: PathBuf) {
+    let spawned = std::thread::Builder::new()
+        .name("ossfs-tier-index".to_owned())
+        .spawn(move || loop {
+            std::thread::sleep(INDEX_INTERVAL);
+            match tier.upgrade() {
+                Some(tier) => save_index(&tier, &index, false),
+                None => return,
+            }
+        });
+    if let Err(e) = spawned {
+        log::error!("failed to spawn the index writer. error: {}", e);
+    }
+}
+
+fn encode_index(tier: &Tier) -> Vec<u8> {
+    // files `indexable` refuses are not promoted with an index
+    let files: Vec<(&str, &Promoted, Duration)> = tier
+        .files
+        .iter()
+        .filter_map(|(path, promoted)| {
+            let mtime = promoted.mtime.duration_since(UNIX_EPOCH).ok()?;
+            Some((path.to_str()?, promoted, mtime))
+        })
+        .collect();
+    let mut data = Vec::with_capacity(INDEX_HEADER + files.len() * INDEX_RECORD);
+    data.extend_from_slice(INDEX_MAGIC);
+    data.extend_from_slice(&(files.len() as u64).to_le_bytes());
+    data.extend_from_slice(&tier.clock.to_le_bytes());
+    let mut offset = 0u64;
+    for (path, promoted, mtime) in files.iter() {
+        data.extend_from_slice(&promoted.size.to_le_bytes());
+        data.extend_from_slice(&mtime.as_secs().to_le_bytes());
+        data.extend_from_slice(&promoted.last_read.to_le_bytes());
+        data.extend_from_slice(&offset.to_le_bytes());
+        data.extend_from_slice(&(path.len() as u32).to_le_bytes());
+        data.extend_from_slice(&mtime.subsec_nanos().to_le_bytes());
+        offset += path.len() as u64;
+    }
+    for (path, _, _) in files.iter() {
+        data.extend_from_slice(path.as_bytes());
+    }
+    data
+}
+
+fn decode_index(data: &[u8]) -> Result<Tier> {
+    let invalid = |message: &str| Error::Other(format!("invalid index, {}", message));
+    let u64_at = |at: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&data[at..at + 8]);
+        u64::from_le_bytes(bytes)
+    };
+    let u32_at = |at: usize| {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&data[at..at + 4]);
+        u32::from_le_bytes(bytes)
+    };
+    if data.len() < INDEX_HEADER || &data[..8] != INDEX_MAGIC {
+        return Err(invalid("bad header"));
+    }
+    let count = u64_at(8) as usize;
+    let paths = count
+        .checked_mul(INDEX_RECORD)
+        .and_then(|records| records.checked_add(INDEX_HEADER))
+        .filter(|paths| *paths <= data.len())
+        .ok_or_else(|| invalid("truncated records"))?;
+    let mut tier = Tier {
+        clock: u64_at(16),
+        ..Tier::default()
+    };
+    for index in 0..count {
+        let record = INDEX_HEADER + index * INDEX_RECORD;
+        let start = u64_at(record + 24).saturating_add(paths as u64);
+        let end = start.saturating_add(u64::from(u32_at(record + 32)));
+        if end > data.len() as u64 {
+            return Err(invalid("truncated paths"));
+        }
+        let path = std::str::from_utf8(&data[start as usize..end as usize])
+            .map_err(|_| invalid("path is not utf-8"))?;
+        let promoted = Promoted {
+            size: u64_at(record),
+            mtime: UNIX_EPOCH + Duration::new(u64_at(record + 8), u32_at(record + 36)),
+            last_read: u64_at(record + 16),
+        };
+        tier.used += promoted.size;
+        tier.files.insert(PathBuf::from(path), promoted);
+    }
+    Ok(tier)
+}
+
+impl<F: Backend, S: Backend> Backend for TieredBackend<F, S> {
+    fn root(&self) -> Node {
+        self.slow.root()
+    }
+
+    fn check(&self) -> Result<()> {
+        self.fast.check()?;
+        self.slow.check()
+    }
+
+    fn probe(&self) -> Result<()> {
+        self.fast.probe()?;
+        self.slow.probe()
+    }
+
+    fn get_children<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<Node>> {
+        let children = self.slow.get_children(path)?;
+        for child in children.iter() {
+            self.check_fresh(&child.path(), &child.attr());
+        }
+        Ok(children)
+    }
+
+    fn for_each_children_page<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        page: &mut dyn FnMut(Vec<Node>),
+    ) -> Result<()> {
+        self.slow.for_each_children_page(path, &mut |children| {
+            for child in children.iter() {
+                self.check_fresh(&child.path(), &child.attr());
+            }
+            page(children)
+        })
+    }
+
+    fn get_node<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Node> {
+        let node = self.slow.get_node(&path)?;
+        self.check_fresh(path.as_ref(), &node.attr());
+        Ok(node)
+    }
+
+    fn get_path<P: AsRef<Path> + Debug>(&self, path: P, depth: usize) -> Result<Option<Vec<Node>>> {
+        let nodes = self.slow.get_path(path, depth)?;
+        for node in nodes.iter().flatten() {
+            self.check_fresh(&node.path(), &node.attr());
+        }
+        Ok(nodes)
+    }
+
+    fn list_below<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Option<Vec<Node>>> {
+        let nodes = self.slow.list_below(path)?;
+        for node in nodes.iter().flatten() {
+            self.check_fresh(&node.path(), &node.attr());
+        }
+        Ok(nodes)
+    }
+
+    fn statfs<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Stat> {
+        self.slow.statfs(path)
+    }
+
+    fn usage<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Usage> {
+        self.slow.usage(path)
+    }
+
+    fn mknod<P: AsRef<Path> + Debug>(&self, path: P, filetype: FileType, mode: u32) -> Result<()> {
+        self.slow.mknod(path, filetype, mode)
+    }
+
+    fn read<P: AsRef<Path> + Debug>(&self, path: P, offset: u64, size: usize) -> Result<Vec<u8>> {
+        let path = path.as_ref();
+        if self.touch(path) {
+            match self.fast.read(self.fast_path(path), offset, size) {
+                Ok(data) => return Ok(data),
+                Err(e) => {
+                    log::warn!("failed to read {:?} from the fast tier. {}", path, e);
+                    self.demote(path);
+                }
+            }
+        }
+        let data = self.slow.read(path, offset, size)?;
+        let head: &[u8] = if offset == 0 { &data } else { &[] };
+        if let Err(e) = self.promote(path, head) {
+            log::warn!("failed to promote {:?} to the fast tier. {}", path, e);
+        }
+        Ok(data)
+    }
+
+    /// The fast tier does not keep etags, conditional reads go to the slow one.
+    fn read_if_match<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        offset: u64,
+        size: usize,
+        etag: &str,
+    ) -> Result<Vec<u8>> {
+        self.slow.read_if_match(path, offset, size, etag)
+    }
+
+    fn put<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        self.demote(path.as_ref());
+        self.slow.put(path, data)
+    }
+
+    fn put_if_absent<P: AsRef<Path> + Debug>(&self, path: P, data: &[u8]) -> Result<()> {
+        self.slow.put_if_absent(path, data)
+    }
+
+    fn remove<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        self.demote(path.as_ref());
+        self.slow.remove(path)
+    }
+
+    fn remove_many(&self, paths: &[PathBuf]) -> Vec<Result<()>> {
+        for path in paths {
+            self.demote(path);
+        }
+        self.slow.remove_many(paths)
+    }
+
+    fn remove_dir<P: AsRef<Path> + Debug>(&self, path: P) -> Result<()> {
+        self.slow.remove_dir(path)
+    }
+
+    fn remove_all<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        progress: &mut dyn FnMut(u64),
+    ) -> Result<u64> {
+        self.demote(path.as_ref());
+        self.slow.remove_all(path, progress)
+    }
+
+    fn copy<P: AsRef<Path> + Debug>(&self, from: P, to: P) -> Result<()> {
+        self.demote(to.as_ref());
+        self.slow.copy(from, to)
+    }
+
+    fn put_with_class<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+    ) -> Result<()> {
+        self.demote(path.as_ref());
+        self.slow.put_with_class(path, data, class)
+    }
+
+    fn put_idempotent<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        data: &[u8],
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        self.demote(path.as_ref());
+        self.slow.put_idempotent(path, data, class, token, retry)
+    }
+
+    fn put_streamed<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        len: u64,
+        body: &dyn Fn() -> Box<dyn Read + Send>,
+        class: Option<&str>,
+        token: &str,
+        retry: bool,
+    ) -> Result<()> {
+        self.demote(path.as_ref());
+        self.slow.put_streamed(path, len, body, class, token, retry)
+    }
+
+    fn set_storage_class<P: AsRef<Path> + Debug>(&self, path: P, class: &str) -> Result<()> {
+        self.slow.set_storage_class(path, class)
+    }
+
+    fn get_tags<P: AsRef<Path> + Debug>(&self, path: P) -> Result<Vec<(String, String)>> {
+        self.slow.get_tags(path)
+    }
+
+    fn set_tags<P: AsRef<Path> + Debug>(&self, path: P, tags: &[(String, String)]) -> Result<()> {
+        self.slow.set_tags(path, tags)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TieredBackend;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::backend::Backend;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_tiered() {
+        let fast = Arc::new(MockBackend::new());
+        let slow = Arc::new(MockBackend::new());
+        slow.add_file("/dir/a", b"hello");
+        slow.add_file("/b", b"four");
+        let backend = TieredBackend::new(fast.clone(), slow.clone(), 8);
+
+        assert_eq!(backend.read("/dir/a", 1, 3).unwrap(), b"ell");
+        assert_eq!(fast.content("/dir/a").unwrap(), b"hello");
+        let reads = slow.calls("read");
+        assert_eq!(backend.read("/dir/a", 0, 16).unwrap(), b"hello");
+        assert_eq!(slow.calls("read"), reads);
+        assert_eq!(backend.used(), 5);
+
+        // the least recently read file makes room
+        assert_eq!(backend.read("/b", 0, 16).unwrap(), b"four");
+        assert_eq!(slow.calls("read"), reads + 1);
+        assert!(fast.content("/dir/a").is_none());
+        assert_eq!(backend.used(), 4);
+
+        // changed by another client
+        slow.add_file("/b", b"fresh");
+        backend.get_node("/b").unwrap();
+        assert!(fast.content("/b").is_none());
+        assert_eq!(backend.read("/b", 0, 16).unwrap(), b"fresh");
+        assert_eq!(fast.content("/b").unwrap(), b"fresh");
+
+        backend.put("/b", b"written").unwrap();
+        assert!(fast.content("/b").is_none());
+        assert_eq!(backend.used(), 0);
+        assert_eq!(slow.content("/b").unwrap(), b"written");
+    }
+
+    #[test]
+    fn test_tiered_index() {
+        let index = std::env::temp_dir().join(format!("ossfs-tiered-{}", std::process::id()));
+        let stale = super::stale_marker(&index);
+        let fast = Arc::new(MockBackend::new());
+        let slow = Arc::new(MockBackend::new());
+        slow.add_file("/dir/a", b"hello");
+        slow.add_file("/b", b"four");
+        let backend = TieredBackend::new(fast.clone(), slow.clone(), 16).with_index(&index);
+        backend.read("/dir/a", 0, 16).unwrap();
+        backend.read("/b", 0, 16).unwrap();
+        // the index does not list them yet
+        assert!(stale.exists());
+        drop(backend);
+        assert!(index.exists());
+        assert!(!stale.exists());
+
+        let backend = TieredBackend::new(fast.clone(), slow.clone(), 16).with_index(&index);
+        assert_eq!(backend.used(), 9);
+        let reads = slow.calls("read");
+        assert_eq!(backend.read("/dir/a", 0, 16).unwrap(), b"hello");
+        assert_eq!(backend.read("/b", 0, 16).unwrap(), b"four");
+        assert_eq!(slow.calls("read"), reads);
+        // a crash after a promotion, without the index listing it
+        slow.add_file("/c", b"c");
+        backend.read("/c", 0, 16).unwrap();
+        slow.add_file("/b", b"fresher");
+        std::mem::forget(backend);
+        assert!(stale.exists());
+
+        // rebuilt from the fast tier, without the copy of the changed file
+        let backend = TieredBackend::new(fast.clone(), slow.clone(), 16).with_index(&index);
+        assert!(!stale.exists());
+        assert_eq!(backend.used(), 6);
+        assert!(fast.content("/b").is_none());
+        let reads = slow.calls("read");
+        assert_eq!(backend.read("/c", 0, 16).unwrap(), b"c");
+        assert_eq!(slow.calls("read"), reads);
+        drop(backend);
+
+        // a smaller fast tier keeps the most recently read files
+        let backend = TieredBackend::new(fast.clone(), slow.clone(), 4).with_index(&index);
+        assert_eq!(backend.used(), 1);
+        assert!(fast.content("/dir/a").is_none());
+        drop(backend);
+
+        std::fs::write(&index, b"OSSFSTI1 not an index").unwrap();
+        let backend = TieredBackend::new(fast, slow, 16).with_index(&index);
+        assert_eq!(backend.used(), 1);
+        drop(backend);
+        std::fs::remove_file(&index).unwrap();
+    }
+}
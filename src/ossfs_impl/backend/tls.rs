@@ -0,0 +1,136 @@
+//! TLS settings of the connections to the store, set in a connection string with the
+//! `ca`, `client_cert`, `client_key` and `tls_verify` query parameters.
+
+use crate::error::{Error, Result};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TlsVerify {
+    /// The chain and the hostname of the certificate of the server are checked.
+    Full,
+    /// Any hostname is accepted, e.g. for endpoints reached through an ip.
+    SkipHostname,
+    /// Any certificate is accepted, which leaves the connection open to
+    /// interception. Meant for tests only.
+    None,
+}
+
+impl Default for TlsVerify {
+    fn default() -> TlsVerify {
+        TlsVerify::Full
+    }
+}
+
+impl FromStr for TlsVerify {
+    type Err = Error;
+
+    fn from_str(verify: &str) -> Result<TlsVerify> {
+        match verify {
+            "full" => Ok(TlsVerify::Full),
+            "skip-hostname" => Ok(TlsVerify::SkipHostname),
+            "none" => Ok(TlsVerify::None),
+            verify => Err(Error::Other(format!(
+                "invalid tls verification {:?}, expected `full`, `skip-hostname` or `none`",
+                verify
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    // pem certificates trusted besides the ones of the system
+    ca_certificates: Vec<PathBuf>,
+    // pem certificate chain and pkcs8 key presented to the server
+    identity: Option<(PathBuf, PathBuf)>,
+    verify: TlsVerify,
+}
+
+impl TlsConfig {
+    pub fn new() -> TlsConfig {
+        TlsConfig::default()
+    }
+
+    /// Trusts the pem certificates in `path` besides the ones of the system.
+    pub fn ca_certificate<P: AsRef<Path>>(mut self, path: P) -> TlsConfig {
+        self.ca_certificates.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Presents the pem certificate chain in `certificate` to servers asking for
+    /// one, `key` holds its pkcs8 private key.
+    pub fn client_identity<P: AsRef<Path>>(mut self, certificate: P, key: P) -> TlsConfig {
+        self.identity = Some((
+            certificate.as_ref().to_path_buf(),
+            key.as_ref().to_path_buf(),
+        ));
+        self
+    }
+
+    pub fn verify(mut self, verify: TlsVerify) -> TlsConfig {
+        self.verify = verify;
+        self
+    }
+
+    /// Reads the certificates and keys, failing if one is missing or invalid.
+    pub fn connector(&self) -> Result<native_tls::TlsConnector> {
+        let invalid = |path: &Path, e: native_tls::Error| {
+            Error::Other(format!("invalid certificate or key {:?}. {}", path, e))
+        };
+        let mut builder = native_tls::TlsConnector::builder();
+        for path in &self.ca_certificates {
+            let certificate =
+                native_tls::Certificate::from_pem(&read(path)?).map_err(|e| invalid(path, e))?;
+            builder.add_root_certificate(certificate);
+        }
+        if let Some((certificate, key)) = &self.identity {
+            let identity = native_tls::Identity::from_pkcs8(&read(certificate)?, &read(key)?)
+                .map_err(|e| invalid(certificate, e))?;
+            builder.identity(identity);
+        }
+        match self.verify {
+            TlsVerify::Full => {}
+            TlsVerify::SkipHostname => {
+                builder.danger_accept_invalid_hostnames(true);
+            }
+            TlsVerify::None => {
+                log::warn!("the certificates of the store are not verified");
+                builder.danger_accept_invalid_certs(true);
+            }
+        }
+        builder
+            .build()
+            .map_err(|e| Error::Other(format!("failed to set up tls. {}", e)))
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| Error::Other(format!("failed to read {:?}. {}", path, e)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TlsConfig, TlsVerify};
+
+    #[test]
+    fn test_tls_config() {
+        assert_eq!(
+            "skip-hostname".parse::<TlsVerify>().unwrap(),
+            TlsVerify::SkipHostname
+        );
+        assert!("maybe".parse::<TlsVerify>().is_err());
+        TlsConfig::new().connector().unwrap();
+        TlsConfig::new()
+            .verify(TlsVerify::None)
+            .connector()
+            .unwrap();
+        let missing = TlsConfig::new().ca_certificate("/nonexistent/ca.pem");
+        assert!(missing.connector().is_err());
+
+        let path = std::env::temp_dir().join(format!("ossfs-ca-{}.pem", std::process::id()));
+        std::fs::write(&path, "not a certificate").unwrap();
+        assert!(TlsConfig::new().ca_certificate(&path).connector().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
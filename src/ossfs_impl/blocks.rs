@@ -0,0 +1,221 @@
+//! Reads of files in fixed size blocks, cached so that the next reads of a block are
+//! served from memory.
+
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Smallest block size accepted by `check_block_size`.
+pub const MIN_BLOCK_SIZE: u64 = 1024 * 1024;
+
+/// Largest block size accepted by `check_block_size`.
+pub const MAX_BLOCK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Fails unless `size` is a power of two between `MIN_BLOCK_SIZE` and
+/// `MAX_BLOCK_SIZE`.
+pub fn check_block_size(size: u64) -> Result<()> {
+    if size < MIN_BLOCK_SIZE || size > MAX_BLOCK_SIZE || !size.is_power_of_two() {
+        return Err(Error::Other(format!(
+            "block size {} is not a power of two between {} and {} bytes",
+            size, MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+        )));
+    }
+    Ok(())
+}
+
+// size and modification time of a file, blocks of another version are stale
+pub type Version = (u64, SystemTime);
+
+#[derive(Debug)]
+struct Block {
+    data: Arc<Vec<u8>>,
+    version: Version,
+    // bytes read from the block, at most its length
+    served: u64,
+    // value of `Blocks::clock` at the last read
+    last_read: u64,
+}
+
+#[derive(Debug, Default)]
+struct Blocks {
+    // by inode and index of the block in the file
+    blocks: HashMap<(u64, u64), Block>,
+    // bytes of the blocks
+    used: u64,
+    // counts the reads, orders the blocks by recency
+    clock: u64,
+}
+
+#[derive(Debug)]
+pub struct BlockCache {
+    block_size: u64,
+    // bytes of blocks kept at most
+    capacity: u64,
+    blocks: Mutex<Blocks>,
+    // bytes of the blocks fetched
+    pub fetched: Arc<AtomicU64>,
+    // bytes of the blocks dropped without being read
+    pub wasted: Arc<AtomicU64>,
+}
+
+impl BlockCache {
+    pub fn new(block_size: u64, capacity: u64) -> BlockCache {
+        BlockCache {
+            block_size,
+            capacity,
+            blocks: Mutex::new(Blocks::default()),
+            fetched: Arc::new(AtomicU64::new(0)),
+            wasted: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Bytes `from..to` of the block `index` of the file `ino`, `None` unless the
+    /// cache holds the block of `version`. Shorter at the end of the file.
+    pub fn read(
+        &self,
+        ino: u64,
+        index: u64,
+        version: Version,
+        from: u64,
+        to: u64,
+    ) -> Option<Vec<u8>> {
+        let mut blocks = self.blocks.lock().unwrap();
+        blocks.clock += 1;
+        let clock = blocks.clock;
+        let stale = match blocks.blocks.get_mut(&(ino, index)) {
+            Some(ref block) if block.version != version => true,
+            Some(block) => {
+                block.last_read = clock;
+                let len = block.data.len() as u64;
+                let part = slice(&block.data, from, to);
+                block.served = std::cmp::min(block.served + part.len() as u64, len);
+                return Some(part);
+            }
+            None => return None,
+        };
+        if stale {
+            self.drop_block(&mut blocks, (ino, index));
+        }
+        None
+    }
+
+    /// Whether the cache holds the block `index` of the file `ino` of `version`,
+    /// without counting as a read of it.
+    pub fn contains(&self, ino: u64, index: u64, version: Version) -> bool {
+        let blocks = self.blocks.lock().unwrap();
+        match blocks.blocks.get(&(ino, index)) {
+            Some(block) => block.version == version,
+            None => false,
+        }
+    }
+
+    /// Caches the block `index` of the file `ino` fetched with `version`, of which
+    /// `served` bytes were read already, dropping the least recently read blocks
+    /// past the capacity.
+    pub fn insert(&self, ino: u64, index: u64, version: Version, data: Arc<Vec<u8>>, served: u64) {
+        let len = data.len() as u64;
+        self.fetched.fetch_add(len, Ordering::Relaxed);
+        let mut blocks = self.blocks.lock().unwrap();
+        self.drop_block(&mut blocks, (ino, index));
+        blocks.clock += 1;
+        let block = Block {
+            data,
+            version,
+            served: std::cmp::min(served, len),
+            last_read: blocks.clock,
+        };
+        blocks.used += len;
+        blocks.blocks.insert((ino, index), block);
+        while blocks.used > self.capacity {
+            let coldest = match blocks
+                .blocks
+                .iter()
+                .min_by_key(|(_, block)| block.last_read)
+            {
+                Some((coldest, _)) => *coldest,
+                None => break,
+            };
+            self.drop_block(&mut blocks, coldest);
+        }
+    }
+
+    /// Drops the blocks of the file `ino`, once it is written.
+    pub fn forget(&self, ino: u64) {
+        let mut blocks = self.blocks.lock().unwrap();
+        let keys: Vec<(u64, u64)> = blocks
+            .blocks
+            .keys()
+            .filter(|key| key.0 == ino)
+            .cloned()
+            .collect();
+        for key in keys {
+            self.drop_block(&mut blocks, key);
+        }
+    }
+
+    fn drop_block(&self, blocks: &mut Blocks, key: (u64, u64)) {
+        if let Some(block) = blocks.blocks.remove(&key) {
+            let len = block.data.len() as u64;
+            blocks.used -= len;
+            self.wasted.fetch_add(len - block.served, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Bytes `from..to` of `data`, shorter if `data` is.
+pub fn slice(data: &[u8], from: u64, to: u64) -> Vec<u8> {
+    let from = std::cmp::min(from as usize, data.len());
+    let to = std::cmp::min(to as usize, data.len());
+    data[from..std::cmp::max(from, to)].to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use super::check_block_size;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+    use std::ffi::OsStr;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_blocks() {
+        assert!(check_block_size(4 * 1024 * 1024).is_ok());
+        assert!(check_block_size(64 * 1024).is_err());
+        assert!(check_block_size(3 * 1024 * 1024).is_err());
+
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/a", b"0123456789");
+        let config = Config {
+            block_size: Some(4),
+            block_cache: 8,
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend.clone(), &config);
+        let a = fs.lookup(ROOT_INODE, OsStr::new("a")).unwrap().ino;
+        let read = |offset: usize, size: usize| {
+            let mut data = vec![];
+            fs.read(a, 0, false, offset, size, |result| data = result.unwrap());
+            data
+        };
+        assert_eq!(read(2, 4), b"2345");
+        assert_eq!(backend.calls("read"), 2);
+        assert_eq!(read(0, 2), b"01");
+        assert_eq!(backend.calls("read"), 2);
+
+        // the least recently read block makes room, half of it was never read
+        assert_eq!(read(8, 2), b"89");
+        assert_eq!(backend.calls("read"), 3);
+        assert_eq!(fs.block_bytes().unwrap(), (10, 2));
+        assert_eq!(read(0, 4), b"0123");
+        assert_eq!(backend.calls("read"), 3);
+        fs.write(a, 0, b"ab").unwrap();
+        assert_eq!(fs.block_bytes().unwrap(), (10, 2));
+    }
+}
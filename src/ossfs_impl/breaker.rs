@@ -0,0 +1,124 @@
+use crate::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct State {
+    consecutive_failures: u32,
+    // set while the breaker is open, requests fail fast until a probe succeeds
+    open_since: Option<Instant>,
+    // start of the recovery probe currently in flight
+    probe_since: Option<Instant>,
+}
+
+/// Tracks consecutive backend failures. Once `threshold` of them happen in a row the
+/// breaker opens: callers should fail fast and leave it to a single background probe,
+/// issued at most once per `cooldown`, to find out when the backend is back.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    state: Mutex<State>,
+    threshold: u32,
+    cooldown: Duration,
+}
+
+impl CircuitBreaker {
+    /// `threshold` of 0 disables the breaker.
+    pub fn new(threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            state: Mutex::new(State {
+                consecutive_failures: 0,
+                open_since: None,
+                probe_since: None,
+            }),
+            threshold,
+            cooldown,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap().open_since.is_some()
+    }
+
+    pub fn record(&self, result: Result<(), &Error>) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Ok(()) => {
+                if state.open_since.is_some() {
+                    log::warn!("backend recovered, circuit closed");
+                }
+                state.consecutive_failures = 0;
+                state.open_since = None;
+                state.probe_since = None;
+            }
+            Err(err) if err.is_retryable() => {
+                state.consecutive_failures += 1;
+                if state.open_since.is_none() && state.consecutive_failures >= self.threshold {
+                    log::error!(
+                        "backend failed {} times in a row, circuit opened. last error: {}",
+                        state.consecutive_failures,
+                        err
+                    );
+                    state.open_since = Some(Instant::now());
+                }
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Returns true if the caller should issue a recovery probe now, at most one probe
+    /// is handed out per cooldown period.
+    pub fn try_probe(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let open_since = match state.open_since {
+            Some(open_since) => open_since,
+            None => return false,
+        };
+        let last = state.probe_since.unwrap_or(open_since);
+        if last.elapsed() < self.cooldown {
+            return false;
+        }
+        state.probe_since = Some(Instant::now());
+        true
+    }
+
+    pub fn probe_finished(&self, err: Option<&Error>) {
+        match err {
+            None => self.record(Ok(())),
+            Some(err) => {
+                log::warn!("backend probe failed, circuit stays open. error: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CircuitBreaker;
+    use crate::error::Error;
+    use std::time::Duration;
+
+    #[test]
+    fn test_open_and_recover() {
+        let breaker = CircuitBreaker::new(3, Duration::from_millis(0));
+        let err = Error::Timeout("get_node".to_owned());
+        breaker.record(Err(&err));
+        breaker.record(Err(&err));
+        assert!(!breaker.is_open());
+        breaker.record(Err(&err));
+        assert!(breaker.is_open());
+        assert!(breaker.try_probe());
+        breaker.probe_finished(None);
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    fn test_not_found_is_not_failure() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(1));
+        let err = Error::NotFound("get_node".to_owned());
+        breaker.record(Err(&err));
+        assert!(!breaker.is_open());
+    }
+}
@@ -0,0 +1,193 @@
+//! Counts the requests sent to the object store and optionally holds them to a rate.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Request {
+    List,
+    Head,
+    Get,
+    Put,
+}
+
+/// Requests sent since the mount started.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RequestCounts {
+    pub list: u64,
+    pub head: u64,
+    pub get: u64,
+    pub put: u64,
+}
+
+/// Prices in dollars per thousand requests.
+#[derive(Debug, Clone, Copy)]
+pub struct Prices {
+    pub list: f64,
+    pub head: f64,
+    pub get: f64,
+    pub put: f64,
+}
+
+impl Default for Prices {
+    /// The prices of S3 standard storage in us-east-1.
+    fn default() -> Prices {
+        Prices {
+            list: 0.005,
+            head: 0.0004,
+            get: 0.0004,
+            put: 0.005,
+        }
+    }
+}
+
+impl RequestCounts {
+    pub fn total(&self) -> u64 {
+        self.list + self.head + self.get + self.put
+    }
+
+    /// Estimated cost of the requests in dollars.
+    pub fn cost(&self, prices: &Prices) -> f64 {
+        (self.list as f64 * prices.list
+            + self.head as f64 * prices.head
+            + self.get as f64 * prices.get
+            + self.put as f64 * prices.put)
+            / 1000.0
+    }
+}
+
+impl std::fmt::Display for RequestCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "LIST {}, HEAD {}, GET {}, PUT {} (about ${:.4})",
+            self.list,
+            self.head,
+            self.get,
+            self.put,
+            self.cost(&Prices::default())
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+#[derive(Debug)]
+pub(crate) struct Budget {
+    // requests per second, `None` counts without limiting
+    rate: Option<f64>,
+    bucket: Mutex<Bucket>,
+    list: AtomicU64,
+    head: AtomicU64,
+    get: AtomicU64,
+    put: AtomicU64,
+}
+
+impl Budget {
+    pub fn new(rate: Option<u32>) -> Budget {
+        let rate = rate.map(|rate| std::cmp::max(rate, 1) as f64);
+        Budget {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate.unwrap_or(0.0),
+                refilled: Instant::now(),
+            }),
+            list: AtomicU64::new(0),
+            head: AtomicU64::new(0),
+            get: AtomicU64::new(0),
+            put: AtomicU64::new(0),
+        }
+    }
+
+    pub fn counts(&self) -> RequestCounts {
+        RequestCounts {
+            list: self.list.load(Ordering::Relaxed),
+            head: self.head.load(Ordering::Relaxed),
+            get: self.get.load(Ordering::Relaxed),
+            put: self.put.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Waits until the rate allows `request`, then counts it.
+    pub fn acquire(&self, request: Request) {
+        while let Some(wait) = self.take(1.0, 0.0) {
+            std::thread::sleep(wait);
+        }
+        self.count(request, 1);
+    }
+
+    /// Counts `requests` of a refresh if the bucket is at least half full, returns
+    /// false if the refresh should be skipped.
+    pub fn try_refresh(&self, request: Request, requests: u64) -> bool {
+        let reserve = self.rate.unwrap_or(0.0) / 2.0;
+        if self.take(requests as f64, reserve).is_some() {
+            log::debug!("skipping a refresh of {} requests, over budget", requests);
+            return false;
+        }
+        self.count(request, requests);
+        true
+    }
+
+    fn count(&self, request: Request, requests: u64) {
+        let counter = match request {
+            Request::List => &self.list,
+            Request::Head => &self.head,
+            Request::Get => &self.get,
+            Request::Put => &self.put,
+        };
+        counter.fetch_add(requests, Ordering::Relaxed);
+    }
+
+    // takes `tokens` if more than `reserve` would be left, otherwise returns how
+    // long it takes until there are enough
+    fn take(&self, tokens: f64, reserve: f64) -> Option<Duration> {
+        let rate = self.rate?;
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.refilled).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(rate);
+        bucket.refilled = now;
+        // a refresh larger than the bucket goes through once it is full
+        let needed = (tokens + reserve).min(rate);
+        if bucket.tokens >= needed {
+            bucket.tokens -= tokens;
+            return None;
+        }
+        Some(Duration::from_secs_f64((needed - bucket.tokens) / rate))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Budget, Request};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_budget() {
+        let budget = Budget::new(Some(10));
+        assert!(budget.try_refresh(Request::List, 4));
+        // 6 left, a refresh has to leave 5
+        assert!(!budget.try_refresh(Request::List, 2));
+        let started = Instant::now();
+        for _ in 0..8 {
+            budget.acquire(Request::Get);
+        }
+        // 6 were in the bucket, the other 2 took 100ms each
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        let counts = budget.counts();
+        assert_eq!((counts.list, counts.get, counts.total()), (4, 8, 12));
+        assert!(counts
+            .to_string()
+            .starts_with("LIST 4, HEAD 0, GET 8, PUT 0"));
+
+        let unlimited = Budget::new(None);
+        assert!(unlimited.try_refresh(Request::List, 1000));
+        unlimited.acquire(Request::Head);
+        assert_eq!(unlimited.counts().total(), 1001);
+    }
+}
@@ -0,0 +1,218 @@
+//! Validation run before mounting, so that a misconfiguration is reported with a
+//! message telling what to fix.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::handles::HandleTable;
+use crate::ossfs_impl::platform;
+use std::ffi::OsStr;
+use std::path::Path;
+
+#[cfg(target_os = "linux")]
+const FUSE_DEVICE: &str = "/dev/fuse";
+
+/// Checks that `mountpoint` is a directory the mount can cover. A non-empty one is
+/// refused unless `nonempty` is set, i.e. the mount options contain `nonempty`.
+pub fn check_mountpoint<P: AsRef<Path>>(mountpoint: P, nonempty: bool) -> Result<()> {
+    let mountpoint = mountpoint.as_ref();
+    let meta = match std::fs::metadata(mountpoint) {
+        Ok(meta) => meta,
+        Err(ref e) if is_dead_mount(e) => {
+            return Err(Error::Conflict(format!(
+                "mountpoint {:?} is a stale fuse mount, run `{}` first",
+                mountpoint,
+                platform::unmount_command(mountpoint)
+            )));
+        }
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::NotFound(format!(
+                "mountpoint {:?} does not exist, create it with `mkdir -p {:?}`",
+                mountpoint, mountpoint
+            )));
+        }
+        Err(e) => {
+            return Err(Error::PermissionDenied(format!(
+                "mountpoint {:?} is not accessible. {}",
+                mountpoint, e
+            )));
+        }
+    };
+    if !meta.is_dir() {
+        return Err(Error::Other(format!(
+            "mountpoint {:?} is not a directory",
+            mountpoint
+        )));
+    }
+    if nonempty {
+        return Ok(());
+    }
+    let mut entries = std::fs::read_dir(mountpoint).map_err(|e| {
+        Error::PermissionDenied(format!(
+            "mountpoint {:?} is not readable. {}",
+            mountpoint, e
+        ))
+    })?;
+    if entries.next().is_some() {
+        return Err(Error::Conflict(format!(
+            "mountpoint {:?} is not empty, its content would be hidden by the mount",
+            mountpoint
+        )));
+    }
+    Ok(())
+}
+
+/// Whether the mount `options`, e.g. `["-o", "ro,nonempty"]`, allow mounting over a
+/// non-empty directory.
+pub fn allows_nonempty(options: &[&OsStr]) -> bool {
+    let mut values = vec![];
+    let mut options = options.iter().map(|option| option.to_string_lossy());
+    while let Some(option) = options.next() {
+        if option == "-o" {
+            if let Some(value) = options.next() {
+                values.push(value.into_owned());
+            }
+        } else if option.starts_with("-o") {
+            values.push(option[2..].to_owned());
+        }
+    }
+    values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .any(|value| value == "nonempty")
+}
+
+/// Whether `mountpoint` is a fuse mount whose session is gone.
+pub fn is_stale<P: AsRef<Path>>(mountpoint: P) -> bool {
+    match std::fs::metadata(mountpoint.as_ref()) {
+        Err(ref e) => is_dead_mount(e),
+        Ok(_) => false,
+    }
+}
+
+// macFUSE reports a dead mount with ENXIO
+fn is_dead_mount(e: &std::io::Error) -> bool {
+    e.raw_os_error() == Some(libc::ENOTCONN) || e.raw_os_error() == Some(libc::ENXIO)
+}
+
+pub fn check_fuse_device() -> Result<()> {
+    let (locations, hint) = platform::fuse_install_locations();
+    if !locations.iter().any(|location| location.exists()) {
+        return Err(Error::NotFound(hint.to_owned()));
+    }
+    #[cfg(target_os = "linux")]
+    {
+        if let Err(e) = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(FUSE_DEVICE)
+        {
+            if e.kind() == std::io::ErrorKind::PermissionDenied {
+                return Err(Error::PermissionDenied(format!(
+                    "{} is not accessible, add the user to the `fuse` group or run as root",
+                    FUSE_DEVICE
+                )));
+            }
+            return Err(Error::Other(format!(
+                "failed to open {}. {}",
+                FUSE_DEVICE, e
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub fn check_backend<B: Backend>(backend: &B) -> Result<()> {
+    backend.check().map_err(|e| match e {
+        Error::PermissionDenied(message) => Error::PermissionDenied(format!(
+            "backend rejected the credentials, check the access key and secret. {}",
+            message
+        )),
+        Error::NotFound(message) => Error::NotFound(format!(
+            "backend root does not exist, check the bucket name. {}",
+            message
+        )),
+        Error::Unavailable(message) | Error::Timeout(message) => Error::Unavailable(format!(
+            "backend is not reachable, check the endpoint and the network. {}",
+            message
+        )),
+        e => e,
+    })
+}
+
+pub fn check_inode_table<P: AsRef<Path>>(path: P) -> Result<()> {
+    HandleTable::open(path.as_ref()).map(|_| ()).map_err(|e| {
+        Error::Other(format!(
+            "inode table {:?} can not be used, fix or remove it. {}",
+            path.as_ref(),
+            e
+        ))
+    })
+}
+
+pub fn check_write_journal<P: AsRef<Path>>(path: P) -> Result<()> {
+    std::fs::create_dir_all(path.as_ref())
+        .and_then(|_| std::fs::read_dir(path.as_ref()))
+        .map(|_| ())
+        .map_err(|e| {
+            Error::PermissionDenied(format!(
+                "write journal {:?} can not be used, point to a writable directory. {}",
+                path.as_ref(),
+                e
+            ))
+        })
+}
+
+pub fn check_manifest<P: AsRef<Path>>(path: P) -> Result<()> {
+    std::fs::File::open(path.as_ref()).map(|_| ()).map_err(|e| {
+        Error::NotFound(format!(
+            "manifest {:?} can not be read, generate it with `manifest::write` or point to an S3 Inventory CSV file. {}",
+            path.as_ref(),
+            e
+        ))
+    })
+}
+
+/// Runs all checks in the order a user would fix them.
+pub fn check<B: Backend, P: AsRef<Path>>(backend: &B, mountpoint: P, nonempty: bool) -> Result<()> {
+    check_mountpoint(mountpoint, nonempty)?;
+    check_fuse_device()?;
+    check_backend(backend)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{allows_nonempty, check_mountpoint};
+    use crate::error::Error;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn test_check_mountpoint() {
+        let dir = std::env::temp_dir().join(format!("ossfs-check-{}", std::process::id()));
+        match check_mountpoint(&dir, false) {
+            Err(Error::NotFound(_)) => {}
+            other => panic!("unexpected: {:?}", other),
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        check_mountpoint(&dir, false).unwrap();
+        std::fs::write(dir.join("file"), b"").unwrap();
+        match check_mountpoint(&dir, false) {
+            Err(Error::Conflict(_)) => {}
+            other => panic!("unexpected: {:?}", other),
+        }
+        check_mountpoint(&dir, true).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_allows_nonempty() {
+        let options = |options: &[&'static str]| -> Vec<&'static OsStr> {
+            options.iter().map(|option| OsStr::new(*option)).collect()
+        };
+        assert!(!allows_nonempty(&options(&[])));
+        assert!(!allows_nonempty(&options(&["-o", "ro"])));
+        assert!(allows_nonempty(&options(&["-o", "nonempty"])));
+        assert!(allows_nonempty(&options(&["-o", "ro,nonempty"])));
+        assert!(allows_nonempty(&options(&["-f", "-oallow_other,nonempty"])));
+        assert!(!allows_nonempty(&options(&["-o", "ro,nonempty_x"])));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::ossfs_impl::direct_io::DirectIo;
+use crate::ossfs_impl::filter::Filter;
+use crate::ossfs_impl::labels::Labels;
+use crate::ossfs_impl::pins::RemoteChange;
+use crate::ossfs_impl::policy::Policy;
+use crate::ossfs_impl::prefetch_policy::PrefetchPolicy;
+use crate::ossfs_impl::stat::DEFAULT_CAPACITY;
+use crate::ossfs_impl::virtual_file::VirtualFile;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    // keep the whole content of a file in memory after its first read
+    pub enable_cache: bool,
+    // upper bound of every backend call, `None` waits forever
+    pub backend_timeout: Option<Duration>,
+    // threads dedicated to backend calls guarded by `backend_timeout`
+    pub backend_threads: usize,
+    // consecutive backend failures before requests fail fast, 0 disables it
+    pub breaker_threshold: u32,
+    // interval between recovery probes while requests fail fast
+    pub breaker_cooldown: Duration,
+    // interval between health probes of the backend, `None` does not probe it
+    pub health_interval: Option<Duration>,
+    // entries taken from the tree at a time while filling a readdir reply, 0 takes
+    // the rest of the directory
+    pub readdir_limit: usize,
+    // file recording the inodes handed out, needed to re-export the mount over nfs
+    pub inode_table: Option<PathBuf>,
+    // interval between listings of watched directories, `None` disables watches and
+    // spawns no watcher thread
+    pub watch_interval: Option<Duration>,
+    // interval between summaries of the operations in the log, `None` disables them
+    pub stats_interval: Option<Duration>,
+    // upper bound of a single read request of the kernel, `None` keeps its default
+    pub max_read: Option<u32>,
+    // bytes the kernel reads ahead of sequential readers, `None` keeps its default
+    pub max_readahead: Option<u32>,
+    // requests the kernel sends without waiting for the answers, `None` keeps its
+    // default
+    pub max_background: Option<u16>,
+    // background requests above which the kernel holds back readahead, `None` keeps
+    // its default
+    pub congestion_threshold: Option<u16>,
+    // estimated bytes of metadata above which cold subtrees are evicted in the
+    // background, `None` never evicts in the background
+    pub metadata_soft_limit: Option<u64>,
+    // estimated bytes of metadata above which cold subtrees are evicted before
+    // adding entries, `None` never evicts before adding entries
+    pub metadata_hard_limit: Option<u64>,
+    // requests per second sent to the backend, `None` does not limit them
+    pub request_rate: Option<u32>,
+    // dirty bytes of written files above which writers block, `None` never blocks
+    pub dirty_high_water: Option<u64>,
+    // files uploaded to the backend at the same time
+    pub upload_concurrency: usize,
+    // attempts of a failed upload after the first one, retries after a timeout do
+    // not put the file again if the first attempt reached the backend
+    pub upload_retries: usize,
+    // directory mirroring the written files until they are uploaded, the next mount
+    // uploads those left by a crash. `None` loses them on a crash
+    pub write_journal: Option<PathBuf>,
+    // on SIGTERM or SIGINT the written files are flushed within it before the mount
+    // stops, `None` leaves the signals alone
+    pub shutdown_deadline: Option<Duration>,
+    // file recording the opens, reads and writes of the mount, `None` records none
+    pub access_trace: Option<PathBuf>,
+    // name the mount in the summaries, the access trace and the logged errors, over
+    // those of the environment
+    pub labels: Labels,
+    // how long entries created or written through the mount are served from local
+    // state over the backend's listings, which may lag behind
+    pub consistency_window: Duration,
+    // how long deleted files are kept in the trash, `None` deletes them right away
+    pub trash_retention: Option<Duration>,
+    // entries of the bucket exposed by the mount, all of them by default
+    pub filter: Filter,
+    // files opened with direct_io, read from the backend around the caches
+    pub direct_io: DirectIo,
+    // reads of opened files go to the local files of the backend, if it has them
+    pub passthrough: bool,
+    // directory the `dump` control command writes to, `None` refuses the command
+    pub dump_dir: Option<PathBuf>,
+    // read-only files served from callbacks, by path relative to the mount root
+    pub virtual_files: Vec<(PathBuf, VirtualFile)>,
+    // writes growing a file beyond it fail with EFBIG, `None` does not limit them
+    pub max_file_size: Option<u64>,
+    // creates in directories with as many entries fail with ENOSPC and larger
+    // listings are warned about, `None` does not limit them
+    pub max_dir_entries: Option<usize>,
+    // how long the kernel caches the entries and attributes of files
+    pub file_ttl: Duration,
+    // how long the kernel caches the entries and attributes of directories
+    pub dir_ttl: Duration,
+    // how long the kernel caches that a name does not exist, zero does not cache it
+    pub negative_ttl: Duration,
+    // bounds of the ttls lengthened for inodes which do not change and shortened
+    // for those which do, `None` keeps the ttls above fixed
+    pub adaptive_ttl: Option<(Duration, Duration)>,
+    // most accessed inodes whose attributes are refreshed before `file_ttl`
+    // expires, `None` leaves them to watches and listings
+    pub refresh_hottest: Option<usize>,
+    // how long after a directory was listed names missing from the listing are
+    // taken as missing without asking the backend, `None` always asks it
+    pub listing_window: Option<Duration>,
+    // directories last listed with at most as many entries are listed again to
+    // look up a name missing from the tree, larger or unknown ones head the name.
+    // `None` always heads it
+    pub list_below: Option<usize>,
+    // prefixes each uid may read or write, everyone may access everything without
+    // rules
+    pub policy: Policy,
+    // rmdir of a directory with entries removes everything below it instead of
+    // failing with ENOTEMPTY, unless deleted files go to the trash
+    pub recursive_rmdir: bool,
+    // tags of the objects exposed as `user.s3.tag.*` extended attributes, listing
+    // the attributes of a file costs a request then
+    pub object_tags: bool,
+    // how long the recursive usage of a directory is served before it is computed
+    // again
+    pub usage_ttl: Duration,
+    // getattr reports the recursive bytes of directories as their size, computed in
+    // the background
+    pub dir_sizes: bool,
+    // reads of each open handle switch between fetching the whole file, reading
+    // ahead and the requested ranges only, following how the handle reads. Only
+    // applies when the content of files is not cached
+    pub adaptive_reads: bool,
+    // reads fetch whole blocks of this many bytes and keep them in memory, `None`
+    // fetches the ranges read. Only applies when the content of files is not cached
+    pub block_size: Option<u64>,
+    // bytes of blocks kept in memory at most
+    pub block_cache: u64,
+    // picks the blocks fetched ahead of the reads, `None` fetches the blocks read
+    // only. Only applies when reads fetch blocks
+    pub prefetch_policy: Option<Arc<dyn PrefetchPolicy>>,
+    // what the handles opened before a remote change of their file read
+    pub remote_change: RemoteChange,
+    // manifest of the bucket, or S3 Inventory CSV file, whose files are added to the
+    // tree at mount instead of listing the directories leading to them. Meant for
+    // datasets which do not change
+    pub manifest: Option<PathBuf>,
+    // bytes the mount may add to the bucket, growing files beyond fails with EDQUOT.
+    // `None` does not limit them
+    pub quota_bytes: Option<u64>,
+    // files the mount may add to the bucket, creates beyond fail with EDQUOT. `None`
+    // does not limit them
+    pub quota_files: Option<u64>,
+    // bytes statfs reports as the size of buckets which have none, their usage is
+    // the one of the mount root
+    pub capacity: u64,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            enable_cache: false,
+            backend_timeout: Some(Duration::from_secs(30)),
+            backend_threads: 32,
+            breaker_threshold: 5,
+            breaker_cooldown: Duration::from_secs(5),
+            health_interval: None,
+            readdir_limit: 85,
+            inode_table: None,
+            watch_interval: None,
+            stats_interval: None,
+            max_read: None,
+            max_readahead: None,
+            max_background: None,
+            congestion_threshold: None,
+            metadata_soft_limit: None,
+            metadata_hard_limit: None,
+            request_rate: None,
+            dirty_high_water: Some(512 * 1024 * 1024),
+            upload_concurrency: 4,
+            upload_retries: 2,
+            write_journal: None,
+            shutdown_deadline: None,
+            access_trace: None,
+            labels: Labels::default(),
+            consistency_window: Duration::from_secs(30),
+            trash_retention: None,
+            filter: Filter::default(),
+            direct_io: DirectIo::default(),
+            passthrough: false,
+            dump_dir: None,
+            virtual_files: vec![],
+            max_file_size: None,
+            max_dir_entries: None,
+            file_ttl: Duration::from_secs(1),
+            dir_ttl: Duration::from_secs(1),
+            negative_ttl: Duration::from_secs(0),
+            adaptive_ttl: None,
+            refresh_hottest: None,
+            listing_window: None,
+            list_below: Some(1000),
+            policy: Policy::default(),
+            recursive_rmdir: false,
+            object_tags: false,
+            usage_ttl: Duration::from_secs(300),
+            dir_sizes: false,
+            adaptive_reads: false,
+            block_size: None,
+            block_cache: 256 * 1024 * 1024,
+            prefetch_policy: None,
+            remote_change: RemoteChange::Invalidate,
+            manifest: None,
+            quota_bytes: None,
+            quota_files: None,
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+}
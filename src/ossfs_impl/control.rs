@@ -0,0 +1,128 @@
+//! Commands controlling a live mount, e.g. `setfattr -n user.ossfs.control -v
+//! invalidate /mnt/data`, getting the attribute dumps the stats of the mount.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Extended attribute running a `Command` on the entry it is set on.
+pub const CONTROL_XATTR: &str = "user.ossfs.control";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// Drops the cached entries below a directory, or the cached attributes and
+    /// content of a file.
+    Invalidate,
+    /// Keeps the entry, and the children of a directory, from being evicted.
+    Pin,
+    Unpin,
+    /// Drops the whole content cache.
+    FlushCache,
+    /// Describes the caches and the requests sent to the backend.
+    Stats,
+    /// Writes the inode tree below the entry to a file, see `FileSystem::dump`.
+    Dump,
+}
+
+impl Command {
+    /// Whether only root and the owner of the mount may run the command: all of
+    /// them but `Stats`, the others drop or keep caches every user relies on.
+    pub fn privileged(&self) -> bool {
+        match self {
+            Command::Stats => false,
+            _ => true,
+        }
+    }
+}
+
+/// Whether `uid` may run privileged commands on a mount owned by `owner`.
+pub fn permitted(uid: u32, owner: u32) -> bool {
+    uid == 0 || uid == owner
+}
+
+/// The file of `dir` a dump of the tree below `ino` is written to.
+pub fn dump_file(dir: &Path, ino: u64) -> PathBuf {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    dir.join(format!("inodes-{}-{}.tsv", ino, now.as_millis()))
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(command: &str) -> std::result::Result<Command, String> {
+        match command.trim() {
+            "invalidate" => Ok(Command::Invalidate),
+            "pin" => Ok(Command::Pin),
+            "unpin" => Ok(Command::Unpin),
+            "flush-cache" => Ok(Command::FlushCache),
+            "stats" => Ok(Command::Stats),
+            "dump" => Ok(Command::Dump),
+            command => Err(format!("unknown control command {:?}", command)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dump_file, permitted, Command};
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+    use std::ffi::OsStr;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_control() {
+        assert_eq!("invalidate\n".parse(), Ok(Command::Invalidate));
+        assert_eq!("flush-cache".parse(), Ok(Command::FlushCache));
+        assert!("reboot".parse::<Command>().is_err());
+        assert_eq!("dump".parse(), Ok(Command::Dump));
+        // the daemon picks the file, callers do not
+        assert!("dump /etc/cron.d/x".parse::<Command>().is_err());
+        assert!(Command::Dump.privileged() && Command::Invalidate.privileged());
+        assert!(Command::Pin.privileged() && Command::FlushCache.privileged());
+        assert!(!Command::Stats.privileged());
+        assert!(permitted(0, 1000) && permitted(1000, 1000) && !permitted(1001, 1000));
+        let file = dump_file(Path::new("/var/lib/ossfs/dumps"), 7);
+        assert_eq!(file.parent(), Some(Path::new("/var/lib/ossfs/dumps")));
+        assert!(file
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("inodes-7-"));
+
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/dir/a", b"1");
+        let config = Config::default();
+        let fs = FileSystem::new(backend.clone(), &config);
+        let dir = fs.lookup(ROOT_INODE, OsStr::new("dir")).unwrap().ino;
+        assert_eq!(fs.readdir(dir, 0, 0).unwrap().len(), 1);
+        let a = fs.lookup(dir, OsStr::new("a")).unwrap().ino;
+
+        backend.add_file("/dir/a", b"22");
+        backend.add_file("/dir/b", b"3");
+        assert_eq!(fs.invalidate(a).unwrap(), vec![a]);
+        assert_eq!(fs.getattr(a).unwrap().size, 2);
+        assert_eq!(fs.readdir(dir, 0, 0).unwrap().len(), 1);
+        assert_eq!(fs.invalidate(dir).unwrap(), vec![a]);
+        assert_eq!(fs.readdir(dir, 0, 0).unwrap().len(), 2);
+
+        fs.pin(dir, true).unwrap();
+        assert_eq!(fs.pinned(), 1);
+        fs.pin(dir, false).unwrap();
+        assert_eq!(fs.pinned(), 0);
+
+        assert_eq!(fs.path_of(a).unwrap(), Path::new("/dir/a"));
+        assert_eq!(fs.path_of(ROOT_INODE).unwrap(), Path::new("/"));
+        assert_eq!(fs.path_of(12345).unwrap_err().errno(0), libc::ENOENT);
+        let mut dump = vec![];
+        assert_eq!(fs.dump(dir, &mut dump).unwrap(), 3);
+        let dump = String::from_utf8(dump).unwrap();
+        assert!(dump.starts_with(&format!("{}\t\"/dir\"\tDirectory\t", dir)));
+        assert!(dump.ends_with("\t0\n"));
+    }
+}
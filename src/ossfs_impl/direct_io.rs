@@ -0,0 +1,53 @@
+//! Files opened with direct_io, whose reads skip the page, block and content caches,
+//! chosen by globs of `Filter` and a minimum size.
+
+use crate::ossfs_impl::filter::Pattern;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default)]
+pub struct DirectIo {
+    // patterns and the size from which their files are opened with direct_io
+    rules: Vec<(Pattern, u64)>,
+}
+
+impl DirectIo {
+    pub fn new() -> DirectIo {
+        DirectIo::default()
+    }
+
+    /// Opens the files matching `pattern` of at least `min_size` bytes with
+    /// direct_io.
+    pub fn rule(mut self, pattern: &str, min_size: u64) -> DirectIo {
+        self.rules.push((Pattern::new(pattern), min_size));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether the file at `path`, relative to the mount root, of `size` bytes is
+    /// opened with direct_io.
+    pub fn applies(&self, path: &Path, size: u64) -> bool {
+        self.rules
+            .iter()
+            .any(|(pattern, min_size)| size >= *min_size && pattern.matches_path(path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DirectIo;
+    use std::path::Path;
+
+    #[test]
+    fn test_direct_io() {
+        let gib = 1024 * 1024 * 1024;
+        let direct_io = DirectIo::new().rule("*.ckpt", gib).rule("/scratch/**", 0);
+        assert!(direct_io.applies(Path::new("models/step-100.ckpt"), 2 * gib));
+        assert!(!direct_io.applies(Path::new("models/step-100.ckpt"), 1024));
+        assert!(!direct_io.applies(Path::new("models/config.json"), 2 * gib));
+        assert!(direct_io.applies(Path::new("scratch/a"), 0));
+        assert!(!DirectIo::new().applies(Path::new("a"), gib));
+    }
+}
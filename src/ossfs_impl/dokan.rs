@@ -0,0 +1,495 @@
+//! Mounts a `FileSystem` on Windows through Dokan, the counterpart of the fuse front
+//! end of unix.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::attr;
+use crate::ossfs_impl::attr::{FileAttr, FileType};
+use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::blocks;
+use crate::ossfs_impl::check;
+use crate::ossfs_impl::config::Config;
+use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+use dokan::{
+    CreateFileInfo, DiskSpaceInfo, Drive, FileInfo, FileSystemHandler, FillDataError, FindData,
+    OperationError, OperationInfo, VolumeInfo,
+};
+use dokan_sys::DOKAN_IO_SECURITY_CONTEXT;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use widestring::{U16CStr, U16CString};
+use winapi::shared::ntdef::NTSTATUS;
+use winapi::shared::ntstatus::*;
+use winapi::um::winnt::{
+    ACCESS_MASK, FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_NORMAL, FILE_CASE_PRESERVED_NAMES,
+    FILE_CASE_SENSITIVE_SEARCH, FILE_UNICODE_ON_DISK,
+};
+
+// create dispositions and options of NtCreateFile
+const FILE_SUPERSEDE: u32 = 0;
+const FILE_OPEN: u32 = 1;
+const FILE_CREATE: u32 = 2;
+const FILE_OVERWRITE: u32 = 4;
+const FILE_OVERWRITE_IF: u32 = 5;
+const FILE_DIRECTORY_FILE: u32 = 0x0000_0001;
+const FILE_NON_DIRECTORY_FILE: u32 = 0x0000_0040;
+
+#[derive(Debug)]
+pub struct Dokan<B>
+where
+    B: Backend + std::fmt::Debug + Send + Sync + 'static,
+{
+    fs: Arc<FileSystem<B>>,
+}
+
+/// An open file or directory of the mount.
+#[derive(Debug)]
+pub struct Handle {
+    ino: u64,
+    fh: u64,
+    dir: bool,
+    // removed from the backend when the last handle was cleaned up
+    deleted: AtomicBool,
+}
+
+impl<B: Backend + std::fmt::Debug + Send + Sync + 'static> Dokan<B> {
+    /// Validates the backend and the config, the same way `FuseBuilder::mount`
+    /// does, before building the filesystem.
+    pub fn new(backend: B, config: Config) -> Result<Dokan<B>> {
+        check::check_backend(&backend)?;
+        if let Some(ref path) = config.inode_table {
+            check::check_inode_table(path)?;
+        }
+        if let Some(ref path) = config.manifest {
+            check::check_manifest(path)?;
+        }
+        if let Some(block_size) = config.block_size {
+            blocks::check_block_size(block_size)?;
+        }
+        Ok(Dokan {
+            fs: Arc::new(FileSystem::new(backend, &config)),
+        })
+    }
+
+    /// The filesystem behind the mount, e.g. to `watch` it while it is mounted.
+    pub fn filesystem(&self) -> Arc<FileSystem<B>> {
+        self.fs.clone()
+    }
+
+    /// Mounts the filesystem at `mountpoint`, a drive letter such as `M:\` or an
+    /// empty NTFS directory, until it is unmounted.
+    pub fn mount(self, mountpoint: &str) -> Result<()> {
+        let mountpoint = U16CString::from_str(mountpoint).map_err(|e| {
+            Error::Other(format!("invalid mountpoint {}. error: {}", mountpoint, e))
+        })?;
+        Drive::new()
+            .mount_point(&mountpoint)
+            .mount(&self)
+            .map_err(|e| Error::Other(format!("dokan mount failed. error: {:?}", e)))
+    }
+
+    fn open(&self, attr: &FileAttr) -> Handle {
+        let dir = attr.kind == FileType::Directory;
+        Handle {
+            ino: attr.ino,
+            fh: if dir {
+                0
+            } else {
+                self.fs.open_handle(attr.ino)
+            },
+            dir,
+            deleted: AtomicBool::new(false),
+        }
+    }
+
+    // the inode of the directory of `path` and the name of `path` in it
+    fn parent_of(&self, path: &Path) -> Result<(u64, OsString)> {
+        let name = match path.file_name() {
+            Some(name) => name.to_os_string(),
+            None => return Err(Error::Fuse(libc::EACCES)),
+        };
+        let parent = match path.parent() {
+            Some(parent) => self.fs.lookup_path(parent)?.ino,
+            None => ROOT_INODE,
+        };
+        Ok((parent, name))
+    }
+
+    fn delete(&self, path: &Path, handle: &Handle) -> Result<()> {
+        let (parent, name) = self.parent_of(path)?;
+        if handle.dir {
+            self.fs.rmdir(parent, &name)
+        } else {
+            self.fs.unlink(parent, &name)
+        }
+    }
+}
+
+impl<'a, 'b: 'a, B> FileSystemHandler<'a, 'b> for Dokan<B>
+where
+    B: Backend + std::fmt::Debug + Send + Sync + 'static,
+{
+    type Context = Handle;
+
+    fn create_file(
+        &'b self,
+        file_name: &U16CStr,
+        _security_context: &DOKAN_IO_SECURITY_CONTEXT,
+        _desired_access: ACCESS_MASK,
+        _file_attributes: u32,
+        _share_access: u32,
+        create_disposition: u32,
+        create_options: u32,
+        _info: &mut OperationInfo<'a, 'b, Self>,
+    ) -> std::result::Result<CreateFileInfo<Handle>, OperationError> {
+        let path = relative_path(file_name);
+        log::trace!(
+            "create_file {:?}, disposition: {}, options: {:#x}",
+            path,
+            create_disposition,
+            create_options
+        );
+        let existing = match self.fs.lookup_path(&path) {
+            Ok(attr) => Some(attr),
+            Err(ref e) if e.errno(libc::EIO) == libc::ENOENT => None,
+            Err(e) => return Err(status(&e)),
+        };
+        let wants_dir = create_options & FILE_DIRECTORY_FILE != 0;
+        match existing {
+            Some(attr) => {
+                let dir = attr.kind == FileType::Directory;
+                if create_disposition == FILE_CREATE {
+                    return Err(OperationError::NtStatus(STATUS_OBJECT_NAME_COLLISION));
+                }
+                if dir && create_options & FILE_NON_DIRECTORY_FILE != 0 {
+                    return Err(OperationError::NtStatus(STATUS_FILE_IS_A_DIRECTORY));
+                }
+                if !dir && wants_dir {
+                    return Err(OperationError::NtStatus(STATUS_NOT_A_DIRECTORY));
+                }
+                let overwrite = create_disposition == FILE_SUPERSEDE
+                    || create_disposition == FILE_OVERWRITE
+                    || create_disposition == FILE_OVERWRITE_IF;
+                if !dir && overwrite {
+                    self.fs.truncate(attr.ino, 0).map_err(|e| status(&e))?;
+                }
+                Ok(CreateFileInfo {
+                    context: self.open(&attr),
+                    is_dir: dir,
+                    new_file_created: false,
+                })
+            }
+            None => {
+                if create_disposition == FILE_OPEN || create_disposition == FILE_OVERWRITE {
+                    return Err(OperationError::NtStatus(STATUS_OBJECT_NAME_NOT_FOUND));
+                }
+                let (parent, name) = self.parent_of(&path).map_err(|e| status(&e))?;
+                let (kind, perm) = if wants_dir {
+                    (FileType::Directory, attr::DIR_PERM)
+                } else {
+                    (FileType::RegularFile, attr::FILE_PERM)
+                };
+                let node = self
+                    .fs
+                    .mknod(parent, &name, kind, perm as u32, 0, 0, 0)
+                    .map_err(|e| status(&e))?;
+                Ok(CreateFileInfo {
+                    context: self.open(&node.attr()),
+                    is_dir: wants_dir,
+                    new_file_created: true,
+                })
+            }
+        }
+    }
+
+    fn cleanup(
+        &'b self,
+        file_name: &U16CStr,
+        info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Handle,
+    ) {
+        let path = relative_path(file_name);
+        if info.delete_on_close() {
+            match self.delete(&path, context) {
+                Ok(()) => context.deleted.store(true, Ordering::SeqCst),
+                Err(e) => log::error!("delete {:?} failed. error: {}", path, e),
+            }
+        } else if !context.dir {
+            if let Err(e) = self.fs.flush(context.ino) {
+                log::error!("flush {:?} failed. error: {}", path, e);
+            }
+        }
+    }
+
+    fn close_file(
+        &'b self,
+        file_name: &U16CStr,
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Handle,
+    ) {
+        if context.dir {
+            return;
+        }
+        self.fs.close_handle(context.fh);
+        if context.deleted.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Err(e) = self.fs.release(context.ino) {
+            log::error!(
+                "release {:?} failed. error: {}",
+                relative_path(file_name),
+                e
+            );
+        }
+    }
+
+    fn read_file(
+        &'b self,
+        _file_name: &U16CStr,
+        offset: i64,
+        buffer: &mut [u8],
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Handle,
+    ) -> std::result::Result<u32, OperationError> {
+        let mut content = None;
+        self.fs.read(
+            context.ino,
+            context.fh,
+            false,
+            offset as usize,
+            buffer.len(),
+            |result| content = Some(result),
+        );
+        let data = content
+            .unwrap_or_else(|| Ok(vec![]))
+            .map_err(|e| status(&e))?;
+        let length = std::cmp::min(data.len(), buffer.len());
+        buffer[..length].copy_from_slice(&data[..length]);
+        Ok(length as u32)
+    }
+
+    fn write_file(
+        &'b self,
+        _file_name: &U16CStr,
+        offset: i64,
+        buffer: &[u8],
+        info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Handle,
+    ) -> std::result::Result<u32, OperationError> {
+        let offset = if info.write_to_eof() {
+            self.fs.getattr(context.ino).map_err(|e| status(&e))?.size
+        } else {
+            offset as u64
+        };
+        self.fs
+            .write(context.ino, offset, buffer)
+            .map_err(|e| status(&e))
+    }
+
+    fn flush_file_buffers(
+        &'b self,
+        _file_name: &U16CStr,
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Handle,
+    ) -> std::result::Result<(), OperationError> {
+        if context.dir {
+            return Ok(());
+        }
+        self.fs.flush(context.ino).map_err(|e| status(&e))
+    }
+
+    fn get_file_information(
+        &'b self,
+        _file_name: &U16CStr,
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Handle,
+    ) -> std::result::Result<FileInfo, OperationError> {
+        let attr = self.fs.getattr(context.ino).map_err(|e| status(&e))?;
+        Ok(FileInfo {
+            attributes: attributes(&attr),
+            creation_time: attr.crtime,
+            last_access_time: attr.atime,
+            last_write_time: attr.mtime,
+            file_size: attr.size,
+            number_of_links: attr.nlink,
+            file_index: attr.ino,
+        })
+    }
+
+    fn find_files(
+        &'b self,
+        _file_name: &U16CStr,
+        mut fill_find_data: impl FnMut(&FindData) -> std::result::Result<(), FillDataError>,
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Handle,
+    ) -> std::result::Result<(), OperationError> {
+        let mut cookie = 0;
+        loop {
+            let nodes = self
+                .fs
+                .readdir(context.ino, context.fh, cookie)
+                .map_err(|e| status(&e))?;
+            cookie = match nodes.last() {
+                Some(last) => last.cookie(),
+                None => return Ok(()),
+            };
+            for node in nodes {
+                let name = node.name();
+                if name.is_empty() {
+                    continue;
+                }
+                let attr = node.attr();
+                let data = FindData {
+                    attributes: attributes(&attr),
+                    creation_time: attr.crtime,
+                    last_access_time: attr.atime,
+                    last_write_time: attr.mtime,
+                    file_size: attr.size,
+                    file_name: U16CString::from_os_str(name)
+                        .map_err(|_| OperationError::NtStatus(STATUS_OBJECT_NAME_INVALID))?,
+                };
+                match fill_find_data(&data) {
+                    Ok(()) => {}
+                    // the buffer of the request is full
+                    Err(FillDataError::BufferFull) => return Ok(()),
+                    Err(e) => {
+                        log::error!("fill_find_data {:?} failed. error: {:?}", node.path(), e);
+                        return Err(OperationError::NtStatus(STATUS_INTERNAL_ERROR));
+                    }
+                }
+            }
+        }
+    }
+
+    fn delete_file(
+        &'b self,
+        _file_name: &U16CStr,
+        _info: &OperationInfo<'a, 'b, Self>,
+        _context: &'a Handle,
+    ) -> std::result::Result<(), OperationError> {
+        // the file is removed by cleanup once the last handle is closed
+        Ok(())
+    }
+
+    fn delete_directory(
+        &'b self,
+        _file_name: &U16CStr,
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Handle,
+    ) -> std::result::Result<(), OperationError> {
+        let children = self
+            .fs
+            .readdir(context.ino, context.fh, 0)
+            .map_err(|e| status(&e))?;
+        if children.is_empty() {
+            Ok(())
+        } else {
+            Err(OperationError::NtStatus(STATUS_DIRECTORY_NOT_EMPTY))
+        }
+    }
+
+    fn set_end_of_file(
+        &'b self,
+        _file_name: &U16CStr,
+        offset: i64,
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Handle,
+    ) -> std::result::Result<(), OperationError> {
+        self.fs
+            .truncate(context.ino, offset as u64)
+            .map(|_| ())
+            .map_err(|e| status(&e))
+    }
+
+    fn set_allocation_size(
+        &'b self,
+        file_name: &U16CStr,
+        alloc_size: i64,
+        info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Handle,
+    ) -> std::result::Result<(), OperationError> {
+        // objects have no preallocated space, only shrinking changes the content
+        let size = self.fs.getattr(context.ino).map_err(|e| status(&e))?.size;
+        if (alloc_size as u64) < size {
+            self.set_end_of_file(file_name, alloc_size, info, context)?;
+        }
+        Ok(())
+    }
+
+    fn get_disk_free_space(
+        &'b self,
+        _info: &OperationInfo<'a, 'b, Self>,
+    ) -> std::result::Result<DiskSpaceInfo, OperationError> {
+        let stat = self.fs.statfs(ROOT_INODE).map_err(|e| status(&e))?;
+        let frsize = stat.frsize as u64;
+        Ok(DiskSpaceInfo {
+            byte_count: stat.blocks * frsize,
+            free_byte_count: stat.blocks_free * frsize,
+            available_byte_count: stat.blocks_available * frsize,
+        })
+    }
+
+    fn get_volume_information(
+        &'b self,
+        _info: &OperationInfo<'a, 'b, Self>,
+    ) -> std::result::Result<VolumeInfo, OperationError> {
+        Ok(VolumeInfo {
+            name: U16CString::from_str("ossfs").unwrap(),
+            serial_number: 0,
+            max_component_length: 255,
+            fs_flags: FILE_CASE_SENSITIVE_SEARCH | FILE_CASE_PRESERVED_NAMES | FILE_UNICODE_ON_DISK,
+            fs_name: U16CString::from_str("ossfs").unwrap(),
+        })
+    }
+}
+
+/// The path relative to the mount root of `file_name`, e.g. `\dir\file` of a
+/// Dokan request.
+pub fn relative_path(file_name: &U16CStr) -> PathBuf {
+    file_name
+        .to_string_lossy()
+        .split('\\')
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+fn attributes(attr: &FileAttr) -> u32 {
+    if attr.kind == FileType::Directory {
+        FILE_ATTRIBUTE_DIRECTORY
+    } else {
+        FILE_ATTRIBUTE_NORMAL
+    }
+}
+
+// the windows counterpart of `Error::errno`
+fn status(e: &Error) -> OperationError {
+    let status: NTSTATUS = match e.errno(libc::EIO) {
+        libc::ENOENT => STATUS_OBJECT_NAME_NOT_FOUND,
+        libc::EACCES | libc::EPERM => STATUS_ACCESS_DENIED,
+        libc::EEXIST => STATUS_OBJECT_NAME_COLLISION,
+        libc::ENOTDIR => STATUS_NOT_A_DIRECTORY,
+        libc::EISDIR => STATUS_FILE_IS_A_DIRECTORY,
+        libc::ENOTEMPTY => STATUS_DIRECTORY_NOT_EMPTY,
+        libc::ENOSYS => STATUS_NOT_IMPLEMENTED,
+        libc::EROFS => STATUS_MEDIA_WRITE_PROTECTED,
+        libc::ENOSPC => STATUS_DISK_FULL,
+        libc::ETIMEDOUT => STATUS_IO_TIMEOUT,
+        _ => STATUS_UNEXPECTED_IO_ERROR,
+    };
+    OperationError::NtStatus(status)
+}
+
+#[cfg(test)]
+mod test {
+    use super::relative_path;
+    use std::path::PathBuf;
+    use widestring::U16CString;
+
+    #[test]
+    fn test_relative_path() {
+        let path = |name: &str| relative_path(&U16CString::from_str(name).unwrap());
+        assert_eq!(path("\\"), PathBuf::new());
+        assert_eq!(path("\\dir\\file"), PathBuf::from("dir").join("file"));
+    }
+}
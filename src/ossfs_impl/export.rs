@@ -0,0 +1,334 @@
+//! Exports a tree of a backend into a ustar archive without going through a mount,
+//! failing with `Error::Conflict` if a file changes while it is exported.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::attr::{FileAttr, FileType};
+use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::node::Node;
+use crate::ossfs_impl::transfer::Progress;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const READ_CHUNK: usize = 4 * 1024 * 1024;
+const BLOCK: usize = 512;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ExportSummary {
+    pub files: u64,
+    pub directories: u64,
+    pub bytes: u64,
+}
+
+/// Writes the tree under `prefix`, relative to the root of `backend`, into `out`
+/// as a tar archive, calling `progress` after each file. An empty prefix exports
+/// the whole tree.
+pub fn export<B, P, W>(
+    backend: &B,
+    prefix: P,
+    out: W,
+    progress: &mut dyn FnMut(&Progress),
+) -> Result<ExportSummary>
+where
+    B: Backend,
+    P: AsRef<Path>,
+    W: Write,
+{
+    let root = backend.root().path();
+    let start = if prefix.as_ref().as_os_str().is_empty() {
+        root
+    } else {
+        root.join(prefix.as_ref())
+    };
+    let entries = list(backend, &start)?;
+    // a single file is named after itself
+    let base = match start.parent() {
+        Some(parent) if entries.len() == 1 && entries[0].path() == start => parent.to_path_buf(),
+        _ => start.clone(),
+    };
+    let files_total = entries
+        .iter()
+        .filter(|node| node.attr().kind != FileType::Directory)
+        .count() as u64;
+    let bytes_total: u64 = entries
+        .iter()
+        .map(|node| node.attr())
+        .filter(|attr| attr.kind != FileType::Directory)
+        .map(|attr| attr.size)
+        .sum();
+
+    let mut out = std::io::BufWriter::new(out);
+    let mut summary = ExportSummary::default();
+    // reads on condition of the etag until the backend turns out not to support it
+    let mut conditional = true;
+    for node in entries.iter() {
+        let path = node.path();
+        let name = path.strip_prefix(&base).unwrap_or(&path).to_path_buf();
+        let attr = node.attr();
+        if attr.kind == FileType::Directory {
+            write_header(&mut out, &name, &attr, b'5', 0)?;
+            summary.directories += 1;
+            continue;
+        }
+        write_header(&mut out, &name, &attr, b'0', attr.size)?;
+        let etag = node.etag();
+        let mut offset = 0;
+        while offset < attr.size {
+            let size = std::cmp::min(READ_CHUNK as u64, attr.size - offset) as usize;
+            let chunk = match (conditional, etag.as_ref()) {
+                (true, Some(etag)) => match backend.read_if_match(&path, offset, size, etag) {
+                    Err(Error::Fuse(libc::ENOSYS)) => {
+                        conditional = false;
+                        backend.read(&path, offset, size)
+                    }
+                    result => result,
+                },
+                _ => backend.read(&path, offset, size),
+            }
+            .map_err(|e| match e {
+                Error::NotFound(_) | Error::Conflict(_) => changed(&path),
+                e => e,
+            })?;
+            if chunk.is_empty() {
+                return Err(changed(&path));
+            }
+            offset += chunk.len() as u64;
+            if offset > attr.size {
+                return Err(changed(&path));
+            }
+            out.write_all(&chunk)?;
+        }
+        pad(&mut out, attr.size)?;
+        summary.files += 1;
+        summary.bytes += attr.size;
+        progress(&Progress {
+            path: name,
+            files_done: summary.files,
+            files_total,
+            bytes_done: summary.bytes,
+            bytes_total,
+        });
+    }
+    // the end of the archive
+    out.write_all(&[0; 2 * BLOCK])?;
+    out.flush()?;
+    Ok(summary)
+}
+
+fn changed(path: &Path) -> Error {
+    Error::Conflict(format!("{:?} changed while being exported", path))
+}
+
+// the entries below `start`, each directory followed by what is in it, by name
+fn list<B: Backend>(backend: &B, start: &Path) -> Result<Vec<Node>> {
+    let node = backend.get_node(start)?;
+    if node.attr().kind != FileType::Directory {
+        return Ok(vec![node]);
+    }
+    let mut entries = vec![];
+    let mut pending = vec![start.to_path_buf()];
+    while let Some(directory) = pending.pop() {
+        for child in backend.get_children(&directory)? {
+            if child.attr().kind == FileType::Directory {
+                pending.push(child.path());
+            }
+            entries.push(child);
+        }
+    }
+    // depth first by name, directories before their content
+    entries.sort_by(|a, b| a.path().cmp(&b.path()));
+    Ok(entries)
+}
+
+fn write_header<W: Write>(
+    out: &mut W,
+    name: &Path,
+    attr: &FileAttr,
+    kind: u8,
+    size: u64,
+) -> Result<()> {
+    let mut name = name.as_os_str().as_bytes().to_vec();
+    if kind == b'5' {
+        name.push(b'/');
+    }
+    let (prefix, short) = match split_name(&name) {
+        Some(split) => split,
+        None => {
+            // a GNU long name entry holds the name, the next header a truncation
+            let mut long = name.clone();
+            long.push(0);
+            let header = header(b"././@LongLink", b"", 0o644, 0, long.len() as u64, b'L');
+            out.write_all(&header)?;
+            out.write_all(&long)?;
+            pad(out, long.len() as u64)?;
+            (vec![], name[..100].to_vec())
+        }
+    };
+    let mtime = attr
+        .mtime
+        .duration_since(UNIX_EPOCH)
+        .map(|mtime| mtime.as_secs())
+        .unwrap_or(0);
+    let header = header(&short, &prefix, u32::from(attr.perm), mtime, size, kind);
+    out.write_all(&header)?;
+    Ok(())
+}
+
+// splits a name into the prefix and name fields of ustar, `None` if it does not fit
+fn split_name(name: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    if name.len() <= 100 {
+        return Some((vec![], name.to_vec()));
+    }
+    // the prefix ends at a slash, which is left out of both fields
+    let trimmed = if name.ends_with(b"/") {
+        &name[..name.len() - 1]
+    } else {
+        name
+    };
+    (0..trimmed.len())
+        .filter(|i| trimmed[*i] == b'/')
+        .find(|i| *i <= 155 && name.len() - i - 1 <= 100)
+        .map(|i| (name[..i].to_vec(), name[i + 1..].to_vec()))
+}
+
+fn header(name: &[u8], prefix: &[u8], mode: u32, mtime: u64, size: u64, kind: u8) -> Vec<u8> {
+    let mut header = vec![0u8; BLOCK];
+    header[..name.len()].copy_from_slice(name);
+    octal(&mut header[100..108], u64::from(mode & 0o7777));
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    if size < 1 << 33 {
+        octal(&mut header[124..136], size);
+    } else {
+        // base-256, the GNU extension for sizes beyond 11 octal digits
+        header[124] = 0x80;
+        for (i, byte) in header[128..136].iter_mut().enumerate() {
+            *byte = (size >> (8 * (7 - i))) as u8;
+        }
+    }
+    octal(&mut header[136..148], mtime);
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix);
+    // computed with the checksum field as spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|byte| u32::from(*byte)).sum();
+    octal(&mut header[148..155], u64::from(checksum));
+    header
+}
+
+// `value` in octal digits filling `field` but its last byte, which stays NUL
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+}
+
+// zeroes up to the next block after `size` bytes of content
+fn pad<W: Write>(out: &mut W, size: u64) -> Result<()> {
+    let rest = (size % BLOCK as u64) as usize;
+    if rest > 0 {
+        out.write_all(&vec![0u8; BLOCK - rest])?;
+    }
+    Ok(())
+}
+
+/// Paths of the entries of `archive`, written by `export`, with the sizes of the
+/// files, e.g. to check an export.
+pub fn entries(archive: &[u8]) -> Result<Vec<(PathBuf, u64)>> {
+    let mut entries = vec![];
+    let mut offset = 0;
+    let mut long_name = None;
+    while offset + BLOCK <= archive.len() {
+        let header = &archive[offset..offset + BLOCK];
+        if header.iter().all(|byte| *byte == 0) {
+            break;
+        }
+        let field = |range: std::ops::Range<usize>| {
+            let field = &header[range];
+            let end = field
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(field.len());
+            field[..end].to_vec()
+        };
+        let size = if header[124] & 0x80 != 0 {
+            header[128..136]
+                .iter()
+                .fold(0u64, |size, byte| size << 8 | u64::from(*byte))
+        } else {
+            let digits = String::from_utf8_lossy(&field(124..136)).into_owned();
+            u64::from_str_radix(digits.trim(), 8)
+                .map_err(|_| Error::Other(format!("invalid size at offset {}", offset)))?
+        };
+        let content = offset + BLOCK;
+        let end = content + size as usize;
+        if end > archive.len() {
+            return Err(Error::Other("the archive is truncated".to_owned()));
+        }
+        if header[156] == b'L' {
+            let name = &archive[content..end];
+            long_name = Some(name[..name.len().saturating_sub(1)].to_vec());
+        } else {
+            let name = match long_name.take() {
+                Some(name) => name,
+                None => {
+                    let prefix = field(345..500);
+                    let name = field(0..100);
+                    if prefix.is_empty() {
+                        name
+                    } else {
+                        [&prefix[..], &b"/"[..], &name[..]].concat()
+                    }
+                }
+            };
+            let name = std::ffi::OsStr::from_bytes(&name);
+            entries.push((PathBuf::from(name), size));
+        }
+        offset = content + (size as usize + BLOCK - 1) / BLOCK * BLOCK;
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{entries, export};
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_export() {
+        let backend = MockBackend::new();
+        backend.add_file("/data/a", b"hello");
+        backend.add_file("/data/dir/b", &vec![7u8; 1000]);
+        let long = format!("/data/{}/{}", "x".repeat(120), "y".repeat(120));
+        backend.add_file(&long, b"long");
+        backend.add_file("/other/c", b"not exported");
+
+        let mut archive = vec![];
+        let mut reported = 0;
+        let summary = export(&backend, "data", &mut archive, &mut |progress| {
+            reported = progress.files_done;
+            assert_eq!(progress.files_total, 3);
+        })
+        .unwrap();
+        assert_eq!(summary.files, 3);
+        assert_eq!(summary.bytes, 1009);
+        assert_eq!(reported, 3);
+        assert_eq!(archive.len() % 512, 0);
+
+        let entries = entries(&archive).unwrap();
+        let names: Vec<PathBuf> = entries.iter().map(|(name, _)| name.clone()).collect();
+        assert_eq!(names[0], PathBuf::from("a"));
+        assert!(names.contains(&PathBuf::from("dir/")));
+        assert!(names.contains(&PathBuf::from("dir/b")));
+        assert!(names.contains(&PathBuf::from(&long["/data/".len()..])));
+        let b = entries
+            .iter()
+            .find(|(name, _)| name.ends_with("b"))
+            .unwrap();
+        assert_eq!(b.1, 1000);
+    }
+}
@@ -1,35 +1,194 @@
 use crate::error::{Error, Result};
-use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::access::{OpenHandles, Plan};
+use crate::ossfs_impl::attr;
+use crate::ossfs_impl::attr::{FileAttr, FileType};
+use crate::ossfs_impl::backend::{key, Backend, Removal};
+use crate::ossfs_impl::blocks::{self, BlockCache, Version};
+use crate::ossfs_impl::breaker::CircuitBreaker;
+use crate::ossfs_impl::budget::{Budget, Request, RequestCounts};
+use crate::ossfs_impl::config::Config;
+use crate::ossfs_impl::direct_io::DirectIo;
+use crate::ossfs_impl::filter::Filter;
+use crate::ossfs_impl::handles::HandleTable;
+use crate::ossfs_impl::health::{self, Health};
+use crate::ossfs_impl::journal::Journal;
+use crate::ossfs_impl::labels::Labels;
 use crate::ossfs_impl::manager::InodeManager;
+use crate::ossfs_impl::manifest::{self, Entry};
 use crate::ossfs_impl::node::Node;
-use crate::ossfs_impl::stat::Stat;
-use fuse::{FileAttr, FileType};
+use crate::ossfs_impl::passthrough::Passthrough;
+use crate::ossfs_impl::pins::{Pin, Pins, RemoteChange};
+use crate::ossfs_impl::policy::{Access, Policy};
+use crate::ossfs_impl::prefetch_policy::PrefetchPolicy;
+use crate::ossfs_impl::quota::Quota;
+use crate::ossfs_impl::refresh::{self, Hotness};
+use crate::ossfs_impl::request_id::{self, Scope};
+use crate::ossfs_impl::singleflight::Group;
+use crate::ossfs_impl::stat::{Stat, Usage};
+use crate::ossfs_impl::stats::{self, Stats};
+use crate::ossfs_impl::trace::{Op, Tracer};
+use crate::ossfs_impl::trash;
+use crate::ossfs_impl::ttl::AdaptiveTtl;
+use crate::ossfs_impl::usage::{UsageCache, DU_BYTES_XATTR, DU_ENTRIES_XATTR};
+use crate::ossfs_impl::virtual_file::{VirtualFile, VirtualFiles};
+use crate::ossfs_impl::watch::{Event, Watches};
+use crate::ossfs_impl::writeback::{Pending, WriteBack};
 use id_tree::InsertBehavior::*;
 use id_tree::{Node as TreeNode, NodeId, Tree, TreeBuilder};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
-use std::time::SystemTime;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 
 pub type Inode = u64;
 
 pub const ROOT_INODE: Inode = 1;
 
+/// Extended attribute choosing the storage class a file is uploaded in.
+pub const STORAGE_CLASS_XATTR: &str = "user.ossfs.storage-class";
+
+/// Extended attribute holding the entity tag the backend has for a file.
+pub const ETAG_XATTR: &str = "user.ossfs.etag";
+
+/// Extended attribute holding the hex md5 of a file, known when its entity tag is
+/// one, as for objects of s3 put in a single part without kms encryption.
+pub const MD5_XATTR: &str = "user.ossfs.md5";
+
+/// Extended attribute of a file telling whether what was written to it reached the
+/// backend: `clean`, `dirty`, `uploading` or `error: ` and the error of the last
+/// upload.
+pub const SYNC_XATTR: &str = "user.ossfs.sync";
+
+/// Prefix of the extended attributes mapped to the tags of an object, the tag
+/// `team` of a file is its attribute `user.s3.tag.team`.
+pub const TAG_XATTR_PREFIX: &str = "user.s3.tag.";
+
+// limits of the tags of an object in s3
+const MAX_TAGS: usize = 10;
+const MAX_TAG_KEY: usize = 128;
+const MAX_TAG_VALUE: usize = 256;
+
+// interval between checks of the soft limit of the metadata memory
+const EVICTION_INTERVAL: Duration = Duration::from_secs(1);
+
+// entries removed between progress lines of a recursive rmdir in the log
+const REMOVE_PROGRESS: u64 = 1000;
+
+/// A listing of a directory fetched in the background. Its pages are added to the
+/// tree as they arrive, so `readdir` does not wait for the whole directory.
+#[derive(Debug, Default)]
+struct Listing {
+    // the result of the listing once it is over
+    state: Mutex<Option<Result<()>>>,
+    changed: Condvar,
+    // whether some of its entries were served before it was over, in the order
+    // they were fetched, which the listing then keeps
+    served: AtomicBool,
+}
+
+impl Listing {
+    fn progressed(&self) {
+        // taken so that the notification can not slip in between the check and the
+        // wait of `wait_listed`
+        let _state = self.state.lock().unwrap();
+        self.changed.notify_all();
+    }
+
+    fn finish(&self, result: Result<()>) {
+        *self.state.lock().unwrap() = Some(result);
+        self.changed.notify_all();
+    }
+}
+
+/// The backend independent part of the filesystem: the inode tree, caching of
+/// metadata and the policies guarding backend calls. `Fuse` translates kernel
+/// requests into calls of its methods.
 #[derive(Debug)]
 pub struct FileSystem<B>
 where
     B: Backend + std::fmt::Debug + Send + Sync + 'static,
 {
-    backend: B,
+    backend: Arc<B>,
     nodes_manager: std::sync::Arc<std::sync::RwLock<InodeManager>>,
     counter: crate::counter::Counter,
-    runtime: tokio::runtime::Runtime,
+    backend_pool: Mutex<threadpool::ThreadPool>,
+    backend_timeout: Option<Duration>,
+    breaker: Arc<CircuitBreaker>,
+    // outcome of the periodic probes of the backend, `None` unless it is probed
+    health: Option<Arc<Health>>,
+    budget: Arc<Budget>,
+    root_path: PathBuf,
+    // listings being fetched in the background, removed once they are over
+    listings: Arc<Mutex<HashMap<u64, Arc<Listing>>>>,
+    readdir_limit: usize,
+    watches: Arc<Watches>,
+    watch_interval: Option<Duration>,
+    // `None` unless a periodic summary is logged
+    stats: Option<Arc<Stats>>,
+    // backend fetches in flight, shared by concurrent callers asking for the same
+    inflight_nodes: Group<PathBuf, Node>,
+    inflight_reads: Group<(PathBuf, u64, u64), Vec<u8>>,
+    writeback: WriteBack,
+    // deleted files are kept in the trash this long, `None` deletes them right away
+    trash_retention: Option<Duration>,
+    // entries of the backend hidden from the mount are never added to the tree
+    filter: Arc<Filter>,
+    // served from callbacks instead of the backend
+    virtual_files: VirtualFiles,
+    // writes beyond it fail with EFBIG, `None` does not limit them
+    max_file_size: Option<u64>,
+    // creates in directories with as many entries fail with ENOSPC, listings of
+    // larger directories are warned about. `None` does not limit them
+    max_dir_entries: Option<usize>,
+    // names missing from a listing this recent are not looked up in the backend
+    listing_window: Option<Duration>,
+    // lookups in directories last listed with at most as many entries list them
+    // again instead of heading the name, `None` always heads it
+    list_below: Option<usize>,
+    // prefixes each uid may read or write, everything without rules
+    policy: Arc<Policy>,
+    // rmdir removes directories with entries and everything below them
+    recursive_rmdir: bool,
+    // the tags of objects are exposed as extended attributes
+    object_tags: bool,
+    // recursive usages of directories
+    usages: Arc<UsageCache>,
+    inflight_usages: Group<PathBuf, Usage>,
+    // getattr reports the recursive bytes of directories as their size
+    dir_sizes: bool,
+    // how the open handles read their files, `None` unless reads adapt to them
+    open_handles: Option<OpenHandles>,
+    // blocks of the files read, `None` unless reads are rounded to blocks
+    blocks: Option<Arc<BlockCache>>,
+    // picks the blocks fetched ahead of the reads, `None` does not fetch ahead
+    prefetch_policy: Option<Arc<dyn PrefetchPolicy>>,
+    // the version of its file each open handle reads
+    pins: Pins,
+    remote_change: RemoteChange,
+    // files opened with direct_io and the handles opening them so
+    direct_io: DirectIo,
+    direct_handles: Mutex<HashSet<u64>>,
+    // the local files read by the open handles, `None` unless reads pass through
+    passthrough: Option<Passthrough>,
+    // records the accesses, `None` unless they are traced
+    tracer: Option<Tracer>,
+    // name the mount, those of the environment overridden by the config
+    labels: Labels,
+    // accesses of the inodes, `None` unless the hottest ones are refreshed
+    hotness: Option<Arc<Hotness>>,
+    // kernel ttls of the inodes, `None` unless they adapt to how often they change
+    ttls: Option<AdaptiveTtl>,
+    // what the mount may add to the bucket, `None` without quota
+    quota: Option<Quota>,
+    // size reported for buckets which have none
+    capacity: u64,
 }
 
-unsafe impl<B: Backend + std::fmt::Debug + Send + Sync> Send for FileSystem<B> {}
-unsafe impl<B: Backend + std::fmt::Debug + Send + Sync> Sync for FileSystem<B> {}
-
-impl<B: Backend + std::fmt::Debug + Send + Sync> FileSystem<B> {
-    pub fn new(backend: B) -> FileSystem<B> {
+impl<B: Backend + std::fmt::Debug + Send + Sync + 'static> FileSystem<B> {
+    pub fn new(backend: B, config: &Config) -> FileSystem<B> {
         let root: Node = backend.root();
         let mut ino_mapper = HashMap::new();
 
@@ -40,145 +199,1088 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> FileSystem<B> {
         ino_mapper.insert(root.inode(), root_index);
         let mut children_name = HashMap::new();
         children_name.insert(root.inode(), HashMap::new());
-        FileSystem {
+        let mut nodes_manager = InodeManager::new(nodes_tree, ino_mapper, children_name);
+        if let Some(ref path) = config.inode_table {
+            nodes_manager.handles = Some(
+                HandleTable::open(path)
+                    .unwrap_or_else(|e| panic!("open inode table {:?}. error: {}", path, e)),
+            );
+        }
+        nodes_manager.soft_limit = config.metadata_soft_limit;
+        nodes_manager.consistency_window = config.consistency_window;
+        nodes_manager.hard_limit = config.metadata_hard_limit;
+        let memory = nodes_manager.memory.clone();
+        let budget = Arc::new(Budget::new(config.request_rate));
+        let blocks = config
+            .block_size
+            .map(|block_size| Arc::new(BlockCache::new(block_size, config.block_cache)));
+        let mut labels = Labels::from_env();
+        labels.extend(&config.labels);
+        let backend = Arc::new(backend);
+        let breaker = Arc::new(CircuitBreaker::new(
+            config.breaker_threshold,
+            config.breaker_cooldown,
+        ));
+        let health = config.health_interval.map(|interval| {
+            let health = Arc::new(Health::default());
+            health::spawn_prober(
+                backend.clone(),
+                health.clone(),
+                breaker.clone(),
+                budget.clone(),
+                interval,
+            );
+            health
+        });
+        let fs = FileSystem {
             backend,
-            nodes_manager: std::sync::Arc::new(std::sync::RwLock::new(InodeManager::new(
-                nodes_tree,
-                ino_mapper,
-                children_name,
-            ))),
+            nodes_manager: std::sync::Arc::new(std::sync::RwLock::new(nodes_manager)),
             counter: crate::counter::Counter::new(1),
-            runtime: tokio::runtime::Runtime::new().unwrap(),
+            backend_pool: Mutex::new(threadpool::ThreadPool::with_name(
+                "ossfs-backend".to_owned(),
+                config.backend_threads,
+            )),
+            backend_timeout: config.backend_timeout,
+            breaker,
+            health,
+            budget: budget.clone(),
+            root_path: root.path(),
+            listings: Arc::new(Mutex::new(HashMap::new())),
+            readdir_limit: config.readdir_limit,
+            watches: Arc::new(Watches::new(root.path())),
+            watch_interval: config.watch_interval,
+            stats: config.stats_interval.map(|interval| {
+                let stats = Arc::new(Stats::new());
+                stats.gauge("metadata", memory);
+                stats.requests(budget.clone());
+                stats.labels(labels.clone());
+                if let Some(ref health) = health {
+                    stats.health(health.clone());
+                }
+                if let Some(ref blocks) = blocks {
+                    stats.gauge("blocks fetched", blocks.fetched.clone());
+                    stats.gauge("blocks wasted", blocks.wasted.clone());
+                }
+                stats::spawn_logger(stats.clone(), interval);
+                stats
+            }),
+            inflight_nodes: Group::new(),
+            inflight_reads: Group::new(),
+            writeback: WriteBack::new(
+                config.dirty_high_water,
+                config.upload_concurrency,
+                config.upload_retries,
+                budget,
+                config.write_journal.as_ref().map(|dir| {
+                    Journal::open(dir)
+                        .unwrap_or_else(|e| panic!("open write journal {:?}. error: {}", dir, e))
+                }),
+            ),
+            trash_retention: config.trash_retention,
+            filter: Arc::new(config.filter.clone()),
+            virtual_files: VirtualFiles::default(),
+            max_file_size: config.max_file_size,
+            max_dir_entries: config.max_dir_entries,
+            listing_window: config.listing_window,
+            list_below: config.list_below,
+            policy: Arc::new(config.policy.clone()),
+            recursive_rmdir: config.recursive_rmdir && config.trash_retention.is_none(),
+            object_tags: config.object_tags,
+            usages: Arc::new(UsageCache::new(config.usage_ttl)),
+            inflight_usages: Group::new(),
+            dir_sizes: config.dir_sizes,
+            open_handles: if config.adaptive_reads && !config.enable_cache {
+                Some(OpenHandles::new())
+            } else {
+                None
+            },
+            blocks,
+            prefetch_policy: config.prefetch_policy.clone(),
+            pins: Pins::new(),
+            remote_change: config.remote_change,
+            direct_io: config.direct_io.clone(),
+            direct_handles: Mutex::new(HashSet::new()),
+            passthrough: if config.passthrough {
+                Some(Passthrough::default())
+            } else {
+                None
+            },
+            tracer: config.access_trace.as_ref().map(|path| {
+                Tracer::create(path, &labels)
+                    .unwrap_or_else(|e| panic!("create access trace {:?}. error: {}", path, e))
+            }),
+            labels,
+            hotness: config.refresh_hottest.map(|_| Arc::new(Hotness::default())),
+            ttls: config
+                .adaptive_ttl
+                .map(|(min, max)| AdaptiveTtl::new(min, max)),
+            quota: if config.quota_bytes.is_some() || config.quota_files.is_some() {
+                Some(Quota::new(config.quota_bytes, config.quota_files))
+            } else {
+                None
+            },
+            capacity: config.capacity,
+        };
+        for (path, file) in config.virtual_files.iter() {
+            fs.add_virtual_file(path, file.clone());
+        }
+        let recovered = fs.writeback.recover(&*fs.backend);
+        if !recovered.is_empty() {
+            log::warn!(
+                "uploaded {} files left unflushed by an earlier mount",
+                recovered.len()
+            );
+        }
+        if let Some(ref path) = config.manifest {
+            let started = Instant::now();
+            match manifest::load(path) {
+                Ok(entries) => log::info!(
+                    "added {} files of manifest {:?} in {:?}",
+                    fs.ingest(&entries),
+                    path,
+                    started.elapsed()
+                ),
+                Err(e) => log::error!("{}, listing the backend instead", e),
+            }
+        }
+        if let Some(interval) = fs.watch_interval {
+            fs.spawn_watcher(interval);
+        }
+        if let Some(soft_limit) = config.metadata_soft_limit {
+            fs.spawn_evictor(soft_limit);
+        }
+        if let Some(retention) = fs.trash_retention {
+            trash::spawn_purger(Arc::downgrade(&fs.backend), fs.root_path.clone(), retention);
         }
+        if let (Some(count), Some(hotness)) = (config.refresh_hottest, fs.hotness.clone()) {
+            refresh::spawn_refresher(
+                &fs.backend,
+                &fs.nodes_manager,
+                hotness,
+                fs.budget.clone(),
+                config.file_ttl,
+                count,
+            );
+        }
+        fs
     }
 
-    pub fn lookup(&self, ino: u64, name: &OsStr) -> Result<FileAttr> {
-        let _start = self.counter.start("fs::lookup".to_owned());
-        {
-            let nodes_manager = self.nodes_manager.read().unwrap();
-            if let Some(child_node) = nodes_manager.get_child_by_name(ino, name)? {
-                return Ok(child_node.attr().clone());
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Requests sent to the backend so far.
+    pub fn requests(&self) -> RequestCounts {
+        self.budget.counts()
+    }
+
+    /// Estimated bytes taken by the cached metadata.
+    pub fn metadata_bytes(&self) -> u64 {
+        self.nodes_manager.read().unwrap().memory()
+    }
+
+    /// Evicts cold subtrees above the soft limit until the filesystem is dropped.
+    fn spawn_evictor(&self, soft_limit: u64) {
+        let nodes_manager = Arc::downgrade(&self.nodes_manager);
+        let spawned = std::thread::Builder::new()
+            .name("ossfs-evictor".to_owned())
+            .spawn(move || loop {
+                std::thread::sleep(EVICTION_INTERVAL);
+                let nodes_manager = match nodes_manager.upgrade() {
+                    Some(nodes_manager) => nodes_manager,
+                    None => return,
+                };
+                if nodes_manager.read().unwrap().memory() > soft_limit {
+                    nodes_manager.write().unwrap().evict(soft_limit, ROOT_INODE);
+                }
+            });
+        if let Err(e) = spawned {
+            log::error!("failed to spawn the evictor thread. error: {}", e);
+        }
+    }
+
+    /// Adds the read-only file at `path`, relative to the mount root, whose content
+    /// is generated by `file` whenever it is read.
+    pub fn add_virtual_file<P: AsRef<Path>>(&self, path: P, file: VirtualFile) {
+        let relative = path
+            .as_ref()
+            .strip_prefix("/")
+            .unwrap_or_else(|_| path.as_ref());
+        self.virtual_files
+            .insert(self.root_path.join(relative), file);
+    }
+
+    /// Adds the files of a manifest, with keys relative to the root of the backend,
+    /// and the directories leading to them to the tree. The directories count as
+    /// listed, so they are served without listing the backend. Returns the number
+    /// of files added.
+    pub fn ingest(&self, entries: &[Entry]) -> usize {
+        let mut nodes_manager = self.nodes_manager.write().unwrap();
+        let root_attr = nodes_manager.get_node_by_inode(ROOT_INODE).unwrap().attr();
+        let mut dirs: HashMap<PathBuf, (NodeId, u64)> = HashMap::new();
+        dirs.insert(
+            self.root_path.clone(),
+            (nodes_manager.ino_mapper[&ROOT_INODE].clone(), ROOT_INODE),
+        );
+        let mut files = 0;
+        for entry in entries {
+            let relative = key::key_to_path(&entry.key);
+            if relative.components().any(|component| match component {
+                std::path::Component::Normal(_) => false,
+                _ => true,
+            }) {
+                log::warn!("skipping {:?} of the manifest", entry.key);
+                continue;
+            }
+            let path = self.root_path.join(&relative);
+            // directory markers only create the directory
+            let dir = entry.key.ends_with('/');
+            let attr = if dir {
+                FileAttr {
+                    ino: 0,
+                    ..root_attr
+                }
+            } else {
+                FileAttr {
+                    uid: root_attr.uid,
+                    gid: root_attr.gid,
+                    ..attr::synthetic(FileType::RegularFile, entry.size, entry.mtime)
+                }
+            };
+            let node = Node::new(0, 0, path.clone(), attr).with_etag(entry.md5.clone());
+            if !exposed(&self.filter, &self.root_path, &node) {
+                continue;
+            }
+            let parent = if dir {
+                path.as_path()
+            } else {
+                path.parent().unwrap()
+            };
+            let (index, ino) = match ingest_dir(&mut nodes_manager, &mut dirs, parent, &root_attr) {
+                Some(parent) => parent,
+                None => continue,
+            };
+            if !dir {
+                nodes_manager.add_child(&index, ino, &node);
+                files += 1;
             }
         }
+        let now = Instant::now();
+        for (_, ino) in dirs.values() {
+            nodes_manager.sort_children(*ino);
+            let entries = nodes_manager
+                .children_name
+                .get(ino)
+                .map(|children| children.len())
+                .unwrap_or(0);
+            nodes_manager.sizes.insert(*ino, entries);
+            nodes_manager.listed.insert(*ino, now);
+        }
+        files
+    }
 
-        Ok(self.fetch_child_by_name(ino, name)?.attr().clone())
+    /// Subscribes to the changes of the direct children of the directory at `path`,
+    /// relative to the mount root, as found by listing it every watch interval.
+    /// Events carry paths relative to the mount root, the subscription ends when
+    /// the receiver is dropped. The first poll reports nothing.
+    pub fn watch<P: AsRef<Path>>(&self, path: P) -> mpsc::Receiver<Event> {
+        if self.watch_interval.is_none() {
+            log::warn!("watching {:?} while watches are disabled", path.as_ref());
+        }
+        let relative = path
+            .as_ref()
+            .strip_prefix("/")
+            .unwrap_or_else(|_| path.as_ref());
+        let path = if relative.as_os_str().is_empty() {
+            self.root_path.clone()
+        } else {
+            self.root_path.join(relative)
+        };
+        self.watches.subscribe(path)
     }
 
-    pub fn getattr(&self, ino: u64) -> Option<FileAttr> {
-        let _start = self.counter.start("fs::getattr".to_owned());
-        let nodes_manager = self.nodes_manager.read().unwrap();
-        let node = nodes_manager.get_node_by_inode(ino).unwrap();
-        Some(node.attr().clone())
+    #[cfg(test)]
+    fn poll_changes(&self) {
+        poll_changes(
+            &*self.backend,
+            &self.nodes_manager,
+            &self.watches,
+            &self.budget,
+            &self.filter,
+            &self.root_path,
+        );
     }
 
-    pub fn add_node_locally(&self, parent_index: &NodeId, parent_inode: u64, child_node: &Node) {
-        let _start = self.counter.start("fs::add_node_locally".to_owned());
-        let mut nodes_manager = self.nodes_manager.write().unwrap();
-        let next_inode = nodes_manager.next_inode();
-        child_node.set_inode(next_inode, parent_inode);
-        let child_index = nodes_manager
-            .nodes_tree
-            .insert(TreeNode::new(child_node.clone()), UnderNode(parent_index))
-            .unwrap();
-        nodes_manager.ino_mapper.insert(next_inode, child_index);
-        match nodes_manager.children_name.get_mut(&parent_inode) {
-            Some(children) => {
-                children.insert(
-                    child_node.path().file_name().unwrap().to_owned(),
-                    child_node.inode(),
+    #[cfg(test)]
+    fn refresh_attributes(&self, count: usize) -> usize {
+        match self.hotness {
+            Some(ref hotness) => refresh::refresh(
+                &*self.backend,
+                &self.nodes_manager,
+                hotness,
+                &self.budget,
+                count,
+            ),
+            None => 0,
+        }
+    }
+
+    /// Polls the watched directories until the filesystem is dropped.
+    fn spawn_watcher(&self, interval: Duration) {
+        let backend = self.backend.clone();
+        let nodes_manager = self.nodes_manager.clone();
+        let watches = self.watches.clone();
+        let budget = self.budget.clone();
+        let filter = self.filter.clone();
+        let root_path = self.root_path.clone();
+        let spawned = std::thread::Builder::new()
+            .name("ossfs-watcher".to_owned())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                // the filesystem is gone
+                if Arc::strong_count(&watches) == 1 {
+                    return;
+                }
+                poll_changes(
+                    &*backend,
+                    &nodes_manager,
+                    &watches,
+                    &budget,
+                    &filter,
+                    &root_path,
+                );
+            });
+        if let Err(e) = spawned {
+            log::error!("failed to spawn the watcher thread. error: {}", e);
+        }
+    }
+
+    fn counted<T, F: FnOnce() -> Result<T>>(&self, op: &'static str, f: F) -> Result<T> {
+        let _operation = request_id::operation();
+        let in_flight = self.stats.as_ref().map(|stats| stats.enter(op));
+        let result = f();
+        drop(in_flight);
+        if let Err(ref e) = result {
+            let level = match *e {
+                // missing entries are looked up all the time
+                _ if e.errno(libc::EIO) == libc::ENOENT => None,
+                // answers to what the filesystem was asked rather than failures
+                Error::Fuse(code) if code != libc::EIO => Some(log::Level::Debug),
+                Error::PermissionDenied(_) | Error::Conflict(_) => Some(log::Level::Debug),
+                _ => Some(log::Level::Warn),
+            };
+            if let Some(level) = level {
+                let labels = if self.labels.is_empty() {
+                    String::new()
+                } else {
+                    format!(", {}", self.labels)
+                };
+                log::log!(
+                    level,
+                    "{} failed, request id: {}{}. {}",
+                    op,
+                    request_id::current_or_next(),
+                    labels,
+                    e
                 );
             }
+        }
+        if let Some(ref stats) = self.stats {
+            stats.record(op, result.is_ok(), 0);
+        }
+        result
+    }
+
+    /// Records how long the kernel request `op` waited for a thread since `since`.
+    pub(crate) fn queued(&self, op: &'static str, since: Instant) {
+        if let Some(ref stats) = self.stats {
+            stats.waited(op, since.elapsed());
+        }
+    }
+
+    /// Runs `f` against the backend unless the circuit breaker is open, in which case
+    /// the request fails fast and a recovery probe may be sent in the background.
+    /// Only metadata already cached locally can be served while the backend is down.
+    /// `request` is what `f` costs, it waits for the request budget.
+    fn call_backend<T, F>(&self, op: &'static str, request: Option<Request>, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&B) -> Result<T> + Send + 'static,
+    {
+        if self.breaker.is_open() {
+            if self.breaker.try_probe() {
+                self.probe_backend();
+            }
+            return Err(Error::Unavailable(format!("{} skipped, circuit open", op)));
+        }
+        if let Some(request) = request {
+            self.budget.acquire(request);
+        }
+        let result = self.call_backend_with_timeout(op, f);
+        self.breaker.record(result.as_ref().map(|_| ()));
+        if let Some(ref stats) = self.stats {
+            stats.record(op, result.is_ok(), 0);
+        }
+        result
+    }
+
+    fn probe_backend(&self) {
+        let root_path = self.root_path.clone();
+        let breaker = self.breaker.clone();
+        let backend = self.backend.clone();
+        let budget = self.budget.clone();
+        self.backend_pool.lock().unwrap().execute(move || {
+            log::info!("probing backend with {:?}", root_path);
+            budget.acquire(Request::Head);
+            breaker.probe_finished(backend.get_node(&root_path).err().as_ref());
+        });
+    }
+
+    /// Runs `f` against the backend, giving up after the configured timeout so that
+    /// a hung backend connection can not block a kernel request forever.
+    /// The call itself keeps running on the backend pool until it returns.
+    fn call_backend_with_timeout<T, F>(&self, op: &'static str, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&B) -> Result<T> + Send + 'static,
+    {
+        let stats = self.stats.clone();
+        let timeout = match self.backend_timeout {
+            Some(timeout) => timeout,
             None => {
-                let mut map = HashMap::new();
-                map.insert(
-                    child_node.path().file_name().unwrap().to_owned(),
-                    child_node.inode(),
-                );
-                nodes_manager.children_name.insert(parent_inode, map);
+                let _in_flight = stats.as_ref().map(|stats| stats.enter(op));
+                return f(&self.backend);
+            }
+        };
+        let (sender, receiver) = mpsc::channel();
+        let backend = self.backend.clone();
+        let queued = Instant::now();
+        let id = request_id::current();
+        self.backend_pool.lock().unwrap().execute(move || {
+            let _scope = id.map(Scope::enter);
+            if let Some(ref stats) = stats {
+                stats.waited(op, queued.elapsed());
+            }
+            let _in_flight = stats.as_ref().map(|stats| stats.enter(op));
+            let _ = sender.send(f(&backend));
+        });
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                log::error!("{} timed out after {:?}", op, timeout);
+                Err(Error::Timeout(format!("{} exceeded {:?}", op, timeout)))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                log::error!("{} aborted", op);
+                Err(Error::Backend(format!("{} aborted", op)))
+            }
+        }
+    }
+
+    pub fn lookup(&self, ino: u64, name: &OsStr) -> Result<FileAttr> {
+        let _start = self.counter.start("fs::lookup".to_owned());
+        self.counted("lookup", || {
+            self.resolve(ino)?;
+            self.nodes_manager.read().unwrap().touch(ino);
+            // nfs looks up "." and ".." to turn a handle back into a dentry
+            if name == "." {
+                return self.getattr(ino);
+            }
+            if name == ".." {
+                let parent = self
+                    .nodes_manager
+                    .read()
+                    .unwrap()
+                    .get_node_by_inode(ino)?
+                    .parent();
+                return self.getattr(parent);
+            }
+            let attr = {
+                let nodes_manager = self.nodes_manager.read().unwrap();
+                nodes_manager
+                    .get_child_by_name(ino, name)?
+                    .map(|child_node| child_node.attr())
+            };
+            let attr = match attr {
+                Some(attr) => attr,
+                None => self.fetch_child_by_name(ino, name)?.attr(),
+            };
+            if let Some(ref hotness) = self.hotness {
+                hotness.record(attr.ino);
+            }
+            Ok(attr)
+        })
+    }
+
+    pub fn getattr(&self, ino: u64) -> Result<FileAttr> {
+        let _start = self.counter.start("fs::getattr".to_owned());
+        self.counted("getattr", || {
+            self.resolve(ino)?;
+            let node = self
+                .nodes_manager
+                .read()
+                .unwrap()
+                .get_node_by_inode(ino)?
+                .clone();
+            // the size of virtual files follows their content
+            if let Some(file) = self.virtual_files.get(&node.path()) {
+                set_size(&node, file.content().len() as u64);
+            }
+            if let Some(ref hotness) = self.hotness {
+                hotness.record(ino);
+            }
+            let mut attr = node.attr();
+            if self.dir_sizes && attr.kind == FileType::Directory {
+                let path = node.path();
+                let last = self.usages.last(&path);
+                if let Some((usage, _)) = last {
+                    attr.size = usage.bytes;
+                    attr.blocks = (usage.bytes + 511) / 512;
+                }
+                match last {
+                    Some((_, true)) => {}
+                    _ => self.refresh_usage(path),
+                }
+            }
+            Ok(attr)
+        })
+    }
+
+    /// Recursive usage of the directory `ino`, computed by the backend unless it
+    /// was within the usage ttl. A computation outliving the backend timeout still
+    /// fills the cache for the next call.
+    pub fn usage(&self, ino: u64) -> Result<Usage> {
+        self.counted("usage", || {
+            self.resolve(ino)?;
+            let node = self
+                .nodes_manager
+                .read()
+                .unwrap()
+                .get_node_by_inode(ino)?
+                .clone();
+            if node.attr().kind != FileType::Directory {
+                return Err(Error::Fuse(libc::ENOTDIR));
+            }
+            let path = node.path();
+            if let Some(usage) = self.usages.fresh(&path) {
+                return Ok(usage);
+            }
+            self.inflight_usages.work(path.clone(), || {
+                let usages = self.usages.clone();
+                self.call_backend("backend usage", Some(Request::List), move |backend| {
+                    let usage = backend.usage(&path)?;
+                    usages.insert(path, usage);
+                    Ok(usage)
+                })
+            })
+        })
+    }
+
+    // stats of a bucket which knows neither its capacity nor its usage: the usage of
+    // the root last computed, refreshed in the background once stale, and the
+    // configured capacity. Nothing is used until the usage is first computed
+    fn estimate_stat(&self, namelen: u32) -> Stat {
+        let last = self.usages.last(&self.root_path);
+        match last {
+            Some((_, true)) => {}
+            _ => self.refresh_usage(self.root_path.clone()),
+        }
+        let usage = last.map(|(usage, _)| usage).unwrap_or_default();
+        Stat::estimated(&usage, self.capacity, namelen)
+    }
+
+    // computes the usage of the directory at `path` in the background, unless it is
+    // computed already
+    fn refresh_usage(&self, path: PathBuf) {
+        if !self.usages.start(&path) {
+            return;
+        }
+        let usages = self.usages.clone();
+        let backend = self.backend.clone();
+        let budget = self.budget.clone();
+        self.backend_pool.lock().unwrap().execute(move || {
+            budget.acquire(Request::List);
+            let usage = match backend.usage(&path) {
+                Ok(usage) => Some(usage),
+                Err(e) => {
+                    log::warn!("failed to compute the usage of {:?}. {}", path, e);
+                    None
+                }
+            };
+            usages.finish(&path, usage);
+        });
+    }
+
+    pub fn generation(&self, ino: u64) -> u64 {
+        self.nodes_manager.read().unwrap().generation(ino)
+    }
+
+    /// Makes sure `ino` is in the tree. The kernel may ask for an inode this process
+    /// has never handed out when it serves an nfs handle from before a restart, such
+    /// an inode is found through the inode table by looking up its path again.
+    fn resolve(&self, ino: u64) -> Result<()> {
+        let path = {
+            let nodes_manager = self.nodes_manager.read().unwrap();
+            if nodes_manager.ino_mapper.contains_key(&ino) {
+                return Ok(());
+            }
+            match nodes_manager.recorded_path(ino) {
+                Some(path) => path,
+                None => return Err(Error::Fuse(libc::ESTALE)),
+            }
+        };
+        let relative = match path.strip_prefix(&self.root_path) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => return Err(Error::Fuse(libc::ESTALE)),
+        };
+        log::info!("resolving inode {} from {:?}", ino, path);
+        let resolved = match self.lookup_path(&relative) {
+            Ok(attr) => attr.ino,
+            Err(ref e) if e.errno(0) == libc::ENOENT => {
+                self.nodes_manager.write().unwrap().retire(ino);
+                return Err(Error::Fuse(libc::ESTALE));
+            }
+            Err(e) => return Err(e),
+        };
+        if resolved != ino {
+            log::error!(
+                "{:?} resolved to inode {} instead of {}",
+                path,
+                resolved,
+                ino
+            );
+            return Err(Error::Fuse(libc::ESTALE));
+        }
+        Ok(())
+    }
+
+    /// Looks up the entry at `path`, relative to the mount root. The components
+    /// missing from the tree are fetched in a single request if the backend resolves
+    /// whole paths, looked up one at a time otherwise.
+    pub fn lookup_path<P: AsRef<Path>>(&self, path: P) -> Result<FileAttr> {
+        let relative = path.as_ref();
+        let relative = relative.strip_prefix("/").unwrap_or(relative);
+        let names: Vec<&OsStr> = relative
+            .components()
+            .map(|component| component.as_os_str())
+            .collect();
+        // the deepest directory of the path in the tree
+        let (mut parent, known) = {
+            let nodes_manager = self.nodes_manager.read().unwrap();
+            let mut parent = ROOT_INODE;
+            let mut known = 0;
+            for name in names.iter() {
+                match nodes_manager
+                    .children_name
+                    .get(&parent)
+                    .and_then(|children| children.get(*name))
+                {
+                    Some(child) => parent = *child,
+                    None => break,
+                }
+                known += 1;
+            }
+            (parent, known)
+        };
+        if names.len() - known > 1 {
+            if let Some(attr) = self.fetch_path(parent, &names[known..])? {
+                return Ok(attr);
+            }
+        }
+        for name in names[known..].iter() {
+            parent = self.lookup(parent, name)?.ino;
+        }
+        self.getattr(parent)
+    }
+
+    // adds the entries `names` below the directory `ino` to the tree with a single
+    // request, `None` if the backend can not resolve whole paths
+    fn fetch_path(&self, ino: u64, names: &[&OsStr]) -> Result<Option<FileAttr>> {
+        let (mut index, dir) = {
+            let nodes_manager = self.nodes_manager.read().unwrap();
+            let index = match nodes_manager.ino_mapper.get(&ino) {
+                Some(index) => index.clone(),
+                None => return Ok(None),
+            };
+            (index, nodes_manager.get_node_by_inode(ino)?.path())
+        };
+        let mut path = dir;
+        for name in names {
+            path.push(name);
+            // virtual files and the directories leading to them are not in the backend
+            if self.virtual_files.node(&path).is_some() {
+                return Ok(None);
+            }
+        }
+        let depth = names.len();
+        let fetched =
+            self.call_backend("backend get_path", Some(Request::Head), move |backend| {
+                backend.get_path(path, depth)
+            })?;
+        let nodes = match fetched {
+            Some(ref nodes) if nodes.len() == depth => nodes,
+            _ => return Ok(None),
+        };
+        let mut parent = ino;
+        let mut attr = None;
+        for (node, name) in nodes.iter().zip(names) {
+            if &*node.name() != *name {
+                return Ok(None);
             }
+            if !exposed(&self.filter, &self.root_path, node) {
+                return Err(Error::Fuse(libc::ENOENT));
+            }
+            let mut nodes_manager = self.nodes_manager.write().unwrap();
+            let added = nodes_manager.add_child(&index, parent, node);
+            index = match nodes_manager.ino_mapper.get(&added.inode()) {
+                Some(index) => index.clone(),
+                None => return Ok(None),
+            };
+            parent = added.inode();
+            attr = Some(added.attr());
         }
+        Ok(attr)
+    }
+
+    pub(crate) fn add_node_locally(
+        &self,
+        parent_index: &NodeId,
+        parent_inode: u64,
+        child_node: &Node,
+    ) -> Node {
+        let _start = self.counter.start("fs::add_node_locally".to_owned());
+        let mut nodes_manager = self.nodes_manager.write().unwrap();
+        nodes_manager.add_child(parent_index, parent_inode, child_node)
     }
 
-    pub fn fetch_child_by_name(&self, ino: u64, name: &OsStr) -> Result<Node> {
+    pub(crate) fn fetch_child_by_name(&self, ino: u64, name: &OsStr) -> Result<Node> {
         let _start = self.counter.start("fs::fetch_child_by_name".to_owned());
-        let (parent_index, child_node) = {
+        let (parent_index, child_path) = {
             let nodes_manager = self.nodes_manager.read().unwrap();
             let parent_index = nodes_manager.ino_mapper.get(&ino).unwrap();
             let parent_node = nodes_manager.nodes_tree.get(parent_index).unwrap().data();
-            let child_node = self.backend.get_node(parent_node.path().join(name))?;
-            (parent_index.clone(), child_node)
+            (parent_index.clone(), parent_node.path().join(name))
+        };
+        let virtual_node = self.virtual_files.node(&child_path);
+        if let Some(ref node) = virtual_node {
+            if node.attr().kind == FileType::RegularFile {
+                return Ok(self.add_node_locally(&parent_index, ino, node));
+            }
+        }
+        // the name would have been listed, `ls` and shell completions look up many
+        // names which do not exist
+        if let Some(window) = self.listing_window {
+            if virtual_node.is_none()
+                && self
+                    .nodes_manager
+                    .read()
+                    .unwrap()
+                    .listed_within(ino, window)
+            {
+                log::trace!("{:?} is missing from a recent listing", child_path);
+                return Err(Error::Fuse(libc::ENOENT));
+            }
+        }
+        if virtual_node.is_none() && self.lists_on_miss(ino) {
+            if let Some(listing) = self.fetch_children(ino, &parent_index) {
+                self.wait_listed(&listing, &parent_index, usize::max_value())?;
+            }
+            let nodes_manager = self.nodes_manager.read().unwrap();
+            return match nodes_manager.get_child_by_name(ino, name)? {
+                Some(child_node) => Ok(child_node.clone()),
+                None => Err(Error::Fuse(libc::ENOENT)),
+            };
+        }
+        let fetched = self.inflight_nodes.work(child_path.clone(), || {
+            let child_path = child_path.clone();
+            self.call_backend("backend get_node", Some(Request::Head), move |backend| {
+                backend.get_node(child_path)
+            })
+        });
+        // directories leading to virtual files need not exist in the backend
+        let child_node = match (fetched, virtual_node) {
+            (Err(Error::NotFound(_)), Some(node)) => node,
+            (fetched, _) => fetched?,
+        };
+        if !exposed(&self.filter, &self.root_path, &child_node) {
+            return Err(Error::Fuse(libc::ENOENT));
+        }
+        Ok(self.add_node_locally(&parent_index, ino, &child_node))
+    }
+
+    /// Whether a name missing from the directory `ino` is better found by listing
+    /// it than by heading the name: its children are not in the tree, e.g. after
+    /// an eviction, and it was small when it was last listed. A listing answers
+    /// the lookups of all its names, a head only one.
+    fn lists_on_miss(&self, ino: u64) -> bool {
+        let max = match self.list_below {
+            Some(max) => max,
+            None => return false,
         };
-        self.add_node_locally(&parent_index, ino, &child_node);
-        Ok(child_node)
+        let nodes_manager = self.nodes_manager.read().unwrap();
+        !nodes_manager.listed.contains_key(&ino)
+            && nodes_manager
+                .sizes
+                .get(&ino)
+                .map(|size| *size <= max)
+                .unwrap_or(false)
     }
 
-    pub fn fetch_children(&self, index: NodeId) -> Result<()> {
+    /// Starts listing the directory `ino` in the background unless its listing is
+    /// already complete or in progress. Returns the listing to wait on, `None` once
+    /// the directory has been listed.
+    fn fetch_children(&self, ino: u64, index: &NodeId) -> Option<Arc<Listing>> {
+        let mut listings = self.listings.lock().unwrap();
+        if self.nodes_manager.read().unwrap().listed.contains_key(&ino) {
+            return None;
+        }
+        if let Some(listing) = listings.get(&ino) {
+            return Some(listing.clone());
+        }
+        let listing = Arc::new(Listing::default());
+        if self.breaker.is_open() {
+            if self.breaker.try_probe() {
+                self.probe_backend();
+            }
+            listing.finish(Err(Error::Unavailable(
+                "backend get_children skipped, circuit open".to_owned(),
+            )));
+            return Some(listing);
+        }
+        listings.insert(ino, listing.clone());
+        drop(listings);
+        self.nodes_manager.write().unwrap().listing.insert(ino);
+
         let _start = self.counter.start("fs::fetch_children".to_owned());
-        let parent_node = {
+        let parent_path = {
             let nodes_manager = self.nodes_manager.read().unwrap();
-            let node = nodes_manager.nodes_tree.get(&index).unwrap();
-            node.data().clone()
+            nodes_manager.nodes_tree.get(index).unwrap().data().path()
         };
-        let parent_inode = parent_node.inode();
+        let backend = self.backend.clone();
+        let nodes_manager = self.nodes_manager.clone();
+        let breaker = self.breaker.clone();
+        let listings = self.listings.clone();
+        let index = index.clone();
+        let fetching = listing.clone();
+        let stats = self.stats.clone();
+        let budget = self.budget.clone();
+        let filter = self.filter.clone();
+        let root_path = self.root_path.clone();
+        let max_dir_entries = self.max_dir_entries;
+        let queued = Instant::now();
+        let id = request_id::current();
+        // on the backend pool like every backend call, readers waiting for the
+        // listing give up after the backend timeout, see `wait_listed`
+        self.backend_pool.lock().unwrap().execute(move || {
+            let _scope = id.map(Scope::enter);
+            if let Some(ref stats) = stats {
+                stats.waited("backend get_children", queued.elapsed());
+            }
+            // one request per page, the next page waits for the budget
+            budget.acquire(Request::List);
+            let mut pages = 0;
+            let in_flight = stats
+                .as_ref()
+                .map(|stats| stats.enter("backend get_children"));
+            let result = backend.for_each_children_page(&parent_path, &mut |children| {
+                {
+                    let mut nodes_manager = nodes_manager.write().unwrap();
+                    for child in children.iter() {
+                        if exposed(&filter, &root_path, child) {
+                            nodes_manager.add_child(&index, ino, child);
+                        }
+                    }
+                }
+                fetching.progressed();
+                pages += 1;
+                if pages > 1 {
+                    budget.acquire(Request::List);
+                }
+            });
+            drop(in_flight);
+            breaker.record(result.as_ref().map(|_| ()));
+            if let Some(ref stats) = stats {
+                stats.record("backend get_children", result.is_ok(), 0);
+            }
+            {
+                let mut nodes_manager = nodes_manager.write().unwrap();
+                nodes_manager.listing.remove(&ino);
+                match result {
+                    Ok(()) => {
+                        // once, and only if no entry was served: sorting moves
+                        // the entries a reader may have been given already
+                        if !fetching.served.load(Ordering::SeqCst) {
+                            nodes_manager.sort_children(ino);
+                        }
+                        nodes_manager.listed.insert(ino, Instant::now());
+                        let entries = nodes_manager
+                            .children_name
+                            .get(&ino)
+                            .map(|children| children.len())
+                            .unwrap_or(0);
+                        nodes_manager.sizes.insert(ino, entries);
+                        match max_dir_entries {
+                            Some(max) if entries > max => log::warn!(
+                                "{:?} has {} entries, above the limit of {}",
+                                parent_path,
+                                entries,
+                                max
+                            ),
+                            _ => {}
+                        }
+                    }
+                    Err(ref err) => {
+                        log::error!(
+                            "get children from backend. {:?}, error: {}",
+                            parent_path,
+                            err
+                        );
+                    }
+                }
+            }
+            listings.lock().unwrap().remove(&ino);
+            fetching.finish(result);
+        });
+        Some(listing)
+    }
 
-        self.backend
-            .get_children(parent_node.path())
-            .map(|children| {
-                let children: Vec<Node> = children;
-                for child in children {
-                    self.add_node_locally(&index, parent_inode, &child);
+    // adds the virtual entries of the directory `ino` missing from the tree, returns
+    // the path of the directory
+    fn add_virtual_children(&self, ino: u64, index: &NodeId) -> Result<PathBuf> {
+        let path = self
+            .nodes_manager
+            .read()
+            .unwrap()
+            .get_node_by_inode(ino)?
+            .path();
+        for name in self.virtual_files.children(&path) {
+            let missing = self
+                .nodes_manager
+                .read()
+                .unwrap()
+                .get_child_by_name(ino, &name)?
+                .is_none();
+            if !missing {
+                continue;
+            }
+            match self.virtual_files.node(&path.join(&name)) {
+                Some(ref node) if node.attr().kind == FileType::RegularFile => {
+                    self.add_node_locally(index, ino, node);
                 }
-                ()
-            })
-            .map_err(|err| {
-                Error::Other(format!(
-                    "get children from backend. {:?}, error: {}",
-                    index, err
-                ))
-            })
+                // the backend may have the directory too
+                _ => {
+                    self.fetch_child_by_name(ino, &name)?;
+                }
+            }
+        }
+        Ok(path)
     }
 
-    pub fn readdir_local(
-        &self,
-        index: NodeId,
-        offset: usize,
-        check_empty: bool,
-    ) -> Result<Option<Vec<Node>>> {
+    pub(crate) fn readdir_local(&self, index: NodeId, cookie: u64) -> Result<Vec<Node>> {
         let _start = self.counter.start("fs::readdir_local".to_owned());
         let nodes_manager = self.nodes_manager.read().unwrap();
-        nodes_manager.get_children_by_index(&index, offset, 85, check_empty)
+        nodes_manager.get_children_after(&index, cookie, self.readdir_limit)
+    }
+
+    /// Waits until the directory has a child after `cookie` in the tree or its
+    /// listing is over. Fails only if the listing failed before going past
+    /// `cookie`, or made no progress within the backend timeout.
+    fn wait_listed(&self, listing: &Listing, index: &NodeId, cookie: u64) -> Result<()> {
+        let mut state = listing.state.lock().unwrap();
+        loop {
+            let last = self.nodes_manager.read().unwrap().last_cookie(index);
+            if last > cookie {
+                return Ok(());
+            }
+            if let Some(ref result) = *state {
+                return result.clone();
+            }
+            state = match self.backend_timeout {
+                None => listing.changed.wait(state).unwrap(),
+                Some(timeout) => {
+                    let (state, waited) = listing.changed.wait_timeout(state, timeout).unwrap();
+                    if waited.timed_out() && state.is_none() {
+                        log::error!("backend get_children timed out after {:?}", timeout);
+                        return Err(Error::Timeout(format!(
+                            "backend get_children exceeded {:?}",
+                            timeout
+                        )));
+                    }
+                    state
+                }
+            };
+        }
     }
 
-    pub fn readdir(&self, parent_ino: u64, file_handle: u64, offset: usize) -> Result<Vec<Node>> {
+    /// Lists the children of `parent_ino` after the one with the cookie `cookie`, 0
+    /// for the first, in the byte order of their names whatever order the backend
+    /// returned them in, so successive listings of an unchanged directory are the
+    /// same. Entries served while the directory is being listed come in the order
+    /// they were fetched, they are sorted by the next listing from the start.
+    pub fn readdir(&self, parent_ino: u64, file_handle: u64, cookie: u64) -> Result<Vec<Node>> {
         let _start = self.counter.start("fs::readdir".to_owned());
-        let parent_index = {
-            let nodes_manager = self.nodes_manager.read().unwrap();
-            match nodes_manager.ino_mapper.get(&parent_ino) {
-                Some(parent_index) => parent_index.clone(),
-                None => {
-                    return Err(Error::Other(format!(
-                        "get index by ino for parent. ino: {}",
-                        parent_ino
-                    )));
+        self.counted("readdir", || {
+            self.resolve(parent_ino)?;
+            self.nodes_manager.read().unwrap().touch(parent_ino);
+            let parent_index = {
+                let nodes_manager = self.nodes_manager.read().unwrap();
+                match nodes_manager.ino_mapper.get(&parent_ino) {
+                    Some(parent_index) => parent_index.clone(),
+                    None => {
+                        return Err(Error::Other(format!(
+                            "get index by ino for parent. ino: {}",
+                            parent_ino
+                        )));
+                    }
                 }
-            }
-        };
+            };
 
-        if let Some(children) = self.readdir_local(parent_index.clone(), offset, true)? {
-            return Ok(children);
-        }
-        self.fetch_children(parent_index.clone())?;
-        if let Some(children) = self.readdir_local(parent_index.clone(), offset, false)? {
-            return Ok(children);
-        }
-        return Ok(vec![]);
+            let parent_path = self.add_virtual_children(parent_ino, &parent_index)?;
+            // entries are served as soon as the listing has fetched them
+            if let Some(listing) = self.fetch_children(parent_ino, &parent_index) {
+                match self.wait_listed(&listing, &parent_index, cookie) {
+                    Err(Error::NotFound(_)) if self.virtual_files.is_dir(&parent_path) => {}
+                    waited => waited?,
+                }
+                // set before the entries are read: either the listing sees it and keeps
+                // their order, or it has sorted them already
+                listing.served.store(true, Ordering::SeqCst);
+            }
+            // only before the first entry of a stream and outside of listings, so the
+            // cookies of the entries served do not change under a reader
+            let sort = cookie == 0 && {
+                let nodes_manager = self.nodes_manager.read().unwrap();
+                nodes_manager.unsorted.contains(&parent_ino)
+                    && !nodes_manager.listing.contains(&parent_ino)
+            };
+            if sort {
+                self.nodes_manager
+                    .write()
+                    .unwrap()
+                    .sort_children(parent_ino);
+            }
+            self.readdir_local(parent_index, cookie)
+        })
     }
 
     pub fn statfs(&self, ino: u64) -> Result<Stat> {
         let _start = self.counter.start("fs::statfs".to_owned());
-        let nodes_manager = self.nodes_manager.read().unwrap();
-        let node = nodes_manager.get_node_by_inode(ino)?;
-        self.backend.statfs(node.path())
+        self.counted("statfs", || {
+            self.resolve(ino)?;
+            let path = {
+                let nodes_manager = self.nodes_manager.read().unwrap();
+                nodes_manager.get_node_by_inode(ino)?.path()
+            };
+            let mut stat =
+                self.call_backend("backend statfs", None, move |backend| backend.statfs(path))?;
+            if !stat.known {
+                stat = self.estimate_stat(stat.namelen);
+            }
+            Ok(match self.quota {
+                Some(ref quota) => quota.statfs(stat),
+                None => stat,
+            })
+        })
     }
 
     pub fn mknod(
@@ -190,108 +1292,1963 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> FileSystem<B> {
         rdev: u32,
         uid: u32,
         gid: u32,
-    ) -> Option<Node> {
-        let (parent_index, parent_node, children) = {
-            let nodes_manager = self.nodes_manager.read().unwrap();
-            let parent_index = nodes_manager.ino_mapper.get(&parent).unwrap();
-            let children = nodes_manager
-                .get_children_by_index(parent_index, 0, -1, false)
-                .unwrap();
-            let parent_node = nodes_manager.get_node_by_inode(parent).unwrap();
-            (parent_index.clone(), parent_node.clone(), children)
-        };
-        let parent_index = parent_index.clone();
-        let already_exists = children.is_some()
-            && children
-                .unwrap()
-                .iter()
-                .find(|child_node| {
-                    let child_node: &&Node = child_node;
-                    child_node.path().file_name().unwrap() == name
-                })
-                .is_some();
-        if already_exists {
-            return None;
-        }
+    ) -> Result<Node> {
+        self.counted("mknod", || {
+            self.resolve(parent)?;
+            self.nodes_manager.read().unwrap().touch(parent);
+            // the name stays reserved until the node is inserted, so concurrent creates of
+            // the same name fail with EEXIST instead of both getting an inode.
+            let (parent_index, parent_path) = {
+                let mut nodes_manager = self.nodes_manager.write().unwrap();
+                let parent_index = nodes_manager
+                    .ino_mapper
+                    .get(&parent)
+                    .cloned()
+                    .ok_or_else(|| Error::Fuse(libc::ENOENT))?;
+                let parent_path = nodes_manager.get_node_by_inode(parent)?.path();
+                // a hidden entry could never be looked up again
+                let child_path = parent_path.join(name);
+                if !self
+                    .filter
+                    .exposes(relative(&self.root_path, &child_path), filetype)
+                {
+                    return Err(Error::Fuse(libc::EACCES));
+                }
+                if let Some(max) = self.max_dir_entries {
+                    let entries = nodes_manager
+                        .children_name
+                        .get(&parent)
+                        .map(|children| children.len())
+                        .unwrap_or(0);
+                    if entries >= max {
+                        log::error!(
+                            "refusing to create {:?}, its directory has {} entries already",
+                            child_path,
+                            entries
+                        );
+                        return Err(Error::Fuse(libc::ENOSPC));
+                    }
+                }
+                nodes_manager.reserve_child(parent, name)?;
+                (parent_index, parent_path)
+            };
+            let counted = filetype == FileType::RegularFile && self.quota.is_some();
+            if counted {
+                if let Err(e) = self.quota.as_ref().unwrap().create() {
+                    self.nodes_manager
+                        .write()
+                        .unwrap()
+                        .release_child(parent, name);
+                    return Err(e);
+                }
+            }
 
-        let parent_path = parent_node.path();
-        let child_path = parent_path.join(name);
-        self.backend.mknod(&child_path, filetype, mode).unwrap();
-        // let next_inode = self.next_inode();
-        let node = Node::new(
-            0,
-            parent,
-            child_path,
-            FileAttr {
-                ino: 0,
-                size: 4096,
-                /// Size in blocks
-                blocks: 1,
-                /// Time of last access
-                atime: SystemTime::now(),
-                /// Time of last modification
-                mtime: SystemTime::now(),
-                /// Time of last change
-                ctime: SystemTime::now(),
-                /// Time of creation (macOS only)
-                crtime: SystemTime::now(),
-                /// Kind of file (directory, file, pipe, etc)
-                kind: filetype,
-                /// Permissions
-                perm: mode as u16,
-                /// Number of hard links
-                nlink: 1,
-                /// User id
-                uid,
-                /// Group id
-                gid,
-                /// Rdev
-                rdev: rdev,
-                /// Flags (macOS only, see chflags(2))
-                flags: 0,
-            },
-        );
-        self.add_node_locally(&parent_index, parent, &node);
-        return Some(node);
+            let child_path = parent_path.join(name);
+            let backend_path = child_path.clone();
+            if let Err(err) =
+                self.call_backend("backend mknod", Some(Request::Put), move |backend| {
+                    backend.mknod(backend_path, filetype, mode)
+                })
+            {
+                log::error!("mknod {:?}, error: {}", child_path, err);
+                if counted {
+                    self.quota.as_ref().unwrap().remove(0);
+                }
+                self.nodes_manager
+                    .write()
+                    .unwrap()
+                    .release_child(parent, name);
+                return Err(err);
+            }
+            // let next_inode = self.next_inode();
+            let node = Node::new(
+                0,
+                parent,
+                child_path,
+                FileAttr {
+                    perm: mode as u16,
+                    uid,
+                    gid,
+                    rdev,
+                    ..attr::synthetic(
+                        filetype,
+                        if filetype == FileType::Directory {
+                            4096
+                        } else {
+                            0
+                        },
+                        SystemTime::now(),
+                    )
+                },
+            );
+            let mut nodes_manager = self.nodes_manager.write().unwrap();
+            nodes_manager.release_child(parent, name);
+            let node = nodes_manager.add_child(&parent_index, parent, &node);
+            nodes_manager.mark_pending(node.inode());
+            Ok(node)
+        })
     }
 
-    pub fn read<F>(&self, ino: u64, _fh: u64, all: bool, offset: usize, size: usize, f: F)
+    pub fn read<F>(&self, ino: u64, fh: u64, all: bool, offset: usize, size: usize, f: F)
     where
         F: FnOnce(Result<Vec<u8>>),
     {
         let _start = self.counter.start("fs::read".to_owned());
+        let _operation = request_id::operation();
+        let _in_flight = self.stats.as_ref().map(|stats| stats.enter("read"));
+        let stats = self.stats.clone();
+        let f = move |result: Result<Vec<u8>>| {
+            if let Some(stats) = stats {
+                let bytes = result.as_ref().map(|data| data.len() as u64).unwrap_or(0);
+                stats.record("read", result.is_ok(), bytes);
+            }
+            f(result)
+        };
+        if let Err(e) = self.resolve(ino) {
+            return f(Err(e));
+        }
         let node = {
             let nodes_manager = self.nodes_manager.read().unwrap();
-            let node = nodes_manager.get_node_by_inode(ino).unwrap();
-            node.clone()
+            match nodes_manager.get_node_by_inode(ino) {
+                Ok(node) => node.clone(),
+                Err(e) => return f(Err(e)),
+            }
         };
-        let attr: &FileAttr = &node.attr();
-        if attr.size == offset as u64 {
-            f(Ok(vec![]));
-            return;
+        self.trace(Op::Read, &node, offset as u64, size as u64);
+        if let Some(file) = self.virtual_files.get(&node.path()) {
+            let content = file.content();
+            let start = if all {
+                0
+            } else {
+                std::cmp::min(offset, content.len())
+            };
+            let end = if all {
+                content.len()
+            } else {
+                std::cmp::min(start + size, content.len())
+            };
+            return f(Ok(content[start..end].to_vec()));
         }
-        if attr.size < offset as u64 {
-            log::error!(
-                "input offset: {} size: {}, file size: {}",
-                offset,
-                size,
-                attr.size
-            );
-            return f(Err(Error::Other(format!(
-                "input offset: {} size: {}, file size: {}",
-                offset, size, attr.size
-            ))));
-        }
-        let size = if all {
-            attr.size
-        } else if attr.size < offset as u64 + size as u64 {
-            attr.size - offset as u64
+        if let Some(pin) = self.pins.get(fh) {
+            let attr = node.attr();
+            let current = (attr.size, attr.mtime);
+            match pin.version {
+                None => self.pins.pin(fh, ino, Some(current), node.etag()),
+                Some(version) if version == current => {}
+                Some(version) => match self.remote_change {
+                    RemoteChange::Invalidate => {
+                        log::info!("ino: {} changed remotely, reading the new version", ino);
+                        self.forget_fetched(ino);
+                        self.pins.pin(fh, ino, Some(current), node.etag());
+                    }
+                    RemoteChange::Pin if !all => {
+                        return f(self.read_pinned(
+                            &node,
+                            &pin,
+                            version,
+                            offset as u64,
+                            size as u64,
+                        ));
+                    }
+                    RemoteChange::Pin => {}
+                },
+            }
+        }
+        let file_size = node.attr().size;
+        // `all` reads the whole content so it can be cached, regardless of `offset`
+        let (offset, size) = if all {
+            (0, file_size)
+        } else if offset as u64 >= file_size {
+            // reading at or past the end of file is a short read, not an error
+            (offset as u64, 0)
         } else {
-            size as u64
+            let offset = offset as u64;
+            (offset, std::cmp::min(size as u64, file_size - offset))
+        };
+        if size == 0 {
+            return f(Ok(vec![]));
+        }
+        // written data not uploaded yet, or not visible in the backend yet
+        if let Some(data) = self.writeback.read(ino, offset, size as usize) {
+            return f(Ok(data));
+        }
+        if let Some(ref passthrough) = self.passthrough {
+            if let Some(result) = passthrough.read(fh, offset, size as usize) {
+                return f(result);
+            }
+        }
+        let direct = self.is_direct(fh);
+        let plan = match self.open_handles {
+            Some(ref handles) if !all && !direct => handles.plan(fh, offset, size, file_size),
+            _ => None,
+        };
+        let adaptive = plan.is_some();
+        let (fetch_offset, fetch_size) = match plan {
+            Some(Plan::Cached(data)) => return f(Ok(data)),
+            Some(Plan::Fetch(fetch_offset, fetch_size)) => (fetch_offset, fetch_size),
+            None => (offset, size),
+        };
+        let result = match self.blocks {
+            Some(ref blocks) if !all && !adaptive && !direct => {
+                let result = self.read_blocks(blocks, &node, offset, size);
+                if let (Ok(data), Some(policy)) = (&result, &self.prefetch_policy) {
+                    let ranges = policy.next(&node.path(), file_size, offset, data);
+                    self.prefetch_blocks(blocks, &node, &ranges);
+                }
+                result
+            }
+            _ => self.read_range(&node, fh, offset, size, fetch_offset, fetch_size),
         };
-        // f(self
-        //     .runtime
-        //     .block_on(self.backend.read(node.path(), offset as u64, size as usize)))
-        f(self.backend.read(node.path(), offset as u64, size as usize))
+        f(result.map(|mut data| {
+            // the object shrank after its attributes were cached, zero-fill up to the
+            // size the kernel was told about instead of failing the read.
+            if (data.len() as u64) < size {
+                log::warn!(
+                    "ino: {}, short read at offset: {}, expected: {}, got: {}",
+                    ino,
+                    offset,
+                    size,
+                    data.len()
+                );
+                data.resize(size as usize, 0);
+            }
+            data
+        }))
+    }
+
+    // fetches `fetch_size` bytes at `fetch_offset` of the file `node` to serve `size`
+    // bytes at `offset`, keeping the rest for the next reads of the handle `fh`
+    fn read_range(
+        &self,
+        node: &Node,
+        fh: u64,
+        offset: u64,
+        size: u64,
+        fetch_offset: u64,
+        fetch_size: u64,
+    ) -> Result<Vec<u8>> {
+        let path = node.path();
+        let data = self
+            .inflight_reads
+            .work((path.clone(), fetch_offset, fetch_size), || {
+                self.call_backend("backend read", Some(Request::Get), move |backend| {
+                    backend.read(path, fetch_offset, fetch_size as usize)
+                })
+            })?;
+        if (fetch_offset, fetch_size) == (offset, size) {
+            return Ok(data);
+        }
+        // more than the read was fetched, kept for the next reads of the handle
+        let from = offset - fetch_offset;
+        let requested = blocks::slice(&data, from, from + size);
+        if let Some(ref handles) = self.open_handles {
+            handles.fill(fh, fetch_offset, Arc::new(data));
+        }
+        Ok(requested)
+    }
+
+    // reads `size` bytes at `offset` of the file `node` block by block, through the
+    // block cache. The blocks missing from the cache are fetched by a single vectored
+    // read.
+    fn read_blocks(
+        &self,
+        blocks: &BlockCache,
+        node: &Node,
+        offset: u64,
+        size: u64,
+    ) -> Result<Vec<u8>> {
+        let ino = node.inode();
+        let attr = node.attr();
+        let version = (attr.size, attr.mtime);
+        let block_size = blocks.block_size();
+        let end = offset + size;
+        // the part of every block of the read, `None` for those missing from the cache
+        let mut parts = vec![];
+        let mut position = offset;
+        while position < end {
+            let index = position / block_size;
+            let start = index * block_size;
+            let (from, to) = (position - start, std::cmp::min(end - start, block_size));
+            parts.push((index, from, to, blocks.read(ino, index, version, from, to)));
+            position = start + to;
+        }
+        let missing: Vec<u64> = parts
+            .iter()
+            .filter(|part| part.3.is_none())
+            .map(|part| part.0)
+            .collect();
+        let mut fetched = self.fetch_blocks(node, block_size, &missing)?.into_iter();
+        let mut data = Vec::with_capacity(size as usize);
+        for (index, from, to, part) in parts {
+            let part = match part {
+                Some(part) => part,
+                None => {
+                    let block = fetched.next().expect("a block per missing one");
+                    let part = blocks::slice(&block, from, to);
+                    blocks.insert(ino, index, version, Arc::new(block), part.len() as u64);
+                    part
+                }
+            };
+            // the end of the object
+            let short = (part.len() as u64) < to - from;
+            data.extend_from_slice(&part);
+            if short {
+                break;
+            }
+        }
+        Ok(data)
+    }
+
+    // reads `size` bytes at `offset` of the version `version` of the file `node`,
+    // pinned by a handle opened before the file changed: from its cached blocks or
+    // from the backend while the object has the etag of that version
+    fn read_pinned(
+        &self,
+        node: &Node,
+        pin: &Pin,
+        version: Version,
+        offset: u64,
+        size: u64,
+    ) -> Result<Vec<u8>> {
+        let ino = node.inode();
+        if offset >= version.0 {
+            return Ok(vec![]);
+        }
+        let size = std::cmp::min(size, version.0 - offset);
+        if let Some(ref blocks) = self.blocks {
+            let block_size = blocks.block_size();
+            let end = offset + size;
+            let mut data = Vec::with_capacity(size as usize);
+            let mut position = offset;
+            while position < end {
+                let index = position / block_size;
+                let start = index * block_size;
+                let (from, to) = (position - start, std::cmp::min(end - start, block_size));
+                // looking up another version would drop the block
+                if !blocks.contains(ino, index, version) {
+                    break;
+                }
+                match blocks.read(ino, index, version, from, to) {
+                    Some(part) => data.extend_from_slice(&part),
+                    None => break,
+                }
+                position = start + to;
+            }
+            if data.len() as u64 == size {
+                return Ok(data);
+            }
+        }
+        let stale = || {
+            log::warn!("ino: {}, the version pinned by the handle is gone", ino);
+            Error::Fuse(libc::ESTALE)
+        };
+        let etag = match pin.etag {
+            Some(ref etag) => etag.clone(),
+            None => return Err(stale()),
+        };
+        let path = node.path();
+        let result = self.call_backend("backend read", Some(Request::Get), move |backend| {
+            backend.read_if_match(path, offset, size as usize, &etag)
+        });
+        match result {
+            Err(Error::Conflict(_)) => Err(stale()),
+            Err(ref e) if e.errno(0) == libc::ENOSYS => Err(stale()),
+            result => result,
+        }
+    }
+
+    // fetches the blocks of the file `node` covering `ranges` which are not cached,
+    // in the background by one vectored read
+    fn prefetch_blocks(&self, blocks: &Arc<BlockCache>, node: &Node, ranges: &[Range<u64>]) {
+        let ino = node.inode();
+        let attr = node.attr();
+        let version = (attr.size, attr.mtime);
+        let block_size = blocks.block_size();
+        let mut indexes: Vec<u64> = ranges
+            .iter()
+            .filter(|range| range.start < range.end)
+            .flat_map(|range| range.start / block_size..=(range.end - 1) / block_size)
+            .collect();
+        indexes.sort();
+        indexes.dedup();
+        indexes.retain(|index| !blocks.contains(ino, *index, version));
+        if indexes.is_empty() {
+            return;
+        }
+        let ranges: Vec<Range<u64>> = indexes
+            .iter()
+            .map(|index| index * block_size..(index + 1) * block_size)
+            .collect();
+        let path = node.path();
+        let backend = self.backend.clone();
+        let budget = self.budget.clone();
+        let blocks = blocks.clone();
+        self.backend_pool.lock().unwrap().execute(move || {
+            budget.acquire(Request::Get);
+            let results = backend.read_vectored(&path, &ranges);
+            for (index, result) in indexes.into_iter().zip(results) {
+                match result {
+                    Ok(block) => blocks.insert(ino, index, version, Arc::new(block), 0),
+                    Err(e) => log::warn!(
+                        "failed to prefetch block {} of {:?}. error: {}",
+                        index,
+                        path,
+                        e
+                    ),
+                }
+            }
+        });
+    }
+
+    // the blocks `indexes` of the file `node`, a single one through the reads in
+    // flight, several by one vectored read
+    fn fetch_blocks(&self, node: &Node, block_size: u64, indexes: &[u64]) -> Result<Vec<Vec<u8>>> {
+        let path = node.path();
+        if indexes.len() == 1 {
+            let start = indexes[0] * block_size;
+            let block = self
+                .inflight_reads
+                .work((path.clone(), start, block_size), || {
+                    self.call_backend("backend read", Some(Request::Get), move |backend| {
+                        backend.read(path, start, block_size as usize)
+                    })
+                })?;
+            return Ok(vec![block]);
+        }
+        if indexes.is_empty() {
+            return Ok(vec![]);
+        }
+        let ranges: Vec<Range<u64>> = indexes
+            .iter()
+            .map(|index| index * block_size..(index + 1) * block_size)
+            .collect();
+        self.call_backend("backend read", Some(Request::Get), move |backend| {
+            backend.read_vectored(path, &ranges).into_iter().collect()
+        })
+    }
+
+    /// Bytes of the blocks fetched and of those dropped without being read, `None`
+    /// unless reads are rounded to blocks.
+    pub fn block_bytes(&self) -> Option<(u64, u64)> {
+        self.blocks.as_ref().map(|blocks| {
+            (
+                blocks.fetched.load(Ordering::Relaxed),
+                blocks.wasted.load(Ordering::Relaxed),
+            )
+        })
+    }
+
+    /// Writes `data` at `offset` of the file `ino`. The data is kept in memory until
+    /// the file is flushed, this blocks while too much written data waits for upload.
+    pub fn write(&self, ino: u64, offset: u64, data: &[u8]) -> Result<u32> {
+        let _start = self.counter.start("fs::write".to_owned());
+        self.counted("write", || {
+            let node = self.regular_file(ino)?;
+            self.trace(Op::Write, &node, offset, data.len() as u64);
+            self.check_file_size(&node, offset + data.len() as u64)?;
+            let grown = self.grow_quota(&node, offset + data.len() as u64)?;
+            let size = self
+                .writeback
+                .write(
+                    &self.backend,
+                    ino,
+                    &node.path(),
+                    node.attr().size,
+                    offset,
+                    data,
+                )
+                .map_err(|e| {
+                    self.shrink_quota(grown);
+                    e
+                })?;
+            set_size(&node, size);
+            self.forget_fetched(ino);
+            self.nodes_manager.read().unwrap().mark_pending(ino);
+            Ok(data.len() as u32)
+        })
+    }
+
+    /// Cuts or zero-extends the file `ino` to `size` bytes.
+    pub fn truncate(&self, ino: u64, size: u64) -> Result<FileAttr> {
+        self.counted("truncate", || {
+            let node = self.regular_file(ino)?;
+            self.check_file_size(&node, size)?;
+            let before = node.attr().size;
+            let grown = self.grow_quota(&node, size)?;
+            let size = self
+                .writeback
+                .truncate(&self.backend, ino, &node.path(), size)
+                .map_err(|e| {
+                    self.shrink_quota(grown);
+                    e
+                })?;
+            self.shrink_quota(before.saturating_sub(size));
+            set_size(&node, size);
+            self.forget_fetched(ino);
+            self.nodes_manager.read().unwrap().mark_pending(ino);
+            Ok(node.attr())
+        })
+    }
+
+    /// Uploads what was written to the file `ino` since its last upload.
+    pub fn flush(&self, ino: u64) -> Result<()> {
+        self.counted("flush", || {
+            // listings may show the upload late
+            if self.writeback.flush(&*self.backend, ino)? {
+                self.nodes_manager.read().unwrap().mark_pending(ino);
+            }
+            Ok(())
+        })
+    }
+
+    /// Files whose written content did not reach the backend yet with their paths
+    /// and sizes, the smallest first.
+    pub fn unflushed(&self) -> Vec<(u64, PathBuf, u64)> {
+        self.writeback.unflushed()
+    }
+
+    /// Writes the metadata kept on disk, the inode table, through to the disk.
+    pub fn sync_metadata(&self) -> Result<()> {
+        match self.nodes_manager.read().unwrap().handles {
+            Some(ref handles) => handles.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Uploads what was written to the files of the directory `ino`, a barrier
+    /// after which its entries are in the backend. Creations, renames and removals
+    /// reach the backend before they return, the written content of the files is
+    /// all write-back leaves pending. Fails with the first failed upload.
+    pub fn sync_dir(&self, ino: u64) -> Result<()> {
+        self.counted("fsyncdir", || {
+            let children: Vec<u64> = {
+                let manager = self.nodes_manager.read().unwrap();
+                if manager.get_node_by_inode(ino)?.attr().kind != FileType::Directory {
+                    return Err(Error::Fuse(libc::ENOTDIR));
+                }
+                self.writeback
+                    .copies()
+                    .into_iter()
+                    .filter(|child| match manager.get_node_by_inode(*child) {
+                        Ok(node) => node.parent() == ino,
+                        Err(_) => false,
+                    })
+                    .collect()
+            };
+            let mut result = Ok(());
+            for child in children {
+                if let Err(e) = self.flush(child) {
+                    log::error!(
+                        "failed to upload inode {} of directory {}. {}",
+                        child,
+                        ino,
+                        e
+                    );
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+            result
+        })
+    }
+
+    /// Counts a lookup of `ino` replied to the kernel, which keeps the inode from
+    /// being evicted until the kernel forgets it.
+    pub fn looked_up(&self, ino: u64) {
+        self.nodes_manager.read().unwrap().looked_up(ino);
+    }
+
+    /// Drops `nlookup` lookups of `ino`, once the kernel forgot them.
+    pub fn forget(&self, ino: u64, nlookup: u64) {
+        self.nodes_manager.read().unwrap().forget(ino, nlookup);
+    }
+
+    /// Number of a new handle of the file `ino`, which reads the current version of
+    /// the file and whose reads adapt to how it is read if reads adapt to handles.
+    pub fn open_handle(&self, ino: u64) -> u64 {
+        let fh = match self.open_handles {
+            Some(ref handles) => handles.open(ino),
+            None => self.pins.next_handle(),
+        };
+        let node = {
+            let nodes_manager = self.nodes_manager.read().unwrap();
+            nodes_manager.open(ino);
+            nodes_manager.get_node_by_inode(ino).ok().cloned()
+        };
+        match node {
+            Some(node) => {
+                let attr = node.attr();
+                self.trace(Op::Open, &node, 0, attr.size);
+                self.pins
+                    .pin(fh, ino, Some((attr.size, attr.mtime)), node.etag());
+                if self
+                    .direct_io
+                    .applies(relative(&self.root_path, &node.path()), attr.size)
+                {
+                    self.direct_handles.lock().unwrap().insert(fh);
+                }
+                if let Some(ref passthrough) = self.passthrough {
+                    if attr.kind == FileType::RegularFile
+                        && self.virtual_files.get(&node.path()).is_none()
+                    {
+                        if let Some(local) = self.backend.local_file(node.path()) {
+                            passthrough.open(fh, &local);
+                        }
+                    }
+                }
+            }
+            None => self.pins.pin(fh, ino, None, None),
+        }
+        fh
+    }
+
+    // records `op` of the file `node` in the access trace, if there is one
+    fn trace(&self, op: Op, node: &Node, offset: u64, size: u64) {
+        if let Some(ref tracer) = self.tracer {
+            tracer.record(op, relative(&self.root_path, &node.path()), offset, size);
+        }
+    }
+
+    /// Whether the handle `fh` reads around the caches, opened with direct_io.
+    pub fn is_direct(&self, fh: u64) -> bool {
+        self.direct_handles.lock().unwrap().contains(&fh)
+    }
+
+    /// Whether the handle `fh` reads the local file of the backend.
+    pub fn is_passthrough(&self, fh: u64) -> bool {
+        match self.passthrough {
+            Some(ref passthrough) => passthrough.is_open(fh),
+            None => false,
+        }
+    }
+
+    /// Forgets the handle `fh` once it is closed.
+    pub fn close_handle(&self, fh: u64) {
+        if let Some(pin) = self.pins.get(fh) {
+            self.nodes_manager.read().unwrap().close(pin.ino);
+        }
+        self.pins.unpin(fh);
+        self.direct_handles.lock().unwrap().remove(&fh);
+        if let Some(ref passthrough) = self.passthrough {
+            passthrough.close(fh);
+        }
+        let handles = match self.open_handles {
+            Some(ref handles) => handles,
+            None => return,
+        };
+        if let Some(pattern) = handles.close(fh) {
+            log::debug!(
+                "fh: {}, reads: {}, sequential: {}, average size: {}",
+                fh,
+                pattern.reads,
+                pattern.sequential,
+                pattern.average()
+            );
+        }
+    }
+
+    // drops what the handles of the file `ino` fetched ahead and its blocks, once
+    // it changes
+    fn forget_fetched(&self, ino: u64) {
+        self.pins.reset(ino);
+        if let Some(ref handles) = self.open_handles {
+            handles.forget(ino);
+        }
+        if let Some(ref blocks) = self.blocks {
+            blocks.forget(ino);
+        }
+    }
+
+    /// Flushes the file `ino` once it is closed and drops its written data from memory.
+    pub fn release(&self, ino: u64) -> Result<()> {
+        self.counted("release", || {
+            if self.writeback.release(&*self.backend, ino)? {
+                self.nodes_manager.read().unwrap().mark_pending(ino);
+            }
+            Ok(())
+        })
+    }
+
+    /// Deletes the file `name` of the directory `parent`. With a trash retention the
+    /// file is copied to the trash first, unless it is in the trash already.
+    pub fn unlink(&self, parent: u64, name: &OsStr) -> Result<()> {
+        self.counted("unlink", || {
+            let node = self.child(parent, name)?;
+            if node.attr().kind == FileType::Directory {
+                return Err(Error::Fuse(libc::EISDIR));
+            }
+            let path = node.path();
+            if self.virtual_files.get(&path).is_some() {
+                return Err(Error::Fuse(libc::EACCES));
+            }
+            if self.trash_retention.is_some() && !trash::in_trash(&self.root_path, &path) {
+                let from = path.clone();
+                let to = trash::trash_path(&self.root_path, &path, SystemTime::now());
+                self.call_backend("backend copy", Some(Request::Put), move |backend| {
+                    backend.copy(from, to)
+                })?;
+            }
+            let removed = path.clone();
+            self.call_backend("backend remove", None, move |backend| {
+                backend.remove(removed)
+            })?;
+            self.writeback.forget(node.inode());
+            if let Some(ref quota) = self.quota {
+                quota.remove(node.attr().size);
+            }
+            self.nodes_manager
+                .write()
+                .unwrap()
+                .remove_path(&self.root_path, &path);
+            Ok(())
+        })
+    }
+
+    /// Deletes the empty directory `name` of the directory `parent`, or the
+    /// directory with everything below it when rmdir is recursive.
+    pub fn rmdir(&self, parent: u64, name: &OsStr) -> Result<()> {
+        self.counted("rmdir", || {
+            let node = self.child(parent, name)?;
+            if node.attr().kind != FileType::Directory {
+                return Err(Error::Fuse(libc::ENOTDIR));
+            }
+            let path = node.path();
+            let listed = path.clone();
+            let children = self.call_backend(
+                "backend get_children",
+                Some(Request::List),
+                move |backend| backend.get_children(listed),
+            )?;
+            if !children.is_empty() && self.recursive_rmdir {
+                return self.remove_tree(&node);
+            }
+            if !children.is_empty() {
+                return Err(Error::Fuse(libc::ENOTEMPTY));
+            }
+            let removed = path.clone();
+            self.call_backend("backend remove_dir", None, move |backend| {
+                backend.remove_dir(removed)
+            })?;
+            self.nodes_manager
+                .write()
+                .unwrap()
+                .remove_path(&self.root_path, &path);
+            Ok(())
+        })
+    }
+
+    /// Removes the directory `node` with everything below it. The entries below it
+    /// are dropped from the tree and their pending uploads cancelled first, when
+    /// some of them can not be removed the directory is listed again. Entries
+    /// hidden by the filter and virtual ones are kept, the directory then stays and
+    /// the removal fails with ENOTEMPTY. The policy is only checked for the
+    /// directory, so with one it is refused.
+    fn remove_tree(&self, node: &Node) -> Result<()> {
+        let path = node.path();
+        if !self.policy.is_empty() {
+            log::debug!("not removing {:?} recursively under a policy", path);
+            return Err(Error::Fuse(libc::EACCES));
+        }
+        let index = self
+            .nodes_manager
+            .read()
+            .unwrap()
+            .ino_mapper
+            .get(&node.inode())
+            .cloned()
+            .ok_or_else(|| Error::Fuse(libc::ENOENT))?;
+        let forgotten = self.nodes_manager.write().unwrap().forget_children(&index);
+        for ino in forgotten {
+            self.writeback.forget(ino);
+        }
+        log::info!("removing {:?} recursively", path);
+        let count = if self.filter.is_empty() && !self.virtual_files.is_dir(&path) {
+            let removed = path.clone();
+            self.call_backend("backend remove_all", None, move |backend| {
+                let mut reported = 0;
+                backend.remove_all(&removed, &mut |count| {
+                    if count >= reported + REMOVE_PROGRESS {
+                        log::info!("removed {} entries below {:?}", count, removed);
+                        reported = count;
+                    }
+                })
+            })?
+        } else {
+            let mut removal = Removal::default();
+            let removed = self.remove_exposed(&path, &mut removal);
+            let count = removal.finish(&path)?;
+            if !removed {
+                log::info!(
+                    "removed {} entries below {:?}, the hidden ones are kept",
+                    count,
+                    path
+                );
+                return Err(Error::Fuse(libc::ENOTEMPTY));
+            }
+            count
+        };
+        log::info!("removed {:?} with {} entries", path, count);
+        self.nodes_manager
+            .write()
+            .unwrap()
+            .remove_path(&self.root_path, &path);
+        Ok(())
+    }
+
+    // removes the entries the mount exposes below `path` depth first, then `path`
+    // itself unless some are left, returns whether it was removed
+    fn remove_exposed(&self, path: &Path, removal: &mut Removal) -> bool {
+        let listed = path.to_path_buf();
+        let children = match self.call_backend(
+            "backend get_children",
+            Some(Request::List),
+            move |backend| backend.get_children(listed),
+        ) {
+            Ok(children) => children,
+            Err(e) => {
+                removal.failed.push((format!("{:?}", path), e));
+                return false;
+            }
+        };
+        let mut kept = self.virtual_files.is_dir(path);
+        for child in children {
+            let child_path = child.path();
+            if !exposed(&self.filter, &self.root_path, &child)
+                || self.virtual_files.get(&child_path).is_some()
+            {
+                kept = true;
+                continue;
+            }
+            if child.attr().kind == FileType::Directory {
+                kept |= !self.remove_exposed(&child_path, removal);
+                continue;
+            }
+            let removed = child_path.clone();
+            match self.call_backend("backend remove", None, move |backend| {
+                backend.remove(removed)
+            }) {
+                Ok(()) => count_removed(removal, path),
+                Err(e) => {
+                    removal.failed.push((format!("{:?}", child_path), e));
+                    kept = true;
+                }
+            }
+        }
+        if kept {
+            return false;
+        }
+        let removed = path.to_path_buf();
+        match self.call_backend("backend remove_dir", None, move |backend| {
+            backend.remove_dir(removed)
+        }) {
+            Ok(()) => {
+                count_removed(removal, path);
+                true
+            }
+            Err(e) => {
+                removal.failed.push((format!("{:?}", path), e));
+                false
+            }
+        }
+    }
+
+    /// Value of the extended attribute `name` of `ino`.
+    pub fn getxattr(&self, ino: u64, name: &OsStr) -> Result<Vec<u8>> {
+        self.counted("getxattr", || {
+            self.resolve(ino)?;
+            if name == STORAGE_CLASS_XATTR {
+                return self
+                    .writeback
+                    .storage_class(ino)
+                    .map(|class| class.into_bytes())
+                    .ok_or_else(|| Error::Fuse(libc::ENODATA));
+            }
+            if let Some(key) = self.tag_key(name) {
+                let node = self.regular_file(ino)?;
+                let path = node.path();
+                let tags =
+                    self.call_backend("backend get_tags", Some(Request::Get), move |backend| {
+                        backend.get_tags(path)
+                    })?;
+                return tags
+                    .into_iter()
+                    .find(|(tag, _)| tag == key)
+                    .map(|(_, value)| value.into_bytes())
+                    .ok_or_else(|| Error::Fuse(libc::ENODATA));
+            }
+            if name == SYNC_XATTR {
+                self.regular_file(ino)?;
+                return Ok(self.writeback.state(ino).to_string().into_bytes());
+            }
+            if name == DU_BYTES_XATTR {
+                return Ok(self.usage(ino)?.bytes.to_string().into_bytes());
+            }
+            if name == DU_ENTRIES_XATTR {
+                return Ok(self.usage(ino)?.entries().to_string().into_bytes());
+            }
+            if name == ETAG_XATTR || name == MD5_XATTR {
+                let etag = self.etag(ino)?.ok_or_else(|| Error::Fuse(libc::ENODATA))?;
+                if name == ETAG_XATTR {
+                    return Ok(etag.into_bytes());
+                }
+                return md5_of(&etag)
+                    .map(|md5| md5.as_bytes().to_vec())
+                    .ok_or_else(|| Error::Fuse(libc::ENODATA));
+            }
+            Err(Error::Fuse(libc::ENODATA))
+        })
+    }
+
+    /// Sets the extended attribute `name` of `ino`. Setting the storage class moves
+    /// the file to it and keeps it there when it is uploaded again, setting a tag
+    /// tags the object until it is uploaded again.
+    pub fn setxattr(&self, ino: u64, name: &OsStr, value: &[u8]) -> Result<()> {
+        self.counted("setxattr", || {
+            if let Some(key) = self.tag_key(name) {
+                return self.update_tag(ino, key, Some(value));
+            }
+            if name != STORAGE_CLASS_XATTR {
+                return Err(Error::Fuse(libc::ENOTSUP));
+            }
+            let node = self.regular_file(ino)?;
+            let class = std::str::from_utf8(value)
+                .map_err(|_| Error::Fuse(libc::EINVAL))?
+                .trim()
+                .to_owned();
+            if class.is_empty() {
+                return Err(Error::Fuse(libc::EINVAL));
+            }
+            let path = node.path();
+            let moved = class.clone();
+            self.call_backend(
+                "backend set_storage_class",
+                Some(Request::Put),
+                move |backend| backend.set_storage_class(path, &moved),
+            )?;
+            self.writeback.set_storage_class(ino, Some(class));
+            Ok(())
+        })
+    }
+
+    /// Names of the extended attributes set on `ino`.
+    pub fn listxattr(&self, ino: u64) -> Result<Vec<String>> {
+        self.counted("listxattr", || {
+            self.resolve(ino)?;
+            let mut names = vec![];
+            if self.writeback.storage_class(ino).is_some() {
+                names.push(STORAGE_CLASS_XATTR.to_owned());
+            }
+            if self.writeback.has_copy(ino) {
+                names.push(SYNC_XATTR.to_owned());
+            }
+            let node = self
+                .nodes_manager
+                .read()
+                .unwrap()
+                .get_node_by_inode(ino)?
+                .clone();
+            if let Some(etag) = node.etag() {
+                names.push(ETAG_XATTR.to_owned());
+                if md5_of(&etag).is_some() {
+                    names.push(MD5_XATTR.to_owned());
+                }
+            }
+            if self.object_tags && self.regular_file(ino).is_ok() && !self.writeback.has_copy(ino) {
+                let path = node.path();
+                let tags =
+                    self.call_backend("backend get_tags", Some(Request::Get), move |backend| {
+                        backend.get_tags(path)
+                    });
+                match tags {
+                    Ok(tags) => names.extend(
+                        tags.into_iter()
+                            .map(|(key, _)| format!("{}{}", TAG_XATTR_PREFIX, key)),
+                    ),
+                    Err(e) => log::warn!("failed to get the tags of {:?}. {}", node.path(), e),
+                }
+            }
+            Ok(names)
+        })
+    }
+
+    // the tag the extended attribute `name` maps to, if tags are exposed
+    fn tag_key<'a>(&self, name: &'a OsStr) -> Option<&'a str> {
+        if !self.object_tags {
+            return None;
+        }
+        let name = name.to_str()?;
+        if name.len() <= TAG_XATTR_PREFIX.len() || !name.starts_with(TAG_XATTR_PREFIX) {
+            return None;
+        }
+        Some(&name[TAG_XATTR_PREFIX.len()..])
+    }
+
+    /// Sets the tag `key` of the file `ino` to `value`, or removes it with `None`.
+    /// The store replaces all the tags of an object at once, so the others are read
+    /// first. Fails with EBUSY while written data waits for upload, the upload would
+    /// drop the tags.
+    fn update_tag(&self, ino: u64, key: &str, value: Option<&[u8]>) -> Result<()> {
+        let node = self.regular_file(ino)?;
+        if self.writeback.has_copy(ino) {
+            return Err(Error::Fuse(libc::EBUSY));
+        }
+        let path = node.path();
+        let listed = path.clone();
+        let mut tags =
+            self.call_backend("backend get_tags", Some(Request::Get), move |backend| {
+                backend.get_tags(listed)
+            })?;
+        let existing = tags.iter().position(|(tag, _)| tag == key);
+        match (value, existing) {
+            (Some(value), existing) => {
+                let value = std::str::from_utf8(value)
+                    .map_err(|_| Error::Fuse(libc::EINVAL))?
+                    .to_owned();
+                if key.chars().count() > MAX_TAG_KEY || value.chars().count() > MAX_TAG_VALUE {
+                    return Err(Error::Fuse(libc::EINVAL));
+                }
+                match existing {
+                    Some(index) => tags[index].1 = value,
+                    None if tags.len() >= MAX_TAGS => return Err(Error::Fuse(libc::ENOSPC)),
+                    None => tags.push((key.to_owned(), value)),
+                }
+            }
+            (None, Some(index)) => {
+                tags.remove(index);
+            }
+            (None, None) => return Err(Error::Fuse(libc::ENODATA)),
+        }
+        self.call_backend("backend set_tags", Some(Request::Put), move |backend| {
+            backend.set_tags(path, &tags)
+        })
+    }
+
+    /// Entity tag the backend has for the file `ino`, the one of its listing or
+    /// else fetched with a head. `None` while written data waits for upload or if
+    /// the backend has none.
+    fn etag(&self, ino: u64) -> Result<Option<String>> {
+        let node = self.regular_file(ino)?;
+        if self.writeback.has_copy(ino) {
+            return Ok(None);
+        }
+        if let Some(etag) = node.etag() {
+            return Ok(Some(etag));
+        }
+        let path = node.path();
+        let fetched =
+            self.call_backend("backend get_node", Some(Request::Head), move |backend| {
+                backend.get_node(path)
+            })?;
+        node.set_etag(fetched.etag());
+        Ok(fetched.etag())
+    }
+
+    /// Removes the extended attribute `name` of `ino`. Without its storage class the
+    /// file is uploaded in the default class again, until then it stays where it is.
+    pub fn removexattr(&self, ino: u64, name: &OsStr) -> Result<()> {
+        self.counted("removexattr", || {
+            self.resolve(ino)?;
+            if let Some(key) = self.tag_key(name) {
+                return self.update_tag(ino, key, None);
+            }
+            if name != STORAGE_CLASS_XATTR || self.writeback.storage_class(ino).is_none() {
+                return Err(Error::Fuse(libc::ENODATA));
+            }
+            self.writeback.set_storage_class(ino, None);
+            Ok(())
+        })
+    }
+
+    /// Drops what is cached about `ino`: the entries below a directory, which is
+    /// listed again when it is read, or the attributes of a file, which are fetched
+    /// again right away. Returns the inodes whose content may be stale.
+    pub fn invalidate(&self, ino: u64) -> Result<Vec<u64>> {
+        self.counted("invalidate", || {
+            self.resolve(ino)?;
+            let (index, node) = {
+                let nodes_manager = self.nodes_manager.read().unwrap();
+                let index = nodes_manager
+                    .ino_mapper
+                    .get(&ino)
+                    .cloned()
+                    .ok_or_else(|| Error::Fuse(libc::ENOENT))?;
+                (index, nodes_manager.get_node_by_inode(ino)?.clone())
+            };
+            if node.attr().kind == FileType::Directory {
+                return Ok(self.nodes_manager.write().unwrap().forget_children(&index));
+            }
+            if self.nodes_manager.read().unwrap().is_pending(ino) {
+                return Ok(vec![]);
+            }
+            let path = node.path();
+            let fetched =
+                self.call_backend("backend get_node", Some(Request::Head), move |backend| {
+                    backend.get_node(path)
+                });
+            match fetched {
+                Ok(fetched) => node.set_attr(fetched.attr()),
+                Err(Error::NotFound(_)) => self
+                    .nodes_manager
+                    .write()
+                    .unwrap()
+                    .remove_path(&self.root_path, &node.path()),
+                Err(e) => return Err(e),
+            }
+            Ok(vec![ino])
+        })
+    }
+
+    /// Keeps `ino`, and the children of a directory, from being evicted when the
+    /// metadata takes too much memory, or lets them be evicted again.
+    pub fn pin(&self, ino: u64, pinned: bool) -> Result<()> {
+        self.resolve(ino)?;
+        let mut nodes_manager = self.nodes_manager.write().unwrap();
+        if pinned {
+            nodes_manager.pinned.insert(ino);
+        } else {
+            nodes_manager.pinned.remove(&ino);
+        }
+        Ok(())
+    }
+
+    /// Files whose changes did not reach the backend yet.
+    pub fn pending_uploads(&self) -> Pending {
+        self.writeback.pending()
+    }
+
+    /// How long the kernel may cache `attr`, `base` unless ttls adapt to how often
+    /// inodes change.
+    pub fn ttl(&self, attr: &FileAttr, base: Duration) -> Duration {
+        match self.ttls {
+            Some(ref ttls) => ttls.of(attr, base),
+            None => base,
+        }
+    }
+
+    /// Labels naming the mount.
+    pub fn labels(&self) -> &Labels {
+        &self.labels
+    }
+
+    /// Health of the backend, `None` unless it is probed.
+    pub fn health(&self) -> Option<Arc<Health>> {
+        self.health.clone()
+    }
+
+    /// Number of entries pinned.
+    pub fn pinned(&self) -> usize {
+        self.nodes_manager.read().unwrap().pinned.len()
+    }
+
+    /// Path of `ino` relative to the mount root, from the tree or, for an inode
+    /// handed out before a restart, from the inode table. Fails with ENOENT for an
+    /// inode known to neither, e.g. one evicted or removed since it was handed out.
+    pub fn path_of(&self, ino: u64) -> Result<PathBuf> {
+        let nodes_manager = self.nodes_manager.read().unwrap();
+        let path = match nodes_manager.ino_mapper.get(&ino) {
+            Some(index) => nodes_manager.nodes_tree.get(index).unwrap().data().path(),
+            None => nodes_manager
+                .recorded_path(ino)
+                .ok_or_else(|| Error::Fuse(libc::ENOENT))?,
+        };
+        Ok(Path::new("/").join(relative(&self.root_path, &path)))
+    }
+
+    /// Writes a line per entry of the tree at and below `ino`, parents first: its
+    /// inode, path, kind, size, mtime and the number of its children in the tree,
+    /// `-` for a directory whose listing is not complete. Returns the number of
+    /// entries written.
+    pub fn dump<W: std::io::Write>(&self, ino: u64, out: &mut W) -> Result<usize> {
+        let lines: Vec<String> = {
+            let nodes_manager = self.nodes_manager.read().unwrap();
+            let index = nodes_manager
+                .ino_mapper
+                .get(&ino)
+                .ok_or_else(|| Error::Fuse(libc::ENOENT))?;
+            let nodes = nodes_manager
+                .nodes_tree
+                .traverse_pre_order(index)
+                .map_err(|e| Error::Other(format!("node id error. {}", e)))?;
+            nodes
+                .map(|tree_node| {
+                    let node = tree_node.data();
+                    let attr = node.attr();
+                    let children = if attr.kind != FileType::Directory {
+                        "0".to_owned()
+                    } else if nodes_manager.listed.contains_key(&node.inode()) {
+                        tree_node.children().len().to_string()
+                    } else {
+                        "-".to_owned()
+                    };
+                    let mtime = attr
+                        .mtime
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map(|mtime| mtime.as_secs())
+                        .unwrap_or(0);
+                    format!(
+                        "{}\t{:?}\t{:?}\t{}\t{}\t{}\n",
+                        node.inode(),
+                        Path::new("/").join(relative(&self.root_path, &node.path())),
+                        attr.kind,
+                        attr.size,
+                        mtime,
+                        children
+                    )
+                })
+                .collect()
+        };
+        for line in &lines {
+            out.write_all(line.as_bytes())?;
+        }
+        Ok(lines.len())
+    }
+
+    /// Fails with EACCES unless the policy lets `uid` access `ino`, or its child
+    /// `name`.
+    pub fn permitted(
+        &self,
+        uid: u32,
+        ino: u64,
+        name: Option<&OsStr>,
+        access: Access,
+    ) -> Result<()> {
+        if self.policy.is_empty() {
+            return Ok(());
+        }
+        self.resolve(ino)?;
+        let mut path = self
+            .nodes_manager
+            .read()
+            .unwrap()
+            .get_node_by_inode(ino)?
+            .path();
+        if let Some(name) = name {
+            path.push(name);
+        }
+        if self
+            .policy
+            .permits(uid, relative(&self.root_path, &path), access)
+        {
+            return Ok(());
+        }
+        log::debug!("uid {} may not {:?} {:?}", uid, access, path);
+        Err(Error::Fuse(libc::EACCES))
+    }
+
+    /// Whether `uid` may see `node` in a listing.
+    pub fn visible(&self, uid: u32, node: &Node) -> bool {
+        self.policy.is_empty()
+            || self
+                .policy
+                .permits(uid, relative(&self.root_path, &node.path()), Access::Read)
+    }
+
+    fn child(&self, parent: u64, name: &OsStr) -> Result<Node> {
+        self.lookup(parent, name)?;
+        self.nodes_manager
+            .read()
+            .unwrap()
+            .get_child_by_name(parent, name)?
+            .cloned()
+            .ok_or_else(|| Error::Fuse(libc::ENOENT))
+    }
+
+    fn check_file_size(&self, node: &Node, size: u64) -> Result<()> {
+        match self.max_file_size {
+            Some(max) if size > max => {
+                log::error!(
+                    "refusing to grow {:?} to {} bytes, above the limit of {}",
+                    node.path(),
+                    size,
+                    max
+                );
+                Err(Error::Fuse(libc::EFBIG))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // takes the bytes growing `node` to `size` from the quota, returns how many
+    fn grow_quota(&self, node: &Node, size: u64) -> Result<u64> {
+        let grown = size.saturating_sub(node.attr().size);
+        if let Some(ref quota) = self.quota {
+            quota.grow(grown)?;
+        }
+        Ok(grown)
+    }
+
+    fn shrink_quota(&self, bytes: u64) {
+        if let Some(ref quota) = self.quota {
+            quota.shrink(bytes);
+        }
+    }
+
+    fn regular_file(&self, ino: u64) -> Result<Node> {
+        self.resolve(ino)?;
+        let node = self
+            .nodes_manager
+            .read()
+            .unwrap()
+            .get_node_by_inode(ino)?
+            .clone();
+        if node.attr().kind != FileType::RegularFile {
+            return Err(Error::Fuse(libc::EISDIR));
+        }
+        if self.virtual_files.get(&node.path()).is_some() {
+            return Err(Error::Fuse(libc::EACCES));
+        }
+        Ok(node)
+    }
+}
+
+// the entity tag goes too, it is fetched again once the content is uploaded
+fn set_size(node: &Node, size: u64) {
+    node.set_etag(None);
+    let now = SystemTime::now();
+    let mut attr = node.attr();
+    attr.size = size;
+    attr.blocks = (size + 511) / 512;
+    attr.mtime = now;
+    attr.ctime = now;
+    node.set_attr(attr);
+}
+
+// the entity tag as an md5, multipart uploads have a part count appended
+fn md5_of(etag: &str) -> Option<&str> {
+    if etag.len() == 32 && etag.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Some(etag);
+    }
+    None
+}
+
+fn relative<'a>(root: &Path, path: &'a Path) -> &'a Path {
+    path.strip_prefix(root).unwrap_or(path)
+}
+
+// adds the directory at `path` and its ancestors to the tree, `None` if it is a
+// file or filtered out
+fn ingest_dir(
+    nodes_manager: &mut InodeManager,
+    dirs: &mut HashMap<PathBuf, (NodeId, u64)>,
+    path: &Path,
+    root_attr: &FileAttr,
+) -> Option<(NodeId, u64)> {
+    if let Some(dir) = dirs.get(path) {
+        return Some(dir.clone());
+    }
+    let (parent_index, parent) = ingest_dir(nodes_manager, dirs, path.parent()?, root_attr)?;
+    let mut attr = *root_attr;
+    attr.ino = 0;
+    let node = nodes_manager.add_child(
+        &parent_index,
+        parent,
+        &Node::new(0, 0, path.to_path_buf(), attr),
+    );
+    if node.attr().kind != FileType::Directory {
+        return None;
+    }
+    let dir = (
+        nodes_manager.ino_mapper.get(&node.inode())?.clone(),
+        node.inode(),
+    );
+    dirs.insert(path.to_path_buf(), dir.clone());
+    Some(dir)
+}
+
+fn exposed(filter: &Filter, root: &Path, node: &Node) -> bool {
+    filter.exposes(relative(root, &node.path()), node.attr().kind)
+}
+
+fn count_removed(removal: &mut Removal, path: &Path) {
+    removal.removed += 1;
+    if removal.removed % REMOVE_PROGRESS == 0 {
+        log::info!(
+            "removed {} entries, the last below {:?}",
+            removal.removed,
+            path
+        );
+    }
+}
+
+fn poll_changes<B: Backend>(
+    backend: &B,
+    nodes_manager: &RwLock<InodeManager>,
+    watches: &Watches,
+    budget: &Budget,
+    filter: &Filter,
+    root_path: &Path,
+) {
+    let count = watches.count();
+    if count == 0 || !budget.try_refresh(Request::List, count as u64) {
+        return;
+    }
+    let mut changes = watches.poll(backend);
+    changes.retain(|change| match change.attr {
+        Some(attr) => filter.exposes(relative(root_path, change.event.path()), attr.kind),
+        None => true,
+    });
+    if changes.is_empty() {
+        return;
+    }
+    log::debug!("{} changes in watched directories", changes.len());
+    {
+        let mut nodes_manager = nodes_manager.write().unwrap();
+        for change in changes.iter() {
+            nodes_manager.apply(root_path, change);
+        }
+    }
+    watches.notify(&changes);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FileSystem, ETAG_XATTR, MD5_XATTR, ROOT_INODE, STORAGE_CLASS_XATTR};
+    use crate::error::Error;
+    use crate::ossfs_impl::attr::FileType;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filter::Filter;
+    use crate::ossfs_impl::node::Node;
+    use crate::ossfs_impl::pins::RemoteChange;
+    use crate::ossfs_impl::policy::{Access, Policy};
+    use std::ffi::OsStr;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_watch() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/dir/a", b"1");
+        backend.add_file("/dir/b", b"2");
+        let config = Config::default();
+        let fs = FileSystem::new(backend.clone(), &config);
+        let dir = fs.lookup(ROOT_INODE, OsStr::new("dir")).unwrap().ino;
+        assert_eq!(fs.readdir(dir, 0, 0).unwrap().len(), 2);
+        let a = fs.lookup(dir, OsStr::new("a")).unwrap().ino;
+        let events = fs.watch("/dir");
+        fs.poll_changes();
+
+        backend.add_file("/dir/a", b"longer");
+        backend.remove("/dir/b");
+        backend.add_file("/dir/c", b"3");
+        fs.poll_changes();
+        assert_eq!(events.try_iter().count(), 3);
+        assert_eq!(fs.getattr(a).unwrap().size, 6);
+        let names: Vec<PathBuf> = fs
+            .readdir(dir, 0, 0)
+            .unwrap()
+            .iter()
+            .map(|node| node.path())
+            .collect();
+        assert_eq!(
+            names,
+            vec![PathBuf::from("/dir/a"), PathBuf::from("/dir/c")]
+        );
+    }
+
+    #[test]
+    fn test_read_after_write() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/dir/a", b"old");
+        let config = Config::default();
+        let fs = FileSystem::new(backend.clone(), &config);
+        let dir = fs.lookup(ROOT_INODE, OsStr::new("dir")).unwrap().ino;
+        let a = fs.lookup(dir, OsStr::new("a")).unwrap().ino;
+        let _events = fs.watch("/dir");
+        fs.poll_changes();
+        let read = |ino| {
+            let mut content = None;
+            fs.read(ino, 0, false, 0, 100, |result| {
+                content = Some(result.unwrap())
+            });
+            content.unwrap()
+        };
+
+        let new = fs
+            .mknod(
+                dir,
+                OsStr::new("new"),
+                FileType::RegularFile,
+                0o644,
+                0,
+                0,
+                0,
+            )
+            .unwrap()
+            .inode();
+        assert_eq!(fs.getattr(new).unwrap().size, 0);
+        fs.write(new, 0, b"fresh").unwrap();
+        fs.write(a, 0, b"NEW").unwrap();
+        assert_eq!(read(new), b"fresh");
+        assert_eq!(read(a), b"NEW");
+        fs.release(new).unwrap();
+        assert_eq!(backend.content("/dir/new").unwrap(), b"fresh");
+
+        // a listing lagging behind the local changes leaves them alone
+        backend.remove("/dir/new");
+        backend.add_file("/dir/a", b"stale, and longer");
+        fs.poll_changes();
+        assert_eq!(fs.lookup(dir, OsStr::new("new")).unwrap().size, 5);
+        assert_eq!(fs.getattr(a).unwrap().size, 3);
+        assert_eq!(read(a), b"NEW");
+    }
+
+    #[test]
+    fn test_sync_dir() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/dir/a", b"old");
+        backend.add_file("/other/b", b"old");
+        let config = Config::default();
+        let fs = FileSystem::new(backend.clone(), &config);
+        let dir = fs.lookup(ROOT_INODE, OsStr::new("dir")).unwrap().ino;
+        let other = fs.lookup(ROOT_INODE, OsStr::new("other")).unwrap().ino;
+        let a = fs.lookup(dir, OsStr::new("a")).unwrap().ino;
+        let b = fs.lookup(other, OsStr::new("b")).unwrap().ino;
+        fs.write(a, 0, b"new").unwrap();
+        fs.write(b, 0, b"new").unwrap();
+        assert_eq!(backend.content("/dir/a").unwrap(), b"old");
+
+        fs.sync_dir(dir).unwrap();
+        assert_eq!(backend.content("/dir/a").unwrap(), b"new");
+        assert_eq!(backend.content("/other/b").unwrap(), b"old");
+        assert!(fs.sync_dir(a).is_err());
+    }
+
+    #[test]
+    fn test_remote_change() {
+        let backend = Arc::new(MockBackend::new());
+        for mode in [RemoteChange::Invalidate, RemoteChange::Pin].iter() {
+            backend.add_file("/dir/a", b"0123456789");
+            let config = Config {
+                block_size: Some(4),
+                remote_change: *mode,
+                ..Config::default()
+            };
+            let fs = FileSystem::new(backend.clone(), &config);
+            let dir = fs.lookup(ROOT_INODE, OsStr::new("dir")).unwrap().ino;
+            let a = fs.lookup(dir, OsStr::new("a")).unwrap().ino;
+            let _events = fs.watch("/dir");
+            fs.poll_changes();
+            let read = |fh, offset| {
+                let mut content = None;
+                fs.read(a, fh, false, offset, 4, |result| content = Some(result));
+                content.unwrap()
+            };
+            let fh = fs.open_handle(a);
+            assert_eq!(read(fh, 0).unwrap(), b"0123");
+
+            backend.add_file("/dir/a", b"abcdefghijkl");
+            fs.poll_changes();
+            if *mode == RemoteChange::Pin {
+                // the cached block of the old version, the other one is gone
+                assert_eq!(read(fh, 0).unwrap(), b"0123");
+                assert_eq!(read(fh, 4).unwrap_err().errno(0), libc::ESTALE);
+            } else {
+                assert_eq!(read(fh, 4).unwrap(), b"efgh");
+            }
+            fs.close_handle(fh);
+            assert_eq!(read(fs.open_handle(a), 0).unwrap(), b"abcd");
+        }
+    }
+
+    #[test]
+    fn test_readdir_while_listing() {
+        let backend = Arc::new(MockBackend::new());
+        for name in ["a", "b", "c", "d", "e"].iter() {
+            backend.add_file(format!("/dir/{}", name), b"");
+        }
+        backend.add_file("/broken/a", b"");
+        backend.add_file("/broken/b", b"");
+        backend.add_file("/broken/c", b"");
+        backend.set_page_size(2);
+        backend.set_latency("list_page", Duration::from_millis(300));
+        let config = Config::default();
+        let fs = FileSystem::new(backend.clone(), &config);
+        let dir = fs.lookup(ROOT_INODE, OsStr::new("dir")).unwrap().ino;
+
+        let started = Instant::now();
+        assert_eq!(fs.readdir(dir, 0, 0).unwrap().len(), 2);
+        assert!(started.elapsed() < Duration::from_millis(300));
+        assert_eq!(fs.readdir(dir, 0, 2).unwrap().len(), 2);
+        assert_eq!(fs.readdir(dir, 0, 4).unwrap().len(), 1);
+        assert!(fs.readdir(dir, 0, 5).unwrap().is_empty());
+        assert_eq!(fs.readdir(dir, 0, 0).unwrap().len(), 5);
+        assert_eq!(backend.calls("get_children"), 1);
+
+        let broken = fs.lookup(ROOT_INODE, OsStr::new("broken")).unwrap().ino;
+        backend.fail_next("list_page", crate::Error::Timeout("second page".to_owned()));
+        assert_eq!(fs.readdir(broken, 0, 0).unwrap().len(), 2);
+        assert!(fs.readdir(broken, 0, 2).is_err());
+        // the failed listing is fetched again
+        assert_eq!(fs.readdir(broken, 0, 2).unwrap().len(), 1);
+        assert_eq!(backend.calls("get_children"), 3);
+    }
+
+    #[test]
+    fn test_listing_timeout() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/dir/a", b"");
+        let config = Config {
+            backend_timeout: Some(Duration::from_millis(100)),
+            backend_threads: 1,
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend.clone(), &config);
+        let dir = fs.lookup(ROOT_INODE, OsStr::new("dir")).unwrap().ino;
+        let calls = backend.calls("get_children");
+
+        backend.set_latency("get_children", Duration::from_millis(300));
+        match fs.readdir(dir, 0, 0) {
+            Err(Error::Timeout(_)) => {}
+            result => panic!("listing did not time out: {:?}", result.map(|_| ())),
+        }
+        // the listing went on on the backend pool and is served once over
+        std::thread::sleep(Duration::from_millis(400));
+        assert_eq!(fs.readdir(dir, 0, 0).unwrap().len(), 1);
+        assert_eq!(backend.calls("get_children"), calls + 1);
+    }
+
+    #[test]
+    fn test_guardrails() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/dir/a", b"");
+        backend.add_file("/dir/b", b"");
+        let config = Config {
+            max_file_size: Some(4),
+            max_dir_entries: Some(2),
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend.clone(), &config);
+        let dir = fs.lookup(ROOT_INODE, OsStr::new("dir")).unwrap().ino;
+        let a = fs.lookup(dir, OsStr::new("a")).unwrap().ino;
+        fs.write(a, 0, b"four").unwrap();
+        assert_eq!(fs.write(a, 4, b"!").unwrap_err().errno(0), libc::EFBIG);
+        assert_eq!(fs.truncate(a, 5).unwrap_err().errno(0), libc::EFBIG);
+        fs.truncate(a, 2).unwrap();
+
+        assert_eq!(fs.readdir(dir, 0, 0).unwrap().len(), 2);
+        let created = fs.mknod(dir, OsStr::new("c"), FileType::RegularFile, 0o644, 0, 0, 0);
+        assert_eq!(created.unwrap_err().errno(0), libc::ENOSPC);
+        assert!(backend.content("/dir/c").is_none());
+    }
+
+    #[test]
+    fn test_recursive_rmdir() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/dir/a", b"1");
+        backend.add_file("/dir/sub/b", b"2");
+        backend.add_file("/dir/sub/c", b"3");
+        let config = Config::default();
+        let fs = FileSystem::new(backend.clone(), &config);
+        let removed = fs.rmdir(ROOT_INODE, OsStr::new("dir"));
+        assert_eq!(removed.unwrap_err().errno(0), libc::ENOTEMPTY);
+
+        let config = Config {
+            recursive_rmdir: true,
+            ..config
+        };
+        let fs = FileSystem::new(backend.clone(), &config);
+        let dir = fs.lookup(ROOT_INODE, OsStr::new("dir")).unwrap().ino;
+        assert_eq!(fs.readdir(dir, 0, 0).unwrap().len(), 2);
+        // the other entries go, the directory stays with what is left
+        backend.fail_next("remove", Error::Backend("injected".to_owned()));
+        assert!(fs.rmdir(ROOT_INODE, OsStr::new("dir")).is_err());
+        let left = ["/dir/a", "/dir/sub/b", "/dir/sub/c"]
+            .iter()
+            .filter(|path| backend.content(path).is_some())
+            .count();
+        assert_eq!(left, 1);
+        assert_eq!(fs.readdir(dir, 0, 0).unwrap().len(), 1);
+
+        fs.rmdir(ROOT_INODE, OsStr::new("dir")).unwrap();
+        assert!(backend.content("/dir/a").is_none());
+        assert!(fs.lookup(ROOT_INODE, OsStr::new("dir")).is_err());
+    }
+
+    #[test]
+    fn test_recursive_rmdir_keeps_hidden() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/dir/a", b"1");
+        backend.add_file("/dir/sub/b.keep", b"2");
+        backend.add_file("/dir/sub/c", b"3");
+        backend.add_file("/other/d", b"4");
+        let config = Config {
+            recursive_rmdir: true,
+            filter: Filter::new().exclude("*.keep"),
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend.clone(), &config);
+        let removed = fs.rmdir(ROOT_INODE, OsStr::new("dir"));
+        assert_eq!(removed.unwrap_err().errno(0), libc::ENOTEMPTY);
+        assert!(backend.content("/dir/a").is_none());
+        assert!(backend.content("/dir/sub/c").is_none());
+        assert_eq!(backend.content("/dir/sub/b.keep").unwrap(), b"2");
+
+        // the policy is not checked below the directory
+        let config = Config {
+            recursive_rmdir: true,
+            policy: Policy::new().allow_everyone("/", Access::Write),
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend.clone(), &config);
+        let removed = fs.rmdir(ROOT_INODE, OsStr::new("other"));
+        assert_eq!(removed.unwrap_err().errno(0), libc::EACCES);
+        assert!(backend.content("/other/d").is_some());
+    }
+
+    #[test]
+    fn test_storage_class() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/a", b"cold");
+        let config = Config::default();
+        let fs = FileSystem::new(backend.clone(), &config);
+        let a = fs.lookup(ROOT_INODE, OsStr::new("a")).unwrap().ino;
+        let name = OsStr::new(STORAGE_CLASS_XATTR);
+        assert!(fs.getxattr(a, name).is_err());
+        fs.setxattr(a, name, b"STANDARD_IA").unwrap();
+        assert_eq!(backend.storage_class("/a").unwrap(), "STANDARD_IA");
+        assert_eq!(fs.getxattr(a, name).unwrap(), b"STANDARD_IA");
+        assert_eq!(
+            fs.listxattr(a).unwrap(),
+            vec![STORAGE_CLASS_XATTR, ETAG_XATTR, MD5_XATTR]
+        );
+
+        // uploads keep the class
+        fs.write(a, 0, b"COLD").unwrap();
+        fs.release(a).unwrap();
+        assert_eq!(backend.storage_class("/a").unwrap(), "STANDARD_IA");
+        fs.removexattr(a, name).unwrap();
+        fs.write(a, 0, b"warm").unwrap();
+        fs.release(a).unwrap();
+        assert!(backend.storage_class("/a").is_none());
+        assert!(fs.setxattr(a, OsStr::new("user.other"), b"").is_err());
+    }
+
+    #[test]
+    fn test_checksums() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/a", b"hello");
+        let config = Config::default();
+        let fs = FileSystem::new(backend.clone(), &config);
+        let a = fs.lookup(ROOT_INODE, OsStr::new("a")).unwrap().ino;
+        let md5 = b"5d41402abc4b2a76b9719d911017c592".to_vec();
+        assert_eq!(fs.getxattr(a, OsStr::new(ETAG_XATTR)).unwrap(), md5);
+        assert_eq!(fs.getxattr(a, OsStr::new(MD5_XATTR)).unwrap(), md5);
+        let heads = backend.calls("get_node");
+
+        // unknown until the written data is uploaded
+        fs.write(a, 0, b"world").unwrap();
+        assert!(fs.getxattr(a, OsStr::new(ETAG_XATTR)).is_err());
+        assert!(fs.listxattr(a).unwrap().is_empty());
+        fs.release(a).unwrap();
+        assert_eq!(
+            fs.getxattr(a, OsStr::new(MD5_XATTR)).unwrap(),
+            b"7d793037a0760186574b0282f2f435e7".to_vec()
+        );
+        assert_eq!(backend.calls("get_node"), heads + 1);
+        let root = fs.getxattr(ROOT_INODE, OsStr::new(ETAG_XATTR));
+        assert_eq!(root.unwrap_err().errno(0), libc::EISDIR);
+    }
+
+    #[test]
+    fn test_object_tags() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/a", b"tagged");
+        let config = Config {
+            object_tags: true,
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend.clone(), &config);
+        let a = fs.lookup(ROOT_INODE, OsStr::new("a")).unwrap().ino;
+        let team = OsStr::new("user.s3.tag.team");
+        assert_eq!(fs.getxattr(a, team).unwrap_err().errno(0), libc::ENODATA);
+        fs.setxattr(a, team, b"ml").unwrap();
+        fs.setxattr(a, OsStr::new("user.s3.tag.expire"), b"30d")
+            .unwrap();
+        fs.setxattr(a, team, b"infra").unwrap();
+        assert_eq!(fs.getxattr(a, team).unwrap(), b"infra");
+        assert!(fs
+            .listxattr(a)
+            .unwrap()
+            .contains(&"user.s3.tag.expire".to_owned()));
+        fs.removexattr(a, team).unwrap();
+        assert_eq!(
+            backend.tags("/a"),
+            vec![("expire".to_owned(), "30d".to_owned())]
+        );
+        let long = vec![b'v'; 257];
+        assert_eq!(
+            fs.setxattr(a, team, &long).unwrap_err().errno(0),
+            libc::EINVAL
+        );
+
+        fs.write(a, 0, b"T").unwrap();
+        assert_eq!(
+            fs.setxattr(a, team, b"ml").unwrap_err().errno(0),
+            libc::EBUSY
+        );
+    }
+
+    #[test]
+    fn test_readdir_order() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/dir/b", b"");
+        backend.add_file("/dir/d", b"");
+        let config = Config::default();
+        let fs = FileSystem::new(backend.clone(), &config);
+        let dir = fs.lookup(ROOT_INODE, OsStr::new("dir")).unwrap().ino;
+        fs.lookup(dir, OsStr::new("d")).unwrap();
+        for name in ["c", "a"].iter() {
+            fs.mknod(dir, OsStr::new(name), FileType::RegularFile, 0o644, 0, 0, 0)
+                .unwrap();
+        }
+        // whole streams: the cookies of a stream never move, entries served while
+        // the directory is being listed keep the order they were fetched in
+        let stream = || {
+            let mut names = vec![];
+            let mut cookie = 0;
+            loop {
+                let page = fs.readdir(dir, 0, cookie).unwrap();
+                cookie = match page.last() {
+                    Some(last) => last.cookie(),
+                    None => return names,
+                };
+                names.extend(
+                    page.iter()
+                        .map(|node| node.name().to_string_lossy().into_owned()),
+                );
+            }
+        };
+        let mut first = stream();
+        first.sort();
+        assert_eq!(first, vec!["a", "b", "c", "d"]);
+        assert_eq!(stream(), vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_list_on_miss() {
+        let backend = Arc::new(MockBackend::new());
+        for name in ["a", "b", "c"].iter() {
+            backend.add_file(format!("/small/{}", name), b"");
+        }
+        let config = Config {
+            list_below: Some(3),
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend.clone(), &config);
+        let small = fs.lookup(ROOT_INODE, OsStr::new("small")).unwrap().ino;
+        // never listed, the name is headed
+        fs.lookup(small, OsStr::new("a")).unwrap();
+        assert_eq!(backend.calls("get_node"), 2);
+        assert_eq!(fs.readdir(small, 0, 0).unwrap().len(), 3);
+        assert_eq!(backend.calls("get_children"), 1);
+
+        fs.nodes_manager.write().unwrap().evict(0, ROOT_INODE);
+        assert!(fs.lookup(small, OsStr::new("missing")).is_err());
+        assert!(fs.lookup(small, OsStr::new("b")).is_ok());
+        assert!(fs.lookup(small, OsStr::new("c")).is_ok());
+        assert_eq!(backend.calls("get_children"), 2);
+        assert_eq!(backend.calls("get_node"), 2);
+
+        backend.add_file("/small/d", b"");
+        fs.nodes_manager.write().unwrap().evict(0, ROOT_INODE);
+        // listed with 4 entries last time
+        fs.readdir(small, 0, 0).unwrap();
+        fs.nodes_manager.write().unwrap().evict(0, ROOT_INODE);
+        assert!(fs.lookup(small, OsStr::new("d")).is_ok());
+        assert_eq!(backend.calls("get_node"), 3);
+    }
+
+    #[test]
+    fn test_evict_held() {
+        let backend = Arc::new(MockBackend::new());
+        for path in ["/kernel/a", "/opened/b", "/cold/c"].iter() {
+            backend.add_file(path, b"");
+        }
+        let config = Config::default();
+        let fs = FileSystem::new(backend.clone(), &config);
+        let mut inodes = vec![];
+        for (dir, name) in [("kernel", "a"), ("opened", "b"), ("cold", "c")].iter() {
+            let dir = fs.lookup(ROOT_INODE, OsStr::new(dir)).unwrap().ino;
+            inodes.push(fs.lookup(dir, OsStr::new(name)).unwrap().ino);
+        }
+        let known = |ino: &u64| {
+            fs.nodes_manager
+                .read()
+                .unwrap()
+                .ino_mapper
+                .contains_key(ino)
+        };
+        fs.looked_up(inodes[0]);
+        fs.looked_up(inodes[0]);
+        let fh = fs.open_handle(inodes[1]);
+
+        fs.nodes_manager.write().unwrap().evict(0, ROOT_INODE);
+        assert!(known(&inodes[0]) && known(&inodes[1]) && !known(&inodes[2]));
+        // still looked up once
+        fs.forget(inodes[0], 1);
+        fs.close_handle(fh);
+        fs.nodes_manager.write().unwrap().evict(0, ROOT_INODE);
+        assert!(known(&inodes[0]) && !known(&inodes[1]));
+        fs.forget(inodes[0], 1);
+        fs.nodes_manager.write().unwrap().evict(0, ROOT_INODE);
+        assert!(!known(&inodes[0]));
+    }
+
+    #[test]
+    fn test_listing_window() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/dir/a", b"");
+        let config = Config {
+            listing_window: Some(Duration::from_millis(200)),
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend.clone(), &config);
+        let dir = fs.lookup(ROOT_INODE, OsStr::new("dir")).unwrap().ino;
+        assert!(fs.lookup(dir, OsStr::new("missing")).is_err());
+        assert_eq!(backend.calls("get_node"), 2);
+
+        fs.readdir(dir, 0, 0).unwrap();
+        backend.add_file("/dir/b", b"");
+        for _ in 0..3 {
+            let missing = fs.lookup(dir, OsStr::new("b")).unwrap_err();
+            assert_eq!(missing.errno(0), libc::ENOENT);
+        }
+        assert_eq!(backend.calls("get_node"), 2);
+
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(fs.lookup(dir, OsStr::new("b")).is_ok());
+        assert_eq!(backend.calls("get_node"), 3);
+    }
+
+    #[test]
+    fn test_lookup_path() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/a/b/c/file", b"hello");
+        let config = Config::default();
+        let fs = FileSystem::new(backend.clone(), &config);
+        assert_eq!(fs.lookup_path("/a/b/c/file").unwrap().size, 5);
+        assert_eq!(backend.calls("get_path"), 1);
+        assert_eq!(backend.calls("get_node"), 0);
+        let c = fs.lookup_path("a/b/c").unwrap();
+        assert_eq!(c.kind, FileType::Directory);
+        assert_eq!(fs.lookup(c.ino, OsStr::new("file")).unwrap().size, 5);
+        assert_eq!(backend.calls("get_path"), 1);
+
+        // a single missing component is looked up as usual
+        let missing = fs.lookup_path("a/b/c/missing").unwrap_err();
+        assert_eq!(missing.errno(0), libc::ENOENT);
+        assert_eq!(backend.calls("get_path"), 1);
+    }
+
+    #[test]
+    fn test_thread_safety() {
+        // fails to compile if a field loses Send or Sync
+        assert_send_sync::<Node>();
+        assert_send_sync::<FileSystem<MockBackend>>();
     }
 }
@@ -0,0 +1,221 @@
+//! Include and exclude filters on the namespace exposed by the mount.
+
+use crate::ossfs_impl::attr::FileType;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Pattern {
+    segments: Vec<String>,
+    // without a `/` the pattern matches names at any depth
+    anywhere: bool,
+}
+
+impl Pattern {
+    pub(crate) fn new(pattern: &str) -> Pattern {
+        Pattern {
+            segments: pattern
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| segment.to_owned())
+                .collect(),
+            anywhere: !pattern.trim_end_matches('/').contains('/'),
+        }
+    }
+
+    /// Whether the entry at `path`, relative to the mount root, matches.
+    pub(crate) fn matches_path(&self, path: &Path) -> bool {
+        self.matches(&components(path))
+    }
+
+    fn matches(&self, components: &[String]) -> bool {
+        if self.anywhere {
+            return match components.last() {
+                Some(name) => self.segments.iter().all(|segment| glob(segment, name)),
+                None => false,
+            };
+        }
+        matches(&self.segments, components, false)
+    }
+
+    // whether entries below the directory at `components` may match
+    fn may_match_below(&self, components: &[String]) -> bool {
+        self.anywhere || matches(&self.segments, components, true)
+    }
+}
+
+/// Globs over paths relative to the mount root: `*` matches within a name, `?` a
+/// character and `**` any number of names. Without a `/` a pattern matches names at
+/// any depth, a leading `/` anchors it at the root.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl Filter {
+    pub fn new() -> Filter {
+        Filter::default()
+    }
+
+    /// Exposes only the entries matching one of the include patterns, and what
+    /// is below them.
+    pub fn include(mut self, pattern: &str) -> Filter {
+        self.include.push(Pattern::new(pattern));
+        self
+    }
+
+    /// Hides the entries matching `pattern`, and what is below them.
+    pub fn exclude(mut self, pattern: &str) -> Filter {
+        self.exclude.push(Pattern::new(pattern));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Whether the entry at `path`, relative to the mount root, is exposed.
+    pub fn exposes(&self, path: &Path, kind: FileType) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let components = components(path);
+        if components.is_empty() {
+            return true;
+        }
+        if self
+            .exclude
+            .iter()
+            .any(|pattern| pattern.matches(&components))
+        {
+            return false;
+        }
+        if self.include.is_empty() {
+            return true;
+        }
+        let included = (1..=components.len()).any(|depth| {
+            self.include
+                .iter()
+                .any(|pattern| pattern.matches(&components[..depth]))
+        });
+        included
+            || (kind == FileType::Directory
+                && self
+                    .include
+                    .iter()
+                    .any(|pattern| pattern.may_match_below(&components)))
+    }
+}
+
+fn components(path: &Path) -> Vec<String> {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .filter(|component| component != "/")
+        .collect()
+}
+
+// matches the names of a path against the segments of a pattern, with `prefix` a
+// path that runs out first still matches
+fn matches(segments: &[String], components: &[String], prefix: bool) -> bool {
+    match segments.split_first() {
+        None => components.is_empty(),
+        Some((segment, rest)) if segment == "**" => {
+            matches(rest, components, prefix)
+                || (!components.is_empty() && matches(segments, &components[1..], prefix))
+        }
+        Some((segment, rest)) => match components.split_first() {
+            None => prefix,
+            Some((name, names)) => glob(segment, name) && matches(rest, names, prefix),
+        },
+    }
+}
+
+// matches a single name against a pattern of `*` and `?`
+fn glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // where the last `*` was seen and the name position it has swallowed up to
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::Filter;
+    use crate::ossfs_impl::attr::FileType;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+    use std::ffi::OsStr;
+    use std::path::Path;
+
+    #[test]
+    fn test_filter() {
+        let file = FileType::RegularFile;
+        let dir = FileType::Directory;
+        let filter = Filter::new().include("images/**").exclude("*.tmp");
+        assert!(filter.exposes(Path::new("images"), dir));
+        assert!(filter.exposes(Path::new("images/2019/a.jpg"), file));
+        assert!(!filter.exposes(Path::new("images/2019/a.jpg.tmp"), file));
+        assert!(!filter.exposes(Path::new("videos"), dir));
+        assert!(!filter.exposes(Path::new("a.jpg"), file));
+
+        let filter = Filter::new().include("data/*/train");
+        assert!(filter.exposes(Path::new("data"), dir));
+        assert!(filter.exposes(Path::new("data/v1"), dir));
+        assert!(!filter.exposes(Path::new("data/v1.csv"), file));
+        assert!(filter.exposes(Path::new("data/v1/train/0001"), file));
+        assert!(!filter.exposes(Path::new("data/v1/test"), dir));
+
+        let filter = Filter::new().exclude("/cache").exclude("?.log");
+        assert!(!filter.exposes(Path::new("cache"), dir));
+        assert!(filter.exposes(Path::new("a/cache"), dir));
+        assert!(!filter.exposes(Path::new("a/b.log"), file));
+        assert!(filter.exposes(Path::new("a/bc.log"), file));
+        assert!(Filter::new().exposes(Path::new("anything"), file));
+
+        let backend = MockBackend::new();
+        backend.add_file("/images/a.jpg", b"a");
+        backend.add_file("/images/a.jpg.tmp", b"a");
+        backend.add_file("/videos/b.mp4", b"b");
+        let config = Config {
+            filter: Filter::new().include("images/**").exclude("*.tmp"),
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend, &config);
+        let names = |ino| -> Vec<String> {
+            fs.readdir(ino, 0, 0)
+                .unwrap()
+                .iter()
+                .map(|node| {
+                    node.path()
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect()
+        };
+        assert_eq!(names(ROOT_INODE), vec!["images"]);
+        let images = fs.lookup(ROOT_INODE, OsStr::new("images")).unwrap().ino;
+        assert_eq!(names(images), vec!["a.jpg"]);
+        assert!(fs.lookup(ROOT_INODE, OsStr::new("videos")).is_err());
+        assert!(fs.lookup(images, OsStr::new("a.jpg.tmp")).is_err());
+    }
+}
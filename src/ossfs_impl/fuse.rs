@@ -1,17 +1,34 @@
 use fuse::*;
 
+use crate::error::Error;
+use crate::ossfs_impl::attr::{self, FileAttr, FileType};
 use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::blocks;
+use crate::ossfs_impl::check;
+use crate::ossfs_impl::config::Config;
+use crate::ossfs_impl::control::{self, Command, CONTROL_XATTR};
+use crate::ossfs_impl::direct_io::DirectIo;
 use crate::ossfs_impl::filesystem::FileSystem;
+use crate::ossfs_impl::filter::Filter;
 use crate::ossfs_impl::node::Node;
-use libc::{c_int, ENOENT, ENOSYS, ENOTDIR};
+use crate::ossfs_impl::pins::RemoteChange;
+use crate::ossfs_impl::platform;
+use crate::ossfs_impl::policy::{Access, Policy};
+use crate::ossfs_impl::prefetch;
+use crate::ossfs_impl::prefetch_policy::PrefetchPolicy;
+use crate::ossfs_impl::shutdown;
+use crate::ossfs_impl::supervisor::{self, Restart};
+use crate::ossfs_impl::tuning::Tuning;
+use crate::ossfs_impl::virtual_file::VirtualFile;
+use libc::{c_int, EACCES, EIO, ENOENT, ENOSYS, ENOTDIR};
 use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{
     atomic::{AtomicU64, Ordering},
     Arc, RwLock,
 };
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Debug)]
 pub struct FileHandle {
@@ -32,6 +49,50 @@ impl HandleGroup {
             total_length: 0,
         }
     }
+
+    // drops the cached content of `ino` once it was written
+    fn forget(&mut self, ino: u64) {
+        if let Some(group) = self.map.remove(&ino) {
+            if let Some(handle) = group.first() {
+                self.total_length = self
+                    .total_length
+                    .saturating_sub(handle.content.len() as u64);
+            }
+        }
+    }
+}
+
+/// How long the kernel may cache what the replies tell it.
+#[derive(Debug, Clone, Copy)]
+struct Ttl {
+    file: Duration,
+    dir: Duration,
+    negative: Duration,
+}
+
+impl Ttl {
+    fn of<B>(&self, fs: &FileSystem<B>, attr: &FileAttr) -> Duration
+    where
+        B: Backend + std::fmt::Debug + Send + Sync + 'static,
+    {
+        let base = if attr.kind == FileType::Directory {
+            self.dir
+        } else {
+            self.file
+        };
+        fs.ttl(attr, base)
+    }
+
+    // a reply caching that a name does not exist, the kernel takes an entry of
+    // inode 0 for a missing name
+    fn negative(&self, reply: ReplyEntry) {
+        let attr = FileAttr {
+            perm: 0,
+            nlink: 0,
+            ..attr::synthetic(FileType::RegularFile, 0, SystemTime::UNIX_EPOCH)
+        };
+        reply.entry(&self.negative, &fuse_attr(&attr), 0);
+    }
 }
 
 #[derive(Debug)]
@@ -40,27 +101,663 @@ where
     B: Backend + std::fmt::Debug + Send + Sync + 'static,
 {
     fs: Arc<FileSystem<B>>,
-    path_cache: HashMap<String, usize>,
     next_handle: AtomicU64,
-    handle_reference: HashMap<u64, u64>,
     pool: threadpool::ThreadPool,
     handle_group: Arc<RwLock<HandleGroup>>,
     counter: crate::counter::Counter,
     enable_cache: bool,
+    ttl: Ttl,
+    tuning: Tuning,
+    // where the mount is, to tune its connection once it is up
+    mountpoint: Option<PathBuf>,
+    // uid the daemon runs as, which may run privileged control commands
+    owner: u32,
+    // where the `dump` control command writes to
+    dump_dir: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct FuseBuilder<B>
+where
+    B: Backend + std::fmt::Debug + Send + Sync + 'static,
+{
+    backend: B,
+    config: Config,
+}
+
+impl<B: Backend + std::fmt::Debug + Send + Sync + 'static> FuseBuilder<B> {
+    pub fn enable_cache(mut self, enable_cache: bool) -> FuseBuilder<B> {
+        self.config.enable_cache = enable_cache;
+        self
+    }
+
+    /// Sets the upper bound of a single backend call, `None` disables the timeout.
+    pub fn backend_timeout(mut self, timeout: Option<Duration>) -> FuseBuilder<B> {
+        self.config.backend_timeout = timeout;
+        self
+    }
+
+    pub fn backend_threads(mut self, threads: usize) -> FuseBuilder<B> {
+        self.config.backend_threads = threads;
+        self
+    }
+
+    /// Fails backend requests fast after `threshold` consecutive failures and probes
+    /// the backend every `cooldown` until it recovers, a `threshold` of 0 disables it.
+    pub fn circuit_breaker(mut self, threshold: u32, cooldown: Duration) -> FuseBuilder<B> {
+        self.config.breaker_threshold = threshold;
+        self.config.breaker_cooldown = cooldown;
+        self
+    }
+
+    /// Probes the backend every `interval` with a lightweight request, see
+    /// `ossfs::health`. The probes close the circuit breaker once the backend is
+    /// back, and the health they find is logged and reported by the `stats` control
+    /// command. `None` does not probe.
+    pub fn health_interval(mut self, interval: Option<Duration>) -> FuseBuilder<B> {
+        self.config.health_interval = interval;
+        self
+    }
+
+    /// Sets the number of entries taken from the tree at a time while filling a
+    /// readdir reply, 0 takes all of them. Replies hold as many entries as fit in
+    /// the buffer of the kernel whatever the limit.
+    pub fn readdir_limit(mut self, limit: usize) -> FuseBuilder<B> {
+        self.config.readdir_limit = limit;
+        self
+    }
+
+    /// Records the inodes handed out in `path`, so handles of an nfs re-export of the
+    /// mount keep resolving after a restart.
+    pub fn inode_table<P: Into<std::path::PathBuf>>(mut self, path: P) -> FuseBuilder<B> {
+        self.config.inode_table = Some(path.into());
+        self
+    }
+
+    /// Builds the tree from the manifest at `path`, written by `manifest::write` or an
+    /// S3 Inventory CSV file, instead of listing the bucket. Directories of the
+    /// manifest are not listed again unless a change is noticed, so it is meant for
+    /// datasets which do not change.
+    pub fn manifest<P: Into<std::path::PathBuf>>(mut self, path: P) -> FuseBuilder<B> {
+        self.config.manifest = Some(path.into());
+        self
+    }
+
+    /// Caps the size of the read requests sent by the kernel, passed as the
+    /// `max_read` mount option.
+    pub fn max_read(mut self, bytes: u32) -> FuseBuilder<B> {
+        self.config.max_read = Some(bytes);
+        self
+    }
+
+    /// Sets how far the kernel reads ahead of sequential readers, 128 KiB by default.
+    /// Every readahead is a backend request, a few MiB let a reader stream a file
+    /// instead of waiting on a request every 128 KiB. Applied through sysfs once
+    /// mounted, which needs root on linux.
+    pub fn max_readahead(mut self, bytes: u32) -> FuseBuilder<B> {
+        self.config.max_readahead = Some(bytes);
+        self
+    }
+
+    /// Sets how many requests the kernel sends without waiting for the answers,
+    /// readahead included, 12 by default. Above `congestion_threshold` of them in
+    /// flight, three quarters of `requests` by default, the kernel holds back
+    /// readahead. Raising both lets more reads reach the backend at once. Applied
+    /// through sysfs once mounted, which needs root on linux.
+    pub fn max_background(
+        mut self,
+        requests: u16,
+        congestion_threshold: Option<u16>,
+    ) -> FuseBuilder<B> {
+        self.config.max_background = Some(requests);
+        self.config.congestion_threshold = congestion_threshold;
+        self
+    }
+
+    /// Bounds the memory taken by the cached metadata, in estimated bytes. Above
+    /// `soft` the children of the least recently used directories are evicted in the
+    /// background until the metadata fits in `soft` again, above `hard` before any
+    /// entry is added. Evicted entries are fetched again when needed.
+    pub fn metadata_limits(mut self, soft: Option<u64>, hard: Option<u64>) -> FuseBuilder<B> {
+        self.config.metadata_soft_limit = soft;
+        self.config.metadata_hard_limit = hard;
+        self
+    }
+
+    /// Bounds the written data kept in memory until it is uploaded: above
+    /// `high_water` dirty bytes writers block while the dirty files are uploaded,
+    /// `uploads` at a time. `None` never blocks writers.
+    pub fn write_back(mut self, high_water: Option<u64>, uploads: usize) -> FuseBuilder<B> {
+        self.config.dirty_high_water = high_water;
+        self.config.upload_concurrency = uploads;
+        self
+    }
+
+    /// Sends uploads failing with a retryable error up to `retries` more times.
+    /// Retries are idempotent: one after a timeout does not put the file again if
+    /// the timed out attempt reached the backend.
+    pub fn upload_retries(mut self, retries: usize) -> FuseBuilder<B> {
+        self.config.upload_retries = retries;
+        self
+    }
+
+    /// Mirrors the written files in the directory `dir` until they are uploaded, so
+    /// a crash does not lose them: the next mount with the same directory uploads
+    /// what it finds there before serving requests, and moves what it fails to
+    /// upload to `unflushed/` in it.
+    pub fn write_journal<P: Into<std::path::PathBuf>>(mut self, dir: P) -> FuseBuilder<B> {
+        self.config.write_journal = Some(dir.into());
+        self
+    }
+
+    /// Flushes the mount on SIGTERM or SIGINT before unmounting it: the written files
+    /// are uploaded, the smallest first, then the inode table is synced, giving up
+    /// on what is not done within `deadline`, which is logged. Keep it below the
+    /// grace period of the pod. `None` leaves the signals alone.
+    pub fn shutdown_deadline(mut self, deadline: Option<Duration>) -> FuseBuilder<B> {
+        self.config.shutdown_deadline = deadline;
+        self
+    }
+
+    /// Records every open, read and write of the mount in a binary trace at `path`,
+    /// see `ossfs::trace`. Records are dropped when the disk falls behind.
+    pub fn access_trace<P: Into<std::path::PathBuf>>(mut self, path: P) -> FuseBuilder<B> {
+        self.config.access_trace = Some(path.into());
+        self
+    }
+
+    /// Names the mount with the label `name` in the summaries of `stats_interval`,
+    /// the access trace and the logged errors, e.g. the job or the version of the
+    /// dataset. Labels set by `OSSFS_LABEL_<NAME>` variables are added, see
+    /// `ossfs::Labels`.
+    pub fn label<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> FuseBuilder<B> {
+        self.config.labels.set(name, value);
+        self
+    }
+
+    /// Serves entries created or written through the mount from local state for
+    /// `window` after their last change, even if listings of the backend do not
+    /// show the change yet.
+    pub fn consistency_window(mut self, window: Duration) -> FuseBuilder<B> {
+        self.config.consistency_window = window;
+        self
+    }
+
+    /// Moves deleted files to `.Trash/<time of deletion>/` of the mount, where they
+    /// are kept for `retention` before being purged. `None` deletes right away.
+    pub fn trash(mut self, retention: Option<Duration>) -> FuseBuilder<B> {
+        self.config.trash_retention = retention;
+        self
+    }
+
+    /// Lets rmdir remove directories with entries, e.g. `rm -r` of a large prefix,
+    /// by listing and deleting everything below them in batches. Ignored while
+    /// deleted files go to the trash.
+    pub fn recursive_rmdir(mut self, recursive: bool) -> FuseBuilder<B> {
+        self.config.recursive_rmdir = recursive;
+        self
+    }
+
+    /// Exposes the tags of objects as `user.s3.tag.<key>` extended attributes, to
+    /// inspect and set lifecycle or cost allocation tags with getfattr and setfattr.
+    /// Listing the extended attributes of a file then costs a request.
+    pub fn object_tags(mut self, enabled: bool) -> FuseBuilder<B> {
+        self.config.object_tags = enabled;
+        self
+    }
+
+    /// Serves the recursive usage of a directory, its `user.ossfs.du_bytes` and
+    /// `user.ossfs.du_entries` extended attributes, for `ttl` before computing it
+    /// again. 5 minutes by default.
+    pub fn usage_ttl(mut self, ttl: Duration) -> FuseBuilder<B> {
+        self.config.usage_ttl = ttl;
+        self
+    }
+
+    /// Reports the recursive bytes of directories as their size in getattr. They
+    /// are computed in the background, until then directories keep their size.
+    pub fn dir_sizes(mut self, enabled: bool) -> FuseBuilder<B> {
+        self.config.dir_sizes = enabled;
+        self
+    }
+
+    /// Adapts the reads of each open handle to how it reads: small files are fetched
+    /// whole, sequential readers get a growing readahead window and random readers
+    /// the ranges they ask for. Ignored when the content of files is cached.
+    pub fn adaptive_reads(mut self, enabled: bool) -> FuseBuilder<B> {
+        self.config.adaptive_reads = enabled;
+        self
+    }
+
+    /// Fetches files in blocks of `block_size` bytes, a power of two between 1 and
+    /// 16 MiB, keeping up to `capacity` bytes of them in memory. Small blocks suit
+    /// datasets of small files, large ones files read from start to end. Ignored
+    /// when the content of files is cached.
+    pub fn block_size(mut self, block_size: u64, capacity: u64) -> FuseBuilder<B> {
+        self.config.block_size = Some(block_size);
+        self.config.block_cache = capacity;
+        self
+    }
+
+    /// Decides what the handles opened before change detection notices a remote
+    /// update of their file read: the new version, dropping what was fetched of the
+    /// old one, or the version they opened. Either way a handle never reads a mix of
+    /// both.
+    pub fn remote_change(mut self, mode: RemoteChange) -> FuseBuilder<B> {
+        self.config.remote_change = mode;
+        self
+    }
+
+    /// Fetches the blocks `policy` expects to be read next in the background, e.g.
+    /// `ColumnarPolicy` for parquet, orc and tfrecord datasets. Needs `block_size`.
+    pub fn prefetch_policy(mut self, policy: Arc<dyn PrefetchPolicy>) -> FuseBuilder<B> {
+        self.config.prefetch_policy = Some(policy);
+        self
+    }
+
+    /// Opens the files matching the rules of `direct_io` with direct_io, e.g.
+    /// `DirectIo::new().rule("*.ckpt", 1 << 30)`. Their reads skip the page cache,
+    /// the block cache and the content cache and go to the backend, so reading a
+    /// large file once does not evict the working set of small files.
+    pub fn direct_io(mut self, direct_io: DirectIo) -> FuseBuilder<B> {
+        self.config.direct_io = direct_io;
+        self
+    }
+
+    /// Reads opened files straight from the local files of the backend, for
+    /// backends keeping them in a local directory like `SimpleBackend`, see
+    /// `Backend::local_file`. Other backends read as usual.
+    pub fn passthrough(mut self, enabled: bool) -> FuseBuilder<B> {
+        self.config.passthrough = enabled;
+        self
+    }
+
+    /// Lets root and the owner of the mount dump inode trees with the `dump`
+    /// control command, each into a new file of `dir`. The command is refused
+    /// without a directory.
+    pub fn dump_dir(mut self, dir: PathBuf) -> FuseBuilder<B> {
+        self.config.dump_dir = Some(dir);
+        self
+    }
+
+    /// Exposes only the entries of the bucket passing `filter`, e.g. to hide
+    /// temporary files or give a job only the prefixes it reads.
+    pub fn filter(mut self, filter: Filter) -> FuseBuilder<B> {
+        self.config.filter = filter;
+        self
+    }
+
+    /// Serves the read-only file at `path`, relative to the mount root, with the
+    /// content `generate` returns whenever it is read.
+    pub fn virtual_file<P, F>(mut self, path: P, generate: F) -> FuseBuilder<B>
+    where
+        P: Into<PathBuf>,
+        F: Fn() -> Vec<u8> + Send + Sync + 'static,
+    {
+        self.config
+            .virtual_files
+            .push((path.into(), VirtualFile::new(generate)));
+        self
+    }
+
+    /// Refuses writes growing a file beyond `max_file_size` bytes with EFBIG and
+    /// creates in directories of `max_dir_entries` entries with ENOSPC, so a
+    /// misconfigured job fails early instead of filling the bucket.
+    pub fn guardrails(
+        mut self,
+        max_file_size: Option<u64>,
+        max_dir_entries: Option<usize>,
+    ) -> FuseBuilder<B> {
+        self.config.max_file_size = max_file_size;
+        self.config.max_dir_entries = max_dir_entries;
+        self
+    }
+
+    /// Sets the size statfs reports for buckets which have none, 1 PiB by default.
+    /// Their usage is the recursive usage of the mount root, computed in the
+    /// background and refreshed every usage ttl, so `df` shows what is stored.
+    pub fn capacity(mut self, bytes: u64) -> FuseBuilder<B> {
+        self.config.capacity = bytes;
+        self
+    }
+
+    /// Limits the bytes and the files the mount may add to the bucket, beyond which
+    /// writes and creates fail with EDQUOT. statfs reports the quota, so `df` shows
+    /// how much of it is left.
+    pub fn quota(mut self, bytes: Option<u64>, files: Option<u64>) -> FuseBuilder<B> {
+        self.config.quota_bytes = bytes;
+        self.config.quota_files = files;
+        self
+    }
+
+    /// Lets the kernel cache the entries and attributes of files for `file`, of
+    /// directories for `dir` and missing names for `negative`, 1 second, 1 second
+    /// and not at all by default. Immutable datasets can be cached for hours, zero
+    /// asks the backend every time.
+    pub fn ttl(mut self, file: Duration, dir: Duration, negative: Duration) -> FuseBuilder<B> {
+        self.config.file_ttl = file;
+        self.config.dir_ttl = dir;
+        self.config.negative_ttl = negative;
+        self
+    }
+
+    /// Adapts the ttls of each inode to how often it changes, between `min` and
+    /// `max`: an inode found unchanged once its ttl expired is cached twice as long
+    /// the next time, one found changed a quarter as long. The ttls set by `ttl` are
+    /// those of the first replies. `None` keeps them fixed.
+    pub fn adaptive_ttl(mut self, bounds: Option<(Duration, Duration)>) -> FuseBuilder<B> {
+        self.config.adaptive_ttl = bounds;
+        self
+    }
+
+    /// Refreshes the attributes of the `hottest` most accessed inodes in the
+    /// background shortly before the ttl of files expires, so they stay fresh
+    /// without getattr waiting for the backend. `None` disables it.
+    pub fn refresh_attributes(mut self, hottest: Option<usize>) -> FuseBuilder<B> {
+        self.config.refresh_hottest = hottest;
+        self
+    }
+
+    /// Answers lookups of names missing from a listing of their directory younger
+    /// than `window` with ENOENT instead of asking the backend, at the price of not
+    /// seeing entries created elsewhere meanwhile. `None` always asks the backend.
+    pub fn listing_window(mut self, window: Option<Duration>) -> FuseBuilder<B> {
+        self.config.listing_window = window;
+        self
+    }
+
+    /// Looks up names missing from the tree in directories last listed with at most
+    /// `max` entries by listing them again, 1000 by default. Larger directories,
+    /// and ones never listed, head the single name instead. `None` always heads.
+    pub fn list_below(mut self, max: Option<usize>) -> FuseBuilder<B> {
+        self.config.list_below = max;
+        self
+    }
+
+    /// Restricts the prefixes each uid may read or write, see `Policy`. Everyone may
+    /// access everything by default.
+    pub fn policy(mut self, policy: Policy) -> FuseBuilder<B> {
+        self.config.policy = policy;
+        self
+    }
+
+    /// Holds the requests sent to the backend to `rate` per second. Requests of the
+    /// kernel wait for their turn, refreshes of metadata are skipped first.
+    pub fn request_rate(mut self, rate: Option<u32>) -> FuseBuilder<B> {
+        self.config.request_rate = rate;
+        self
+    }
+
+    /// Logs a summary of the operations every `interval` at info level: calls per
+    /// second, bytes read, errors, duration, concurrency and time spent queued of
+    /// each kind of kernel request and backend call. `None` disables it.
+    pub fn stats_interval(mut self, interval: Option<Duration>) -> FuseBuilder<B> {
+        self.config.stats_interval = interval;
+        self
+    }
+
+    /// Sets how often watched directories are listed again, `None`, the default,
+    /// disables `FileSystem::watch`.
+    pub fn watch_interval(mut self, interval: Option<Duration>) -> FuseBuilder<B> {
+        self.config.watch_interval = interval;
+        self
+    }
+
+    pub fn build(self) -> Fuse<B> {
+        Fuse::with_config(self.backend, self.config)
+    }
+
+    /// Validates the mountpoint, the fuse device and the backend before mounting, so a
+    /// misconfiguration fails with an actionable error instead of a panic.
+    pub fn mount<P: AsRef<Path>>(
+        self,
+        mountpoint: P,
+        options: &[&OsStr],
+    ) -> crate::error::Result<()> {
+        self.mount_with_binds(mountpoint, &[], options)
+    }
+
+    /// Mounts like `mount`, and binds the same filesystem read-only at each of
+    /// `binds`, e.g. a path per container. The mounts share the inodes, the metadata
+    /// and the content caches, the backend is only asked once for all of them. The
+    /// binds are unmounted once the mount at `mountpoint` ends.
+    pub fn mount_with_binds<P: AsRef<Path>>(
+        self,
+        mountpoint: P,
+        binds: &[PathBuf],
+        options: &[&OsStr],
+    ) -> crate::error::Result<()> {
+        self.check(&mountpoint, options)?;
+        let nonempty = check::allows_nonempty(options);
+        for bind in binds {
+            check::check_mountpoint(bind, nonempty)?;
+        }
+        let options = self.options(options);
+        let mut bind_options = options.clone();
+        bind_options.push("-o".into());
+        bind_options.push("ro".into());
+        let options: Vec<&OsStr> = options.iter().map(|option| option.as_os_str()).collect();
+        let deadline = self.config.shutdown_deadline;
+        let signals = match deadline {
+            Some(_) => Some(shutdown::block_signals()?),
+            None => None,
+        };
+        let mut fuse = self.build();
+        fuse.mountpoint = Some(mountpoint.as_ref().to_owned());
+        if let (Some(signals), Some(deadline)) = (signals, deadline) {
+            let mountpoint = mountpoint.as_ref().to_owned();
+            shutdown::spawn_handler(fuse.fs.clone(), mountpoint, signals, deadline);
+        }
+        let mut bound = vec![];
+        for bind in binds {
+            let mut bind_fuse = fuse.bind();
+            bind_fuse.mountpoint = Some(bind.clone());
+            let bind = bind.clone();
+            let options = bind_options.clone();
+            let spawned = std::thread::Builder::new()
+                .name("ossfs-bind".to_owned())
+                .spawn(move || {
+                    let options: Vec<&OsStr> =
+                        options.iter().map(|option| option.as_os_str()).collect();
+                    if let Err(e) = fuse::mount(bind_fuse, &bind, &options) {
+                        log::error!("failed to bind {:?}. error: {}", bind, e);
+                    }
+                })?;
+            bound.push(spawned);
+        }
+        let result = fuse::mount(fuse, &mountpoint, &options);
+        for bind in binds {
+            if let Err(e) = platform::lazy_unmount(bind) {
+                log::error!("failed to unmount the bind {:?}. {}", bind, e);
+            }
+        }
+        for thread in bound {
+            let _ = thread.join();
+        }
+        result?;
+        Ok(())
+    }
+
+    /// Mounts like `mount`, then mounts the same filesystem again following `restart`
+    /// whenever the fuse session dies, until the user unmounts it. The caches of the
+    /// filesystem survive the remounts.
+    pub fn supervise<P: AsRef<Path>>(
+        self,
+        mountpoint: P,
+        options: &[&OsStr],
+        restart: &Restart,
+    ) -> crate::error::Result<()> {
+        self.check(&mountpoint, options)?;
+        let options = self.options(options);
+        let options: Vec<&OsStr> = options.iter().map(|option| option.as_os_str()).collect();
+        let config = self.config.clone();
+        let signals = match config.shutdown_deadline {
+            Some(_) => Some(shutdown::block_signals()?),
+            None => None,
+        };
+        let fs = Arc::new(FileSystem::new(self.backend, &config));
+        if let (Some(signals), Some(deadline)) = (signals, config.shutdown_deadline) {
+            let mountpoint = mountpoint.as_ref().to_owned();
+            shutdown::spawn_handler(fs.clone(), mountpoint, signals, deadline);
+        }
+        supervisor::supervise(&mountpoint, restart, || {
+            check::check_backend(fs.backend())?;
+            let mut fuse = Fuse::with_filesystem(fs.clone(), &config);
+            fuse.mountpoint = Some(mountpoint.as_ref().to_owned());
+            fuse::mount(fuse, &mountpoint, &options)?;
+            Ok(())
+        })
+    }
+
+    fn check<P: AsRef<Path>>(&self, mountpoint: P, options: &[&OsStr]) -> crate::error::Result<()> {
+        check::check(&self.backend, &mountpoint, check::allows_nonempty(options))?;
+        if let Some(ref path) = self.config.inode_table {
+            check::check_inode_table(path)?;
+        }
+        if let Some(ref path) = self.config.manifest {
+            check::check_manifest(path)?;
+        }
+        if let Some(ref path) = self.config.write_journal {
+            check::check_write_journal(path)?;
+        }
+        if let Some(block_size) = self.config.block_size {
+            blocks::check_block_size(block_size)?;
+        }
+        Tuning::from_config(&self.config).check()?;
+        if self.config.prefetch_policy.is_some() && self.config.block_size.is_none() {
+            return Err(Error::Other(
+                "a prefetch policy needs reads in blocks, set a block size".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    // `options` with the ones the config adds
+    fn options(&self, options: &[&OsStr]) -> Vec<std::ffi::OsString> {
+        let mut options: Vec<std::ffi::OsString> =
+            options.iter().map(|option| option.to_os_string()).collect();
+        if let Some(max_read) = self.config.max_read {
+            options.push("-o".into());
+            options.push(format!("max_read={}", max_read).into());
+        }
+        options
+    }
 }
 
 impl<B: Backend + std::fmt::Debug + Send + Sync + 'static> Fuse<B> {
     pub fn new(backend: B, enable_cache: bool) -> Fuse<B> {
+        Fuse::builder(backend).enable_cache(enable_cache).build()
+    }
+
+    pub fn builder(backend: B) -> FuseBuilder<B> {
+        FuseBuilder {
+            backend,
+            config: Config::default(),
+        }
+    }
+
+    pub fn with_config(backend: B, config: Config) -> Fuse<B> {
+        let fs = Arc::new(FileSystem::new(backend, &config));
+        Fuse::with_filesystem(fs, &config)
+    }
+
+    /// A mount of `fs`, which may have been mounted before.
+    pub fn with_filesystem(fs: Arc<FileSystem<B>>, config: &Config) -> Fuse<B> {
         Fuse {
-            fs: Arc::new(FileSystem::new(backend)),
-            // inode_cache: HashMap::new(),
-            path_cache: HashMap::new(),
+            fs,
             next_handle: AtomicU64::new(2),
-            handle_reference: HashMap::new(),
             pool: threadpool::ThreadPool::new(32),
             handle_group: Arc::new(RwLock::new(HandleGroup::new())),
             counter: crate::counter::Counter::new(1),
-            enable_cache,
+            enable_cache: config.enable_cache,
+            ttl: Ttl {
+                file: config.file_ttl,
+                dir: config.dir_ttl,
+                negative: config.negative_ttl,
+            },
+            tuning: Tuning::from_config(config),
+            mountpoint: None,
+            owner: unsafe { libc::getuid() },
+            dump_dir: config.dump_dir.clone(),
+        }
+    }
+
+    /// Another mount of the same filesystem sharing the content cache of this one.
+    fn bind(&self) -> Fuse<B> {
+        Fuse {
+            fs: self.fs.clone(),
+            next_handle: AtomicU64::new(2),
+            pool: threadpool::ThreadPool::new(32),
+            handle_group: self.handle_group.clone(),
+            counter: crate::counter::Counter::new(1),
+            enable_cache: self.enable_cache,
+            ttl: self.ttl,
+            tuning: self.tuning,
+            mountpoint: None,
+            owner: self.owner,
+            dump_dir: self.dump_dir.clone(),
+        }
+    }
+
+    // FOPEN flags of the replies opening the handle `fh`
+    fn open_flags(&self, fh: u64) -> u32 {
+        if self.fs.is_direct(fh) {
+            consts::FOPEN_DIRECT_IO
+        } else {
+            0
+        }
+    }
+
+    /// The filesystem behind the mount, e.g. to `watch` it after the mount took
+    /// ownership of `self`.
+    pub fn filesystem(&self) -> Arc<FileSystem<B>> {
+        self.fs.clone()
+    }
+
+    /// Prefetches the tree below `ino` in the background, into the content cache
+    /// if it is enabled.
+    fn warm(&self, ino: u64, concurrency: usize, reply: ReplyEmpty) {
+        let fs = self.fs.clone();
+        let handle_group = self.handle_group.clone();
+        let enable_cache = self.enable_cache;
+        let spawned = std::thread::Builder::new()
+            .name("ossfs-warm".to_owned())
+            .spawn(move || {
+                let started = Instant::now();
+                // the kernel opens every file with handle 0, reads of the other
+                // handles share the content of the first one
+                let cache = |ino: u64, content: Vec<u8>| {
+                    let mut handle_group = handle_group.write().unwrap();
+                    if handle_group.map.contains_key(&ino) {
+                        return;
+                    }
+                    handle_group.total_length += content.len() as u64;
+                    handle_group.map.insert(
+                        ino,
+                        vec![FileHandle {
+                            handle: 0,
+                            content: Arc::new(content),
+                        }],
+                    );
+                };
+                let fetched: Option<&(dyn Fn(u64, Vec<u8>) + Sync)> =
+                    if enable_cache { Some(&cache) } else { None };
+                match prefetch::warm(&fs, ino, concurrency, fetched) {
+                    Ok(warmed) => log::info!(
+                        "warmed inode {} in {:?}: {:?}",
+                        ino,
+                        started.elapsed(),
+                        warmed
+                    ),
+                    Err(e) => log::error!("warming inode {} failed. error: {}", ino, e),
+                }
+            });
+        match spawned {
+            Ok(_) => reply.ok(),
+            Err(e) => {
+                log::error!("failed to spawn the warm thread. error: {}", e);
+                reply.error(EIO);
+            }
         }
     }
 }
@@ -68,25 +765,53 @@ impl<B: Backend + std::fmt::Debug + Send + Sync + 'static> Fuse<B> {
 impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
     /// Initialize filesystem.
     /// Called before any other filesystem method.
-    fn init(&mut self, _req: &Request) -> Result<(), c_int> {
-        // log::debug!("line: {}, func: {}", std::line!(), );
+    ///
+    /// The fuse crate answers the kernel itself: it always enables asynchronous
+    /// reads and big writes with a max_write of 16 MiB, takes the readahead the
+    /// kernel proposes and has no way to enable the writeback cache. The size of
+    /// reads can be tuned through `FuseBuilder::max_read`, the readahead and the
+    /// background requests through sysfs once the mount is up.
+    fn init(&mut self, req: &Request) -> Result<(), c_int> {
+        log::info!(
+            "initialized by pid: {}, uid: {}, cache: {}",
+            req.pid(),
+            req.uid(),
+            self.enable_cache
+        );
+        if let (false, Some(mountpoint)) = (self.tuning.is_empty(), self.mountpoint.clone()) {
+            // the mountpoint answers once init returned
+            let tuning = self.tuning;
+            std::thread::spawn(move || match tuning.apply(&mountpoint) {
+                Ok(()) => log::info!("tuned the connection of {:?}. {:?}", mountpoint, tuning),
+                Err(e) => log::warn!("failed to tune the connection of {:?}. {}", mountpoint, e),
+            });
+        }
         Ok(())
     }
 
     /// Clean up filesystem.
     /// Called on filesystem exit.
     fn destroy(&mut self, _req: &Request) {
-        // log::debug!("line: {}, func: {}", std::line!(), );
+        // log::trace!("line: {}, func: {}", std::line!(), );
     }
 
     /// Look up a directory entry by name and get its attributes.
 
-    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+    fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        if let Err(e) = self
+            .fs
+            .permitted(req.uid(), parent, Some(name), Access::Read)
+        {
+            return reply.error(e.errno(EACCES));
+        }
         let fs = self.fs.clone();
         let name = Arc::new(name.to_owned());
         let name = name.clone();
         let counter = self.counter.clone();
+        let ttl = self.ttl;
+        let queued = Instant::now();
         self.pool.execute(move || {
+            fs.queued("lookup", queued);
             let _start = counter.start("lookup".to_owned());
             match fs.lookup(parent, &name) {
                 Ok(attr) => {
@@ -98,7 +823,15 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
                         name.to_string_lossy(),
                         attr
                     );
-                    reply.entry(&std::time::Duration::from_secs(1), &attr, 0);
+                    fs.looked_up(attr.ino);
+                    reply.entry(
+                        &ttl.of(&fs, &attr),
+                        &fuse_attr(&attr),
+                        fs.generation(attr.ino),
+                    );
+                }
+                Err(ref e) if e.errno(EIO) == ENOENT && ttl.negative > Duration::from_secs(0) => {
+                    ttl.negative(reply)
                 }
                 Err(e) => {
                     log::error!(
@@ -109,7 +842,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
                         name.to_string_lossy(),
                         e
                     );
-                    reply.error(ENOENT);
+                    reply.error(e.errno(ENOENT));
                 }
             }
         });
@@ -123,39 +856,49 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
     /// have a limited lifetime. On unmount it is not guaranteed, that all referenced
     /// inodes will receive a forget message.
 
-    fn forget(&mut self, _req: &Request, _ino: u64, _nlookup: u64) {
+    fn forget(&mut self, _req: &Request, ino: u64, nlookup: u64) {
         log::trace!(
             "{}:{} ino: {}, nlookup: {}",
             std::file!(),
             std::line!(),
-            _ino,
-            _nlookup
+            ino,
+            nlookup
         );
+        self.fs.forget(ino, nlookup);
     }
 
     /// Get file attributes.
 
-    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+    fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
+        if let Err(e) = self.fs.permitted(req.uid(), ino, None, Access::Read) {
+            return reply.error(e.errno(EACCES));
+        }
         let fs = self.fs.clone();
-        self.pool.execute(move || match fs.getattr(ino) {
-            Some(attr) => {
-                log::debug!(
-                    "{}:{} ino: {}, attr: {:?}",
-                    std::file!(),
-                    std::line!(),
-                    ino,
-                    attr
-                );
-                reply.attr(&std::time::Duration::from_secs(1), &attr);
-            }
-            None => {
-                log::error!(
-                    "{}:{} ino: {}, attr not found",
-                    std::file!(),
-                    std::line!(),
-                    ino,
-                );
-                reply.error(ENOSYS);
+        let ttl = self.ttl;
+        let queued = Instant::now();
+        self.pool.execute(move || {
+            fs.queued("getattr", queued);
+            match fs.getattr(ino) {
+                Ok(attr) => {
+                    log::trace!(
+                        "{}:{} ino: {}, attr: {:?}",
+                        std::file!(),
+                        std::line!(),
+                        ino,
+                        attr
+                    );
+                    reply.attr(&ttl.of(&fs, &attr), &fuse_attr(&attr));
+                }
+                Err(e) => {
+                    log::error!(
+                        "{}:{} ino: {}, error: {}",
+                        std::file!(),
+                        std::line!(),
+                        ino,
+                        e
+                    );
+                    reply.error(e.errno(ENOENT));
+                }
             }
         });
     }
@@ -164,7 +907,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
 
     fn setattr(
         &mut self,
-        _req: &Request<'_>,
+        req: &Request<'_>,
         _ino: u64,
         _mode: Option<u32>,
         _uid: Option<u32>,
@@ -179,7 +922,10 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
         _flags: Option<u32>,
         reply: ReplyAttr,
     ) {
-        log::debug!(
+        if let Err(e) = self.fs.permitted(req.uid(), _ino, None, Access::Write) {
+            return reply.error(e.errno(EACCES));
+        }
+        log::trace!(
             "{}:{} inode: {:?}, mode: {:?}, uid: {:?}, gid: {:?}, size: {:?}, atime: {:?}, mtime: {:?}, fh: {:?}, crtime: {:?}, bkuptime: {:?}, flag: {:?}",
             std::file!(),
             std::line!(),
@@ -195,14 +941,37 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
             _chgtime,
             _bkuptime
         );
-
-        reply.error(ENOSYS);
+        // only truncation is supported, it goes through the written data
+        let size = match _size {
+            Some(size) => size,
+            None => return reply.error(ENOSYS),
+        };
+        let fs = self.fs.clone();
+        let handle_group = self.handle_group.clone();
+        let ttl = self.ttl;
+        self.pool.execute(move || match fs.truncate(_ino, size) {
+            Ok(attr) => {
+                handle_group.write().unwrap().forget(_ino);
+                reply.attr(&ttl.of(&fs, &attr), &fuse_attr(&attr))
+            }
+            Err(e) => {
+                log::error!(
+                    "{}:{} ino: {}, size: {}, error: {}",
+                    std::file!(),
+                    std::line!(),
+                    _ino,
+                    size,
+                    e
+                );
+                reply.error(e.errno(EIO));
+            }
+        });
     }
 
     /// Read symbolic link.
 
     fn readlink(&mut self, _req: &Request, _ino: u64, reply: ReplyData) {
-        log::debug!("{}:{}, ino: {}", std::file!(), std::line!(), _ino);
+        log::trace!("{}:{}, ino: {}", std::file!(), std::line!(), _ino);
         reply.error(ENOSYS);
     }
 
@@ -218,6 +987,12 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
         rdev: u32,
         reply: ReplyEntry,
     ) {
+        if let Err(e) = self
+            .fs
+            .permitted(req.uid(), parent, Some(name), Access::Write)
+        {
+            return reply.error(e.errno(EACCES));
+        }
         log::warn!(
             "{}:{}, parent: {}, name: {}, mode: [{:o}:{:o}], rdev: {}",
             std::file!(),
@@ -238,18 +1013,25 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
             req.uid(),
             req.gid(),
         ) {
-            Some(node) => {
-                reply.entry(&std::time::Duration::from_secs(1), &node.attr(), 0);
+            Ok(node) => {
+                let attr = node.attr();
+                self.fs.looked_up(attr.ino);
+                reply.entry(
+                    &self.ttl.of(&self.fs, &attr),
+                    &fuse_attr(&attr),
+                    self.fs.generation(node.inode()),
+                );
             }
-            None => {
+            Err(e) => {
                 log::error!(
-                    "line: {}, parent: {}, name: {:?}, mode: {}",
+                    "line: {}, parent: {}, name: {:?}, mode: {}, error: {}",
                     std::line!(),
                     parent,
                     name,
-                    mode
+                    mode,
+                    e
                 );
-                reply.error(ENOSYS);
+                reply.error(e.errno(EIO));
             }
         }
     }
@@ -257,7 +1039,13 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
     /// Create a directory.
 
     fn mkdir(&mut self, req: &Request, parent: u64, name: &OsStr, mode: u32, reply: ReplyEntry) {
-        log::debug!(
+        if let Err(e) = self
+            .fs
+            .permitted(req.uid(), parent, Some(name), Access::Write)
+        {
+            return reply.error(e.errno(EACCES));
+        }
+        log::trace!(
             "{}:{}, parent: {}, name: {:?}, mode: [{:o}:{:o}]",
             std::file!(),
             std::line!(),
@@ -276,48 +1064,95 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
             req.uid(),
             req.gid(),
         ) {
-            Some(node) => {
-                reply.entry(&std::time::Duration::from_secs(1), &node.attr(), 0);
+            Ok(node) => {
+                let attr = node.attr();
+                self.fs.looked_up(attr.ino);
+                reply.entry(
+                    &self.ttl.of(&self.fs, &attr),
+                    &fuse_attr(&attr),
+                    self.fs.generation(node.inode()),
+                );
             }
-            None => {
+            Err(e) => {
                 log::error!(
-                    "line: {}, parent: {}, name: {:?}, mode: {}",
+                    "line: {}, parent: {}, name: {:?}, mode: {}, error: {}",
                     std::line!(),
                     parent,
                     name,
-                    mode
+                    mode,
+                    e
                 );
-                reply.error(ENOSYS);
+                reply.error(e.errno(EIO));
             }
         }
     }
 
     /// Remove a file.
 
-    fn unlink(&mut self, _req: &Request, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
-        log::debug!(
+    fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        if let Err(e) = self
+            .fs
+            .permitted(req.uid(), parent, Some(name), Access::Write)
+        {
+            return reply.error(e.errno(EACCES));
+        }
+        log::trace!(
             "{}:{} parent: {}, name: {:?}",
             std::file!(),
             std::line!(),
-            _parent,
-            _name
+            parent,
+            name
         );
-
-        reply.error(ENOSYS);
+        let fs = self.fs.clone();
+        let name = name.to_owned();
+        self.pool.execute(move || match fs.unlink(parent, &name) {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                log::error!(
+                    "{}:{} unlink parent: {}, name: {:?}, error: {}",
+                    std::file!(),
+                    std::line!(),
+                    parent,
+                    name,
+                    e
+                );
+                reply.error(e.errno(EIO));
+            }
+        });
     }
 
     /// Remove a directory.
 
-    fn rmdir(&mut self, _req: &Request, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
-        log::debug!(
+    fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        if let Err(e) = self
+            .fs
+            .permitted(req.uid(), parent, Some(name), Access::Write)
+        {
+            return reply.error(e.errno(EACCES));
+        }
+        log::trace!(
             "{}:{} parent: {}, name: {:?}",
             std::file!(),
             std::line!(),
-            _parent,
-            _name
+            parent,
+            name
         );
-
-        reply.error(ENOSYS);
+        let fs = self.fs.clone();
+        let name = name.to_owned();
+        self.pool.execute(move || match fs.rmdir(parent, &name) {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                log::error!(
+                    "{}:{} rmdir parent: {}, name: {:?}, error: {}",
+                    std::file!(),
+                    std::line!(),
+                    parent,
+                    name,
+                    e
+                );
+                reply.error(e.errno(EIO));
+            }
+        });
     }
 
     /// Create a symbolic link.
@@ -330,7 +1165,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
         _link: &Path,
         reply: ReplyEntry,
     ) {
-        log::debug!(
+        log::trace!(
             "{}:{} parent: {}, name: {:?}, link: {:?}",
             std::file!(),
             std::line!(),
@@ -353,7 +1188,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
         _newname: &OsStr,
         reply: ReplyEmpty,
     ) {
-        log::debug!(
+        log::trace!(
             "{}:{} parent: {}, name: {:?}, newparent: {}, newname: {:?}",
             std::file!(),
             std::line!(),
@@ -376,7 +1211,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
         _newname: &OsStr,
         reply: ReplyEntry,
     ) {
-        log::debug!(
+        log::trace!(
             "{}:{}, ino: {}, newparent: {}, newname: {:?}",
             std::file!(),
             std::line!(),
@@ -396,8 +1231,16 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
     /// filesystem may set, to change the way the file is opened. See fuse_file_info
     /// structure in <fuse_common.h> for more details.
 
-    fn open(&mut self, _req: &Request, _ino: u64, _flags: u32, reply: ReplyOpen) {
-        log::debug!(
+    fn open(&mut self, req: &Request, _ino: u64, _flags: u32, reply: ReplyOpen) {
+        let access = if _flags as c_int & libc::O_ACCMODE == libc::O_RDONLY {
+            Access::Read
+        } else {
+            Access::Write
+        };
+        if let Err(e) = self.fs.permitted(req.uid(), _ino, None, access) {
+            return reply.error(e.errno(EACCES));
+        }
+        log::trace!(
             "{}:{}, ino: {}, flags: {}",
             std::file!(),
             std::line!(),
@@ -405,8 +1248,9 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
             _flags
         );
         let _start = self.counter.start("open".to_owned());
-        // reply.opened()
-        self.pool.execute(move || reply.opened(0, _flags))
+        let fh = self.fs.open_handle(_ino);
+        let flags = self.open_flags(fh);
+        self.pool.execute(move || reply.opened(fh, flags))
     }
 
     /// Read data.
@@ -418,7 +1262,10 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
     /// if the open method didn't set any value.
 
     fn read(&mut self, req: &Request, ino: u64, fh: u64, offset: i64, size: u32, reply: ReplyData) {
-        log::debug!(
+        if let Err(e) = self.fs.permitted(req.uid(), ino, None, Access::Read) {
+            return reply.error(e.errno(EACCES));
+        }
+        log::trace!(
             "{}:{}, ino: {}, fh: {}, offset: {}, size: {}",
             std::file!(),
             std::line!(),
@@ -439,9 +1286,12 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
         let request_id = req.unique();
         let fs = self.fs.clone();
         let handle_group = self.handle_group.clone();
-        let enable_cache = self.enable_cache;
+        let enable_cache =
+            self.enable_cache && !self.fs.is_direct(fh) && !self.fs.is_passthrough(fh);
         let counter = self.counter.clone();
+        let queued = Instant::now();
         self.pool.execute(move || {
+            fs.queued("read", queued);
             let _start = counter.start("read".to_owned());
             // try read from cache
             let offset: usize = offset as usize;
@@ -455,8 +1305,8 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
                             if elem.handle == fh {
                                 let data: &[u8] = &elem.content;
                                 let end = read_to(offset, size, data.len());
-                                reply.data(&data[offset..end]);
-                                log::debug!(
+                                reply.data(&data[std::cmp::min(offset, end)..end]);
+                                log::trace!(
                                 "{}:{} request_id: {}. ino: {}, fh: {}, data.len(): {}, offset: {}, size: {}, end: {}",
                                 std::file!(), std::line!(), request_id,
                                 ino,
@@ -481,8 +1331,8 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
                             };
                             let data: &[u8] = &new_elem.content;
                             let end = read_to(offset, size, data.len());
-                            reply.data(&data[offset..end]);
-                            log::debug!(
+                            reply.data(&data[std::cmp::min(offset, end)..end]);
+                            log::trace!(
                                 "{}:{} request_id: {}. ino: {}, fh: {}, length: {}, offset: {}, size: {}, end: {}",
                                 std::file!(), std::line!(), request_id,
                                 ino,
@@ -501,7 +1351,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
                 Ok(data) => {
                     if enable_cache && data.len() != 0 {
                         let end = read_to(offset, size, data.len());
-                        log::debug!(
+                        log::trace!(
                             "{}:{} request_id: {}, ino: {}, fh: {}, data.len(): {}, offset: {}, size: {}, end: {}",
                             std::file!(),
                             std::line!(),
@@ -513,7 +1363,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
                             size,
                             end
                         );
-                        reply.data(&data[offset..end]);
+                        reply.data(&data[std::cmp::min(offset, end)..end]);
                         let mut handle_group = handle_group.write().unwrap();
                         handle_group.total_length += data.len() as u64;
                         handle_group
@@ -525,7 +1375,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
                                 handle: fh,
                             });
                     } else {
-                        log::debug!(
+                        log::trace!(
                             "{}:{} request_id: {}, ino: {}, fh: {}, data.len(): {}, offset: {}, size: {}",
                             std::file!(),
                             std::line!(),
@@ -551,7 +1401,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
                         size,
                         err
                     );
-                    reply.error(ENOSYS);
+                    reply.error(err.errno(ENOSYS));
                 }
             });
         });
@@ -566,7 +1416,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
 
     fn write(
         &mut self,
-        _req: &Request,
+        req: &Request,
         _ino: u64,
         _fh: u64,
         _offset: i64,
@@ -574,18 +1424,43 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
         _flags: u32,
         reply: ReplyWrite,
     ) {
-        log::debug!(
-            "{}:{}, ino: {}, fh: {}, offset: {}, data: {:?}, flags: {}",
+        if let Err(e) = self.fs.permitted(req.uid(), _ino, None, Access::Write) {
+            return reply.error(e.errno(EACCES));
+        }
+        log::trace!(
+            "{}:{}, ino: {}, fh: {}, offset: {}, size: {}, flags: {}",
             std::file!(),
             std::line!(),
             _ino,
             _fh,
             _offset,
-            _data,
+            _data.len(),
             _flags,
         );
-
-        reply.error(ENOSYS);
+        let fs = self.fs.clone();
+        let handle_group = self.handle_group.clone();
+        let data = _data.to_vec();
+        let queued = Instant::now();
+        self.pool.execute(move || {
+            fs.queued("write", queued);
+            match fs.write(_ino, _offset as u64, &data) {
+                Ok(written) => {
+                    handle_group.write().unwrap().forget(_ino);
+                    reply.written(written)
+                }
+                Err(e) => {
+                    log::error!(
+                        "{}:{} ino: {}, offset: {}, error: {}",
+                        std::file!(),
+                        std::line!(),
+                        _ino,
+                        _offset,
+                        e
+                    );
+                    reply.error(e.errno(EIO));
+                }
+            }
+        });
     }
 
     /// Flush method.
@@ -599,7 +1474,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
     /// filesystem wants to return write errors. If the filesystem supports file locking
     /// operations (setlk, getlk) it should remove all locks belonging to 'lock_owner'.
     fn flush(&mut self, _req: &Request, _ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
-        log::debug!(
+        log::trace!(
             "{}:{}, ino: {}, fh: {}, lock_owner: {}",
             std::file!(),
             std::line!(),
@@ -607,7 +1482,9 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
             _fh,
             _lock_owner,
         );
-        reply.error(ENOSYS);
+        let fs = self.fs.clone();
+        self.pool
+            .execute(move || reply_flushed(_ino, fs.flush(_ino), reply));
     }
 
     /// Release an open file.
@@ -628,7 +1505,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
         _flush: bool,
         reply: ReplyEmpty,
     ) {
-        log::debug!(
+        log::trace!(
             "{}:{}, ino: {}, fh: {}, flags: {}, lock_owner: {}, flush: {}",
             std::file!(),
             std::line!(),
@@ -638,7 +1515,10 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
             _lock_owner,
             _flush,
         );
-        reply.error(ENOSYS)
+        self.fs.close_handle(_fh);
+        let fs = self.fs.clone();
+        self.pool
+            .execute(move || reply_flushed(_ino, fs.release(_ino), reply));
     }
 
     /// Synchronize file contents.
@@ -646,7 +1526,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
     /// not the meta data.
 
     fn fsync(&mut self, _req: &Request, _ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
-        log::debug!(
+        log::trace!(
             "{}:{}, ino: {}, fh: {}, datasync: {}",
             std::file!(),
             std::line!(),
@@ -654,7 +1534,9 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
             _fh,
             _datasync,
         );
-        reply.error(ENOSYS);
+        let fs = self.fs.clone();
+        self.pool
+            .execute(move || reply_flushed(_ino, fs.flush(_ino), reply));
     }
 
     /// Open a directory.
@@ -680,7 +1562,6 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
             panic!("open dir ino: 0");
         }
         let previous = self.next_handle.fetch_add(1, Ordering::SeqCst);
-        *self.handle_reference.entry(_ino).or_insert(_ino) += 1;
         reply.opened(previous, 0o777);
     }
     /// Read directory.
@@ -691,12 +1572,15 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
 
     fn readdir(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
         fh: u64,
         offset: i64,
         mut reply: ReplyDirectory,
     ) {
+        if let Err(e) = self.fs.permitted(req.uid(), ino, None, Access::Read) {
+            return reply.error(e.errno(EACCES));
+        }
         // log::info!(
         //     "{}:{} ino: {}, offset: {}",
         //     std::file!(),
@@ -706,45 +1590,66 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
         // );
         let fs = self.fs.clone();
         let counter = self.counter.clone();
+        let uid = req.uid();
+        let queued = Instant::now();
         self.pool.execute(move || {
+            fs.queued("readdir", queued);
             let _start = counter.start("readdir".to_owned());
-            let mut curr_offset = offset + 1;
-            match fs.readdir(ino, fh, offset as usize) {
-                Ok(children) => {
-                    for child in children {
-                        let child: Node = child;
-                        if reply.add(
-                            child.inode(),
-                            curr_offset,
-                            child.attr().kind,
-                            child.path().file_name().unwrap(),
-                        ) {
-                            log::trace!(
-                                "current offset: {}, next offset: {}",
-                                curr_offset,
-                                curr_offset + 1
-                            );
-                            break;
-                        } else {
-                            // break;
-                            curr_offset += 1;
-                        }
+            // the reply is filled up to the buffer size of the kernel, through as
+            // many pages of the directory as it takes
+            let mut next = offset as u64;
+            let mut added = 0;
+            loop {
+                let children = match fs.readdir(ino, fh, next) {
+                    Ok(children) => children,
+                    // what was added is sent, the error comes with the next call
+                    Err(_) if added > 0 => break,
+                    Err(e) => {
+                        log::error!(
+                            "{}:{}, _ino: {}, _fh: {}, _offset: {}, error: {}",
+                            std::file!(),
+                            std::line!(),
+                            ino,
+                            fh,
+                            next,
+                            e
+                        );
+                        return reply.error(e.errno(ENOTDIR));
                     }
-                    reply.ok();
+                };
+                if children.is_empty() {
+                    break;
                 }
-                Err(e) => {
-                    log::error!(
-                        "{}:{}, _ino: {}, _fh: {}, _offset: {}, error: {}",
-                        std::file!(),
-                        std::line!(),
-                        ino,
-                        fh,
-                        offset,
-                        e
-                    );
-                    reply.error(ENOTDIR);
+                let mut full = false;
+                for child in children {
+                    let child: Node = child;
+                    next = child.cookie();
+                    if !fs.visible(uid, &child) {
+                        continue;
+                    }
+                    // an entry which does not fit is left to the next call
+                    if reply.add(
+                        child.inode(),
+                        next as i64,
+                        fuse_kind(child.attr().kind),
+                        child.name(),
+                    ) {
+                        log::trace!("readdir of {} filled before cookie {}", ino, next);
+                        full = true;
+                        break;
+                    }
+                    added += 1;
+                }
+                if full {
+                    // the buffer is smaller than the name, an empty reply would end
+                    // the listing
+                    if added == 0 {
+                        return reply.error(libc::EINVAL);
+                    }
+                    break;
                 }
             }
+            reply.ok();
         });
     }
 
@@ -754,7 +1659,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
     /// opendir method didn't set any value.
 
     fn releasedir(&mut self, _req: &Request, _ino: u64, _fh: u64, _flags: u32, reply: ReplyEmpty) {
-        log::debug!(
+        log::trace!(
             "{}:{} ino: {}, fh: {}, flags: {}",
             std::file!(),
             std::line!(),
@@ -779,7 +1684,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
         _datasync: bool,
         reply: ReplyEmpty,
     ) {
-        log::debug!(
+        log::trace!(
             "{}:{} ino: {}, fh: {}, datasync: {}",
             std::file!(),
             std::line!(),
@@ -787,8 +1692,9 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
             _fh,
             _datasync
         );
-
-        reply.error(ENOSYS);
+        let fs = self.fs.clone();
+        self.pool
+            .execute(move || reply_flushed(_ino, fs.sync_dir(_ino), reply));
     }
 
     /// Get file system statistics.
@@ -796,7 +1702,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
     fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
         match self.fs.statfs(_ino) {
             Ok(stat) => {
-                log::debug!(
+                log::trace!(
                     "{}:{}, ino: {}, stat: {:?}",
                     std::file!(),
                     std::line!(),
@@ -805,7 +1711,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
                 );
                 reply.statfs(
                     stat.blocks,
-                    stat.block_size as u64,
+                    stat.blocks_free,
                     stat.blocks_available,
                     stat.files,
                     stat.files_free,
@@ -822,7 +1728,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
                     _ino,
                     e
                 );
-                reply.error(ENOENT);
+                reply.error(e.errno(ENOENT));
             }
         }
     }
@@ -831,26 +1737,90 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
 
     fn setxattr(
         &mut self,
-        _req: &Request,
-        _ino: u64,
-        _name: &OsStr,
-        _value: &[u8],
+        req: &Request,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
         _flags: u32,
         _position: u32,
         reply: ReplyEmpty,
     ) {
-        log::debug!(
+        if let Err(e) = self.fs.permitted(req.uid(), ino, None, Access::Write) {
+            return reply.error(e.errno(EACCES));
+        }
+        log::trace!(
             "{}:{}, ino: {}, name: {:?}, value: {:?} flags: {}, position: {}",
             std::file!(),
             std::line!(),
-            _ino,
-            _name,
-            _value,
+            ino,
+            name,
+            value,
             _flags,
             _position
         );
-
-        reply.error(ENOSYS);
+        if name == prefetch::WARM_XATTR {
+            return self.warm(ino, prefetch::concurrency(value), reply);
+        }
+        if name == CONTROL_XATTR {
+            let command = match std::str::from_utf8(value)
+                .map_err(|e| e.to_string())
+                .and_then(|value| value.parse::<Command>())
+            {
+                Ok(command) => command,
+                Err(e) => {
+                    log::error!("{}:{} ino: {}, {}", std::file!(), std::line!(), ino, e);
+                    return reply.error(libc::EINVAL);
+                }
+            };
+            if command.privileged() && !control::permitted(req.uid(), self.owner) {
+                log::warn!(
+                    "{}:{} refused control command {:?} on ino: {} to uid: {}",
+                    std::file!(),
+                    std::line!(),
+                    command,
+                    ino,
+                    req.uid()
+                );
+                return reply.error(libc::EPERM);
+            }
+            let fs = self.fs.clone();
+            let handle_group = self.handle_group.clone();
+            let dump_dir = self.dump_dir.clone();
+            return self.pool.execute(move || {
+                match control(&fs, &handle_group, dump_dir.as_ref(), ino, command) {
+                    Ok(_) => reply.ok(),
+                    Err(e) => {
+                        log::error!(
+                            "{}:{} control ino: {}, command: {:?}, error: {}",
+                            std::file!(),
+                            std::line!(),
+                            ino,
+                            command,
+                            e
+                        );
+                        reply.error(e.errno(EIO));
+                    }
+                }
+            });
+        }
+        let fs = self.fs.clone();
+        let name = name.to_owned();
+        let value = value.to_vec();
+        self.pool
+            .execute(move || match fs.setxattr(ino, &name, &value) {
+                Ok(()) => reply.ok(),
+                Err(e) => {
+                    log::error!(
+                        "{}:{} setxattr ino: {}, name: {:?}, error: {}",
+                        std::file!(),
+                        std::line!(),
+                        ino,
+                        name,
+                        e
+                    );
+                    reply.error(e.errno(EIO));
+                }
+            });
     }
 
     /// Get an extended attribute.
@@ -858,24 +1828,34 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
     /// If `size` is not 0, and the value fits, send it with `reply.data()`, or
     /// `reply.error(ERANGE)` if it doesn't.
 
-    fn getxattr(
-        &mut self,
-        _req: &Request,
-        _ino: u64,
-        _name: &OsStr,
-        _size: u32,
-        reply: ReplyXattr,
-    ) {
-        log::debug!(
+    fn getxattr(&mut self, req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        if let Err(e) = self.fs.permitted(req.uid(), ino, None, Access::Read) {
+            return reply.error(e.errno(EACCES));
+        }
+        log::trace!(
             "{}:{}, ino: {}, name: {:?}, size: {}",
             std::file!(),
             std::line!(),
-            _ino,
-            _name,
-            _size
+            ino,
+            name,
+            size
         );
-
-        reply.error(ENOSYS);
+        if name == CONTROL_XATTR {
+            let fs = self.fs.clone();
+            let handle_group = self.handle_group.clone();
+            return self.pool.execute(move || {
+                match control(&fs, &handle_group, None, ino, Command::Stats) {
+                    Ok(stats) => reply_xattr(size, &stats, reply),
+                    Err(e) => reply.error(e.errno(EIO)),
+                }
+            });
+        }
+        let fs = self.fs.clone();
+        let name = name.to_owned();
+        self.pool.execute(move || match fs.getxattr(ino, &name) {
+            Ok(value) => reply_xattr(size, &value, reply),
+            Err(e) => reply.error(e.errno(EIO)),
+        });
     }
 
     /// List extended attribute names.
@@ -883,30 +1863,51 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
     /// If `size` is not 0, and the value fits, send it with `reply.data()`, or
     /// `reply.error(ERANGE)` if it doesn't.
 
-    fn listxattr(&mut self, _req: &Request, _ino: u64, _size: u32, reply: ReplyXattr) {
-        log::debug!(
+    fn listxattr(&mut self, req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        if let Err(e) = self.fs.permitted(req.uid(), ino, None, Access::Read) {
+            return reply.error(e.errno(EACCES));
+        }
+        log::trace!(
             "{}:{}, ino: {}, size: {}",
             std::file!(),
             std::line!(),
-            _ino,
-            _size
+            ino,
+            size
         );
-
-        reply.error(ENOSYS);
+        let fs = self.fs.clone();
+        self.pool.execute(move || match fs.listxattr(ino) {
+            Ok(names) => {
+                // names are separated by a nul byte
+                let mut value = vec![];
+                for name in names {
+                    value.extend_from_slice(name.as_bytes());
+                    value.push(0);
+                }
+                reply_xattr(size, &value, reply)
+            }
+            Err(e) => reply.error(e.errno(EIO)),
+        });
     }
 
     /// Remove an extended attribute.
 
-    fn removexattr(&mut self, _req: &Request, _ino: u64, _name: &OsStr, reply: ReplyEmpty) {
-        log::debug!(
+    fn removexattr(&mut self, req: &Request, ino: u64, name: &OsStr, reply: ReplyEmpty) {
+        if let Err(e) = self.fs.permitted(req.uid(), ino, None, Access::Write) {
+            return reply.error(e.errno(EACCES));
+        }
+        log::trace!(
             "{}:{}, ino: {}, name: {:?}",
             std::file!(),
             std::line!(),
-            _ino,
-            _name
+            ino,
+            name
         );
-
-        reply.error(ENOSYS);
+        let fs = self.fs.clone();
+        let name = name.to_owned();
+        self.pool.execute(move || match fs.removexattr(ino, &name) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e.errno(EIO)),
+        });
     }
 
     /// Check file access permissions.
@@ -915,7 +1916,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
     /// under Linux kernel versions 2.4.x
 
     fn access(&mut self, _req: &Request, _ino: u64, _mask: u32, reply: ReplyEmpty) {
-        log::debug!(
+        log::trace!(
             "{}:{}, ino: {}, mask: {}",
             std::file!(),
             std::line!(),
@@ -939,24 +1940,75 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
 
     fn create(
         &mut self,
-        _req: &Request,
-        _parent: u64,
-        _name: &OsStr,
-        _mode: u32,
-        _flags: u32,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
         reply: ReplyCreate,
     ) {
-        log::debug!(
+        if let Err(e) = self
+            .fs
+            .permitted(req.uid(), parent, Some(name), Access::Write)
+        {
+            return reply.error(e.errno(EACCES));
+        }
+        log::trace!(
             "{}:{}, parent: {}, name: {:?}, mode: {}, flags: {}",
             std::file!(),
             std::line!(),
-            _parent,
-            _name,
-            _mode,
-            _flags
+            parent,
+            name,
+            mode,
+            flags
         );
-
-        reply.error(ENOSYS);
+        // the backend creates exclusively so O_EXCL also holds against other mounts of
+        // the bucket. Without it a name created meanwhile is opened as it is
+        let created = match self.fs.mknod(
+            parent,
+            name,
+            FileType::RegularFile,
+            (0x8000 | (mode as u16 & 0x0fff)) as u32,
+            0,
+            req.uid(),
+            req.gid(),
+        ) {
+            Ok(node) => Ok(node.attr()),
+            Err(ref e) if e.errno(EIO) == libc::EEXIST && flags as c_int & libc::O_EXCL == 0 => {
+                self.fs.lookup(parent, name).and_then(|attr| {
+                    if flags as c_int & libc::O_TRUNC != 0 && attr.size > 0 {
+                        self.fs.truncate(attr.ino, 0)
+                    } else {
+                        Ok(attr)
+                    }
+                })
+            }
+            Err(e) => Err(e),
+        };
+        match created {
+            Ok(attr) => {
+                self.fs.looked_up(attr.ino);
+                let fh = self.fs.open_handle(attr.ino);
+                reply.created(
+                    &self.ttl.of(&self.fs, &attr),
+                    &fuse_attr(&attr),
+                    self.fs.generation(attr.ino),
+                    fh,
+                    self.open_flags(fh),
+                );
+            }
+            Err(e) => {
+                log::error!(
+                    "line: {}, parent: {}, name: {:?}, mode: {}, error: {}",
+                    std::line!(),
+                    parent,
+                    name,
+                    mode,
+                    e
+                );
+                reply.error(e.errno(EIO));
+            }
+        }
     }
 
     /// Test for a POSIX file lock.
@@ -973,7 +2025,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
         _pid: u32,
         reply: ReplyLock,
     ) {
-        log::debug!(
+        log::trace!(
             "{}:{} ino: {}, fh: {}, lock_owner: {}, start: {}, end: {}, typ: {}, pid: {}",
             std::file!(),
             std::line!(),
@@ -1010,7 +2062,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
         _sleep: bool,
         reply: ReplyEmpty,
     ) {
-        log::debug!(
+        log::trace!(
             "{}:{} ino: {}, fh: {}, lock_owner: {}, start: {}, end: {}, typ: {}, pid: {}, sleep: {}",
             std::file!(),
             std::line!(),
@@ -1032,7 +2084,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
     /// with the 'blkdev' option
 
     fn bmap(&mut self, _req: &Request, _ino: u64, _blocksize: u32, _idx: u64, reply: ReplyBmap) {
-        log::debug!(
+        log::trace!(
             "{}:{}, ino: {}, blocksize: {}, idx: {}",
             std::file!(),
             std::line!(),
@@ -1049,7 +2101,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
 
     #[cfg(target_os = "macos")]
     fn setvolname(&mut self, _req: &Request, _name: &OsStr, reply: ReplyEmpty) {
-        log::debug!("{}:{} name: {:?}", std::file!(), std::line!(), _name);
+        log::trace!("{}:{} name: {:?}", std::file!(), std::line!(), _name);
 
         reply.error(ENOSYS);
     }
@@ -1066,7 +2118,7 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
         _options: u64,
         reply: ReplyEmpty,
     ) {
-        log::debug!(
+        log::trace!(
             "{}:{} parent: {}, name: {:?}, newparent: {}, newname: {:?}, option: {}",
             std::file!(),
             std::line!(),
@@ -1084,8 +2136,134 @@ impl<B: Backend + std::fmt::Debug + Send + Sync> Filesystem for Fuse<B> {
     /// during init to FUSE_XTIMES to enable
     #[cfg(target_os = "macos")]
     fn getxtimes(&mut self, _req: &Request, _ino: u64, reply: ReplyXTimes) {
-        log::debug!("{}:{} ino: {}", std::file!(), std::line!(), _ino);
+        log::trace!("{}:{} ino: {}", std::file!(), std::line!(), _ino);
 
         reply.error(ENOSYS);
     }
 }
+
+// answers with the size of `value` when asked for it, `size` being 0
+// runs `command` on `ino`, returns what it has to tell
+fn control<B>(
+    fs: &FileSystem<B>,
+    handle_group: &RwLock<HandleGroup>,
+    dump_dir: Option<&PathBuf>,
+    ino: u64,
+    command: Command,
+) -> crate::error::Result<Vec<u8>>
+where
+    B: Backend + std::fmt::Debug + Send + Sync + 'static,
+{
+    match command {
+        Command::Invalidate => {
+            let stale = fs.invalidate(ino)?;
+            let mut handle_group = handle_group.write().unwrap();
+            for ino in stale {
+                handle_group.forget(ino);
+            }
+        }
+        Command::Pin => fs.pin(ino, true)?,
+        Command::Unpin => fs.pin(ino, false)?,
+        Command::FlushCache => {
+            let mut handle_group = handle_group.write().unwrap();
+            handle_group.map.clear();
+            handle_group.total_length = 0;
+        }
+        Command::Dump => {
+            let dir = dump_dir.ok_or_else(|| {
+                Error::PermissionDenied("no directory is set for dumps".to_owned())
+            })?;
+            let path = control::dump_file(dir, ino);
+            // a new file, never one which is already there
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)?;
+            let mut out = std::io::BufWriter::new(file);
+            let entries = fs.dump(ino, &mut out)?;
+            std::io::Write::flush(&mut out)?;
+            log::info!(
+                "dumped {} entries below inode {} to {:?}",
+                entries,
+                ino,
+                path
+            );
+        }
+        Command::Stats => {
+            let handle_group = handle_group.read().unwrap();
+            let stats = format!(
+                "metadata: {} bytes\ncontent cache: {} bytes in {} files\npinned: {}\nrequests: {}\nuploads: {}\n",
+                fs.metadata_bytes(),
+                handle_group.total_length,
+                handle_group.map.len(),
+                fs.pinned(),
+                fs.requests(),
+                fs.pending_uploads()
+            );
+            let stats = match fs.health() {
+                Some(health) => format!("{}backend: {}\n", stats, health),
+                None => stats,
+            };
+            return Ok(stats.into_bytes());
+        }
+    }
+    Ok(vec![])
+}
+
+// `attr` in the type of the fuse crate
+fn fuse_attr(attr: &FileAttr) -> fuse::FileAttr {
+    fuse::FileAttr {
+        ino: attr.ino,
+        size: attr.size,
+        blocks: attr.blocks,
+        atime: attr.atime,
+        mtime: attr.mtime,
+        ctime: attr.ctime,
+        crtime: attr.crtime,
+        kind: fuse_kind(attr.kind),
+        perm: attr.perm,
+        nlink: attr.nlink,
+        uid: attr.uid,
+        gid: attr.gid,
+        rdev: attr.rdev,
+        flags: attr.flags,
+    }
+}
+
+fn fuse_kind(kind: FileType) -> fuse::FileType {
+    match kind {
+        FileType::NamedPipe => fuse::FileType::NamedPipe,
+        FileType::CharDevice => fuse::FileType::CharDevice,
+        FileType::BlockDevice => fuse::FileType::BlockDevice,
+        FileType::Directory => fuse::FileType::Directory,
+        FileType::RegularFile => fuse::FileType::RegularFile,
+        FileType::Symlink => fuse::FileType::Symlink,
+        FileType::Socket => fuse::FileType::Socket,
+    }
+}
+
+fn reply_xattr(size: u32, value: &[u8], reply: ReplyXattr) {
+    if size == 0 {
+        reply.size(value.len() as u32);
+    } else if value.len() > size as usize {
+        reply.error(libc::ERANGE);
+    } else {
+        reply.data(value);
+    }
+}
+
+fn reply_flushed(ino: u64, result: crate::error::Result<()>, reply: ReplyEmpty) {
+    match result {
+        Ok(()) => reply.ok(),
+        Err(e) => {
+            log::error!(
+                "{}:{} flush ino: {}, error: {}",
+                std::file!(),
+                std::line!(),
+                ino,
+                e
+            );
+            reply.error(e.errno(EIO));
+        }
+    }
+}
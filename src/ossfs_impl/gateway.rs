@@ -0,0 +1,528 @@
+//! Serves a `Backend` over a minimal, unauthenticated and read-only S3 compatible http
+//! api, so that tools speaking S3 can read a SeaweedFS or a local backend.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::attr::{FileAttr, FileType};
+use crate::ossfs_impl::backend::{key, Backend};
+use crate::ossfs_impl::node::Node;
+use crate::ossfs_impl::object_store::ObjectStore;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+const DEFAULT_MAX_KEYS: usize = 1000;
+// threads calling the backend, the executor serving the requests never blocks on it
+const BACKEND_THREADS: usize = 32;
+
+#[derive(Debug)]
+pub struct Gateway<B> {
+    store: ObjectStore<B>,
+    bucket: String,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Listing {
+    objects: Vec<(String, u64, SystemTime)>,
+    prefixes: Vec<String>,
+    truncated: bool,
+}
+
+impl<B: Backend + Send + Sync + 'static> Gateway<B> {
+    /// Exposes the root of `backend` as `bucket`.
+    pub fn new<S: Into<String>>(backend: B, bucket: S) -> Gateway<B> {
+        Gateway {
+            store: ObjectStore::new(backend, BACKEND_THREADS),
+            bucket: bucket.into(),
+        }
+    }
+
+    /// Serves requests on `addr` until the server fails.
+    pub fn serve(self, addr: SocketAddr) -> Result<()> {
+        log::info!("serving bucket {} on {}", self.bucket, addr);
+        let gateway = Arc::new(self);
+        let runtime = tokio::runtime::Runtime::new()?;
+        runtime.block_on(async move {
+            let service = make_service_fn(move |_| {
+                let gateway = gateway.clone();
+                async move {
+                    Ok::<_, hyper::Error>(service_fn(move |request| {
+                        let gateway = gateway.clone();
+                        async move { Ok::<_, hyper::Error>(gateway.handle(request).await) }
+                    }))
+                }
+            });
+            Server::bind(&addr).serve(service).await
+        })?;
+        Ok(())
+    }
+
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        log::debug!("{} {}", request.method(), request.uri());
+        let head = match *request.method() {
+            Method::GET => false,
+            Method::HEAD => true,
+            _ => {
+                return error_response(
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "MethodNotAllowed",
+                    "only GET and HEAD are supported",
+                )
+            }
+        };
+        let path = match key::unescape_url_path(request.uri().path()) {
+            Ok(path) => path,
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, "InvalidURI", &e.to_string()),
+        };
+        let path = path.trim_start_matches('/');
+        let (bucket, object) = match path.find('/') {
+            Some(index) => (&path[..index], &path[index + 1..]),
+            None => (path, ""),
+        };
+        if bucket != self.bucket {
+            return error_response(StatusCode::NOT_FOUND, "NoSuchBucket", bucket);
+        }
+        let result = if object.is_empty() {
+            let query = query_pairs(request.uri().query().unwrap_or(""));
+            self.list(&query).await
+        } else {
+            let range = request
+                .headers()
+                .get(hyper::header::RANGE)
+                .and_then(|range| range.to_str().ok());
+            self.object(object, range, head).await
+        };
+        result.unwrap_or_else(|e| {
+            log::error!(
+                "{}:{} {} {}. error: {}",
+                std::file!(),
+                std::line!(),
+                request.method(),
+                request.uri(),
+                e
+            );
+            match e {
+                Error::NotFound(message) => {
+                    error_response(StatusCode::NOT_FOUND, "NoSuchKey", &message)
+                }
+                Error::PermissionDenied(message) => {
+                    error_response(StatusCode::FORBIDDEN, "AccessDenied", &message)
+                }
+                Error::Throttled(message) => {
+                    error_response(StatusCode::SERVICE_UNAVAILABLE, "SlowDown", &message)
+                }
+                e => error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "InternalError",
+                    &e.to_string(),
+                ),
+            }
+        })
+    }
+
+    // the body is streamed from the backend chunk by chunk as the client reads it
+    async fn object(
+        &self,
+        object: &str,
+        range: Option<&str>,
+        head: bool,
+    ) -> Result<Response<Body>> {
+        let path = path_of(self.store.backend(), object);
+        let attr: FileAttr = {
+            let path = path.clone();
+            self.store
+                .call(move |backend| backend.get_node(path))
+                .await?
+                .attr()
+        };
+        if attr.kind == FileType::Directory {
+            return Err(Error::NotFound(format!("{} is a directory", object)));
+        }
+        let (start, end) = match range {
+            Some(range) => match parse_range(range, attr.size) {
+                Some(range) => range,
+                None => {
+                    let mut builder = Response::builder();
+                    builder
+                        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                        .header("Content-Range", format!("bytes */{}", attr.size));
+                    return Ok(builder.body(Body::empty()).unwrap());
+                }
+            },
+            None => (0, attr.size),
+        };
+        let mut builder = Response::builder();
+        builder
+            .header("Content-Type", "application/octet-stream")
+            .header("Content-Length", (end - start).to_string())
+            .header("Accept-Ranges", "bytes")
+            .header("Last-Modified", http_date(attr.mtime));
+        if range.is_some() {
+            builder.status(StatusCode::PARTIAL_CONTENT).header(
+                "Content-Range",
+                format!("bytes {}-{}/{}", start, end - 1, attr.size),
+            );
+        }
+        if head {
+            return Ok(builder.body(Body::empty()).unwrap());
+        }
+        let body = self.store.stream(path, start..end);
+        Ok(builder.body(Body::wrap_stream(body)).unwrap())
+    }
+
+    async fn list(&self, query: &[(String, String)]) -> Result<Response<Body>> {
+        let get = |name: &str| {
+            query
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let prefix = get("prefix").unwrap_or("");
+        let delimiter = get("delimiter").filter(|delimiter| !delimiter.is_empty());
+        if delimiter.is_some() && delimiter != Some("/") {
+            return Ok(error_response(
+                StatusCode::BAD_REQUEST,
+                "InvalidArgument",
+                "only / is supported as delimiter",
+            ));
+        }
+        let max_keys = match get("max-keys") {
+            Some(max_keys) => match max_keys.parse() {
+                Ok(max_keys) => max_keys,
+                Err(_) => {
+                    return Ok(error_response(
+                        StatusCode::BAD_REQUEST,
+                        "InvalidArgument",
+                        "max-keys is not a number",
+                    ))
+                }
+            },
+            None => DEFAULT_MAX_KEYS,
+        };
+        let start_after = get("continuation-token").or_else(|| get("start-after"));
+        let listing = {
+            let prefix = prefix.to_owned();
+            let delimited = delimiter.is_some();
+            let start_after = start_after.map(|start_after| start_after.to_owned());
+            self.store
+                .call(move |backend| {
+                    list_objects(
+                        backend,
+                        &prefix,
+                        delimited,
+                        start_after.as_ref().map(|start_after| start_after.as_str()),
+                        max_keys,
+                    )
+                })
+                .await?
+        };
+
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">",
+        );
+        xml += &format!("<Name>{}</Name>", xml_escape(&self.bucket));
+        xml += &format!("<Prefix>{}</Prefix>", xml_escape(prefix));
+        if let Some(delimiter) = delimiter {
+            xml += &format!("<Delimiter>{}</Delimiter>", xml_escape(delimiter));
+        }
+        xml += &format!("<MaxKeys>{}</MaxKeys>", max_keys);
+        xml += &format!(
+            "<KeyCount>{}</KeyCount>",
+            listing.objects.len() + listing.prefixes.len()
+        );
+        xml += &format!("<IsTruncated>{}</IsTruncated>", listing.truncated);
+        if let Some(token) = get("continuation-token") {
+            xml += &format!(
+                "<ContinuationToken>{}</ContinuationToken>",
+                xml_escape(token)
+            );
+        }
+        if listing.truncated {
+            let last = listing
+                .objects
+                .iter()
+                .map(|(key, _, _)| key)
+                .chain(listing.prefixes.iter())
+                .max();
+            if let Some(last) = last {
+                xml += &format!(
+                    "<NextContinuationToken>{}</NextContinuationToken>",
+                    xml_escape(last)
+                );
+            }
+        }
+        for (key, size, mtime) in listing.objects.iter() {
+            xml += &format!(
+                "<Contents><Key>{}</Key><LastModified>{}</LastModified><Size>{}</Size>\
+                 <StorageClass>STANDARD</StorageClass></Contents>",
+                xml_escape(key),
+                chrono::DateTime::<chrono::Utc>::from(*mtime)
+                    .to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                size
+            );
+        }
+        for prefix in listing.prefixes.iter() {
+            xml += &format!(
+                "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                xml_escape(prefix)
+            );
+        }
+        xml += "</ListBucketResult>";
+
+        let mut builder = Response::builder();
+        builder.header("Content-Type", "application/xml");
+        Ok(builder.body(Body::from(xml)).unwrap())
+    }
+}
+
+fn path_of<B: Backend>(backend: &B, object: &str) -> PathBuf {
+    let root = backend.root().path();
+    if object.is_empty() {
+        root
+    } else {
+        root.join(key::key_to_path(object))
+    }
+}
+
+fn key_of(root: &Path, path: &Path) -> String {
+    key::path_to_key(path.strip_prefix(root).unwrap_or(path))
+}
+
+/// Lists the keys starting with `prefix` in key order, after `start_after`. With
+/// `delimited`, the content of sub directories is rolled up into common prefixes.
+///
+/// The directories are walked in key order and the walk stops once a page is
+/// full: a page lists the directories holding its keys, those before the
+/// continuation marker and after the page are left alone.
+pub(crate) fn list_objects<B: Backend>(
+    backend: &B,
+    prefix: &str,
+    delimited: bool,
+    start_after: Option<&str>,
+    max_keys: usize,
+) -> Result<Listing> {
+    let root = backend.root().path();
+    let directory = match prefix.rfind('/') {
+        Some(index) => &prefix[..index],
+        None => "",
+    };
+    let mut walk = Walk {
+        backend,
+        root: &root,
+        prefix,
+        delimited,
+        start_after: start_after.unwrap_or(""),
+        // one more tells whether the page is truncated
+        limit: max_keys + 1,
+        listing: Listing::default(),
+        found: 0,
+    };
+    walk.directory(path_of(backend, directory))?;
+    let mut listing = walk.listing;
+    listing.truncated = walk.found > max_keys;
+    // the last key was only found to tell it
+    if listing.truncated {
+        let last_object = listing.objects.last().map(|(key, _, _)| key.clone());
+        let last_prefix = listing.prefixes.last().cloned();
+        if last_object > last_prefix {
+            listing.objects.pop();
+        } else {
+            listing.prefixes.pop();
+        }
+    }
+    Ok(listing)
+}
+
+// the state of a walk of `list_objects`
+struct Walk<'a, B> {
+    backend: &'a B,
+    root: &'a Path,
+    prefix: &'a str,
+    delimited: bool,
+    start_after: &'a str,
+    limit: usize,
+    listing: Listing,
+    // keys and prefixes found so far
+    found: usize,
+}
+
+impl<'a, B: Backend> Walk<'a, B> {
+    // adds the keys below the directory at `path`, returns whether the page is full
+    fn directory(&mut self, path: PathBuf) -> Result<bool> {
+        let children = match self.backend.get_children(&path) {
+            Ok(children) => children,
+            Err(Error::NotFound(_)) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let mut children: Vec<(String, Node)> = children
+            .into_iter()
+            .map(|child| {
+                let mut key = key_of(self.root, &child.path());
+                if child.attr().kind == FileType::Directory {
+                    key.push('/');
+                }
+                (key, child)
+            })
+            .collect();
+        // the keys below a directory sort right after it, before its next sibling
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+        for (key, child) in children {
+            if self.found >= self.limit {
+                return Ok(true);
+            }
+            if !key.ends_with('/') {
+                if key.starts_with(self.prefix) && key.as_str() > self.start_after {
+                    let attr = child.attr();
+                    self.listing.objects.push((key, attr.size, attr.mtime));
+                    self.found += 1;
+                }
+                continue;
+            }
+            if !key.starts_with(self.prefix) && !self.prefix.starts_with(&key) {
+                continue;
+            }
+            if self.delimited && key.starts_with(self.prefix) {
+                if key.as_str() > self.start_after {
+                    self.listing.prefixes.push(key);
+                    self.found += 1;
+                }
+                continue;
+            }
+            // every key below sorts before the marker
+            if key.as_str() <= self.start_after && !self.start_after.starts_with(&key) {
+                continue;
+            }
+            if self.directory(child.path())? {
+                return Ok(true);
+            }
+        }
+        Ok(self.found >= self.limit)
+    }
+}
+
+/// Parses a single `bytes=` range against an object of `size` bytes into a half
+/// open interval, `None` if it can not be satisfied.
+fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+    let range = range.trim();
+    if !range.starts_with("bytes=") || range.contains(',') {
+        return None;
+    }
+    let range = &range["bytes=".len()..];
+    let index = range.find('-')?;
+    let (first, last) = (range[..index].trim(), range[index + 1..].trim());
+    let (start, end) = if first.is_empty() {
+        // the last n bytes
+        let suffix: u64 = last.parse().ok()?;
+        (size.saturating_sub(suffix), size)
+    } else {
+        let start: u64 = first.parse().ok()?;
+        let end = if last.is_empty() {
+            size
+        } else {
+            std::cmp::min(last.parse::<u64>().ok()?.checked_add(1)?, size)
+        };
+        (start, end)
+    };
+    if start >= end {
+        return None;
+    }
+    Some((start, end))
+}
+
+fn query_pairs(query: &str) -> Vec<(String, String)> {
+    url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect()
+}
+
+fn http_date(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn error_response(status: StatusCode, code: &str, message: &str) -> Response<Body> {
+    let xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <Error><Code>{}</Code><Message>{}</Message></Error>",
+        code,
+        xml_escape(message)
+    );
+    let mut builder = Response::builder();
+    builder
+        .status(status)
+        .header("Content-Type", "application/xml");
+    builder.body(Body::from(xml)).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{list_objects, parse_range};
+    use crate::ossfs_impl::backend::mock::MockBackend;
+
+    #[test]
+    fn test_list_objects() {
+        let mock = MockBackend::new();
+        mock.add_file("/a", b"1");
+        mock.add_file("/photos/2019/x.jpg", b"22");
+        mock.add_file("/photos/2020/y.jpg", b"333");
+        mock.add_file("/photos/index", b"");
+
+        let listing = list_objects(&mock, "", true, None, 1000).unwrap();
+        let keys: Vec<&str> = listing.objects.iter().map(|o| o.0.as_str()).collect();
+        assert_eq!(keys, vec!["a"]);
+        assert_eq!(listing.prefixes, vec!["photos/"]);
+
+        let listing = list_objects(&mock, "photos/20", false, None, 1).unwrap();
+        assert_eq!(listing.objects.len(), 1);
+        assert_eq!(listing.objects[0].0, "photos/2019/x.jpg");
+        assert_eq!(listing.objects[0].1, 2);
+        assert!(listing.truncated);
+        let listing =
+            list_objects(&mock, "photos/20", false, Some("photos/2019/x.jpg"), 1).unwrap();
+        assert_eq!(listing.objects.len(), 1);
+        assert_eq!(listing.objects[0].0, "photos/2020/y.jpg");
+        assert!(!listing.truncated);
+
+        assert_eq!(parse_range("bytes=0-0", 10), Some((0, 1)));
+        assert_eq!(parse_range("bytes=5-", 10), Some((5, 10)));
+        assert_eq!(parse_range("bytes=-3", 10), Some((7, 10)));
+        assert_eq!(parse_range("bytes=8-100", 10), Some((8, 10)));
+        assert_eq!(parse_range("bytes=10-", 10), None);
+        assert_eq!(parse_range("bytes=0-1,3-4", 10), None);
+    }
+
+    #[test]
+    fn test_list_objects_from_marker() {
+        let mock = MockBackend::new();
+        for dir in &["a", "b", "c", "d"] {
+            mock.add_file(&format!("/{}/1", dir), b"1");
+            mock.add_file(&format!("/{}/2", dir), b"2");
+        }
+
+        let listing = list_objects(&mock, "", false, Some("c/1"), 2).unwrap();
+        let keys: Vec<&str> = listing.objects.iter().map(|o| o.0.as_str()).collect();
+        assert_eq!(keys, vec!["c/2", "d/1"]);
+        assert!(listing.truncated);
+        // the root, c and d: a and b sort before the marker and are not listed
+        assert_eq!(mock.calls("get_children"), 3);
+    }
+}
@@ -0,0 +1,119 @@
+//! Persistent record of the inode numbers handed out to the kernel, so that NFS file
+//! handles resolve after a restart.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::backend::key;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+// append only, one `<inode> <generation> <escaped key>` line per change, later lines win
+#[derive(Debug)]
+pub struct HandleTable {
+    file: File,
+    by_inode: HashMap<u64, (u64, PathBuf)>,
+    by_path: HashMap<PathBuf, u64>,
+}
+
+impl HandleTable {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<HandleTable> {
+        let path = path.as_ref();
+        let mut by_inode = HashMap::new();
+        let mut by_path = HashMap::new();
+        if path.exists() {
+            for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+                let line = line?;
+                let invalid = || {
+                    Error::Other(format!(
+                        "invalid line {} in inode table {:?}",
+                        number + 1,
+                        path
+                    ))
+                };
+                let fields: Vec<&str> = line.splitn(3, ' ').collect();
+                if fields.len() != 3 {
+                    return Err(invalid());
+                }
+                let inode: u64 = fields[0].parse().map_err(|_| invalid())?;
+                let generation: u64 = fields[1].parse().map_err(|_| invalid())?;
+                let node_path = key::key_to_path(key::unescape_url_path(fields[2])?);
+                by_path.insert(node_path.clone(), inode);
+                by_inode.insert(inode, (generation, node_path));
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        log::info!("loaded {} inodes from {:?}", by_inode.len(), path);
+        Ok(HandleTable {
+            file,
+            by_inode,
+            by_path,
+        })
+    }
+
+    pub fn inode_of(&self, path: &Path) -> Option<u64> {
+        self.by_path.get(path).cloned()
+    }
+
+    pub fn path_of(&self, inode: u64) -> Option<&Path> {
+        self.by_inode.get(&inode).map(|(_, path)| path.as_path())
+    }
+
+    pub fn generation(&self, inode: u64) -> Option<u64> {
+        self.by_inode.get(&inode).map(|(generation, _)| *generation)
+    }
+
+    pub fn record(&mut self, inode: u64, generation: u64, path: &Path) -> Result<()> {
+        writeln!(
+            self.file,
+            "{} {} {}",
+            inode,
+            generation,
+            key::escape_url_path(&key::path_to_key(path))
+        )?;
+        self.by_path.insert(path.to_path_buf(), inode);
+        self.by_inode
+            .insert(inode, (generation, path.to_path_buf()));
+        Ok(())
+    }
+
+    /// Writes the lines recorded so far through to the disk.
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// The entry behind `inode` is gone, handles to it become stale. The number keeps
+    /// belonging to the path, but with a new generation.
+    pub fn retire(&mut self, inode: u64) -> Result<()> {
+        let (generation, path) = match self.by_inode.get(&inode) {
+            Some((generation, path)) => (*generation, path.clone()),
+            None => return Ok(()),
+        };
+        self.record(inode, generation + 1, &path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HandleTable;
+    use std::path::Path;
+
+    #[test]
+    fn test_reload() {
+        let file = std::env::temp_dir().join(format!("ossfs-handles-{}", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        {
+            let mut table = HandleTable::open(&file).unwrap();
+            table.record(42, 1, Path::new("bucket/a b\nc")).unwrap();
+            table.record(43, 1, Path::new("bucket/d")).unwrap();
+            table.retire(43).unwrap();
+        }
+        let table = HandleTable::open(&file).unwrap();
+        assert_eq!(table.inode_of(Path::new("bucket/a b\nc")), Some(42));
+        assert_eq!(table.path_of(43), Some(Path::new("bucket/d")));
+        assert_eq!(table.generation(42), Some(1));
+        assert_eq!(table.generation(43), Some(2));
+        std::fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+//! Periodic health probes of the backend with `Backend::probe`, the outcome of the
+//! recent probes sets whether it is healthy, degraded or unhealthy.
+
+use crate::error::Error;
+use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::breaker::CircuitBreaker;
+use crate::ossfs_impl::budget::{Budget, Request};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// probes the success rate is computed over
+const WINDOW: usize = 20;
+// probes failed in a row making the backend unhealthy
+const DOWN_AFTER: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HealthState {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+impl fmt::Display for HealthState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HealthState::Healthy => write!(f, "healthy"),
+            HealthState::Degraded => write!(f, "degraded"),
+            HealthState::Unhealthy => write!(f, "unhealthy"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Probes {
+    // outcomes of the last probes, the most recent last
+    recent: VecDeque<bool>,
+    state: HealthState,
+}
+
+/// Health of a backend from the outcome of its recent probes.
+#[derive(Debug)]
+pub struct Health {
+    probes: Mutex<Probes>,
+    // percent of the recent probes which succeeded
+    pub success_rate: Arc<AtomicU64>,
+    // microseconds the last probe took
+    pub latency: Arc<AtomicU64>,
+}
+
+impl Default for Health {
+    fn default() -> Health {
+        Health {
+            probes: Mutex::new(Probes {
+                recent: VecDeque::with_capacity(WINDOW),
+                state: HealthState::Healthy,
+            }),
+            success_rate: Arc::new(AtomicU64::new(100)),
+            latency: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl Health {
+    /// Counts a probe which took `latency`, logging the transition it causes.
+    pub fn record(&self, result: Result<(), &Error>, latency: Duration) -> HealthState {
+        let mut probes = self.probes.lock().unwrap();
+        if probes.recent.len() == WINDOW {
+            probes.recent.pop_front();
+        }
+        probes.recent.push_back(result.is_ok());
+        let succeeded = probes.recent.iter().filter(|ok| **ok).count();
+        self.success_rate.store(
+            (succeeded * 100 / probes.recent.len()) as u64,
+            Ordering::Relaxed,
+        );
+        self.latency
+            .store(latency.as_micros() as u64, Ordering::Relaxed);
+
+        let failed_in_row = probes.recent.iter().rev().take_while(|ok| !**ok).count();
+        let state = if failed_in_row >= DOWN_AFTER {
+            HealthState::Unhealthy
+        } else if succeeded < probes.recent.len() {
+            HealthState::Degraded
+        } else {
+            HealthState::Healthy
+        };
+        if state != probes.state {
+            let message = format!("backend went from {} to {}", probes.state, state);
+            match (state, result) {
+                (HealthState::Healthy, _) => log::info!("{}", message),
+                (HealthState::Unhealthy, Err(e)) => log::error!("{}. error: {}", message, e),
+                (_, Err(e)) => log::warn!("{}. error: {}", message, e),
+                (_, Ok(())) => log::info!("{}", message),
+            }
+            probes.state = state;
+        }
+        state
+    }
+
+    pub fn state(&self) -> HealthState {
+        self.probes.lock().unwrap().state
+    }
+
+    /// Whether the mount can serve requests from the backend, unless it is unhealthy.
+    pub fn is_ready(&self) -> bool {
+        self.state() != HealthState::Unhealthy
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let probes = self.probes.lock().unwrap();
+        write!(
+            f,
+            "{}, {}% of {} probes succeeded, last took {:?}",
+            probes.state,
+            self.success_rate.load(Ordering::Relaxed),
+            probes.recent.len(),
+            Duration::from_micros(self.latency.load(Ordering::Relaxed))
+        )
+    }
+}
+
+/// Probes `backend` every `interval` until nobody else holds `health`.
+pub(crate) fn spawn_prober<B>(
+    backend: Arc<B>,
+    health: Arc<Health>,
+    breaker: Arc<CircuitBreaker>,
+    budget: Arc<Budget>,
+    interval: Duration,
+) where
+    B: Backend + Send + Sync + 'static,
+{
+    let spawned = std::thread::Builder::new()
+        .name("ossfs-health".to_owned())
+        .spawn(move || loop {
+            std::thread::sleep(interval);
+            if Arc::strong_count(&health) == 1 {
+                return;
+            }
+            budget.acquire(Request::Head);
+            let started = Instant::now();
+            let result = backend.probe();
+            health.record(result.as_ref().map(|_| ()), started.elapsed());
+            breaker.record(result.as_ref().map(|_| ()));
+        });
+    if let Err(e) = spawned {
+        log::error!("failed to spawn the health thread. error: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Health, HealthState};
+    use crate::error::Error;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    #[test]
+    fn test_health() {
+        let health = Health::default();
+        let err = Error::Timeout("probe".to_owned());
+        let latency = Duration::from_millis(10);
+        assert_eq!(health.record(Ok(()), latency), HealthState::Healthy);
+        assert_eq!(health.record(Err(&err), latency), HealthState::Degraded);
+        assert_eq!(health.record(Err(&err), latency), HealthState::Degraded);
+        assert_eq!(health.record(Err(&err), latency), HealthState::Unhealthy);
+        assert!(!health.is_ready());
+        assert_eq!(health.success_rate.load(Ordering::Relaxed), 25);
+
+        // degraded until the failures leave the window
+        assert_eq!(health.record(Ok(()), latency), HealthState::Degraded);
+        assert!(health.is_ready());
+        for _ in 0..19 {
+            health.record(Ok(()), latency);
+        }
+        assert_eq!(health.state(), HealthState::Healthy);
+        assert_eq!(health.latency.load(Ordering::Relaxed), 10_000);
+    }
+}
@@ -0,0 +1,80 @@
+//! Retries of uploads whose failure is ambiguous, each attempt carrying the token of
+//! the first one so that an upload which reached the store is not put again.
+
+use crate::error::Result;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A token no other upload of this or any other mount takes.
+pub fn token() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or(0);
+    format!(
+        "{:x}-{:x}-{:x}",
+        nanos,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+/// Runs `attempt` with a new token, then again with the same token up to
+/// `retries` times while it fails with a retryable error. The second argument of
+/// `attempt` tells a retry from the first attempt.
+pub fn retry<T, D, F>(what: D, retries: usize, mut attempt: F) -> Result<T>
+where
+    D: Debug,
+    F: FnMut(&str, bool) -> Result<T>,
+{
+    let token = token();
+    let mut tries = 0;
+    loop {
+        match attempt(&token, tries > 0) {
+            Err(ref e) if e.is_retryable() && tries < retries => {
+                log::warn!("retrying {:?}. token: {}, error: {}", what, token, e);
+                tries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::retry;
+    use crate::error::Error;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::backend::Backend;
+
+    #[test]
+    fn test_retry() {
+        let backend = MockBackend::new();
+        // the first put reaches the store but its response is lost
+        backend.lose_next("put", Error::Timeout("put".to_owned()));
+        retry("/a", 2, |token, again| {
+            backend.put_idempotent("/a", b"hello", None, token, again)
+        })
+        .unwrap();
+        assert_eq!(backend.content("/a").unwrap(), b"hello");
+        assert_eq!(backend.calls("put"), 1);
+
+        // a put failing before it reaches the store is sent again
+        backend.fail_next("put", Error::Timeout("put".to_owned()));
+        retry("/a", 2, |token, again| {
+            backend.put_idempotent("/a", b"world", None, token, again)
+        })
+        .unwrap();
+        assert_eq!(backend.content("/a").unwrap(), b"world");
+        assert_eq!(backend.calls("put"), 3);
+
+        backend.fail_next("put", Error::Fuse(libc::EIO));
+        assert!(retry("/a", 2, |token, again| {
+            backend.put_idempotent("/a", b"!", None, token, again)
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! On-disk journal of the files written through a write-back mount but not uploaded
+//! yet, put by the next mount after a crash of the process.
+
+use crate::error::Result;
+use crate::ossfs_impl::backend::Backend;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const UNFLUSHED: &str = "unflushed";
+
+#[derive(Debug)]
+pub(crate) struct Journal {
+    dir: PathBuf,
+}
+
+impl Journal {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Journal> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        Ok(Journal {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Starts the entry of the file `ino`, dirty from now on, whose copy holds
+    /// `content` and goes to `path`.
+    pub fn begin(&self, ino: u64, path: &Path, content: &mut dyn Read) -> Result<()> {
+        std::io::copy(content, &mut std::fs::File::create(self.data(ino))?)?;
+        // the path file goes last, an entry without one is incomplete
+        let temporary = self.dir.join(format!("{}.path.tmp", ino));
+        std::fs::write(&temporary, path.to_string_lossy().as_bytes())?;
+        std::fs::rename(&temporary, self.path(ino))?;
+        Ok(())
+    }
+
+    /// Writes `data` at `offset` of the copy of the file `ino`.
+    pub fn write(&self, ino: u64, offset: u64, data: &[u8]) -> Result<()> {
+        let mut file = OpenOptions::new().write(true).open(self.data(ino))?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        Ok(())
+    }
+
+    /// Cuts or zero-extends the copy of the file `ino` to `size` bytes.
+    pub fn truncate(&self, ino: u64, size: u64) -> Result<()> {
+        let file = OpenOptions::new().write(true).open(self.data(ino))?;
+        file.set_len(size)?;
+        Ok(())
+    }
+
+    /// Drops the entry of the file `ino`, uploaded or deleted.
+    pub fn commit(&self, ino: u64) {
+        for file in &[self.path(ino), self.data(ino)] {
+            if let Err(e) = std::fs::remove_file(file) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::error!("failed to remove {:?} from the journal. {}", file, e);
+                }
+            }
+        }
+    }
+
+    /// Puts the files left by an earlier mount which did not upload them. Returns
+    /// the paths put, the others are moved aside and logged.
+    pub fn recover<B: Backend>(&self, backend: &B) -> Vec<PathBuf> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::error!("failed to read the journal {:?}. {}", self.dir, e);
+                return vec![];
+            }
+        };
+        let mut recovered = vec![];
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let ino: u64 = match name.trim_end_matches(".path").parse() {
+                Ok(ino) if name.ends_with(".path") => ino,
+                _ => continue,
+            };
+            let path = match std::fs::read_to_string(entry.path()) {
+                Ok(path) => PathBuf::from(path),
+                Err(e) => {
+                    log::error!("failed to read {:?}. {}", entry.path(), e);
+                    continue;
+                }
+            };
+            let put = std::fs::read(self.data(ino))
+                .map_err(Into::into)
+                .and_then(|data| backend.put(&path, &data));
+            match put {
+                Ok(()) => {
+                    log::warn!("uploaded {:?}, left unflushed by an earlier mount", path);
+                    self.commit(ino);
+                    recovered.push(path);
+                }
+                Err(e) => {
+                    log::error!(
+                        "failed to upload {:?}, left unflushed by an earlier mount, its content is kept in {:?}. {}",
+                        path,
+                        self.dir.join(UNFLUSHED),
+                        e
+                    );
+                    self.set_aside(ino);
+                }
+            }
+        }
+        recovered
+    }
+
+    // moves the entry of the file `ino` out of the way of the entries of this mount
+    fn set_aside(&self, ino: u64) {
+        let unflushed = self.dir.join(UNFLUSHED);
+        let moved = std::fs::create_dir_all(&unflushed)
+            .and_then(|_| std::fs::rename(self.data(ino), unflushed.join(format!("{}.data", ino))))
+            .and_then(|_| std::fs::rename(self.path(ino), unflushed.join(format!("{}.path", ino))));
+        if let Err(e) = moved {
+            log::error!("failed to move the entry of inode {} aside. {}", ino, e);
+        }
+    }
+
+    fn data(&self, ino: u64) -> PathBuf {
+        self.dir.join(format!("{}.data", ino))
+    }
+
+    fn path(&self, ino: u64) -> PathBuf {
+        self.dir.join(format!("{}.path", ino))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Journal;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::budget::Budget;
+    use crate::ossfs_impl::writeback::WriteBack;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_journal() {
+        let dir = std::env::temp_dir().join(format!("ossfs-journal-{}", std::process::id()));
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/a", b"hello world");
+        let writeback = |journal: Option<Journal>| {
+            WriteBack::new(None, 1, 0, Arc::new(Budget::new(None)), journal)
+        };
+
+        let crashed = writeback(Some(Journal::open(&dir).unwrap()));
+        let (a, b) = (Path::new("/a"), Path::new("/b"));
+        crashed.write(&backend, 2, a, 11, 6, b"there").unwrap();
+        crashed.write(&backend, 3, b, 0, 0, b"new").unwrap();
+        crashed.truncate(&backend, 3, b, 2).unwrap();
+        crashed.flush(&*backend, 3).unwrap();
+        crashed.write(&backend, 3, b, 2, 2, b"w!").unwrap();
+        drop(crashed);
+        assert_eq!(backend.content("/a").unwrap(), b"hello world");
+
+        let recovered = writeback(Some(Journal::open(&dir).unwrap())).recover(&*backend);
+        assert_eq!(recovered.len(), 2);
+        assert_eq!(backend.content("/a").unwrap(), b"hello there");
+        assert_eq!(backend.content("/b").unwrap(), b"new!");
+        assert!(Journal::open(&dir).unwrap().recover(&*backend).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
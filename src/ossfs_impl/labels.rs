@@ -0,0 +1,92 @@
+//! Labels naming the mount, like the pod or the job, added to the operation summaries,
+//! the access traces and the logged errors, set on the builder or by `OSSFS_LABEL_*`.
+
+use std::fmt;
+
+const ENV_PREFIX: &str = "OSSFS_LABEL_";
+
+/// Labels by name, in the order they were set.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Labels(Vec<(String, String)>);
+
+impl Labels {
+    /// The labels set by `OSSFS_LABEL_*` variables.
+    pub fn from_env() -> Labels {
+        let mut labels = Labels::default();
+        let mut vars: Vec<(String, String)> = std::env::vars()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX) && name.len() > ENV_PREFIX.len())
+            .collect();
+        vars.sort();
+        for (name, value) in vars {
+            labels.set(name[ENV_PREFIX.len()..].to_lowercase(), value);
+        }
+        labels
+    }
+
+    /// Sets the label `name`, replacing its value if it is set already.
+    pub fn set<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) {
+        let (name, value) = (name.into(), value.into());
+        match self.0.iter_mut().find(|(set, _)| *set == name) {
+            Some(label) => label.1 = value,
+            None => self.0.push((name, value)),
+        }
+    }
+
+    /// Sets the labels of `other` over these.
+    pub fn extend(&mut self, other: &Labels) {
+        for (name, value) in other.iter() {
+            self.set(name.as_str(), value.as_str());
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(set, _)| set == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(String, String)> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// `name=value` pairs separated by spaces.
+impl fmt::Display for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (name, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Labels;
+
+    #[test]
+    fn test_labels() {
+        std::env::set_var("OSSFS_LABEL_POD", "trainer-0");
+        std::env::set_var("OSSFS_LABEL_DATASET", "imagenet-v2");
+        let mut labels = Labels::from_env();
+        assert_eq!(labels.get("pod"), Some("trainer-0"));
+        let mut job = Labels::default();
+        job.set("job", "resnet");
+        job.set("dataset", "imagenet-v3");
+        labels.extend(&job);
+        assert_eq!(
+            labels.to_string(),
+            "dataset=imagenet-v3 pod=trainer-0 job=resnet"
+        );
+        std::env::remove_var("OSSFS_LABEL_POD");
+        std::env::remove_var("OSSFS_LABEL_DATASET");
+    }
+}
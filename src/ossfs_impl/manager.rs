@@ -1,31 +1,247 @@
 use crate::error::{Error, Result};
+use crate::ossfs_impl::backend::key;
+use crate::ossfs_impl::filesystem::ROOT_INODE;
+use crate::ossfs_impl::handles::HandleTable;
 use crate::ossfs_impl::node::Node;
-use id_tree::{NodeId, Tree};
-use std::collections::HashMap;
-use std::ffi::OsStr;
+use crate::ossfs_impl::watch::{Change, Event};
+use id_tree::InsertBehavior::UnderNode;
+use id_tree::RemoveBehavior::DropChildren;
+use id_tree::{Node as TreeNode, NodeId, Tree};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub(crate) struct InodeManager {
     pub nodes_tree: Tree<Node>,
     pub ino_mapper: HashMap<u64, NodeId>,
-    pub children_name: HashMap<u64, HashMap<std::ffi::OsString, u64>>,
+    // the names are those of the nodes
+    pub children_name: HashMap<u64, HashMap<Arc<OsStr>, u64>>,
     pub counter: crate::counter::Counter,
+    // (parent inode, name) of creates waiting for the backend
+    pub creating: HashSet<(u64, OsString)>,
+    // directories whose complete listing has been fetched from the backend, with
+    // when it was
+    pub listed: HashMap<u64, Instant>,
+    // entries found by the last complete listing of directories, kept through
+    // eviction to choose between listing a directory again and heading a name
+    pub sizes: HashMap<u64, usize>,
+    // entries kept from eviction, with the children of directories
+    pub pinned: HashSet<u64>,
+    // directories being listed in the background, kept from eviction
+    pub listing: HashSet<u64>,
+    // directories whose children were not added in the order of their names
+    pub unsorted: HashSet<u64>,
+    // last readdir cookie handed out in each directory, never handed out again
+    pub cookies: HashMap<u64, u64>,
+    // inodes handed out by earlier runs, only kept when re-exporting over nfs
+    pub handles: Option<HandleTable>,
+    // estimated bytes taken by the entries of the tree, shared with the stats
+    pub memory: Arc<AtomicU64>,
+    // last use of the directories below or at which something was used, only
+    // tracked when the memory is limited
+    pub accessed: Mutex<HashMap<u64, Instant>>,
+    // above it the coldest subtrees are evicted in the background
+    pub soft_limit: Option<u64>,
+    // above it the coldest subtrees are evicted before anything else is added
+    pub hard_limit: Option<u64>,
+    // last change of the entries created or written through this mount
+    pub pending: Mutex<HashMap<u64, Instant>>,
+    // how long after a local change the local state wins over the backend's
+    pub consistency_window: Duration,
+    // lookups of the inodes replied to the kernel and not forgotten by it yet, the
+    // kernel may use those inodes at any time so they are not evicted
+    pub lookups: Mutex<HashMap<u64, u64>>,
+    // open handles of the inodes, not evicted either
+    pub opened: Mutex<HashMap<u64, u64>>,
+    // memory at which the next eviction for the hard limit runs, raised when one
+    // could not get below the limit so it does not run again for every entry
+    next_eviction: u64,
 }
 
 impl InodeManager {
     pub fn new(
         nodes_tree: Tree<Node>,
         ino_mapper: HashMap<u64, NodeId>,
-        children_name: HashMap<u64, HashMap<std::ffi::OsString, u64>>,
+        children_name: HashMap<u64, HashMap<Arc<OsStr>, u64>>,
     ) -> Self {
+        let memory: usize = ino_mapper
+            .values()
+            .map(|index| entry_bytes(nodes_tree.get(index).unwrap().data()))
+            .sum();
         InodeManager {
             nodes_tree,
             ino_mapper,
             children_name,
             counter: crate::counter::Counter::new(1),
+            creating: HashSet::new(),
+            listed: HashMap::new(),
+            sizes: HashMap::new(),
+            pinned: HashSet::new(),
+            listing: HashSet::new(),
+            unsorted: HashSet::new(),
+            cookies: HashMap::new(),
+            handles: None,
+            memory: Arc::new(AtomicU64::new(memory as u64)),
+            accessed: Mutex::new(HashMap::new()),
+            soft_limit: None,
+            hard_limit: None,
+            pending: Mutex::new(HashMap::new()),
+            consistency_window: Duration::from_secs(0),
+            lookups: Mutex::new(HashMap::new()),
+            opened: Mutex::new(HashMap::new()),
+            next_eviction: 0,
         }
     }
 
+    /// Estimated bytes taken by the entries of the tree.
+    pub fn memory(&self) -> u64 {
+        self.memory.load(Ordering::Relaxed)
+    }
+
+    /// Records a use of the directory `ino`, which keeps it and its ancestors from
+    /// being evicted before colder subtrees.
+    pub fn touch(&self, ino: u64) {
+        if self.soft_limit.is_none() && self.hard_limit.is_none() {
+            return;
+        }
+        let now = Instant::now();
+        let mut accessed = self.accessed.lock().unwrap();
+        let mut ino = ino;
+        while ino != ROOT_INODE {
+            accessed.insert(ino, now);
+            ino = match self.ino_mapper.get(&ino) {
+                Some(index) => self.nodes_tree.get(index).unwrap().data().parent(),
+                None => return,
+            };
+        }
+    }
+
+    /// Whether the complete listing of the directory `ino` was fetched within
+    /// `window`, so a name missing from it can be taken as missing in the backend.
+    pub fn listed_within(&self, ino: u64, window: Duration) -> bool {
+        match self.listed.get(&ino) {
+            Some(listed) => listed.elapsed() < window,
+            None => false,
+        }
+    }
+
+    /// Records a change of `ino` made through this mount. For `consistency_window`
+    /// the backend may not show it yet, meanwhile refreshes from the backend leave
+    /// the entry alone and it is not evicted.
+    pub fn mark_pending(&self, ino: u64) {
+        self.pending.lock().unwrap().insert(ino, Instant::now());
+    }
+
+    /// Whether `ino` changed locally within the consistency window.
+    pub fn is_pending(&self, ino: u64) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.get(&ino) {
+            Some(changed) if changed.elapsed() < self.consistency_window => true,
+            Some(_) => {
+                pending.remove(&ino);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Counts a lookup of `ino` replied to the kernel, it is not evicted until the
+    /// kernel forgets it.
+    pub fn looked_up(&self, ino: u64) {
+        *self.lookups.lock().unwrap().entry(ino).or_insert(0) += 1;
+    }
+
+    /// Drops `nlookup` lookups of `ino` the kernel forgot.
+    pub fn forget(&self, ino: u64, nlookup: u64) {
+        release(&mut self.lookups.lock().unwrap(), ino, nlookup);
+    }
+
+    /// Counts a handle opened on `ino`, it is not evicted until it is closed.
+    pub fn open(&self, ino: u64) {
+        *self.opened.lock().unwrap().entry(ino).or_insert(0) += 1;
+    }
+
+    pub fn close(&self, ino: u64) {
+        release(&mut self.opened.lock().unwrap(), ino, 1);
+    }
+
+    /// Drops the children of the least recently used directories until the entries
+    /// take at most `target` bytes. `keep`, the directories being listed, the
+    /// entries changed locally, pinned, held by the kernel or open and their
+    /// ancestors are left alone.
+    /// Evicted entries are fetched again when they are looked up, with the same
+    /// inodes since those derive from the paths. Returns the number of directories
+    /// whose children were evicted.
+    pub fn evict(&mut self, target: u64, keep: u64) -> usize {
+        if self.memory() <= target {
+            return 0;
+        }
+        let _start = self.counter.start("im::evict");
+        let pending: Vec<u64> = {
+            let window = self.consistency_window;
+            let mut pending = self.pending.lock().unwrap();
+            pending.retain(|_, changed| changed.elapsed() < window);
+            pending.keys().cloned().collect()
+        };
+        let held: Vec<u64> = {
+            let lookups = self.lookups.lock().unwrap();
+            let opened = self.opened.lock().unwrap();
+            lookups.keys().chain(opened.keys()).cloned().collect()
+        };
+        let mut kept = HashSet::new();
+        for keep in self
+            .listing
+            .iter()
+            .chain(self.pinned.iter())
+            .chain(pending.iter())
+            .chain(held.iter())
+            .chain(std::iter::once(&keep))
+        {
+            let mut ino = *keep;
+            while kept.insert(ino) && ino != ROOT_INODE {
+                ino = match self.ino_mapper.get(&ino) {
+                    Some(index) => self.nodes_tree.get(index).unwrap().data().parent(),
+                    None => break,
+                };
+            }
+        }
+        let mut candidates: Vec<(Option<Instant>, u64)> = {
+            let accessed = self.accessed.lock().unwrap();
+            self.children_name
+                .iter()
+                .filter(|(ino, children)| !children.is_empty() && !kept.contains(*ino))
+                .map(|(ino, _)| (accessed.get(ino).cloned(), *ino))
+                .collect()
+        };
+        // never used first
+        candidates.sort();
+        let before = self.memory();
+        let mut evicted = 0;
+        for (_, ino) in candidates {
+            if self.memory() <= target {
+                break;
+            }
+            // gone with an ancestor evicted before
+            let index = match self.ino_mapper.get(&ino) {
+                Some(index) => index.clone(),
+                None => continue,
+            };
+            self.forget_children(&index);
+            evicted += 1;
+        }
+        log::info!(
+            "evicted the children of {} directories, metadata from {} to {} bytes",
+            evicted,
+            before,
+            self.memory()
+        );
+        evicted
+    }
+
     pub fn get_node_by_inode(&self, ino: u64) -> Result<&Node> {
         let _start = self.counter.start("im::get_node_by_inode".to_owned());
         let node_index: &NodeId = self.ino_mapper.get(&ino).ok_or_else(|| {
@@ -36,55 +252,731 @@ impl InodeManager {
         Ok(node.data())
     }
 
-    pub fn get_children_by_index(
+    /// Returns at most `limit` children whose cookie comes after `cookie`, a `limit`
+    /// of 0 means all. Children are kept in the order of their cookies and a cookie
+    /// is never handed out twice in a directory, so entries added or removed
+    /// between two calls do not move the others.
+    pub fn get_children_after(
         &self,
         index: &NodeId,
-        offset: usize,
-        limit: i64,
-        check_empty: bool,
-    ) -> Result<Option<Vec<Node>>> {
-        // log::trace!("{:#?}", self.nodes_tree);
-        let _start = self.counter.start("im::get_children_by_index".to_owned());
-        match self.nodes_tree.children(index) {
-            Ok(children) => {
-                let mut children = children.peekable();
-                if check_empty && children.peek().is_none() {
-                    return Ok(None);
-                }
-                let children = children.skip(offset);
-                let mut result = vec![];
-                for (i, child) in children.enumerate() {
-                    if limit > 0 && i == limit as usize {
-                        break;
-                    }
-                    let node = child.data().clone();
-                    result.push(node);
-                }
-
-                return Ok(Some(result));
-            }
+        cookie: u64,
+        limit: usize,
+    ) -> Result<Vec<Node>> {
+        let _start = self.counter.start("im::get_children_after".to_owned());
+        let children = match self.nodes_tree.get(index) {
+            Ok(node) => node.children(),
             Err(err) => {
                 log::error!("get children of index: {:?}, error: {}", index, err);
-                Err(Error::Other(format!("node id error. {}", err)))
+                return Err(Error::Other(format!("node id error. {}", err)));
             }
+        };
+        let start = match children.binary_search_by_key(&cookie, |child| {
+            self.nodes_tree.get(child).unwrap().data().cookie()
+        }) {
+            Ok(position) => position + 1,
+            Err(position) => position,
+        };
+        let end = if limit == 0 {
+            children.len()
+        } else {
+            std::cmp::min(children.len(), start + limit)
+        };
+        Ok(children[start..end]
+            .iter()
+            .map(|child| self.nodes_tree.get(child).unwrap().data().clone())
+            .collect())
+    }
+
+    /// Cookie of the last child of the directory at `index`, 0 without children.
+    pub fn last_cookie(&self, index: &NodeId) -> u64 {
+        self.nodes_tree
+            .get(index)
+            .ok()
+            .and_then(|node| node.children().last())
+            .map(|child| self.nodes_tree.get(child).unwrap().data().cookie())
+            .unwrap_or(0)
+    }
+
+    // the next readdir cookie of the directory `ino`
+    fn next_cookie(&mut self, ino: u64) -> u64 {
+        let cookie = self.cookies.entry(ino).or_insert(0);
+        *cookie += 1;
+        *cookie
+    }
+
+    /// Inode numbers are a hash of the path, so an entry keeps its number across
+    /// restarts. A collision moves to the next free number, which is only stable
+    /// if a handle table records it.
+    pub fn allocate_inode(&mut self, path: &Path) -> u64 {
+        let _start = self.counter.start("im::allocate_inode".to_owned());
+        if let Some(inode) = self.handles.as_ref().and_then(|h| h.inode_of(path)) {
+            return inode;
+        }
+        let mut inode = path_hash(path);
+        while inode == 0
+            || inode == ROOT_INODE
+            || self.ino_mapper.contains_key(&inode)
+            || self
+                .handles
+                .as_ref()
+                .map(|h| h.path_of(inode).is_some())
+                .unwrap_or(false)
+        {
+            log::warn!("inode {} of {:?} is taken, probing", inode, path);
+            inode = inode.wrapping_add(1);
         }
+        if let Some(handles) = self.handles.as_mut() {
+            if let Err(e) = handles.record(inode, 1, path) {
+                log::error!("record inode {} of {:?}. error: {}", inode, path, e);
+            }
+        }
+        inode
+    }
+
+    pub fn generation(&self, ino: u64) -> u64 {
+        self.handles
+            .as_ref()
+            .and_then(|h| h.generation(ino))
+            .unwrap_or(1)
+    }
+
+    /// Path of an inode unknown to the tree, as recorded by an earlier run.
+    pub fn recorded_path(&self, ino: u64) -> Option<PathBuf> {
+        self.handles
+            .as_ref()
+            .and_then(|h| h.path_of(ino))
+            .map(|path| path.to_path_buf())
     }
 
-    pub fn next_inode(&self) -> u64 {
-        let _start = self.counter.start("im::next_inode".to_owned());
-        self.ino_mapper.len() as u64 + 1
+    pub fn retire(&mut self, ino: u64) {
+        if let Some(handles) = self.handles.as_mut() {
+            if let Err(e) = handles.retire(ino) {
+                log::error!("retire inode {}. error: {}", ino, e);
+            }
+        }
     }
 
     pub fn get_child_by_name<'a>(&'a self, ino: u64, name: &OsStr) -> Result<Option<&'a Node>> {
         let _start = self.counter.start("im::get_child_by_name");
-        let children_set = self
+        let child_inode = match self
             .children_name
             .get(&ino)
-            .expect(&format!("get ino: {}", ino));
-        if let Some(child_inode) = children_set.get(name) {
-            let child_node = self.get_node_by_inode(*child_inode)?;
-            return Ok(Some(child_node));
+            .and_then(|children| children.get(name))
+        {
+            Some(child_inode) => *child_inode,
+            None => return Ok(None),
+        };
+        Ok(Some(self.get_node_by_inode(child_inode)?))
+    }
+
+    /// Inserts `child_node` under the parent. If a child with the same name is already
+    /// known that one is returned instead, so racing lookups, listings and creates
+    /// never end up with two inodes for a single entry.
+    pub fn add_child(
+        &mut self,
+        parent_index: &NodeId,
+        parent_inode: u64,
+        child_node: &Node,
+    ) -> Node {
+        let _start = self.counter.start("im::add_child");
+        let name = child_node.name();
+        let existing = self
+            .children_name
+            .get(&parent_inode)
+            .and_then(|children| children.get(&name))
+            .copied();
+        if let Some(existing) = existing {
+            if let Ok(node) = self.get_node_by_inode(existing) {
+                return node.clone();
+            }
+        }
+        let path = child_node.path();
+        let next_inode = self.allocate_inode(&path);
+        child_node.set_inode(next_inode, parent_inode);
+        if let Ok(parent) = self.nodes_tree.get(parent_index) {
+            child_node.share_dir(parent.data());
+        }
+        let child_index = match self
+            .nodes_tree
+            .insert(TreeNode::new(child_node.clone()), UnderNode(parent_index))
+        {
+            Ok(child_index) => child_index,
+            Err(e) => {
+                // the parent was removed or evicted meanwhile
+                log::warn!("add {:?} to the tree. error: {}", path, e);
+                return child_node.clone();
+            }
+        };
+        self.ino_mapper.insert(next_inode, child_index);
+        let cookie = self.next_cookie(parent_inode);
+        child_node.set_cookie(cookie);
+        if self.after_last_sibling(parent_index, &name) {
+            self.unsorted.insert(parent_inode);
+        }
+        self.children_name
+            .entry(parent_inode)
+            .or_insert_with(HashMap::new)
+            .insert(name, next_inode);
+        self.memory
+            .fetch_add(entry_bytes(child_node) as u64, Ordering::Relaxed);
+        if let Some(hard_limit) = self.hard_limit {
+            if self.memory() > std::cmp::max(hard_limit, self.next_eviction) {
+                self.evict(self.soft_limit.unwrap_or(hard_limit), parent_inode);
+                self.next_eviction = self.memory() + hard_limit / 16;
+            }
+        }
+        child_node.clone()
+    }
+
+    // whether the sibling added before the last child of `parent` sorts after `name`
+    fn after_last_sibling(&self, parent: &NodeId, name: &OsStr) -> bool {
+        let children = match self.nodes_tree.get(parent) {
+            Ok(parent) => parent.children(),
+            Err(_) => return false,
+        };
+        if children.len() < 2 {
+            return false;
+        }
+        let previous = self.nodes_tree.get(&children[children.len() - 2]).unwrap();
+        &*previous.data().name() > name
+    }
+
+    /// Orders the children of the directory `ino` by name if some were added out of
+    /// order, so listings keep the same order whatever the backend returned. They
+    /// get new cookies in their new order, a listing resumed across the sort may
+    /// see entries again but skips none.
+    pub fn sort_children(&mut self, ino: u64) {
+        if !self.unsorted.remove(&ino) {
+            return;
+        }
+        let index = match self.ino_mapper.get(&ino) {
+            Some(index) => index.clone(),
+            None => return,
+        };
+        let _start = self.counter.start("im::sort_children");
+        if let Err(e) = self
+            .nodes_tree
+            .sort_children_by(&index, |a, b| a.data().name().cmp(&b.data().name()))
+        {
+            log::error!("sort the children of {}. error: {}", ino, e);
+            return;
+        }
+        let children = self.nodes_tree.get(&index).unwrap().children().clone();
+        for child in children.iter() {
+            let cookie = self.next_cookie(ino);
+            self.nodes_tree
+                .get(child)
+                .unwrap()
+                .data()
+                .set_cookie(cookie);
+        }
+    }
+
+    /// Reserves `name` under `parent` for a create in flight. Fails with EEXIST if
+    /// the name is already known or another create of it has not finished yet.
+    pub fn reserve_child(&mut self, parent: u64, name: &OsStr) -> Result<()> {
+        if self.get_child_by_name(parent, name)?.is_some()
+            || !self.creating.insert((parent, name.to_owned()))
+        {
+            return Err(Error::Fuse(libc::EEXIST));
+        }
+        Ok(())
+    }
+
+    pub fn release_child(&mut self, parent: u64, name: &OsStr) {
+        self.creating.remove(&(parent, name.to_owned()));
+    }
+
+    /// Inode of the node at `path` if it is in the tree, `root` being the path of
+    /// the root node.
+    pub fn inode_of_path(&self, root: &Path, path: &Path) -> Option<u64> {
+        let mut inode = ROOT_INODE;
+        for component in path.strip_prefix(root).ok()?.components() {
+            inode = *self.children_name.get(&inode)?.get(component.as_os_str())?;
+        }
+        Some(inode)
+    }
+
+    /// Applies a change made to the backend behind our back.
+    pub fn apply(&mut self, root: &Path, change: &Change) {
+        let path = change.event.path();
+        if let Some(inode) = self.inode_of_path(root, path) {
+            // the listing may not show a local change yet
+            if self.is_pending(inode) {
+                log::debug!("keeping the local state of {:?}, changed locally", path);
+                return;
+            }
         }
-        Ok(None)
+        match change.event {
+            Event::Created(_) => {
+                // the next readdir lists the directory again
+                if let Some(parent) = path.parent().and_then(|p| self.inode_of_path(root, p)) {
+                    self.listed.remove(&parent);
+                }
+            }
+            Event::Modified(_) => {
+                let node = self
+                    .inode_of_path(root, path)
+                    .and_then(|inode| self.get_node_by_inode(inode).ok());
+                if let (Some(node), Some(attr)) = (node, change.attr) {
+                    node.set_attr(attr);
+                    // the tag of the old content
+                    node.set_etag(None);
+                }
+            }
+            Event::Removed(_) => self.remove_path(root, path),
+        }
+    }
+
+    /// Drops everything below the directory at `index` from the tree, it is listed
+    /// again when it is read. Returns the inodes dropped.
+    pub fn forget_children(&mut self, index: &NodeId) -> Vec<u64> {
+        let ino = self.nodes_tree.get(index).unwrap().data().inode();
+        let children: Vec<NodeId> = self.nodes_tree.get(index).unwrap().children().clone();
+        let mut forgotten = vec![];
+        for child in children {
+            if let Ok(nodes) = self.nodes_tree.traverse_pre_order(&child) {
+                forgotten.extend(nodes.map(|node| node.data().inode()));
+            }
+            self.remove_subtree(child);
+        }
+        if let Some(names) = self.children_name.get_mut(&ino) {
+            names.clear();
+        }
+        self.listed.remove(&ino);
+        forgotten
+    }
+
+    /// Drops the node at `path` and everything below it from the tree.
+    pub fn remove_path(&mut self, root: &Path, path: &Path) {
+        let inode = match self.inode_of_path(root, path) {
+            Some(inode) if inode != ROOT_INODE => inode,
+            _ => return,
+        };
+        let index = match self.ino_mapper.get(&inode) {
+            Some(index) => index.clone(),
+            None => return,
+        };
+        let parent = self.nodes_tree.get(&index).unwrap().data().parent();
+        self.sizes.remove(&inode);
+        self.remove_subtree(index);
+        if let (Some(children), Some(name)) =
+            (self.children_name.get_mut(&parent), path.file_name())
+        {
+            children.remove(name);
+        }
+    }
+
+    /// Drops the node at `index` and everything below it, except the name of the
+    /// node in its parent.
+    fn remove_subtree(&mut self, index: NodeId) {
+        let removed: Vec<(u64, usize)> = match self.nodes_tree.traverse_pre_order(&index) {
+            Ok(nodes) => nodes
+                .map(|node| (node.data().inode(), entry_bytes(node.data())))
+                .collect(),
+            Err(_) => return,
+        };
+        {
+            let mut accessed = self.accessed.lock().unwrap();
+            for (inode, bytes) in removed.iter() {
+                self.ino_mapper.remove(inode);
+                self.children_name.remove(inode);
+                self.unsorted.remove(inode);
+                self.cookies.remove(inode);
+                self.listed.remove(inode);
+                accessed.remove(inode);
+                self.memory.fetch_sub(*bytes as u64, Ordering::Relaxed);
+            }
+        }
+        if let Err(e) = self.nodes_tree.remove_node(index, DropChildren) {
+            log::error!("remove subtree from the tree. error: {}", e);
+        }
+    }
+}
+
+// takes `count` off the count of `ino`, dropping it at zero
+fn release(counts: &mut HashMap<u64, u64>, ino: u64, count: u64) {
+    let left = match counts.get_mut(&ino) {
+        Some(left) => {
+            *left = left.saturating_sub(count);
+            *left
+        }
+        None => return,
+    };
+    if left == 0 {
+        counts.remove(&ino);
+    }
+}
+
+// rough number of bytes of an entry: the node, its slot in the tree and in the
+// children of its parent, and its entries in the inode and name maps
+fn entry_bytes(node: &Node) -> usize {
+    node.memory_bytes()
+        + std::mem::size_of::<TreeNode<Node>>()
+        + std::mem::size_of::<NodeId>()
+        + std::mem::size_of::<(u64, NodeId)>()
+        + std::mem::size_of::<(Arc<OsStr>, u64)>()
+}
+
+// FNV-1a over the object key
+fn path_hash(path: &Path) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key::path_to_key(path).bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use super::InodeManager;
+    use crate::ossfs_impl::attr::{FileAttr, FileType};
+    use crate::ossfs_impl::filesystem::ROOT_INODE;
+    use crate::ossfs_impl::node::Node;
+    use id_tree::InsertBehavior::AsRoot;
+    use id_tree::{Node as TreeNode, NodeId, TreeBuilder};
+    use quickcheck::{Arbitrary, Gen, QuickCheck};
+    use std::collections::{HashMap, HashSet};
+    use std::ffi::{OsStr, OsString};
+    use std::path::{Path, PathBuf};
+    use std::time::{Instant, UNIX_EPOCH};
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert {
+            parent: usize,
+            name: u8,
+            dir: bool,
+        },
+        Lookup {
+            parent: usize,
+            name: u8,
+        },
+        Readdir {
+            parent: usize,
+            cookie: u64,
+            limit: usize,
+        },
+        // removes an entry, between the readdirs of its directory
+        Remove {
+            parent: usize,
+            name: u8,
+        },
+        Sort {
+            parent: usize,
+        },
+        Reserve {
+            parent: usize,
+            name: u8,
+        },
+        Release {
+            parent: usize,
+            name: u8,
+        },
+    }
+
+    impl Arbitrary for Op {
+        fn arbitrary<G: Gen>(g: &mut G) -> Op {
+            let parent = usize::arbitrary(g);
+            // few distinct names, so that operations collide often
+            let name = u8::arbitrary(g) % 8;
+            match u8::arbitrary(g) % 7 {
+                0 => Op::Insert {
+                    parent,
+                    name,
+                    dir: bool::arbitrary(g),
+                },
+                1 => Op::Lookup { parent, name },
+                2 => Op::Readdir {
+                    parent,
+                    cookie: u64::arbitrary(g) % 20,
+                    limit: usize::arbitrary(g) % 4,
+                },
+                3 => Op::Reserve { parent, name },
+                4 => Op::Release { parent, name },
+                5 => Op::Remove { parent, name },
+                _ => Op::Sort { parent },
+            }
+        }
+    }
+
+    fn attr(kind: FileType) -> FileAttr {
+        FileAttr {
+            ino: 0,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind,
+            perm: 0o755,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            flags: 0,
+        }
+    }
+
+    fn new_manager() -> InodeManager {
+        let root = Node::new(
+            ROOT_INODE,
+            ROOT_INODE,
+            PathBuf::from("/"),
+            attr(FileType::Directory),
+        );
+        let mut nodes_tree = TreeBuilder::new().build();
+        let root_index = nodes_tree.insert(TreeNode::new(root), AsRoot).unwrap();
+        let mut ino_mapper = HashMap::new();
+        ino_mapper.insert(ROOT_INODE, root_index);
+        let mut children_name = HashMap::new();
+        children_name.insert(ROOT_INODE, HashMap::new());
+        InodeManager::new(nodes_tree, ino_mapper, children_name)
+    }
+
+    // the state the manager is expected to be in
+    #[derive(Default)]
+    struct Model {
+        directories: Vec<u64>,
+        // (cookie, inode, name) of the children in listing order
+        children: HashMap<u64, Vec<(u64, u64, OsString)>>,
+        names: HashMap<(u64, OsString), u64>,
+        creating: HashSet<(u64, OsString)>,
+        cookies: HashMap<u64, u64>,
+        unsorted: HashSet<u64>,
+    }
+
+    impl Model {
+        fn next_cookie(&mut self, parent: u64) -> u64 {
+            let cookie = self.cookies.entry(parent).or_insert(0);
+            *cookie += 1;
+            *cookie
+        }
+
+        // drops `ino` and everything below it, but its name in its parent
+        fn remove(&mut self, ino: u64) {
+            for (_, child, name) in self.children.remove(&ino).unwrap_or_default() {
+                self.names.remove(&(ino, name));
+                self.remove(child);
+            }
+            self.directories.retain(|dir| *dir != ino);
+            self.cookies.remove(&ino);
+            self.unsorted.remove(&ino);
+        }
+    }
+
+    fn index_of(manager: &InodeManager, ino: u64) -> NodeId {
+        manager.ino_mapper.get(&ino).unwrap().clone()
+    }
+
+    fn check_invariants(manager: &InodeManager, model: &Model) {
+        // every inode maps to a distinct tree node carrying that inode
+        let root_index = manager.nodes_tree.root_node_id().unwrap();
+        let nodes = manager
+            .nodes_tree
+            .traverse_pre_order(root_index)
+            .unwrap()
+            .count();
+        assert_eq!(nodes, manager.ino_mapper.len());
+        for (ino, index) in manager.ino_mapper.iter() {
+            let node = manager.nodes_tree.get(index).unwrap();
+            assert_eq!(node.data().inode(), *ino);
+            assert_eq!(node.data().attr().ino, *ino);
+            if *ino != ROOT_INODE {
+                let parent = manager.nodes_tree.get(node.parent().unwrap()).unwrap();
+                assert_eq!(node.data().parent(), parent.data().inode());
+            }
+        }
+        // names point at children of the right parent
+        for (parent, names) in manager.children_name.iter() {
+            for (name, child) in names.iter() {
+                let node = manager.get_node_by_inode(*child).unwrap();
+                assert_eq!(node.parent(), *parent);
+                assert_eq!(node.name(), *name);
+                assert_eq!(
+                    model.names.get(&(*parent, name.to_os_string())),
+                    Some(child)
+                );
+            }
+        }
+        assert_eq!(manager.creating, model.creating);
+    }
+
+    fn run(ops: Vec<Op>) -> bool {
+        let mut manager = new_manager();
+        let mut model = Model::default();
+        model.directories.push(ROOT_INODE);
+        for op in ops {
+            match op {
+                Op::Insert { parent, name, dir } => {
+                    let parent = model.directories[parent % model.directories.len()];
+                    let name = OsString::from(format!("n{}", name));
+                    let parent_node = manager.get_node_by_inode(parent).unwrap().clone();
+                    let kind = if dir {
+                        FileType::Directory
+                    } else {
+                        FileType::RegularFile
+                    };
+                    let child = Node::new(0, 0, parent_node.path().join(&name), attr(kind));
+                    let index = index_of(&manager, parent);
+                    let added = manager.add_child(&index, parent, &child);
+                    match model.names.get(&(parent, name.clone())) {
+                        Some(existing) => assert_eq!(added.inode(), *existing),
+                        None => {
+                            model.names.insert((parent, name.clone()), added.inode());
+                            let cookie = model.next_cookie(parent);
+                            let children = model.children.entry(parent).or_insert_with(Vec::new);
+                            if children.last().map(|last| last.2 > name).unwrap_or(false) {
+                                model.unsorted.insert(parent);
+                            }
+                            children.push((cookie, added.inode(), name));
+                            if dir {
+                                model.directories.push(added.inode());
+                            }
+                        }
+                    }
+                }
+                Op::Lookup { parent, name } => {
+                    let parent = model.directories[parent % model.directories.len()];
+                    let name = OsString::from(format!("n{}", name));
+                    let found = manager
+                        .get_child_by_name(parent, &name)
+                        .unwrap()
+                        .map(|node| node.inode());
+                    assert_eq!(found, model.names.get(&(parent, name)).cloned());
+                }
+                Op::Readdir {
+                    parent,
+                    cookie,
+                    limit,
+                } => {
+                    let parent = model.directories[parent % model.directories.len()];
+                    let index = index_of(&manager, parent);
+                    let page: Vec<(u64, u64)> = manager
+                        .get_children_after(&index, cookie, limit)
+                        .unwrap()
+                        .iter()
+                        .map(|node| (node.cookie(), node.inode()))
+                        .collect();
+                    let empty = vec![];
+                    let mut expected: Vec<(u64, u64)> = model
+                        .children
+                        .get(&parent)
+                        .unwrap_or(&empty)
+                        .iter()
+                        .filter(|child| child.0 > cookie)
+                        .map(|child| (child.0, child.1))
+                        .collect();
+                    if limit > 0 {
+                        expected.truncate(limit);
+                    }
+                    assert_eq!(page, expected);
+                }
+                Op::Remove { parent, name } => {
+                    let parent = model.directories[parent % model.directories.len()];
+                    let name = OsString::from(format!("n{}", name));
+                    let path = manager
+                        .get_node_by_inode(parent)
+                        .unwrap()
+                        .path()
+                        .join(&name);
+                    manager.remove_path(Path::new("/"), &path);
+                    if let Some(ino) = model.names.remove(&(parent, name)) {
+                        model.remove(ino);
+                        if let Some(children) = model.children.get_mut(&parent) {
+                            children.retain(|child| child.1 != ino);
+                        }
+                    }
+                }
+                Op::Sort { parent } => {
+                    let parent = model.directories[parent % model.directories.len()];
+                    manager.sort_children(parent);
+                    if model.unsorted.remove(&parent) {
+                        let mut children = model.children.remove(&parent).unwrap_or_default();
+                        children.sort_by(|a, b| a.2.cmp(&b.2));
+                        for child in children.iter_mut() {
+                            child.0 = model.next_cookie(parent);
+                        }
+                        model.children.insert(parent, children);
+                    }
+                }
+                Op::Reserve { parent, name } => {
+                    let parent = model.directories[parent % model.directories.len()];
+                    let name = OsString::from(format!("n{}", name));
+                    let expected = !model.names.contains_key(&(parent, name.clone()))
+                        && !model.creating.contains(&(parent, name.clone()));
+                    assert_eq!(manager.reserve_child(parent, &name).is_ok(), expected);
+                    if expected {
+                        model.creating.insert((parent, name));
+                    }
+                }
+                Op::Release { parent, name } => {
+                    let parent = model.directories[parent % model.directories.len()];
+                    let name = OsString::from(format!("n{}", name));
+                    manager.release_child(parent, &name);
+                    model.creating.remove(&(parent, name));
+                }
+            }
+            check_invariants(&manager, &model);
+        }
+        true
+    }
+
+    #[test]
+    fn test_evict() {
+        let mut manager = new_manager();
+        // tracks the uses of directories
+        manager.soft_limit = Some(0);
+        let root = index_of(&manager, ROOT_INODE);
+        let dirs: Vec<Node> = ["cold", "hot"]
+            .iter()
+            .map(|name| {
+                let dir = Node::new(
+                    0,
+                    0,
+                    PathBuf::from("/").join(name),
+                    attr(FileType::Directory),
+                );
+                manager.add_child(&root, ROOT_INODE, &dir)
+            })
+            .collect();
+        let without_files = manager.memory();
+        for dir in dirs.iter() {
+            let index = index_of(&manager, dir.inode());
+            for i in 0..10 {
+                let file = Node::new(
+                    0,
+                    0,
+                    dir.path().join(format!("f{}", i)),
+                    attr(FileType::RegularFile),
+                );
+                manager.add_child(&index, dir.inode(), &file);
+            }
+            manager.listed.insert(dir.inode(), Instant::now());
+        }
+        let (cold, hot) = (dirs[0].inode(), dirs[1].inode());
+        manager.touch(hot);
+
+        let full = manager.memory();
+        assert!(full > without_files);
+        assert_eq!(manager.evict(full - 1, ROOT_INODE), 1);
+        assert!(!manager.listed.contains_key(&cold));
+        assert!(manager.listed.contains_key(&hot));
+        let f0 = OsStr::new("f0");
+        assert!(manager.get_child_by_name(cold, f0).unwrap().is_none());
+        assert!(manager.get_child_by_name(hot, f0).unwrap().is_some());
+        assert_eq!(manager.evict(0, hot), 0);
+        assert_eq!(manager.evict(0, ROOT_INODE), 1);
+        assert_eq!(manager.memory(), without_files);
+        assert_eq!(manager.ino_mapper.len(), 3);
+    }
+
+    #[test]
+    fn test_random_operations() {
+        QuickCheck::new()
+            .tests(500)
+            .quickcheck(run as fn(Vec<Op>) -> bool);
     }
 }
@@ -0,0 +1,308 @@
+//! Manifests of a tree, listing every file with its size, modification time and md5,
+//! generated by walking a backend directly instead of a mount.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::backend::{key, Backend};
+use crate::ossfs_impl::transfer;
+use rayon::prelude::*;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const READ_CHUNK: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Key of the file, relative to the root of the backend.
+    pub key: String,
+    pub size: u64,
+    pub mtime: SystemTime,
+    /// Hex encoded md5 of the content, if checksums were computed.
+    pub md5: Option<String>,
+}
+
+impl std::fmt::Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.md5.as_ref().map(|md5| md5.as_str()).unwrap_or("-"),
+            self.size,
+            self.mtime
+                .duration_since(UNIX_EPOCH)
+                .map(|mtime| mtime.as_secs())
+                .unwrap_or(0),
+            key::escape_url_path(&self.key)
+        )
+    }
+}
+
+/// Lists the files under `prefix`, relative to the root of `backend`, computing
+/// their md5 with `concurrency` threads when `checksum` is set.
+pub fn generate<B, P>(
+    backend: &B,
+    prefix: P,
+    checksum: bool,
+    concurrency: usize,
+) -> Result<Vec<Entry>>
+where
+    B: Backend + Sync,
+    P: AsRef<Path>,
+{
+    let root = backend.root().path();
+    let start = if prefix.as_ref().as_os_str().is_empty() {
+        root.clone()
+    } else {
+        root.join(prefix.as_ref())
+    };
+    let (_, files) = transfer::walk(backend, &start)?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(std::cmp::max(concurrency, 1))
+        .build()
+        .map_err(|e| Error::Other(format!("failed to create manifest threads. {}", e)))?;
+    let mut entries = pool.install(|| {
+        files
+            .par_iter()
+            .map(|(path, _)| {
+                let attr = backend.get_node(path)?.attr();
+                let md5 = if checksum {
+                    Some(format!("{:x}", md5sum(backend, path)?))
+                } else {
+                    None
+                };
+                Ok(Entry {
+                    key: key::path_to_key(path.strip_prefix(&root).unwrap_or(path)),
+                    size: attr.size,
+                    mtime: attr.mtime,
+                    md5,
+                })
+            })
+            .collect::<Result<Vec<Entry>>>()
+    })?;
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(entries)
+}
+
+/// Writes `entries` in the manifest format, a line per file sorted by key:
+/// `<md5 or -> <size> <mtime in seconds since the epoch> <escaped key>`.
+pub fn write<W: Write>(entries: &[Entry], mut writer: W) -> Result<()> {
+    for entry in entries {
+        writeln!(writer, "{}", entry)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes `entries` as the CSV file of an S3 Inventory report of `bucket`.
+pub fn write_inventory<W: Write>(entries: &[Entry], bucket: &str, mut writer: W) -> Result<()> {
+    for entry in entries {
+        let modified = chrono::DateTime::<chrono::Utc>::from(entry.mtime);
+        writeln!(
+            writer,
+            "{},{},\"{}\",\"{}\",{}",
+            csv_quote(bucket),
+            csv_quote(&key::escape_url_path(&entry.key)),
+            entry.size,
+            modified.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            csv_quote(entry.md5.as_ref().map(|md5| md5.as_str()).unwrap_or(""))
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn csv_quote(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+/// Reads the entries of a manifest written by `write` or of an S3 Inventory CSV
+/// file, failing at the first invalid line.
+pub fn read<R: BufRead>(reader: R) -> Result<Vec<Entry>> {
+    let mut entries = vec![];
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = if line.starts_with('"') {
+            parse_inventory(&line)
+        } else {
+            parse_line(&line)
+        };
+        match entry {
+            Some(entry) => entries.push(entry),
+            None => {
+                return Err(Error::Other(format!(
+                    "invalid line {} in manifest: {}",
+                    number + 1,
+                    line
+                )))
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Reads the manifest at `path`.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Entry>> {
+    let file = std::fs::File::open(path.as_ref()).map_err(|e| {
+        Error::Other(format!(
+            "failed to open manifest {:?}. {}",
+            path.as_ref(),
+            e
+        ))
+    })?;
+    read(BufReader::new(file))
+}
+
+fn parse_line(line: &str) -> Option<Entry> {
+    let mut fields = line.splitn(4, ' ');
+    let md5 = fields.next()?;
+    let size = fields.next()?.parse().ok()?;
+    let mtime = fields.next()?.parse().ok()?;
+    let key = key::unescape_url_path(fields.next()?).ok()?;
+    Some(Entry {
+        key,
+        size,
+        mtime: UNIX_EPOCH + Duration::from_secs(mtime),
+        md5: if md5 == "-" {
+            None
+        } else {
+            Some(md5.to_owned())
+        },
+    })
+}
+
+// `"bucket","key","size","last modified date"[,"etag",...]`, keys are url encoded
+// with `+` for spaces
+fn parse_inventory(line: &str) -> Option<Entry> {
+    let fields = csv_fields(line);
+    if fields.len() < 4 {
+        return None;
+    }
+    let key = key::unescape_url_path(&fields[1].replace('+', "%20")).ok()?;
+    let modified = chrono::DateTime::parse_from_rfc3339(&fields[3]).ok()?;
+    if modified.timestamp() < 0 {
+        return None;
+    }
+    // the entity tag of objects uploaded at once is their md5
+    let md5 = fields
+        .get(4)
+        .filter(|etag| etag.len() == 32 && etag.chars().all(|c| c.is_ascii_hexdigit()))
+        .cloned();
+    Some(Entry {
+        key,
+        size: fields[2].parse().ok()?,
+        mtime: UNIX_EPOCH
+            + Duration::new(
+                modified.timestamp() as u64,
+                modified.timestamp_subsec_nanos(),
+            ),
+        md5,
+    })
+}
+
+// fields of a line of CSV, quoted or not
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::replace(&mut field, String::new())),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn md5sum<B: Backend>(backend: &B, path: &Path) -> Result<md5::Digest> {
+    let mut context = md5::Context::new();
+    let mut offset = 0;
+    loop {
+        let chunk = backend.read(path, offset, READ_CHUNK)?;
+        if chunk.is_empty() {
+            return Ok(context.compute());
+        }
+        offset += chunk.len() as u64;
+        context.consume(&chunk);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{generate, read, write, write_inventory};
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+    use std::ffi::OsStr;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_generate() {
+        let backend = MockBackend::new();
+        backend.add_file("/b", b"");
+        backend.add_file("/a dir/c", b"hello");
+        backend.add_dir("/empty");
+        let entries = generate(&backend, "", true, 2).unwrap();
+        let mut output = vec![];
+        write(&entries, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<Vec<&str>> = output
+            .lines()
+            .map(|line| line.split(' ').collect())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0][0], "5d41402abc4b2a76b9719d911017c592");
+        assert_eq!(lines[0][1], "5");
+        assert_eq!(lines[0][3], "a%20dir/c");
+        assert_eq!(lines[1][0], "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(lines[1][3], "b");
+        assert_eq!(generate(&backend, "a dir", false, 1).unwrap()[0].md5, None);
+        // modification times are written in seconds
+        let reread = read(output.as_bytes()).unwrap();
+        assert_eq!(reread.len(), 2);
+        assert_eq!((reread[0].key.as_str(), reread[0].size), ("a dir/c", 5));
+        assert_eq!(reread[0].md5, entries[0].md5);
+
+        let inventory = "\"bucket\",\"a+dir/c%2B\",\"5\",\"2019-10-13T12:00:00.000Z\",\"5d41402abc4b2a76b9719d911017c592\"\n";
+        let entries = read(inventory.as_bytes()).unwrap();
+        assert_eq!(entries[0].key, "a dir/c+");
+        assert_eq!(entries[0].size, 5);
+        assert!(entries[0].md5.is_some());
+        assert!(read("- five 0 key\n".as_bytes()).is_err());
+
+        let mut inventory = vec![];
+        write_inventory(&reread, "bucket", &mut inventory).unwrap();
+        let inventory = String::from_utf8(inventory).unwrap();
+        assert!(inventory.starts_with("\"bucket\",\"a%20dir/c\",\"5\",\""));
+        assert_eq!(read(inventory.as_bytes()).unwrap(), reread);
+    }
+
+    #[test]
+    fn test_ingest() {
+        let backend = Arc::new(MockBackend::new());
+        let config = Config::default();
+        let fs = FileSystem::new(backend.clone(), &config);
+        let manifest = "- 5 1570000000 data/a\n- 3 1570000000 data/sub/b%20c\n- 0 0 empty/\n";
+        assert_eq!(fs.ingest(&read(manifest.as_bytes()).unwrap()), 2);
+
+        // served from the tree only
+        assert_eq!(fs.readdir(ROOT_INODE, 0, 0).unwrap().len(), 2);
+        let data = fs.lookup(ROOT_INODE, OsStr::new("data")).unwrap().ino;
+        let sub = fs.lookup(data, OsStr::new("sub")).unwrap().ino;
+        assert_eq!(fs.lookup(sub, OsStr::new("b c")).unwrap().size, 3);
+        assert_eq!(fs.readdir(data, 0, 0).unwrap().len(), 2);
+        let empty = fs.lookup(ROOT_INODE, OsStr::new("empty")).unwrap().ino;
+        assert!(fs.readdir(empty, 0, 0).unwrap().is_empty());
+        assert_eq!(backend.calls("get_children"), 0);
+        assert_eq!(backend.calls("get_node"), 0);
+    }
+}
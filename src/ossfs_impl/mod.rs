@@ -1,8 +1,61 @@
+pub mod access;
+pub mod attr;
 pub mod backend;
+pub mod blocks;
+pub mod breaker;
+pub mod budget;
+pub mod check;
+pub mod config;
+pub mod control;
+pub mod direct_io;
+#[cfg(all(windows, feature = "windows"))]
+pub mod dokan;
+#[cfg(unix)]
+pub mod export;
 pub mod filesystem;
+pub mod filter;
+#[cfg(unix)]
 pub mod fuse;
+#[cfg(feature = "gateway")]
+pub mod gateway;
+pub mod handles;
+pub mod health;
+pub mod idempotency;
+pub mod journal;
+pub mod labels;
 pub mod manager;
+pub mod manifest;
 pub mod node;
+pub mod object_store;
+pub mod packed;
+pub mod passthrough;
+pub mod pins;
+pub mod platform;
+pub mod policy;
+pub mod prefetch;
+pub mod prefetch_policy;
+pub mod quota;
+pub mod refresh;
+pub mod request_id;
+#[cfg(unix)]
+pub mod shutdown;
+pub mod simulate;
+pub mod singleflight;
 pub mod stat;
+pub mod stats;
+#[cfg(unix)]
+pub mod supervisor;
+pub mod trace;
+pub mod transfer;
+pub mod trash;
+pub mod ttl;
+pub mod tuning;
+pub mod usage;
+pub mod vfs;
+pub mod virtual_file;
+pub mod watch;
+pub mod writeback;
 
-pub use self::fuse::Fuse;
+pub use self::config::Config;
+#[cfg(unix)]
+pub use self::fuse::{Fuse, FuseBuilder};
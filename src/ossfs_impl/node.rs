@@ -1,34 +1,69 @@
-use fuse::FileAttr;
+use crate::ossfs_impl::attr::FileAttr;
+use crate::ossfs_impl::packed::PackedAttr;
+use std::ffi::OsStr;
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 
 #[derive(Debug, Clone)]
-pub struct InnerNode {
+pub(crate) struct InnerNode {
     // inode of it self
     pub inode: u64,
     // inode id of parent, parent of root is also root
     pub parent: u64,
-    // block offset
-    // size of current node
-    pub path: PathBuf,
-    pub attr: FileAttr,
+    // path of the parent, shared with the siblings once in the tree
+    pub dir: Arc<PathBuf>,
+    // last component of the path, empty if `dir` is the whole path. The name map of
+    // the parent in the tree holds the same name
+    pub name: Arc<OsStr>,
+    pub attr: PackedAttr,
+    // entity tag of the content in the backend, if the backend has one
+    pub etag: Option<String>,
+    // the path handed to the children, once the node has some
+    pub shared: Option<Arc<PathBuf>>,
+    // readdir cookie of the node in its directory, 0 until it is in the tree
+    pub cookie: u64,
 }
 
+/// An entry of the filesystem. Backends create nodes with an inode of 0, the real
+/// inode is assigned once the node is inserted into the tree. Clones share state.
 #[derive(Debug, Clone)]
 pub struct Node {
     inner: Arc<RwLock<InnerNode>>,
 }
 
 impl InnerNode {
-    fn new(inode: u64, parent: u64, path: PathBuf, mut attr: FileAttr) -> InnerNode {
-        attr.ino = inode;
+    fn new(inode: u64, parent: u64, path: PathBuf, attr: FileAttr) -> InnerNode {
+        let (dir, name) = split(path);
         InnerNode {
             inode,
             parent,
-            path,
-            attr,
+            dir,
+            name,
+            attr: PackedAttr::pack(&attr),
+            etag: None,
+            shared: None,
+            cookie: 0,
         }
     }
+
+    fn path(&self) -> PathBuf {
+        if self.name.is_empty() {
+            (*self.dir).clone()
+        } else {
+            self.dir.join(&self.name)
+        }
+    }
+}
+
+// the path of the parent and the name, or the whole path and no name when joining
+// them back would not give the same path, e.g. for the root
+fn split(path: PathBuf) -> (Arc<PathBuf>, Arc<OsStr>) {
+    if let (Some(dir), Some(name)) = (path.parent(), path.file_name()) {
+        if dir.join(name).as_os_str() == path.as_os_str() {
+            return (Arc::new(dir.to_path_buf()), name.into());
+        }
+    }
+    (Arc::new(path), OsStr::new("").into())
 }
 
 impl Node {
@@ -38,6 +73,12 @@ impl Node {
         }
     }
 
+    /// Sets the entity tag the backend has for the content of the node.
+    pub fn with_etag(self, etag: Option<String>) -> Node {
+        self.set_etag(etag);
+        self
+    }
+
     pub fn inode(&self) -> u64 {
         let node = self.inner.read().unwrap();
         node.inode
@@ -48,23 +89,130 @@ impl Node {
         node.parent
     }
 
+    /// The full path, joined from the path of the parent and the name.
     pub fn path(&self) -> PathBuf {
         let node = self.inner.read().unwrap();
-        node.path.to_owned()
+        node.path()
+    }
+
+    /// The last component of the path, empty if there is none, e.g. for `/`. Cheaper
+    /// than taking it from `path`, which is built on every call.
+    pub fn name(&self) -> Arc<OsStr> {
+        let node = self.inner.read().unwrap();
+        if !node.name.is_empty() {
+            return node.name.clone();
+        }
+        node.dir.file_name().unwrap_or_default().into()
     }
 
     pub fn attr(&self) -> FileAttr {
         let node = self.inner.read().unwrap();
-        node.attr.clone()
+        node.attr.unpack(node.inode)
+    }
+
+    pub fn etag(&self) -> Option<String> {
+        let node = self.inner.read().unwrap();
+        node.etag.clone()
+    }
+
+    /// Where listings of the directory resume after the node, see
+    /// `InodeManager::get_children_after`.
+    pub fn cookie(&self) -> u64 {
+        let node = self.inner.read().unwrap();
+        node.cookie
+    }
+
+    pub(crate) fn set_cookie(&self, cookie: u64) {
+        let mut node = self.inner.write().unwrap();
+        node.cookie = cookie;
     }
 
-    pub fn set_inode(&self, inode: u64, parent: u64) {
+    pub(crate) fn set_inode(&self, inode: u64, parent: u64) {
         let mut node = self.inner.write().unwrap();
         node.inode = inode;
         node.parent = parent;
-        node.attr.ino = inode;
+    }
+
+    /// Shares the path of `parent` in place of the copy the node was created with,
+    /// the children of a directory hold a single path and their names.
+    pub(crate) fn share_dir(&self, parent: &Node) {
+        let dir = {
+            let mut parent = parent.inner.write().unwrap();
+            if parent.shared.is_none() {
+                let path = Arc::new(parent.path());
+                parent.shared = Some(path);
+            }
+            parent.shared.clone().unwrap()
+        };
+        let mut node = self.inner.write().unwrap();
+        if !node.name.is_empty() && *node.dir == *dir {
+            node.dir = dir;
+        }
+    }
+
+    /// Rough number of bytes the node takes: its shared state and its name, which
+    /// the name maps of the tree share. The path of the parent is shared by the
+    /// siblings and left out, unless the node holds a whole path.
+    pub(crate) fn memory_bytes(&self) -> usize {
+        let node = self.inner.read().unwrap();
+        let dir = if node.name.is_empty() {
+            node.dir.as_os_str().len()
+        } else {
+            0
+        };
+        // the two reference counts of the `Arc`s of the state and of the name
+        4 * std::mem::size_of::<usize>()
+            + std::mem::size_of::<RwLock<InnerNode>>()
+            + node.name.len()
+            + dir
+            + node.attr.heap_bytes()
+            + node.etag.as_ref().map(|etag| etag.len()).unwrap_or(0)
+    }
+
+    pub(crate) fn set_etag(&self, etag: Option<String>) {
+        let mut node = self.inner.write().unwrap();
+        node.etag = etag;
+    }
+
+    /// Replaces the attributes fetched from the backend, keeping the inode.
+    pub(crate) fn set_attr(&self, attr: FileAttr) {
+        let mut node = self.inner.write().unwrap();
+        node.attr = PackedAttr::pack(&attr);
     }
 }
 
-unsafe impl std::marker::Sync for Node {}
-unsafe impl std::marker::Send for Node {}
+#[cfg(test)]
+mod test {
+    use super::Node;
+    use crate::ossfs_impl::attr;
+    use crate::ossfs_impl::attr::FileType;
+    use std::ffi::OsStr;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_node_paths() {
+        let dir_attr = attr::synthetic(FileType::Directory, 0, UNIX_EPOCH);
+        for path in &["/", "/a", "/a/b/", "relative/c", "/a/.."] {
+            let node = Node::new(0, 0, PathBuf::from(path), dir_attr);
+            assert_eq!(node.path().as_os_str(), *path);
+        }
+
+        let parent = Node::new(2, 1, PathBuf::from("/data/train"), dir_attr);
+        let file_attr = attr::synthetic(FileType::RegularFile, 5, UNIX_EPOCH);
+        let a = Node::new(0, 0, PathBuf::from("/data/train/a"), file_attr);
+        let b = Node::new(0, 0, PathBuf::from("/data/train/b"), file_attr);
+        a.share_dir(&parent);
+        b.share_dir(&parent);
+        assert!(Arc::ptr_eq(
+            &a.inner.read().unwrap().dir,
+            &b.inner.read().unwrap().dir
+        ));
+        assert_eq!(b.path(), PathBuf::from("/data/train/b"));
+        assert_eq!(&*b.name(), OsStr::new("b"));
+        assert_eq!(&*parent.name(), OsStr::new("train"));
+        a.set_inode(3, 2);
+        assert_eq!((a.attr().ino, a.attr().size), (3, 5));
+    }
+}
@@ -0,0 +1,293 @@
+//! An async object store api over a `Backend`, shaped after the `ObjectStore` trait of
+//! the object_store crate, backend calls run on a pool of threads.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::attr::FileType;
+use crate::ossfs_impl::backend::{key, Backend};
+use crate::ossfs_impl::node::Node;
+use futures::channel::oneshot;
+use futures::future::Future;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+// bytes of each chunk of a streamed body
+const CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Metadata of an object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectMeta {
+    pub location: String,
+    pub last_modified: SystemTime,
+    pub size: u64,
+    pub e_tag: Option<String>,
+}
+
+/// The objects and common prefixes right below a prefix.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ListResult {
+    pub common_prefixes: Vec<String>,
+    pub objects: Vec<ObjectMeta>,
+}
+
+/// An object being read, its body is fetched chunk by chunk as it is polled.
+pub struct GetResult {
+    pub meta: ObjectMeta,
+    pub body: BoxStream<'static, Result<Vec<u8>>>,
+}
+
+#[derive(Debug)]
+pub struct ObjectStore<B> {
+    backend: Arc<B>,
+    pool: Arc<Mutex<threadpool::ThreadPool>>,
+}
+
+impl<B> Clone for ObjectStore<B> {
+    fn clone(&self) -> ObjectStore<B> {
+        ObjectStore {
+            backend: self.backend.clone(),
+            pool: self.pool.clone(),
+        }
+    }
+}
+
+impl<B: Backend + Send + Sync + 'static> ObjectStore<B> {
+    /// Serves `backend`, calling it from up to `threads` threads at once.
+    pub fn new(backend: B, threads: usize) -> ObjectStore<B> {
+        ObjectStore {
+            backend: Arc::new(backend),
+            pool: Arc::new(Mutex::new(threadpool::ThreadPool::new(threads))),
+        }
+    }
+
+    pub async fn head(&self, location: &str) -> Result<ObjectMeta> {
+        let path = self.path_of(location);
+        let node = self.call(move |backend| backend.get_node(path)).await?;
+        if node.attr().kind == FileType::Directory {
+            return Err(Error::NotFound(format!("{} is a directory", location)));
+        }
+        Ok(meta(location.to_owned(), &node))
+    }
+
+    /// The object at `location`, with a body streaming its content.
+    pub async fn get(&self, location: &str) -> Result<GetResult> {
+        let meta = self.head(location).await?;
+        let body = self.stream(self.path_of(location), 0..meta.size);
+        Ok(GetResult { meta, body })
+    }
+
+    /// The bytes of `range` of the object at `location`, fewer if it ends before.
+    pub async fn get_range(&self, location: &str, range: Range<u64>) -> Result<Vec<u8>> {
+        let mut body = self.stream(self.path_of(location), range);
+        let mut data = vec![];
+        while let Some(chunk) = body.next().await {
+            data.extend_from_slice(&chunk?);
+        }
+        Ok(data)
+    }
+
+    /// Replaces the object at `location` with `data`.
+    pub async fn put(&self, location: &str, data: Vec<u8>) -> Result<()> {
+        let path = self.path_of(location);
+        self.call(move |backend| backend.put(path, &data)).await
+    }
+
+    pub async fn delete(&self, location: &str) -> Result<()> {
+        let path = self.path_of(location);
+        self.call(move |backend| backend.remove(path)).await
+    }
+
+    /// The objects below `prefix`, at any depth, in no particular order. The
+    /// directories are listed as the stream is polled.
+    pub fn list(&self, prefix: Option<&str>) -> BoxStream<'static, Result<ObjectMeta>> {
+        let root = self.backend.root().path();
+        let start = self.path_of(prefix.unwrap_or(""));
+        let state = (self.clone(), vec![start], VecDeque::new());
+        stream::unfold(state, move |(store, mut pending, mut ready)| {
+            let root = root.clone();
+            async move {
+                loop {
+                    if let Some(meta) = ready.pop_front() {
+                        return Some((Ok(meta), (store, pending, ready)));
+                    }
+                    let path = pending.pop()?;
+                    let children = match store.children(path).await {
+                        Ok(children) => children,
+                        // stop at the first error
+                        Err(e) => return Some((Err(e), (store, vec![], ready))),
+                    };
+                    for child in children {
+                        if child.attr().kind == FileType::Directory {
+                            pending.push(child.path());
+                        } else {
+                            ready.push_back(meta(key_of(&root, &child.path()), &child));
+                        }
+                    }
+                }
+            }
+        })
+        .boxed()
+    }
+
+    /// The objects and the common prefixes right below `prefix`, in key order.
+    pub async fn list_with_delimiter(&self, prefix: Option<&str>) -> Result<ListResult> {
+        let root = self.backend.root().path();
+        let mut result = ListResult::default();
+        for child in self.children(self.path_of(prefix.unwrap_or(""))).await? {
+            let key = key_of(&root, &child.path());
+            if child.attr().kind == FileType::Directory {
+                result.common_prefixes.push(key);
+            } else {
+                result.objects.push(meta(key, &child));
+            }
+        }
+        result.common_prefixes.sort();
+        result.objects.sort_by(|a, b| a.location.cmp(&b.location));
+        Ok(result)
+    }
+
+    pub(crate) fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    // the children of the directory at `path`, none if it does not exist
+    async fn children(&self, path: PathBuf) -> Result<Vec<Node>> {
+        match self.call(move |backend| backend.get_children(path)).await {
+            Err(ref e) if e.errno(0) == libc::ENOENT => Ok(vec![]),
+            children => children,
+        }
+    }
+
+    // the object at `path` over `range`, read in chunks as the stream is polled
+    pub(crate) fn stream(
+        &self,
+        path: PathBuf,
+        range: Range<u64>,
+    ) -> BoxStream<'static, Result<Vec<u8>>> {
+        let end = range.end;
+        stream::unfold(
+            (self.clone(), path, range.start),
+            move |(store, path, offset)| {
+                async move {
+                    if offset >= end {
+                        return None;
+                    }
+                    let size = std::cmp::min(CHUNK_SIZE, end - offset) as usize;
+                    let chunk = {
+                        let path = path.clone();
+                        store
+                            .call(move |backend| backend.read(path, offset, size))
+                            .await
+                    };
+                    match chunk {
+                        // the object is shorter than the range
+                        Ok(ref chunk) if chunk.is_empty() => None,
+                        Ok(chunk) => {
+                            let offset = offset + chunk.len() as u64;
+                            Some((Ok(chunk), (store, path, offset)))
+                        }
+                        Err(e) => Some((Err(e), (store, path, end))),
+                    }
+                }
+            },
+        )
+        .boxed()
+    }
+
+    // runs `f` on the pool, the future resolves to its result
+    pub(crate) fn call<T, F>(&self, f: F) -> impl Future<Output = Result<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&B) -> Result<T> + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let backend = self.backend.clone();
+        self.pool.lock().unwrap().execute(move || {
+            let _ = sender.send(f(&backend));
+        });
+        async move {
+            match receiver.await {
+                Ok(result) => result,
+                Err(_) => Err(Error::Other("the backend call panicked".to_owned())),
+            }
+        }
+    }
+
+    fn path_of(&self, location: &str) -> PathBuf {
+        let root = self.backend.root().path();
+        let location = location.trim_matches('/');
+        if location.is_empty() {
+            root
+        } else {
+            root.join(key::key_to_path(location))
+        }
+    }
+}
+
+fn key_of(root: &Path, path: &Path) -> String {
+    key::path_to_key(path.strip_prefix(root).unwrap_or(path))
+}
+
+fn meta(location: String, node: &Node) -> ObjectMeta {
+    let attr = node.attr();
+    ObjectMeta {
+        location,
+        last_modified: attr.mtime,
+        size: attr.size,
+        e_tag: node.etag(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ObjectStore;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use futures::executor::block_on;
+    use futures::stream::StreamExt;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_object_store() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/a/b/c", b"hello world");
+        backend.add_file("/a/d", b"");
+        let store = ObjectStore::new(backend.clone(), 2);
+        block_on(async {
+            let meta = store.head("a/b/c").await.unwrap();
+            assert_eq!((meta.location.as_str(), meta.size), ("a/b/c", 11));
+            assert!(store.head("a/b").await.is_err());
+
+            let get = store.get("a/b/c").await.unwrap();
+            let chunks: Vec<_> = get.body.collect().await;
+            assert_eq!(chunks.len(), 1);
+            assert_eq!(chunks[0].as_ref().unwrap(), b"hello world");
+            assert_eq!(store.get_range("a/b/c", 6..20).await.unwrap(), b"world");
+
+            store.put("a/e", b"new".to_vec()).await.unwrap();
+            assert_eq!(backend.content("/a/e").unwrap(), b"new");
+
+            let listing = store.list_with_delimiter(Some("a")).await.unwrap();
+            assert_eq!(listing.common_prefixes, vec!["a/b"]);
+            let objects: Vec<_> = listing.objects.iter().map(|o| &o.location).collect();
+            assert_eq!(objects, vec!["a/d", "a/e"]);
+
+            let mut all: Vec<_> = store
+                .list(None)
+                .map(|meta| meta.unwrap().location)
+                .collect()
+                .await;
+            all.sort();
+            assert_eq!(all, vec!["a/b/c", "a/d", "a/e"]);
+            assert_eq!(
+                store.list(Some("missing")).collect::<Vec<_>>().await.len(),
+                0
+            );
+
+            store.delete("a/e").await.unwrap();
+            assert!(backend.content("/a/e").is_none());
+        });
+    }
+}
@@ -0,0 +1,230 @@
+//! Attributes of nodes packed for mounts of hundreds of millions of entries, the fields
+//! most entries share are kept once in a table shared by the process.
+
+use crate::ossfs_impl::attr;
+use crate::ossfs_impl::attr::{FileAttr, FileType};
+use std::collections::HashMap;
+use std::sync::{Once, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// fields the attributes of many entries have in common
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Shape {
+    kind: u8,
+    perm: u16,
+    nlink: u32,
+    uid: u32,
+    gid: u32,
+    rdev: u32,
+    flags: u32,
+}
+
+#[derive(Debug, Default)]
+struct Shapes {
+    all: Vec<Shape>,
+    index: HashMap<Shape, u32>,
+}
+
+// the shapes seen by the process, never shrinks: there are few of them
+fn shapes() -> &'static RwLock<Shapes> {
+    static INIT: Once = Once::new();
+    static mut SHAPES: Option<RwLock<Shapes>> = None;
+    // written once, before any reference to it is handed out
+    unsafe {
+        INIT.call_once(|| SHAPES = Some(RwLock::new(Shapes::default())));
+        SHAPES.as_ref().unwrap()
+    }
+}
+
+fn intern(shape: Shape) -> u32 {
+    if let Some(index) = shapes().read().unwrap().index.get(&shape) {
+        return *index;
+    }
+    let mut shapes = shapes().write().unwrap();
+    if let Some(index) = shapes.index.get(&shape) {
+        return *index;
+    }
+    let index = shapes.all.len() as u32;
+    shapes.all.push(shape);
+    shapes.index.insert(shape, index);
+    index
+}
+
+// seconds and nanoseconds since the epoch, the seconds negative before it
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Time {
+    secs: i64,
+    nanos: u32,
+}
+
+impl Time {
+    fn pack(time: SystemTime) -> Time {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => Time {
+                secs: since.as_secs() as i64,
+                nanos: since.subsec_nanos(),
+            },
+            Err(e) => {
+                let before = e.duration();
+                let mut secs = -(before.as_secs() as i64);
+                let mut nanos = before.subsec_nanos();
+                if nanos > 0 {
+                    secs -= 1;
+                    nanos = 1_000_000_000 - nanos;
+                }
+                Time { secs, nanos }
+            }
+        }
+    }
+
+    fn unpack(self) -> SystemTime {
+        attr::system_time(self.secs, i64::from(self.nanos))
+    }
+}
+
+/// `FileAttr` without the inode, see the module documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PackedAttr {
+    size: u64,
+    blocks: u64,
+    mtime_secs: i64,
+    mtime_nanos: u32,
+    shape: u32,
+    // access, change and creation times, `None` if they all are the modification time
+    times: Option<Box<[Time; 3]>>,
+}
+
+impl PackedAttr {
+    pub fn pack(attr: &FileAttr) -> PackedAttr {
+        let mtime = Time::pack(attr.mtime);
+        let times =
+            if attr.atime == attr.mtime && attr.ctime == attr.mtime && attr.crtime == attr.mtime {
+                None
+            } else {
+                Some(Box::new([
+                    Time::pack(attr.atime),
+                    Time::pack(attr.ctime),
+                    Time::pack(attr.crtime),
+                ]))
+            };
+        PackedAttr {
+            size: attr.size,
+            blocks: attr.blocks,
+            mtime_secs: mtime.secs,
+            mtime_nanos: mtime.nanos,
+            shape: intern(Shape {
+                kind: kind_to_u8(attr.kind),
+                perm: attr.perm,
+                nlink: attr.nlink,
+                uid: attr.uid,
+                gid: attr.gid,
+                rdev: attr.rdev,
+                flags: attr.flags,
+            }),
+            times,
+        }
+    }
+
+    pub fn unpack(&self, ino: u64) -> FileAttr {
+        let shape = shapes().read().unwrap().all[self.shape as usize];
+        let mtime = Time {
+            secs: self.mtime_secs,
+            nanos: self.mtime_nanos,
+        }
+        .unpack();
+        let (atime, ctime, crtime) = match self.times {
+            Some(ref times) => (times[0].unpack(), times[1].unpack(), times[2].unpack()),
+            None => (mtime, mtime, mtime),
+        };
+        FileAttr {
+            ino,
+            size: self.size,
+            blocks: self.blocks,
+            atime,
+            mtime,
+            ctime,
+            crtime,
+            kind: kind_from_u8(shape.kind),
+            perm: shape.perm,
+            nlink: shape.nlink,
+            uid: shape.uid,
+            gid: shape.gid,
+            rdev: shape.rdev,
+            flags: shape.flags,
+        }
+    }
+
+    /// Bytes taken outside of the struct itself.
+    pub fn heap_bytes(&self) -> usize {
+        self.times
+            .as_ref()
+            .map(|_| std::mem::size_of::<[Time; 3]>())
+            .unwrap_or(0)
+    }
+}
+
+fn kind_to_u8(kind: FileType) -> u8 {
+    match kind {
+        FileType::NamedPipe => 0,
+        FileType::CharDevice => 1,
+        FileType::BlockDevice => 2,
+        FileType::Directory => 3,
+        FileType::RegularFile => 4,
+        FileType::Symlink => 5,
+        FileType::Socket => 6,
+    }
+}
+
+fn kind_from_u8(kind: u8) -> FileType {
+    match kind {
+        0 => FileType::NamedPipe,
+        1 => FileType::CharDevice,
+        2 => FileType::BlockDevice,
+        3 => FileType::Directory,
+        5 => FileType::Symlink,
+        6 => FileType::Socket,
+        _ => FileType::RegularFile,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PackedAttr;
+    use crate::ossfs_impl::attr;
+    use crate::ossfs_impl::attr::{FileAttr, FileType};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // `FileAttr` is not `PartialEq`, every field is in its debug output
+    fn debug(attr: &FileAttr) -> String {
+        format!("{:?}", attr)
+    }
+
+    #[test]
+    fn test_packed_attr() {
+        let mtime = UNIX_EPOCH + Duration::new(1_570_000_000, 123_456_789);
+        let file = FileAttr {
+            ino: 7,
+            ..attr::synthetic(FileType::RegularFile, 1 << 40, mtime)
+        };
+        let packed = PackedAttr::pack(&file);
+        assert!(packed.times.is_none());
+        assert_eq!(debug(&packed.unpack(7)), debug(&file));
+
+        // times of their own, before the epoch too
+        let local = FileAttr {
+            ino: 8,
+            atime: UNIX_EPOCH - Duration::new(5, 250_000_000),
+            ctime: mtime + Duration::from_secs(1),
+            uid: 1000,
+            perm: 0o4750,
+            ..attr::synthetic(FileType::Symlink, 3, mtime)
+        };
+        assert_eq!(debug(&PackedAttr::pack(&local).unpack(8)), debug(&local));
+        // the same shape is kept once
+        let other = FileAttr { size: 4, ..local };
+        assert_eq!(
+            PackedAttr::pack(&other).shape,
+            PackedAttr::pack(&local).shape
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Reads going straight to the files of a local backend, see `Backend::local_file`,
+//! without the backend pool or the block cache.
+
+use crate::error::Result;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Default)]
+pub(crate) struct Passthrough {
+    // local files by the handles reading them
+    files: Mutex<HashMap<u64, Arc<File>>>,
+}
+
+impl Passthrough {
+    /// Reads of the handle `fh` go to the file at `path` from now on. Returns
+    /// whether it could be opened, the reads go through the backend otherwise.
+    pub fn open(&self, fh: u64, path: &Path) -> bool {
+        match File::open(path) {
+            Ok(file) => {
+                self.files.lock().unwrap().insert(fh, Arc::new(file));
+                true
+            }
+            Err(e) => {
+                log::warn!("failed to open {:?} for passthrough. error: {}", path, e);
+                false
+            }
+        }
+    }
+
+    pub fn is_open(&self, fh: u64) -> bool {
+        self.files.lock().unwrap().contains_key(&fh)
+    }
+
+    /// Reads `size` bytes at `offset` of the file of `fh`, fewer at its end. `None`
+    /// if the handle does not pass through.
+    pub fn read(&self, fh: u64, offset: u64, size: usize) -> Option<Result<Vec<u8>>> {
+        let file = self.files.lock().unwrap().get(&fh).cloned()?;
+        let mut data = vec![0u8; size];
+        let mut filled = 0;
+        while filled < size {
+            match read_at(&file, &mut data[filled..], offset + filled as u64) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+        data.truncate(filled);
+        Some(Ok(data))
+    }
+
+    pub fn close(&self, fh: u64) {
+        self.files.lock().unwrap().remove(&fh);
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buffer, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buffer, offset)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ossfs_impl::backend::simple::SimpleBackend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+    use std::ffi::OsStr;
+
+    #[test]
+    fn test_passthrough() {
+        let root = std::env::temp_dir().join(format!("ossfs-passthrough-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("data"), b"hello world").unwrap();
+        let config = Config {
+            passthrough: true,
+            ..Config::default()
+        };
+        let backend = SimpleBackend::new(root.to_str().unwrap());
+        let fs = FileSystem::new(backend, &config);
+        let ino = fs.lookup(ROOT_INODE, OsStr::new("data")).unwrap().ino;
+        let fh = fs.open_handle(ino);
+        assert!(fs.is_passthrough(fh));
+
+        let mut read = None;
+        fs.read(ino, fh, false, 6, 100, |result| {
+            read = Some(result.unwrap())
+        });
+        assert_eq!(read.unwrap(), b"world");
+        // the local file is read as it is now
+        std::fs::write(root.join("data"), b"HELLO WORLD").unwrap();
+        let mut read = None;
+        fs.read(ino, fh, false, 0, 5, |result| read = Some(result.unwrap()));
+        assert_eq!(read.unwrap(), b"HELLO");
+
+        fs.close_handle(fh);
+        assert!(!fs.is_passthrough(fh));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
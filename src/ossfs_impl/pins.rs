@@ -0,0 +1,77 @@
+//! The version of its file each open handle reads, so a change detected meanwhile does
+//! not give the handle a torn mix of both versions.
+
+use crate::ossfs_impl::blocks::Version;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// What the handles opened before a remote change of their file read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RemoteChange {
+    /// The new version: what was fetched of the old one is dropped.
+    Invalidate,
+    /// The version they opened: from the blocks of it which are cached or, with
+    /// backends reading on condition of the etag, from the backend while it has not
+    /// changed. Reads fail with ESTALE once the version is gone.
+    Pin,
+}
+
+/// The version a handle reads, with the etag of the object then.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pin {
+    pub ino: u64,
+    // `None` adopts the version of the next read
+    pub version: Option<Version>,
+    pub etag: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Pins {
+    next: AtomicU64,
+    handles: Mutex<HashMap<u64, Pin>>,
+}
+
+impl Pins {
+    pub fn new() -> Pins {
+        Pins {
+            next: AtomicU64::new(1),
+            handles: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A new handle, for mounts which do not number them otherwise.
+    pub fn next_handle(&self) -> u64 {
+        self.next.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Records the version the handle `fh` of the file `ino` reads.
+    pub fn pin(&self, fh: u64, ino: u64, version: Option<Version>, etag: Option<String>) {
+        let pin = Pin { ino, version, etag };
+        self.handles.lock().unwrap().insert(fh, pin);
+    }
+
+    pub fn get(&self, fh: u64) -> Option<Pin> {
+        self.handles.lock().unwrap().get(&fh).cloned()
+    }
+
+    pub fn unpin(&self, fh: u64) {
+        self.handles.lock().unwrap().remove(&fh);
+    }
+
+    /// Lets the handles of the file `ino` adopt the version of their next read,
+    /// once the mount changed the file.
+    pub fn reset(&self, ino: u64) {
+        let mut handles = self.handles.lock().unwrap();
+        for pin in handles.values_mut().filter(|pin| pin.ino == ino) {
+            pin.version = None;
+            pin.etag = None;
+        }
+    }
+}
+
+impl Default for Pins {
+    fn default() -> Pins {
+        Pins::new()
+    }
+}
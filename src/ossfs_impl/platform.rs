@@ -0,0 +1,78 @@
+//! Differences between the fuse implementations of the supported platforms,
+//! libfuse on linux and macFUSE on macOS.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Options passed to `fuse::mount` unless the caller has its own.
+pub fn default_mount_options(fsname: &str) -> Vec<OsString> {
+    let mut options = vec![format!("fsname={}", fsname)];
+    #[cfg(target_os = "macos")]
+    {
+        // the name shown in Finder, and keep Finder from littering the bucket with
+        // ._ files and from asking for resource fork attributes
+        options.push(format!("volname={}", fsname));
+        options.push("noappledouble".to_owned());
+        options.push("noapplexattr".to_owned());
+    }
+    options
+        .into_iter()
+        .flat_map(|option| vec![OsString::from("-o"), OsString::from(option)])
+        .collect()
+}
+
+/// The command a user should run to get rid of a stale mount.
+pub fn unmount_command(mountpoint: &Path) -> String {
+    #[cfg(target_os = "macos")]
+    {
+        format!("umount {:?}", mountpoint)
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        format!("fusermount -u {:?}", mountpoint)
+    }
+}
+
+/// Detaches the stale mount at `mountpoint`, even while processes still use it,
+/// so it can be mounted again.
+pub fn lazy_unmount(mountpoint: &Path) -> std::io::Result<()> {
+    #[cfg(target_os = "macos")]
+    let status = Command::new("umount").arg("-f").arg(mountpoint).status()?;
+    #[cfg(not(target_os = "macos"))]
+    let status = Command::new("fusermount")
+        .arg("-u")
+        .arg("-z")
+        .arg(mountpoint)
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("failed to unmount {:?}, {}", mountpoint, status),
+        ))
+    }
+}
+
+/// Places where the fuse implementation is expected to be installed, and what to
+/// tell the user when none of them exists.
+pub fn fuse_install_locations() -> (Vec<PathBuf>, &'static str) {
+    #[cfg(target_os = "macos")]
+    {
+        (
+            vec![
+                PathBuf::from("/Library/Filesystems/macfuse.fs"),
+                PathBuf::from("/Library/Filesystems/osxfuse.fs"),
+            ],
+            "macFUSE is not installed, get it from https://osxfuse.github.io",
+        )
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        (
+            vec![PathBuf::from("/dev/fuse")],
+            "/dev/fuse does not exist, load the fuse module with `modprobe fuse`",
+        )
+    }
+}
@@ -0,0 +1,117 @@
+//! Per user access policies, restricting which uids may read or write which prefixes
+//! of the mount, so several tenants of a node can share one mount.
+
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    // `None` applies to every uid
+    uid: Option<u32>,
+    // relative to the mount root
+    prefix: PathBuf,
+    access: Access,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    rules: Vec<Rule>,
+}
+
+impl Policy {
+    pub fn new() -> Policy {
+        Policy::default()
+    }
+
+    /// Lets `uid` access the entries below `prefix`, relative to the mount root.
+    /// Writing implies reading.
+    pub fn allow<P: AsRef<Path>>(self, uid: u32, prefix: P, access: Access) -> Policy {
+        self.rule(Some(uid), prefix.as_ref(), access)
+    }
+
+    /// Lets every uid access the entries below `prefix`.
+    pub fn allow_everyone<P: AsRef<Path>>(self, prefix: P, access: Access) -> Policy {
+        self.rule(None, prefix.as_ref(), access)
+    }
+
+    fn rule(mut self, uid: Option<u32>, prefix: &Path, access: Access) -> Policy {
+        let prefix = prefix.strip_prefix("/").unwrap_or(prefix).to_path_buf();
+        self.rules.push(Rule {
+            uid,
+            prefix,
+            access,
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether `uid` may access the entry at `path`, relative to the mount root.
+    pub fn permits(&self, uid: u32, path: &Path, access: Access) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let path = path.strip_prefix("/").unwrap_or(path);
+        self.rules
+            .iter()
+            .filter(|rule| rule.uid.map(|rule_uid| rule_uid == uid).unwrap_or(true))
+            .any(|rule| {
+                let below = path.starts_with(&rule.prefix)
+                    && (access == Access::Read || rule.access == Access::Write);
+                // the directories leading to the prefix
+                let leading = access == Access::Read && rule.prefix.starts_with(path);
+                below || leading
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Access, Policy};
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+    use std::ffi::OsStr;
+    use std::path::Path;
+
+    #[test]
+    fn test_policy() {
+        let policy = Policy::new()
+            .allow(1000, "/tenants/a", Access::Write)
+            .allow(1001, "tenants/b", Access::Write)
+            .allow_everyone("shared", Access::Read);
+        assert!(policy.permits(1000, Path::new("tenants/a/x"), Access::Write));
+        assert!(policy.permits(1000, Path::new(""), Access::Read));
+        assert!(policy.permits(1000, Path::new("tenants"), Access::Read));
+        assert!(!policy.permits(1000, Path::new("tenants"), Access::Write));
+        assert!(!policy.permits(1000, Path::new("tenants/b/x"), Access::Read));
+        assert!(!policy.permits(1000, Path::new("tenants/ab"), Access::Read));
+        assert!(policy.permits(1001, Path::new("shared/x"), Access::Read));
+        assert!(!policy.permits(1001, Path::new("shared/x"), Access::Write));
+        assert!(!policy.permits(1002, Path::new("tenants"), Access::Read));
+        assert!(Policy::new().permits(1002, Path::new("x"), Access::Write));
+
+        let backend = MockBackend::new();
+        backend.add_file("/tenants/a/x", b"");
+        backend.add_file("/tenants/b/y", b"");
+        let config = Config {
+            policy,
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend, &config);
+        let tenants = fs.lookup(ROOT_INODE, OsStr::new("tenants")).unwrap().ino;
+        let a = OsStr::new("a");
+        assert!(fs.permitted(1000, tenants, Some(a), Access::Write).is_ok());
+        let denied = fs.permitted(1001, tenants, Some(a), Access::Read);
+        assert_eq!(denied.unwrap_err().errno(0), libc::EACCES);
+        assert!(fs.permitted(1001, tenants, None, Access::Read).is_ok());
+        assert!(fs.permitted(1001, tenants, None, Access::Write).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! Recursive prefetch of a directory tree, started in the background by setting
+//! `WARM_XATTR` on a directory of the mount.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::attr::FileType;
+use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::filesystem::FileSystem;
+use rayon::prelude::*;
+
+/// Extended attribute starting a prefetch of the directory it is set on.
+pub const WARM_XATTR: &str = "user.ossfs.warm";
+
+/// Threads fetching when the value of `WARM_XATTR` is not a number.
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// What a prefetch fetched.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Warmed {
+    pub dirs: usize,
+    pub files: usize,
+    pub bytes: u64,
+    // directories and files which could not be fetched
+    pub failed: usize,
+}
+
+/// The number of threads asked for by the value of `WARM_XATTR`.
+pub fn concurrency(value: &[u8]) -> usize {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .filter(|concurrency| *concurrency > 0)
+        .unwrap_or(DEFAULT_CONCURRENCY)
+}
+
+/// Lists the tree below `ino` with `concurrency` threads and, when `fetched` is
+/// set, reads the whole content of its files and hands it over with their inode.
+/// Entries which fail are logged and counted, the others are still fetched.
+pub fn warm<B>(
+    fs: &FileSystem<B>,
+    ino: u64,
+    concurrency: usize,
+    fetched: Option<&(dyn Fn(u64, Vec<u8>) + Sync)>,
+) -> Result<Warmed>
+where
+    B: Backend + std::fmt::Debug + Send + Sync + 'static,
+{
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(std::cmp::max(concurrency, 1))
+        .build()
+        .map_err(|e| Error::Other(format!("failed to create prefetch threads. {}", e)))?;
+    let mut warmed = Warmed::default();
+    let mut files = vec![];
+    let mut level = vec![];
+    match fs.getattr(ino)?.kind {
+        FileType::Directory => level.push(ino),
+        _ => files.push(ino),
+    }
+    while !level.is_empty() {
+        let listings: Vec<Result<Vec<(u64, FileType)>>> =
+            pool.install(|| level.par_iter().map(|dir| list(fs, *dir)).collect());
+        level = vec![];
+        for listing in listings {
+            let children = match listing {
+                Ok(children) => children,
+                Err(e) => {
+                    log::error!("prefetch failed to list a directory. error: {}", e);
+                    warmed.failed += 1;
+                    continue;
+                }
+            };
+            warmed.dirs += 1;
+            for (child, kind) in children {
+                match kind {
+                    FileType::Directory => level.push(child),
+                    FileType::RegularFile => files.push(child),
+                    _ => {}
+                }
+            }
+        }
+    }
+    let fetched = match fetched {
+        Some(fetched) => fetched,
+        None => return Ok(warmed),
+    };
+    let reads: Vec<Result<u64>> = pool.install(|| {
+        files
+            .par_iter()
+            .map(|file| {
+                let mut content = None;
+                fs.read(*file, 0, true, 0, 0, |result| content = Some(result));
+                let data = content.unwrap_or_else(|| Ok(vec![]))?;
+                let bytes = data.len() as u64;
+                fetched(*file, data);
+                Ok(bytes)
+            })
+            .collect()
+    });
+    for read in reads {
+        match read {
+            Ok(bytes) => {
+                warmed.files += 1;
+                warmed.bytes += bytes;
+            }
+            Err(e) => {
+                log::error!("prefetch failed to read a file. error: {}", e);
+                warmed.failed += 1;
+            }
+        }
+    }
+    Ok(warmed)
+}
+
+// the children of the directory `ino`, through every page of readdir
+fn list<B>(fs: &FileSystem<B>, ino: u64) -> Result<Vec<(u64, FileType)>>
+where
+    B: Backend + std::fmt::Debug + Send + Sync + 'static,
+{
+    let mut children = vec![];
+    let mut cookie = 0;
+    loop {
+        let page = fs.readdir(ino, 0, cookie)?;
+        cookie = match page.last() {
+            Some(last) => last.cookie(),
+            None => return Ok(children),
+        };
+        children.extend(page.iter().map(|node| (node.inode(), node.attr().kind)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{concurrency, warm, Warmed};
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+    use std::collections::HashMap;
+    use std::ffi::OsStr;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_warm() {
+        assert_eq!(concurrency(b"16"), 16);
+        assert_eq!(concurrency(b"0"), 8);
+        assert_eq!(concurrency(b""), 8);
+
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/data/a", b"aa");
+        backend.add_file("/data/train/b", b"bbb");
+        backend.add_file("/data/train/shards/c", b"c");
+        backend.add_file("/other/d", b"d");
+        let config = Config {
+            readdir_limit: 1,
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend.clone(), &config);
+        let data = fs.lookup(ROOT_INODE, OsStr::new("data")).unwrap().ino;
+        let cached = Mutex::new(HashMap::new());
+        let warmed = warm(
+            &fs,
+            data,
+            2,
+            Some(&|ino, content| {
+                cached.lock().unwrap().insert(ino, content);
+            }),
+        )
+        .unwrap();
+        assert_eq!(
+            warmed,
+            Warmed {
+                dirs: 3,
+                files: 3,
+                bytes: 6,
+                failed: 0
+            }
+        );
+        let listed = backend.calls("get_children");
+        let train = fs.lookup(data, OsStr::new("train")).unwrap().ino;
+        let b = fs.lookup(train, OsStr::new("b")).unwrap().ino;
+        assert_eq!(cached.lock().unwrap()[&b], b"bbb");
+        assert_eq!(fs.readdir(train, 0, 0).unwrap().len(), 1);
+        assert_eq!(backend.calls("get_children"), listed);
+
+        let warmed = warm(&fs, data, 1, None).unwrap();
+        assert_eq!((warmed.dirs, warmed.files), (3, 0));
+        assert_eq!(backend.calls("get_children"), listed);
+    }
+}
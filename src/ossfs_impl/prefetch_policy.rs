@@ -0,0 +1,191 @@
+//! Policies fetching into the block cache the ranges the readers of a mount are about
+//! to read, guessed from the reads they make.
+
+use std::fmt::Debug;
+use std::ops::Range;
+use std::path::Path;
+
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+const ORC_MAGIC: &[u8] = b"ORC";
+
+pub trait PrefetchPolicy: Debug + Send + Sync {
+    /// Ranges of the file at `path`, of `size` bytes, worth fetching after the read
+    /// of `data` at `offset`. Ranges which are cached already are not fetched again.
+    fn next(&self, path: &Path, size: u64, offset: u64, data: &[u8]) -> Vec<Range<u64>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Parquet,
+    Orc,
+    TfRecord,
+}
+
+/// Prefetches the footers and column chunks of parquet and orc files and the next
+/// records of tfrecord files.
+#[derive(Debug, Clone)]
+pub struct ColumnarPolicy {
+    // bytes at the end of a columnar file fetched when its start is read
+    pub tail: u64,
+    // bytes fetched after a read of a tfrecord file
+    pub records_ahead: u64,
+}
+
+impl Default for ColumnarPolicy {
+    fn default() -> ColumnarPolicy {
+        ColumnarPolicy {
+            tail: 64 * 1024,
+            records_ahead: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl PrefetchPolicy for ColumnarPolicy {
+    fn next(&self, path: &Path, size: u64, offset: u64, data: &[u8]) -> Vec<Range<u64>> {
+        let end = offset + data.len() as u64;
+        let format = match format(path, offset, data) {
+            Some(format) => format,
+            None => return vec![],
+        };
+        let ranges = match format {
+            Format::TfRecord => vec![end..end + self.records_ahead],
+            // readers look for the footer first
+            _ if offset == 0 => vec![size.saturating_sub(self.tail)..size],
+            Format::Parquet if end == size => parquet_footer(size, data).into_iter().collect(),
+            Format::Orc if end == size => orc_footer(size, data).into_iter().collect(),
+            // the next column chunk, short of the footer
+            Format::Parquet => {
+                vec![end..std::cmp::min(end + (end - offset), size.saturating_sub(8))]
+            }
+            Format::Orc => vec![end..end + (end - offset)],
+        };
+        ranges
+            .into_iter()
+            .map(|range| range.start..std::cmp::min(range.end, size))
+            .filter(|range| range.start < range.end && !(offset <= range.start && range.end <= end))
+            .collect()
+    }
+}
+
+fn format(path: &Path, offset: u64, data: &[u8]) -> Option<Format> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    match extension.as_ref().map(|extension| extension.as_str()) {
+        Some("parquet") | Some("parq") => return Some(Format::Parquet),
+        Some("orc") => return Some(Format::Orc),
+        Some("tfrecord") | Some("tfrecords") => return Some(Format::TfRecord),
+        _ => {}
+    }
+    if offset == 0 && data.starts_with(PARQUET_MAGIC) {
+        return Some(Format::Parquet);
+    }
+    if offset == 0 && data.starts_with(ORC_MAGIC) {
+        return Some(Format::Orc);
+    }
+    None
+}
+
+// the metadata of a parquet file of `size` bytes, from the read of its `tail`: the
+// metadata is followed by its length, 4 bytes little endian, and the magic
+fn parquet_footer(size: u64, tail: &[u8]) -> Option<Range<u64>> {
+    if tail.len() < 8 || !tail.ends_with(PARQUET_MAGIC) {
+        return None;
+    }
+    let mut length = [0u8; 4];
+    length.copy_from_slice(&tail[tail.len() - 8..tail.len() - 4]);
+    let length = u64::from(u32::from_le_bytes(length));
+    Some(size.checked_sub(8 + length)?..size - 8)
+}
+
+// the footer and metadata of an orc file of `size` bytes, from the read of its
+// `tail`: the last byte is the length of the postscript before it, which holds the
+// lengths of the footer and the metadata before it
+fn orc_footer(size: u64, tail: &[u8]) -> Option<Range<u64>> {
+    let postscript_length = *tail.last()? as usize;
+    if tail.len() < postscript_length + 1 {
+        return None;
+    }
+    let postscript = &tail[tail.len() - 1 - postscript_length..tail.len() - 1];
+    let (mut footer, mut metadata) = (0, 0);
+    let mut position = 0;
+    while position < postscript.len() {
+        let (key, next) = varint(postscript, position)?;
+        position = next;
+        match key & 7 {
+            0 => {
+                let (value, next) = varint(postscript, position)?;
+                position = next;
+                match key >> 3 {
+                    1 => footer = value,
+                    5 => metadata = value,
+                    _ => {}
+                }
+            }
+            2 => {
+                let (length, next) = varint(postscript, position)?;
+                position = next + length as usize;
+            }
+            // no other wire types in a postscript
+            _ => return None,
+        }
+    }
+    let end = size.checked_sub(1 + postscript_length as u64)?;
+    Some(end.checked_sub(footer + metadata)?..end)
+}
+
+// the protobuf varint at `position` of `data` and the position after it
+fn varint(data: &[u8], mut position: usize) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(position)?;
+        position += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some((value, position));
+        }
+        shift += 7;
+        if shift >= 64 {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ColumnarPolicy, PrefetchPolicy};
+    use std::path::Path;
+
+    #[test]
+    fn test_columnar_policy() {
+        let policy = ColumnarPolicy {
+            tail: 100,
+            records_ahead: 10,
+        };
+        let parquet = Path::new("/data/part-0.parquet");
+        assert_eq!(policy.next(parquet, 1000, 0, b"PAR1"), vec![900..1000]);
+        // 20 bytes of metadata, their length and the magic
+        let mut tail = vec![0u8; 4];
+        tail.extend_from_slice(&20u32.to_le_bytes());
+        tail.extend_from_slice(b"PAR1");
+        assert_eq!(policy.next(parquet, 1000, 988, &tail), vec![972..992]);
+        assert_eq!(policy.next(parquet, 1000, 100, &[0; 50]), vec![150..200]);
+
+        // postscript: footer length 30, metadata length 10, magic
+        let mut tail = vec![0x08, 30, 0x28, 10, 0x82, 0xf4, 0x03, 3];
+        tail.extend_from_slice(b"ORC");
+        let length = tail.len() as u8;
+        tail.push(length);
+        let orc = Path::new("/data/part-0.orc");
+        assert_eq!(policy.next(orc, 500, 488, &tail), vec![448..488]);
+
+        let records = Path::new("/data/train.tfrecord");
+        assert_eq!(policy.next(records, 1000, 0, &[0; 16]), vec![16..26]);
+        assert_eq!(policy.next(records, 1000, 984, &[0; 16]), vec![]);
+        assert!(policy
+            .next(Path::new("/data/a.csv"), 1000, 0, b"a,b")
+            .is_empty());
+    }
+}
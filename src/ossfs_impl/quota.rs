@@ -0,0 +1,121 @@
+//! Quota of the bytes and the files a mount may add to the bucket, beyond which
+//! writes fail with EDQUOT.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::stat::Stat;
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub struct Quota {
+    max_bytes: Option<u64>,
+    max_files: Option<u64>,
+    // bytes and files added so far
+    used: Mutex<(u64, u64)>,
+}
+
+impl Quota {
+    pub fn new(max_bytes: Option<u64>, max_files: Option<u64>) -> Quota {
+        Quota {
+            max_bytes,
+            max_files,
+            used: Mutex::new((0, 0)),
+        }
+    }
+
+    /// Bytes and files added so far.
+    pub fn used(&self) -> (u64, u64) {
+        *self.used.lock().unwrap()
+    }
+
+    /// Takes `bytes` more bytes, fails with EDQUOT above the quota.
+    pub fn grow(&self, bytes: u64) -> Result<()> {
+        let mut used = self.used.lock().unwrap();
+        if let Some(max) = self.max_bytes {
+            if used.0 + bytes > max {
+                log::error!(
+                    "refusing to grow to {} bytes, above the quota of {}",
+                    used.0 + bytes,
+                    max
+                );
+                return Err(Error::Fuse(libc::EDQUOT));
+            }
+        }
+        used.0 += bytes;
+        Ok(())
+    }
+
+    /// Gives `bytes` back.
+    pub fn shrink(&self, bytes: u64) {
+        let mut used = self.used.lock().unwrap();
+        used.0 = used.0.saturating_sub(bytes);
+    }
+
+    /// Takes a file more, fails with EDQUOT above the quota.
+    pub fn create(&self) -> Result<()> {
+        let mut used = self.used.lock().unwrap();
+        if let Some(max) = self.max_files {
+            if used.1 >= max {
+                log::error!("refusing to create a file, {} are the quota", max);
+                return Err(Error::Fuse(libc::EDQUOT));
+            }
+        }
+        used.1 += 1;
+        Ok(())
+    }
+
+    /// Gives back a file of `bytes` bytes.
+    pub fn remove(&self, bytes: u64) {
+        let mut used = self.used.lock().unwrap();
+        used.0 = used.0.saturating_sub(bytes);
+        used.1 = used.1.saturating_sub(1);
+    }
+
+    /// `stat` of the backend with the quotas as the size of the filesystem.
+    pub fn statfs(&self, mut stat: Stat) -> Stat {
+        let (bytes, files) = self.used();
+        if let Some(max) = self.max_bytes {
+            let frsize = std::cmp::max(stat.frsize as u64, 1);
+            stat.blocks = max / frsize;
+            stat.blocks_free = max.saturating_sub(bytes) / frsize;
+            stat.blocks_available = stat.blocks_free;
+        }
+        if let Some(max) = self.max_files {
+            stat.files = max;
+            stat.files_free = max.saturating_sub(files);
+        }
+        stat
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Quota;
+    use crate::ossfs_impl::stat::Stat;
+
+    #[test]
+    fn test_quota() {
+        let quota = Quota::new(Some(10 * 4096), Some(2));
+        quota.create().unwrap();
+        quota.grow(4 * 4096).unwrap();
+        quota.create().unwrap();
+        assert_eq!(quota.create().unwrap_err().errno(0), libc::EDQUOT);
+        assert_eq!(quota.grow(7 * 4096).unwrap_err().errno(0), libc::EDQUOT);
+        quota.grow(6 * 4096).unwrap();
+        quota.remove(4 * 4096);
+        assert_eq!(quota.used(), (6 * 4096, 1));
+
+        let stat = quota.statfs(Stat {
+            blocks: 1 << 40,
+            blocks_free: 1 << 40,
+            blocks_available: 1 << 40,
+            files: 1 << 40,
+            files_free: 1 << 40,
+            block_size: 4096,
+            namelen: 255,
+            frsize: 4096,
+            known: true,
+        });
+        assert_eq!((stat.blocks, stat.blocks_free), (10, 4));
+        assert_eq!((stat.files, stat.files_free), (2, 1));
+    }
+}
@@ -0,0 +1,163 @@
+//! Background refresh of the attributes of the hottest inodes, shortly before the
+//! kernel ttl of their attributes expires.
+
+use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::budget::{Budget, Request};
+use crate::ossfs_impl::filesystem::ROOT_INODE;
+use crate::ossfs_impl::manager::InodeManager;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+// the shortest interval between two refreshes
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Counts the accesses of each inode. The counts are halved after every refresh,
+/// so inodes which were hot long ago give way to the ones hot now.
+#[derive(Debug, Default)]
+pub(crate) struct Hotness {
+    accesses: Mutex<HashMap<u64, u64>>,
+}
+
+impl Hotness {
+    pub fn record(&self, ino: u64) {
+        if ino == ROOT_INODE {
+            return;
+        }
+        *self.accesses.lock().unwrap().entry(ino).or_insert(0) += 1;
+    }
+
+    /// The `count` most accessed inodes, the most accessed first. Ages the counts.
+    pub fn hottest(&self, count: usize) -> Vec<u64> {
+        let mut accesses = self.accesses.lock().unwrap();
+        let mut inodes: Vec<(u64, u64)> = accesses
+            .iter()
+            .map(|(ino, accessed)| (*ino, *accessed))
+            .collect();
+        inodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        inodes.truncate(count);
+        accesses.retain(|_, accessed| {
+            *accessed /= 2;
+            *accessed > 0
+        });
+        inodes.into_iter().map(|(ino, _)| ino).collect()
+    }
+}
+
+/// Refreshes the attributes of the `count` hottest inodes from the backend.
+/// Returns the number of inodes refreshed.
+pub(crate) fn refresh<B: Backend>(
+    backend: &B,
+    nodes_manager: &RwLock<InodeManager>,
+    hotness: &Hotness,
+    budget: &Budget,
+    count: usize,
+) -> usize {
+    let inodes: Vec<(u64, std::path::PathBuf)> = {
+        let nodes_manager = nodes_manager.read().unwrap();
+        hotness
+            .hottest(count)
+            .into_iter()
+            .filter(|ino| !nodes_manager.is_pending(*ino))
+            .filter_map(|ino| {
+                let node = nodes_manager.get_node_by_inode(ino).ok()?;
+                Some((ino, node.path()))
+            })
+            .collect()
+    };
+    if inodes.is_empty() || !budget.try_refresh(Request::Head, inodes.len() as u64) {
+        return 0;
+    }
+    let mut refreshed = 0;
+    for (ino, path) in inodes {
+        let attr = match backend.get_node(&path) {
+            Ok(node) => node.attr(),
+            Err(e) => {
+                // removals are left to lookups and watches
+                log::debug!("refreshing {:?} failed. error: {}", path, e);
+                continue;
+            }
+        };
+        let nodes_manager = nodes_manager.read().unwrap();
+        if nodes_manager.is_pending(ino) {
+            continue;
+        }
+        if let Ok(node) = nodes_manager.get_node_by_inode(ino) {
+            if node.path() == path {
+                node.set_attr(attr);
+                refreshed += 1;
+            }
+        }
+    }
+    refreshed
+}
+
+/// Refreshes the `count` hottest inodes shortly before `ttl` expires, until the
+/// filesystem is dropped.
+pub(crate) fn spawn_refresher<B>(
+    backend: &Arc<B>,
+    nodes_manager: &Arc<RwLock<InodeManager>>,
+    hotness: Arc<Hotness>,
+    budget: Arc<Budget>,
+    ttl: Duration,
+    count: usize,
+) where
+    B: Backend + Send + Sync + 'static,
+{
+    let interval = std::cmp::max(ttl - ttl / 10, MIN_INTERVAL);
+    let backend = Arc::downgrade(backend);
+    let nodes_manager = Arc::downgrade(nodes_manager);
+    let spawned = std::thread::Builder::new()
+        .name("ossfs-refresher".to_owned())
+        .spawn(move || loop {
+            std::thread::sleep(interval);
+            let (backend, nodes_manager) = match (backend.upgrade(), nodes_manager.upgrade()) {
+                (Some(backend), Some(nodes_manager)) => (backend, nodes_manager),
+                _ => return,
+            };
+            let refreshed = refresh(&*backend, &nodes_manager, &hotness, &budget, count);
+            log::trace!("refreshed the attributes of {} hot inodes", refreshed);
+        });
+    if let Err(e) = spawned {
+        log::error!("failed to spawn the refresher thread. error: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Hotness;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+    use std::ffi::OsStr;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_refresh() {
+        let hotness = Hotness::default();
+        for ino in [2, 3, 3, 4, 4, 4].iter() {
+            hotness.record(*ino);
+        }
+        assert_eq!(hotness.hottest(2), vec![4, 3]);
+        // 4 and 3 halved to 1, 2 aged out
+        assert_eq!(hotness.hottest(5), vec![3, 4]);
+        assert!(hotness.hottest(5).is_empty());
+
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/hot", b"1");
+        backend.add_file("/cold", b"1");
+        let config = Config {
+            refresh_hottest: Some(1),
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend.clone(), &config);
+        let hot = fs.lookup(ROOT_INODE, OsStr::new("hot")).unwrap().ino;
+        let cold = fs.lookup(ROOT_INODE, OsStr::new("cold")).unwrap().ino;
+        fs.getattr(hot).unwrap();
+        backend.add_file("/hot", b"22");
+        backend.add_file("/cold", b"22");
+        assert_eq!(fs.refresh_attributes(1), 1);
+        assert_eq!(fs.getattr(hot).unwrap().size, 2);
+        assert_eq!(fs.getattr(cold).unwrap().size, 1);
+    }
+}
@@ -0,0 +1,85 @@
+//! Ids of the operations of a mount, sent with their backend requests as
+//! `x-ossfs-request-id` and logged with their errors.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const HEADER: &str = "x-ossfs-request-id";
+
+static NEXT: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static CURRENT: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// A new id, unique among the mounts of a host: the process id and a counter.
+pub fn next() -> String {
+    format!(
+        "{:x}-{:x}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Id of the operation running on this thread.
+pub fn current() -> Option<String> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Id of the running operation, or a new one outside of operations.
+pub fn current_or_next() -> String {
+    current().unwrap_or_else(next)
+}
+
+/// Sets the id of the operation on this thread until the scope is dropped.
+pub struct Scope {
+    previous: Option<String>,
+}
+
+impl Scope {
+    pub fn enter(id: String) -> Scope {
+        let previous = CURRENT.with(|current| current.replace(Some(id)));
+        Scope { previous }
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Enters a new operation unless one is running on this thread already, nested
+/// calls keep the id of the outermost operation.
+pub fn operation() -> Option<Scope> {
+    match current() {
+        Some(_) => None,
+        None => Some(Scope::enter(next())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{current, operation, Scope};
+
+    #[test]
+    fn test_request_id() {
+        assert!(current().is_none());
+        {
+            let _operation = operation();
+            let id = current().unwrap();
+            assert!(operation().is_none());
+            // carried to another thread
+            let carried = id.clone();
+            let seen = std::thread::spawn(move || {
+                let _scope = Scope::enter(carried);
+                current()
+            })
+            .join()
+            .unwrap();
+            assert_eq!(seen, Some(id));
+        }
+        assert!(current().is_none());
+    }
+}
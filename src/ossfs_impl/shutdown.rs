@@ -0,0 +1,184 @@
+//! Flushes a mount on SIGTERM or SIGINT, e.g. when Kubernetes evicts the pod, before
+//! unmounting it, within a deadline.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::filesystem::FileSystem;
+use crate::ossfs_impl::platform;
+use nix::sys::signal::{SigSet, Signal};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// What a flush before terminating achieved.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Report {
+    pub uploaded: Vec<PathBuf>,
+    // uploads which failed, with their errors
+    pub failed: Vec<(PathBuf, String)>,
+    // files not uploaded by the deadline
+    pub left: Vec<PathBuf>,
+    pub metadata_synced: bool,
+}
+
+impl Report {
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty() && self.left.is_empty() && self.metadata_synced
+    }
+}
+
+/// Blocks the termination signals in the calling thread and the threads it spawns
+/// from now on. Returns the signals blocked.
+pub(crate) fn block_signals() -> Result<SigSet> {
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGTERM);
+    signals.add(Signal::SIGINT);
+    signals.thread_block().map_err(Error::Nix)?;
+    Ok(signals)
+}
+
+/// Waits for one of `signals`, then flushes `fs` within `deadline` and unmounts
+/// `mountpoint`.
+pub(crate) fn spawn_handler<B>(
+    fs: Arc<FileSystem<B>>,
+    mountpoint: PathBuf,
+    signals: SigSet,
+    deadline: Duration,
+) where
+    B: Backend + std::fmt::Debug + Send + Sync + 'static,
+{
+    let spawned = std::thread::Builder::new()
+        .name("ossfs-shutdown".to_owned())
+        .spawn(move || {
+            let signal = match signals.wait() {
+                Ok(signal) => signal,
+                Err(e) => {
+                    log::error!("failed to wait for termination signals. error: {}", e);
+                    return;
+                }
+            };
+            log::warn!(
+                "received {:?}, flushing {:?} within {:?}",
+                signal,
+                mountpoint,
+                deadline
+            );
+            let report = flush(&fs, deadline);
+            if report.is_complete() {
+                log::info!("uploaded {} files before stopping", report.uploaded.len());
+            } else {
+                log::error!(
+                    "stopping with {} files unflushed, {} failed: {:?}, {} past the deadline: {:?}{}",
+                    report.failed.len() + report.left.len(),
+                    report.failed.len(),
+                    report.failed,
+                    report.left.len(),
+                    report.left,
+                    if report.metadata_synced {
+                        ""
+                    } else {
+                        ", metadata not synced"
+                    }
+                );
+            }
+            if let Err(e) = platform::lazy_unmount(&mountpoint) {
+                log::error!("failed to unmount {:?}. {}", mountpoint, e);
+                std::process::exit(1);
+            }
+        });
+    if let Err(e) = spawned {
+        log::error!("failed to spawn the shutdown thread. error: {}", e);
+    }
+}
+
+/// Uploads the written files of `fs`, the smallest first, then syncs its metadata,
+/// giving up on what is not done within `deadline`.
+pub fn flush<B>(fs: &Arc<FileSystem<B>>, deadline: Duration) -> Report
+where
+    B: Backend + std::fmt::Debug + Send + Sync + 'static,
+{
+    let started = Instant::now();
+    let files = fs.unflushed();
+    let (sender, receiver) = mpsc::channel();
+    let flushing = fs.clone();
+    let queued: Vec<(u64, PathBuf)> = files
+        .iter()
+        .map(|(ino, path, _)| (*ino, path.clone()))
+        .collect();
+    // the uploads go on in the background past the deadline, until the process ends
+    std::thread::spawn(move || {
+        for (ino, path) in queued {
+            let result = flushing.flush(ino).map_err(|e| e.to_string());
+            if sender.send((Some(path), result)).is_err() {
+                return;
+            }
+        }
+        let _ = sender.send((None, flushing.sync_metadata().map_err(|e| e.to_string())));
+    });
+
+    let mut report = Report::default();
+    let mut done = 0;
+    while let Some(left) = deadline.checked_sub(started.elapsed()) {
+        match receiver.recv_timeout(left) {
+            Ok((Some(path), Ok(()))) => report.uploaded.push(path),
+            Ok((Some(path), Err(e))) => report.failed.push((path, e)),
+            Ok((None, result)) => {
+                if let Err(ref e) = result {
+                    log::error!("failed to sync the metadata. error: {}", e);
+                }
+                report.metadata_synced = result.is_ok();
+                break;
+            }
+            Err(_) => break,
+        }
+        done += 1;
+    }
+    report.left = files
+        .into_iter()
+        .skip(done)
+        .map(|(_, path, _)| path)
+        .collect();
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::flush;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+    use std::ffi::OsStr;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_flush() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/a", b"old");
+        backend.add_file("/b", b"old");
+        let config = Config::default();
+        let fs = Arc::new(FileSystem::new(backend.clone(), &config));
+        let a = fs.lookup(ROOT_INODE, OsStr::new("a")).unwrap().ino;
+        let b = fs.lookup(ROOT_INODE, OsStr::new("b")).unwrap().ino;
+        fs.write(a, 0, b"larger").unwrap();
+        fs.write(b, 0, b"new").unwrap();
+
+        let report = flush(&fs, Duration::from_secs(10));
+        assert!(report.is_complete());
+        // the smallest first
+        assert_eq!(
+            report.uploaded,
+            vec![PathBuf::from("/b"), PathBuf::from("/a")]
+        );
+        assert_eq!(backend.content("/a").unwrap(), b"larger");
+
+        // nothing is done past the deadline
+        backend.set_latency("put", Duration::from_millis(200));
+        fs.write(a, 0, b"newest").unwrap();
+        let report = flush(&fs, Duration::from_millis(50));
+        assert_eq!(report.left, vec![PathBuf::from("/a")]);
+        assert!(!report.metadata_synced);
+    }
+}
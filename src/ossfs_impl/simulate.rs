@@ -0,0 +1,235 @@
+//! Replays a trace of reads against caches of other sizes and policies than the mount
+//! had, offline, to size a cache before buying the disks for it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+
+/// A read of the trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Access {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// What the cache holds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    /// Blocks of this many bytes, fetched whole at the first read of a byte of them.
+    Blocks(u64),
+    /// Whole files, fetched at their first read.
+    Files,
+}
+
+/// Which entry the cache drops once it is full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Eviction {
+    /// The least recently read.
+    Lru,
+    /// The first fetched.
+    Fifo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Policy {
+    pub unit: Unit,
+    pub eviction: Eviction,
+    // bytes the cache holds at most
+    pub capacity: u64,
+}
+
+/// Outcome of a simulation.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Report {
+    pub reads: u64,
+    // reads served from the cache only
+    pub hits: u64,
+    // bytes read
+    pub bytes: u64,
+    // bytes fetched from the backend
+    pub fetched: u64,
+}
+
+impl Report {
+    /// Ratio of the reads served from the cache only.
+    pub fn hit_rate(&self) -> f64 {
+        if self.reads == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / self.reads as f64
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "reads: {}, hit rate: {:.1}%, read: {} bytes, fetched: {} bytes",
+            self.reads,
+            self.hit_rate() * 100.0,
+            self.bytes,
+            self.fetched
+        )
+    }
+}
+
+// entries of the cache ordered by the time of their last read or of their fetch
+#[derive(Debug, Default)]
+struct Cache {
+    // by file and index of the block, 0 for whole files: size and time
+    entries: HashMap<(usize, u64), (u64, u64)>,
+    order: BTreeMap<u64, (usize, u64)>,
+    used: u64,
+    clock: u64,
+}
+
+impl Cache {
+    // whether the cache holds `key`, counting a read of it
+    fn read(&mut self, key: (usize, u64), eviction: Eviction) -> bool {
+        self.clock += 1;
+        let clock = self.clock;
+        let entry = match self.entries.get_mut(&key) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if eviction == Eviction::Lru {
+            self.order.remove(&entry.1);
+            entry.1 = clock;
+            self.order.insert(clock, key);
+        }
+        true
+    }
+
+    fn insert(&mut self, key: (usize, u64), size: u64, capacity: u64) {
+        if size > capacity {
+            return;
+        }
+        self.clock += 1;
+        self.entries.insert(key, (size, self.clock));
+        self.order.insert(self.clock, key);
+        self.used += size;
+        while self.used > capacity {
+            let (&time, &coldest) = self.order.iter().next().unwrap();
+            self.order.remove(&time);
+            self.used -= self.entries.remove(&coldest).unwrap().0;
+        }
+    }
+}
+
+/// Replays `trace` `epochs` times against a cache of `policy`, the files in the
+/// order of the trace for the first epoch and shuffled with `seed` for the next
+/// ones. The reads of a file keep their order.
+pub fn simulate(trace: &[Access], policy: Policy, epochs: usize, seed: u64) -> Report {
+    // files by order of first read, with their reads and sizes
+    let mut index = HashMap::new();
+    let mut files: Vec<Vec<&Access>> = vec![];
+    let mut sizes: Vec<u64> = vec![];
+    for access in trace {
+        let file = *index.entry(&access.path).or_insert_with(|| {
+            files.push(vec![]);
+            sizes.push(0);
+            files.len() - 1
+        });
+        files[file].push(access);
+        sizes[file] = std::cmp::max(sizes[file], access.offset + access.size);
+    }
+
+    let mut report = Report::default();
+    let mut cache = Cache::default();
+    let mut order: Vec<usize> = (0..files.len()).collect();
+    let mut state = if seed == 0 {
+        0x9E37_79B9_7F4A_7C15
+    } else {
+        seed
+    };
+    for epoch in 0..epochs {
+        if epoch > 0 {
+            shuffle(&mut order, &mut state);
+        }
+        for &file in order.iter() {
+            for access in files[file].iter() {
+                report.reads += 1;
+                report.bytes += access.size;
+                let end = std::cmp::min(access.offset + access.size, sizes[file]);
+                let keys: Vec<((usize, u64), u64)> = match policy.unit {
+                    Unit::Files => vec![((file, 0), sizes[file])],
+                    Unit::Blocks(block) => {
+                        let last = if end > access.offset {
+                            (end - 1) / block
+                        } else {
+                            access.offset / block
+                        };
+                        (access.offset / block..=last)
+                            .map(|i| {
+                                let size = std::cmp::min(block, sizes[file] - i * block);
+                                ((file, i), size)
+                            })
+                            .collect()
+                    }
+                };
+                let mut hit = true;
+                for (key, size) in keys {
+                    if !cache.read(key, policy.eviction) {
+                        hit = false;
+                        report.fetched += size;
+                        cache.insert(key, size, policy.capacity);
+                    }
+                }
+                if hit {
+                    report.hits += 1;
+                }
+            }
+        }
+    }
+    report
+}
+
+// Fisher-Yates with a xorshift generator
+fn shuffle(order: &mut [usize], state: &mut u64) {
+    for i in (1..order.len()).rev() {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        order.swap(i, (*state % (i as u64 + 1)) as usize);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{simulate, Access, Eviction, Policy, Unit};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_simulate() {
+        // 10 files of 1 MiB read whole in 4 reads
+        let mib = 1024 * 1024;
+        let trace: Vec<Access> = (0..10)
+            .flat_map(|file| {
+                (0..4).map(move |i| Access {
+                    path: PathBuf::from(format!("/data/{}", file)),
+                    offset: i * mib / 4,
+                    size: mib / 4,
+                })
+            })
+            .collect();
+        let policy = |unit, capacity| Policy {
+            unit,
+            eviction: Eviction::Lru,
+            capacity,
+        };
+
+        // the first read of each file misses
+        let report = simulate(&trace, policy(Unit::Files, 10 * mib), 3, 1);
+        assert_eq!(report.reads, 120);
+        assert_eq!(report.hits, 110);
+        assert_eq!(report.fetched, 10 * mib);
+        let report = simulate(&trace, policy(Unit::Blocks(mib / 2), 10 * mib), 3, 1);
+        assert_eq!(report.hits, 100);
+
+        // too small for the dataset, the files are evicted before the next epoch
+        let report = simulate(&trace, policy(Unit::Files, 5 * mib), 3, 1);
+        assert!(report.hit_rate() < 0.9);
+        assert!(report.fetched > 10 * mib);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, Mutex};
+
+#[derive(Debug)]
+struct Call<V> {
+    result: Mutex<Option<Result<V>>>,
+    done: Condvar,
+}
+
+/// Coalesces identical concurrent calls: while a call for a key is in flight,
+/// callers with the same key wait for it and share its result instead of issuing
+/// their own. Typical when every worker of a job opens the same cold files at once.
+#[derive(Debug)]
+pub struct Group<K, V> {
+    calls: Mutex<HashMap<K, Arc<Call<V>>>>,
+}
+
+// completes the call even if `f` panics, so the waiters do not hang
+struct Finish<'a, K: Eq + Hash, V> {
+    group: &'a Group<K, V>,
+    key: Option<K>,
+    call: Arc<Call<V>>,
+}
+
+impl<'a, K: Eq + Hash, V> Drop for Finish<'a, K, V> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.group.calls.lock().unwrap().remove(&key);
+        }
+        let mut result = self.call.result.lock().unwrap();
+        if result.is_none() {
+            *result = Some(Err(Error::Backend("coalesced call panicked".to_owned())));
+        }
+        self.call.done.notify_all();
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Group<K, V> {
+    pub fn new() -> Group<K, V> {
+        Group {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn work<F: FnOnce() -> Result<V>>(&self, key: K, f: F) -> Result<V> {
+        let (call, leader) = {
+            let mut calls = self.calls.lock().unwrap();
+            match calls.get(&key) {
+                Some(call) => (call.clone(), false),
+                None => {
+                    let call = Arc::new(Call {
+                        result: Mutex::new(None),
+                        done: Condvar::new(),
+                    });
+                    calls.insert(key.clone(), call.clone());
+                    (call, true)
+                }
+            }
+        };
+        if !leader {
+            let mut result = call.result.lock().unwrap();
+            while result.is_none() {
+                result = call.done.wait(result).unwrap();
+            }
+            return result.as_ref().unwrap().clone();
+        }
+        let finish = Finish {
+            group: self,
+            key: Some(key),
+            call: call.clone(),
+        };
+        let result = f();
+        *call.result.lock().unwrap() = Some(result.clone());
+        drop(finish);
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Group;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+    use std::time::Duration;
+
+    #[test]
+    fn test_coalesce() {
+        let group = Arc::new(Group::<&'static str, u64>::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (group, calls, barrier) = (group.clone(), calls.clone(), barrier.clone());
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    group.work("object", || {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(200));
+                        Ok(42)
+                    })
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap().unwrap(), 42);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        // nothing in flight anymore, the next call runs again
+        assert_eq!(group.work("object", || Ok(7)).unwrap(), 7);
+    }
+}
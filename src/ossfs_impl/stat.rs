@@ -1,3 +1,9 @@
+/// Size reported for a bucket without capacity unless the mount sets one, 1 PiB.
+pub const DEFAULT_CAPACITY: u64 = 1 << 50;
+
+// unit of the blocks of estimated stats
+const FRSIZE: u32 = 4096;
+
 #[derive(Debug, Copy, Clone)]
 pub struct Stat {
     pub blocks: u64,
@@ -8,4 +14,79 @@ pub struct Stat {
     pub block_size: u32,
     pub namelen: u32,
     pub frsize: u32,
+    // false when the backend knows neither its capacity nor its usage, the
+    // filesystem estimates them then
+    pub known: bool,
+}
+
+impl Stat {
+    /// Stats of a backend which knows neither its capacity nor its usage.
+    pub fn unknown(namelen: u32) -> Stat {
+        Stat {
+            blocks: 0,
+            blocks_free: 0,
+            blocks_available: 0,
+            files: 0,
+            files_free: 0,
+            block_size: FRSIZE,
+            namelen,
+            frsize: FRSIZE,
+            known: false,
+        }
+    }
+
+    /// Stats of a bucket of `capacity` bytes, of which `usage` is used. Files are
+    /// limited by the free bytes only, so as many as one per free block may be
+    /// created.
+    pub fn estimated(usage: &Usage, capacity: u64, namelen: u32) -> Stat {
+        let frsize = FRSIZE as u64;
+        let blocks_free = capacity.saturating_sub(usage.bytes) / frsize;
+        Stat {
+            blocks: std::cmp::max(capacity, usage.bytes) / frsize,
+            blocks_free,
+            blocks_available: blocks_free,
+            files: usage.entries() + blocks_free,
+            files_free: blocks_free,
+            block_size: FRSIZE,
+            namelen,
+            frsize: FRSIZE,
+            known: true,
+        }
+    }
+}
+
+/// Recursive usage of a directory: the bytes of the files below it, and the files
+/// and directories below it.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Usage {
+    pub bytes: u64,
+    pub files: u64,
+    pub dirs: u64,
+}
+
+impl Usage {
+    /// Entries below the directory.
+    pub fn entries(&self) -> u64 {
+        self.files + self.dirs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Stat, Usage};
+
+    #[test]
+    fn test_estimated() {
+        let usage = Usage {
+            bytes: 3 * 4096,
+            files: 2,
+            dirs: 1,
+        };
+        let stat = Stat::estimated(&usage, 10 * 4096, 255);
+        assert_eq!((stat.blocks, stat.blocks_free), (10, 7));
+        assert_eq!((stat.files, stat.files_free), (10, 7));
+        // above the capacity nothing is free
+        let stat = Stat::estimated(&usage, 4096, 255);
+        assert_eq!((stat.blocks, stat.blocks_free), (3, 0));
+    }
 }
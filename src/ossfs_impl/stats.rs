@@ -0,0 +1,264 @@
+//! Per operation counters of the kernel requests and the backend calls, summarized in
+//! the log every interval.
+
+use crate::ossfs_impl::budget::Budget;
+use crate::ossfs_impl::health::Health;
+use crate::ossfs_impl::labels::Labels;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counts {
+    calls: u64,
+    errors: u64,
+    bytes: u64,
+    // running now, carried over from one summary to the next
+    in_flight: u64,
+    max_in_flight: u64,
+    finished: u64,
+    busy: Duration,
+    waits: u64,
+    wait_total: Duration,
+    wait_max: Duration,
+}
+
+#[derive(Debug)]
+pub(crate) struct Stats {
+    // counts since `since`, keyed by operation
+    ops: Mutex<(Instant, BTreeMap<&'static str, Counts>)>,
+    gauges: Mutex<BTreeMap<&'static str, Arc<AtomicU64>>>,
+    budget: Mutex<Option<Arc<Budget>>>,
+    health: Mutex<Option<Arc<Health>>>,
+    labels: Mutex<Labels>,
+}
+
+/// Counts an operation as running until dropped.
+pub(crate) struct InFlight<'a> {
+    stats: &'a Stats,
+    op: &'static str,
+    started: Instant,
+}
+
+impl<'a> Drop for InFlight<'a> {
+    fn drop(&mut self) {
+        let mut ops = self.stats.ops.lock().unwrap();
+        let counts = ops.1.entry(self.op).or_insert_with(Counts::default);
+        counts.in_flight = counts.in_flight.saturating_sub(1);
+        counts.finished += 1;
+        counts.busy += self.started.elapsed();
+    }
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            ops: Mutex::new((Instant::now(), BTreeMap::new())),
+            gauges: Mutex::new(BTreeMap::new()),
+            budget: Mutex::new(None),
+            health: Mutex::new(None),
+            labels: Mutex::new(Labels::default()),
+        }
+    }
+
+    /// Logs the requests counted by `budget` with every summary.
+    pub fn requests(&self, budget: Arc<Budget>) {
+        *self.budget.lock().unwrap() = Some(budget);
+    }
+
+    /// Logs the health of the backend with every summary.
+    pub fn health(&self, health: Arc<Health>) {
+        *self.health.lock().unwrap() = Some(health);
+    }
+
+    /// Starts every summary with `labels`.
+    pub fn labels(&self, labels: Labels) {
+        *self.labels.lock().unwrap() = labels;
+    }
+
+    /// Logs the current value of `bytes` with every summary.
+    pub fn gauge(&self, name: &'static str, bytes: Arc<AtomicU64>) {
+        self.gauges.lock().unwrap().insert(name, bytes);
+    }
+
+    pub fn record(&self, op: &'static str, ok: bool, bytes: u64) {
+        let mut ops = self.ops.lock().unwrap();
+        let counts = ops.1.entry(op).or_insert_with(Counts::default);
+        counts.calls += 1;
+        if !ok {
+            counts.errors += 1;
+        }
+        counts.bytes += bytes;
+    }
+
+    /// Counts `op` as running until the returned guard is dropped.
+    pub fn enter(&self, op: &'static str) -> InFlight<'_> {
+        let mut ops = self.ops.lock().unwrap();
+        let counts = ops.1.entry(op).or_insert_with(Counts::default);
+        counts.in_flight += 1;
+        counts.max_in_flight = std::cmp::max(counts.max_in_flight, counts.in_flight);
+        InFlight {
+            stats: self,
+            op,
+            started: Instant::now(),
+        }
+    }
+
+    /// Records that `op` waited `wait` in a queue before a thread picked it up.
+    pub fn waited(&self, op: &'static str, wait: Duration) {
+        let mut ops = self.ops.lock().unwrap();
+        let counts = ops.1.entry(op).or_insert_with(Counts::default);
+        counts.waits += 1;
+        counts.wait_total += wait;
+        counts.wait_max = std::cmp::max(counts.wait_max, wait);
+    }
+
+    /// Describes the operations since the previous summary and starts over.
+    pub fn summary(&self) -> String {
+        let (since, ops) = {
+            let mut ops = self.ops.lock().unwrap();
+            let since = std::mem::replace(&mut ops.0, Instant::now());
+            let running: BTreeMap<&'static str, Counts> = ops
+                .1
+                .iter()
+                .filter(|(_, counts)| counts.in_flight > 0)
+                .map(|(op, counts)| {
+                    let carried = Counts {
+                        in_flight: counts.in_flight,
+                        max_in_flight: counts.in_flight,
+                        ..Counts::default()
+                    };
+                    (*op, carried)
+                })
+                .collect();
+            (since, std::mem::replace(&mut ops.1, running))
+        };
+        let elapsed = since.elapsed();
+        let seconds = elapsed.as_secs_f64().max(0.001);
+        let mut gauges: String = self
+            .gauges
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, bytes)| {
+                format!(
+                    ", {} {:.2} MiB",
+                    name,
+                    bytes.load(Ordering::Relaxed) as f64 / (1024.0 * 1024.0)
+                )
+            })
+            .collect();
+        if let Some(ref budget) = *self.budget.lock().unwrap() {
+            gauges += &format!(", requests {}", budget.counts());
+        }
+        if let Some(ref health) = *self.health.lock().unwrap() {
+            gauges += &format!(", backend {}", health);
+        }
+        let parts: Vec<String> = ops
+            .iter()
+            .filter(|(_, counts)| counts.calls > 0 || counts.finished > 0 || counts.waits > 0)
+            .map(|(op, counts)| {
+                let calls = std::cmp::max(counts.calls, counts.finished);
+                let mut part = format!("{} {:.1}/s", op, calls as f64 / seconds);
+                if counts.bytes > 0 {
+                    part += &format!(
+                        " {:.2} MiB/s",
+                        counts.bytes as f64 / seconds / (1024.0 * 1024.0)
+                    );
+                }
+                if counts.errors > 0 {
+                    part += &format!(" ({} errors)", counts.errors);
+                }
+                if counts.finished > 0 {
+                    part += &format!(
+                        " took {:.1?}, {} at most at once",
+                        counts.busy / counts.finished as u32,
+                        counts.max_in_flight
+                    );
+                }
+                if counts.waits > 0 {
+                    part += &format!(
+                        ", queued {:.1?} max {:.1?}",
+                        counts.wait_total / counts.waits as u32,
+                        counts.wait_max
+                    );
+                }
+                part
+            })
+            .collect();
+        let labels = self.labels.lock().unwrap();
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("[{}] ", labels)
+        };
+        if parts.is_empty() {
+            return format!("{}no operations in {:.1?}{}", labels, elapsed, gauges);
+        }
+        format!(
+            "{}operations in {:.1?}: {}{}",
+            labels,
+            elapsed,
+            parts.join(", "),
+            gauges
+        )
+    }
+}
+
+/// Logs a summary of `stats` every `interval` until nobody else holds `stats`.
+pub(crate) fn spawn_logger(stats: Arc<Stats>, interval: Duration) {
+    let spawned = std::thread::Builder::new()
+        .name("ossfs-stats".to_owned())
+        .spawn(move || loop {
+            std::thread::sleep(interval);
+            if Arc::strong_count(&stats) == 1 {
+                return;
+            }
+            log::info!("{}", stats.summary());
+        });
+    if let Err(e) = spawned {
+        log::error!("failed to spawn the stats thread. error: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Stats;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_summary() {
+        let stats = Stats::new();
+        stats.record("read", true, 1024 * 1024);
+        stats.record("lookup", true, 0);
+        stats.record("lookup", false, 0);
+        let summary = stats.summary();
+        assert!(summary.contains("lookup"), "{}", summary);
+        assert!(summary.contains("(1 errors)"), "{}", summary);
+        assert!(summary.contains("MiB/s"), "{}", summary);
+        stats.gauge("metadata", Arc::new(AtomicU64::new(3 * 1024 * 1024)));
+        let summary = stats.summary();
+        assert!(summary.starts_with("no operations"), "{}", summary);
+        assert!(summary.ends_with(", metadata 3.00 MiB"), "{}", summary);
+    }
+
+    #[test]
+    fn test_concurrency() {
+        let stats = Stats::new();
+        let first = stats.enter("backend read");
+        {
+            let _second = stats.enter("backend read");
+        }
+        stats.waited("backend read", Duration::from_millis(4));
+        let summary = stats.summary();
+        assert!(summary.contains("2 at most at once"), "{}", summary);
+        assert!(summary.contains("queued 4.0ms max 4.0ms"), "{}", summary);
+        // still running, counted in the next summary
+        drop(first);
+        let summary = stats.summary();
+        assert!(summary.contains("1 at most at once"), "{}", summary);
+    }
+}
@@ -0,0 +1,116 @@
+//! Keeps a mount alive, mounting the filesystem again when its fuse session ends
+//! without the user unmounting it.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::check;
+use crate::ossfs_impl::platform;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How the supervisor mounts again.
+#[derive(Debug, Clone)]
+pub struct Restart {
+    // wait before the first remount, doubled after each failed attempt
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    // remounts in a row before giving up, `None` never gives up
+    pub max_restarts: Option<usize>,
+}
+
+impl Default for Restart {
+    fn default() -> Restart {
+        Restart {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_restarts: None,
+        }
+    }
+}
+
+/// Runs `mount` until a session ends with the mountpoint unmounted by the user,
+/// mounting again with backoff whenever the session dies or the mount fails. A
+/// session which lasted longer than the longest backoff was healthy, the backoff
+/// and the count of restarts start over after it.
+pub fn supervise<P, F>(mountpoint: P, restart: &Restart, mut mount: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut() -> Result<()>,
+{
+    let mountpoint = mountpoint.as_ref();
+    let mut backoff = restart.initial_backoff;
+    let mut restarts = 0;
+    loop {
+        let started = Instant::now();
+        let result = mount();
+        let stale = check::is_stale(mountpoint);
+        match result {
+            Ok(()) if !stale => {
+                log::info!("{:?} was unmounted, stopping", mountpoint);
+                return Ok(());
+            }
+            Ok(()) => log::error!("the fuse session of {:?} died", mountpoint),
+            Err(ref e) => log::error!("mount of {:?} failed. {}", mountpoint, e),
+        }
+        if stale {
+            if let Err(e) = platform::lazy_unmount(mountpoint) {
+                log::error!("failed to detach the stale mount {:?}. {}", mountpoint, e);
+            }
+        }
+        if started.elapsed() > restart.max_backoff {
+            backoff = restart.initial_backoff;
+            restarts = 0;
+        }
+        if let Some(max) = restart.max_restarts {
+            if restarts >= max {
+                return Err(result.err().unwrap_or_else(|| {
+                    Error::Other(format!(
+                        "the fuse session of {:?} died {} times in a row",
+                        mountpoint,
+                        restarts + 1
+                    ))
+                }));
+            }
+        }
+        log::warn!("mounting {:?} again in {:?}", mountpoint, backoff);
+        std::thread::sleep(backoff);
+        backoff = std::cmp::min(backoff * 2, restart.max_backoff);
+        restarts += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{supervise, Restart};
+    use crate::error::Error;
+    use std::time::Duration;
+
+    #[test]
+    fn test_supervise() {
+        let mountpoint = std::env::temp_dir();
+        let restart = Restart {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+            max_restarts: Some(2),
+        };
+        // the backend is down for the first attempts
+        let mut attempts = 0;
+        supervise(&mountpoint, &restart, || {
+            attempts += 1;
+            if attempts < 3 {
+                Err(Error::Unavailable("backend".to_owned()))
+            } else {
+                Ok(())
+            }
+        })
+        .unwrap();
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let result = supervise(&mountpoint, &restart, || {
+            attempts += 1;
+            Err(Error::Unavailable("backend".to_owned()))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+}
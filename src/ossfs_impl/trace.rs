@@ -0,0 +1,319 @@
+//! Opt-in trace of the accesses of a mount, every open, read and write with its time,
+//! path, offset and size, for the cache simulator or to analyze how data is read.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::labels::Labels;
+use crate::ossfs_impl::simulate::Access;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{BufWriter, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// followed by the labels, then the records: the op as a byte, the microseconds since
+// the previous record, the index of the path, the offset and the size as varints. The
+// first record of a path is followed by its length and bytes.
+const MAGIC: &[u8] = b"OSSFSTR2";
+// traces without labels
+const MAGIC_V1: &[u8] = b"OSSFSTR1";
+
+// records waiting for the writer at most
+const QUEUE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Open = 0,
+    Read = 1,
+    Write = 2,
+}
+
+impl Op {
+    fn from_u8(op: u8) -> Option<Op> {
+        match op {
+            0 => Some(Op::Open),
+            1 => Some(Op::Read),
+            2 => Some(Op::Write),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub time: SystemTime,
+    pub op: Op,
+    pub path: PathBuf,
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Debug)]
+pub struct Tracer {
+    sender: SyncSender<Record>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Tracer {
+    /// Traces to a new file at `path`, replacing what is there, recording `labels`
+    /// in its header.
+    pub fn create<P: AsRef<Path>>(path: P, labels: &Labels) -> Result<Tracer> {
+        let mut out = BufWriter::new(std::fs::File::create(path.as_ref())?);
+        let mut header = MAGIC.to_vec();
+        let labels: Vec<&(String, String)> = labels.iter().collect();
+        put_varint(&mut header, labels.len() as u64);
+        for (name, value) in labels {
+            put_bytes(&mut header, name.as_bytes());
+            put_bytes(&mut header, value.as_bytes());
+        }
+        out.write_all(&header)?;
+        let (sender, receiver) = mpsc::sync_channel::<Record>(QUEUE);
+        let path = path.as_ref().to_path_buf();
+        std::thread::Builder::new()
+            .name("ossfs-trace".to_owned())
+            .spawn(move || {
+                if let Err(e) = write_records(&receiver, &mut out) {
+                    log::error!("failed to write the access trace {:?}. {}", path, e);
+                }
+            })?;
+        Ok(Tracer {
+            sender,
+            dropped: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Records `op` of `size` bytes at `offset` of the file at `path`, dropped if
+    /// the writer is behind.
+    pub fn record(&self, op: Op, path: &Path, offset: u64, size: u64) {
+        let record = Record {
+            time: SystemTime::now(),
+            op,
+            path: path.to_path_buf(),
+            offset,
+            size,
+        };
+        match self.sender.try_send(record) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            log::warn!(
+                "{} accesses were not traced, the trace fell behind",
+                dropped
+            );
+        }
+    }
+}
+
+// writes the records until the tracer is dropped, flushing whenever the queue is
+// empty so a quiet mount does not keep its last records in memory
+fn write_records<W: Write>(receiver: &Receiver<Record>, out: &mut W) -> Result<()> {
+    let mut encoder = Encoder::default();
+    while let Ok(record) = receiver.recv() {
+        encoder.encode(out, &record)?;
+        while let Ok(record) = receiver.try_recv() {
+            encoder.encode(out, &record)?;
+        }
+        out.flush()?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct Encoder {
+    paths: HashMap<PathBuf, u64>,
+    // of the previous record, microseconds since the epoch
+    last: u64,
+}
+
+impl Encoder {
+    fn encode<W: Write>(&mut self, out: &mut W, record: &Record) -> Result<()> {
+        let time = micros(record.time);
+        let mut buffer = vec![record.op as u8];
+        put_varint(&mut buffer, time.saturating_sub(self.last));
+        self.last = std::cmp::max(self.last, time);
+        let next = self.paths.len() as u64;
+        let index = *self.paths.entry(record.path.clone()).or_insert(next);
+        put_varint(&mut buffer, index);
+        put_varint(&mut buffer, record.offset);
+        put_varint(&mut buffer, record.size);
+        if index == next {
+            put_bytes(&mut buffer, record.path.as_os_str().as_bytes());
+        }
+        out.write_all(&buffer)?;
+        Ok(())
+    }
+}
+
+/// Reads the records of a trace written by a `Tracer`.
+pub fn read<R: Read>(input: R) -> Result<Vec<Record>> {
+    read_with_labels(input).map(|(_, records)| records)
+}
+
+/// Reads the labels and the records of a trace written by a `Tracer`.
+pub fn read_with_labels<R: Read>(input: R) -> Result<(Labels, Vec<Record>)> {
+    let mut bytes = vec![];
+    std::io::BufReader::new(input).read_to_end(&mut bytes)?;
+    let mut labels = Labels::default();
+    let mut input = if bytes.starts_with(MAGIC) {
+        let mut input = &bytes[MAGIC.len()..];
+        let invalid = || Error::Other("invalid labels in the access trace".to_owned());
+        let count = get_varint(&mut input).ok_or_else(invalid)?;
+        for _ in 0..count {
+            let name = get_bytes(&mut input).ok_or_else(invalid)?;
+            let value = get_bytes(&mut input).ok_or_else(invalid)?;
+            labels.set(
+                String::from_utf8_lossy(name).into_owned(),
+                String::from_utf8_lossy(value).into_owned(),
+            );
+        }
+        input
+    } else if bytes.starts_with(MAGIC_V1) {
+        &bytes[MAGIC_V1.len()..]
+    } else {
+        return Err(Error::Other("not an access trace".to_owned()));
+    };
+    let mut paths: Vec<PathBuf> = vec![];
+    let mut time = 0;
+    let mut records = vec![];
+    // a record cut short by a crash ends the trace
+    while let Some((&op, rest)) = input.split_first() {
+        input = rest;
+        let fields = (0..4)
+            .map(|_| get_varint(&mut input))
+            .collect::<Option<Vec<u64>>>();
+        let (op, fields) = match (Op::from_u8(op), fields) {
+            (Some(op), Some(fields)) => (op, fields),
+            _ => break,
+        };
+        time += fields[0];
+        let index = fields[1] as usize;
+        if index == paths.len() {
+            match get_bytes(&mut input) {
+                Some(path) => paths.push(PathBuf::from(OsString::from_vec(path.to_vec()))),
+                None => break,
+            }
+        }
+        let path = match paths.get(index) {
+            Some(path) => path.clone(),
+            None => return Err(Error::Other(format!("unknown path {} in the trace", index))),
+        };
+        records.push(Record {
+            time: UNIX_EPOCH + Duration::from_micros(time),
+            op,
+            path,
+            offset: fields[2],
+            size: fields[3],
+        });
+    }
+    Ok((labels, records))
+}
+
+/// The reads of `records`, to replay them with the cache simulator.
+pub fn reads(records: &[Record]) -> Vec<Access> {
+    records
+        .iter()
+        .filter(|record| record.op == Op::Read)
+        .map(|record| Access {
+            path: record.path.clone(),
+            offset: record.offset,
+            size: record.size,
+        })
+        .collect()
+}
+
+fn micros(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+fn put_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn put_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    put_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+// bytes preceded by their length
+fn get_bytes<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = get_varint(input)? as usize;
+    let bytes: &'a [u8] = *input;
+    if len > bytes.len() {
+        return None;
+    }
+    *input = &bytes[len..];
+    Some(&bytes[..len])
+}
+
+fn get_varint(input: &mut &[u8]) -> Option<u64> {
+    let bytes: &[u8] = *input;
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *input = &bytes[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_with_labels, reads, Op, Tracer};
+    use crate::ossfs_impl::labels::Labels;
+    use std::path::Path;
+
+    #[test]
+    fn test_trace() {
+        let path = std::env::temp_dir().join(format!("ossfs-trace-{}", std::process::id()));
+        let mut labels = Labels::default();
+        labels.set("job", "resnet");
+        let tracer = Tracer::create(&path, &labels).unwrap();
+        tracer.record(Op::Open, Path::new("data/a"), 0, 0);
+        tracer.record(Op::Read, Path::new("data/a"), 0, 4096);
+        tracer.record(Op::Read, Path::new("data/b"), 1 << 40, 128);
+        tracer.record(Op::Write, Path::new("data/a"), 4096, 3);
+        drop(tracer);
+
+        // the writer flushes once the tracer is dropped
+        let mut records = vec![];
+        for _ in 0..100 {
+            let (read, read_records) =
+                read_with_labels(std::fs::File::open(&path).unwrap()).unwrap();
+            assert_eq!(read, labels);
+            records = read_records;
+            if records.len() == 4 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[2].path, Path::new("data/b"));
+        assert_eq!(records[2].offset, 1 << 40);
+        assert_eq!(records[3].op, Op::Write);
+        assert!(records[0].time <= records[3].time);
+        let reads = reads(&records);
+        assert_eq!(reads.len(), 2);
+        assert_eq!(reads[0].size, 4096);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
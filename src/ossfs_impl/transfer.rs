@@ -0,0 +1,317 @@
+//! Copies trees between backends without going through a mount, e.g. to migrate a
+//! dataset from SeaweedFS to S3.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::attr::FileType;
+use crate::ossfs_impl::backend::Backend;
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+const READ_CHUNK: usize = 4 * 1024 * 1024;
+
+/// Reported after each file.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    /// Path of the file, relative to the roots of the backends.
+    pub path: PathBuf,
+    pub files_done: u64,
+    pub files_total: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+pub struct CopyOptions {
+    concurrency: usize,
+    verify: bool,
+    skip_existing: bool,
+    retries: usize,
+    progress: Option<Arc<dyn Fn(&Progress) + Send + Sync>>,
+}
+
+impl std::fmt::Debug for CopyOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "concurrency: {}, verify: {}, skip_existing: {}, retries: {}",
+            self.concurrency, self.verify, self.skip_existing, self.retries
+        )
+    }
+}
+
+impl Default for CopyOptions {
+    fn default() -> CopyOptions {
+        CopyOptions {
+            concurrency: num_cpus::get(),
+            verify: false,
+            skip_existing: false,
+            retries: 2,
+            progress: None,
+        }
+    }
+}
+
+impl CopyOptions {
+    /// Number of files copied at the same time.
+    pub fn concurrency(mut self, concurrency: usize) -> CopyOptions {
+        self.concurrency = std::cmp::max(concurrency, 1);
+        self
+    }
+
+    /// Reads every file back from the destination and compares its md5 with the
+    /// one of the source.
+    pub fn verify(mut self, verify: bool) -> CopyOptions {
+        self.verify = verify;
+        self
+    }
+
+    /// Skips files already present in the destination with the same size.
+    pub fn skip_existing(mut self, skip_existing: bool) -> CopyOptions {
+        self.skip_existing = skip_existing;
+        self
+    }
+
+    /// Number of times a file is copied again after a retryable error.
+    pub fn retries(mut self, retries: usize) -> CopyOptions {
+        self.retries = retries;
+        self
+    }
+
+    pub fn on_progress<F>(mut self, progress: F) -> CopyOptions
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CopySummary {
+    pub files: u64,
+    pub bytes: u64,
+    pub skipped: u64,
+    /// Files that could not be copied, the copy carries on with the others.
+    pub failed: Vec<(PathBuf, Error)>,
+}
+
+/// Copies everything under `prefix`, relative to the root of `src`, to the same
+/// path relative to the root of `dst`. An empty prefix copies the whole tree.
+///
+/// Fails only if the tree can not be listed, errors of single files are reported
+/// in the summary.
+pub fn copy_tree<S, D, P>(src: &S, dst: &D, prefix: P, options: &CopyOptions) -> Result<CopySummary>
+where
+    S: Backend + Sync,
+    D: Backend + Sync,
+    P: AsRef<Path>,
+{
+    let src_root = src.root().path();
+    let dst_root = dst.root().path();
+    let start = if prefix.as_ref().as_os_str().is_empty() {
+        src_root.clone()
+    } else {
+        src_root.join(prefix.as_ref())
+    };
+
+    let (walked, files) = walk(src, &start)?;
+    let relative = |path: &Path| path.strip_prefix(&src_root).unwrap_or(path).to_path_buf();
+    // the ancestors of the prefix first, parents have to exist before their children
+    let mut directories: Vec<PathBuf> = match relative(&start).parent() {
+        Some(parent) => parent.ancestors().map(Path::to_path_buf).collect(),
+        None => vec![],
+    };
+    directories.reverse();
+    directories.extend(walked.iter().map(|directory| relative(directory)));
+    for directory in directories.iter() {
+        if directory.as_os_str().is_empty() {
+            continue;
+        }
+        let target = dst_root.join(directory);
+        match dst.mknod(&target, FileType::Directory, 0o755) {
+            Ok(()) | Err(Error::Conflict(_)) => {}
+            Err(e) => {
+                log::warn!("failed to create directory {:?}. error: {}", target, e);
+            }
+        }
+    }
+
+    let files_total = files.len() as u64;
+    let bytes_total: u64 = files.iter().map(|(_, size)| size).sum();
+    let files_done = AtomicU64::new(0);
+    let bytes_done = AtomicU64::new(0);
+    let summary = Mutex::new(CopySummary::default());
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.concurrency)
+        .build()
+        .map_err(|e| Error::Other(format!("failed to create copy threads. {}", e)))?;
+    pool.install(|| {
+        files.par_iter().for_each(|(path, size)| {
+            let path_in_dst = dst_root.join(relative(path));
+            let mut attempt = 0;
+            let result = loop {
+                match copy_file(src, dst, path, &path_in_dst, *size, options) {
+                    Err(ref e) if e.is_retryable() && attempt < options.retries => {
+                        log::warn!("retrying copy of {:?}. error: {}", path, e);
+                        attempt += 1;
+                    }
+                    result => break result,
+                }
+            };
+            {
+                let mut summary = summary.lock().unwrap();
+                match result {
+                    Ok(true) => {
+                        summary.files += 1;
+                        summary.bytes += size;
+                    }
+                    Ok(false) => summary.skipped += 1,
+                    Err(e) => {
+                        log::error!(
+                            "{}:{} failed to copy {:?}. error: {}",
+                            std::file!(),
+                            std::line!(),
+                            path,
+                            e
+                        );
+                        summary.failed.push((path.clone(), e));
+                    }
+                }
+            }
+            let progress = Progress {
+                path: relative(path),
+                files_done: files_done.fetch_add(1, Ordering::SeqCst) + 1,
+                files_total,
+                bytes_done: bytes_done.fetch_add(*size, Ordering::SeqCst) + size,
+                bytes_total,
+            };
+            if let Some(ref callback) = options.progress {
+                callback(&progress);
+            }
+        })
+    });
+    Ok(summary.into_inner().unwrap())
+}
+
+/// Directories, `start` included, in the order they have to be created, and files
+/// with their size.
+pub(crate) fn walk<B: Backend>(
+    backend: &B,
+    start: &Path,
+) -> Result<(Vec<PathBuf>, Vec<(PathBuf, u64)>)> {
+    let node = backend.get_node(start)?;
+    if node.attr().kind != FileType::Directory {
+        return Ok((vec![], vec![(start.to_path_buf(), node.attr().size)]));
+    }
+    let mut directories = vec![start.to_path_buf()];
+    let mut files = vec![];
+    let mut pending = vec![start.to_path_buf()];
+    while let Some(directory) = pending.pop() {
+        for child in backend.get_children(&directory)? {
+            let attr = child.attr();
+            if attr.kind == FileType::Directory {
+                directories.push(child.path());
+                pending.push(child.path());
+            } else {
+                files.push((child.path(), attr.size));
+            }
+        }
+    }
+    Ok((directories, files))
+}
+
+fn read_all<B: Backend>(backend: &B, path: &Path, size: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(size as usize);
+    loop {
+        let chunk = backend.read(path, data.len() as u64, READ_CHUNK)?;
+        if chunk.is_empty() {
+            return Ok(data);
+        }
+        data.extend_from_slice(&chunk);
+    }
+}
+
+/// Returns whether the file was copied, `false` if it was skipped.
+fn copy_file<S: Backend, D: Backend>(
+    src: &S,
+    dst: &D,
+    path: &Path,
+    path_in_dst: &Path,
+    size: u64,
+    options: &CopyOptions,
+) -> Result<bool> {
+    if options.skip_existing {
+        match dst.get_node(path_in_dst) {
+            Ok(node) if node.attr().size == size => return Ok(false),
+            Ok(_) | Err(Error::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let data = read_all(src, path, size)?;
+    if data.len() as u64 != size {
+        return Err(Error::Conflict(format!(
+            "{:?} changed while being copied, expected {} bytes, read {}",
+            path,
+            size,
+            data.len()
+        )));
+    }
+    dst.put(path_in_dst, &data)?;
+    if options.verify {
+        let expected = md5::compute(&data);
+        let actual = md5::compute(read_all(dst, path_in_dst, size)?);
+        if expected != actual {
+            return Err(Error::Backend(format!(
+                "checksum mismatch after copying {:?}, expected {:x}, found {:x}",
+                path, expected, actual
+            )));
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{copy_tree, CopyOptions};
+    use crate::error::Error;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::backend::Backend;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_copy_tree() {
+        let src = MockBackend::new();
+        src.add_file("/data/a", b"hello");
+        src.add_file("/data/sub/b", &[7u8; 10000]);
+        src.add_file("/other", b"not copied");
+        src.add_dir("/data/empty");
+        let dst = MockBackend::new();
+        dst.fail_next("put", Error::Timeout("first put".to_owned()));
+
+        let reported = Arc::new(AtomicU64::new(0));
+        let counter = reported.clone();
+        let options = CopyOptions::default()
+            .concurrency(2)
+            .verify(true)
+            .on_progress(move |progress| {
+                assert!(progress.files_done <= progress.files_total);
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        let summary = copy_tree(&src, &dst, "data", &options).unwrap();
+        assert_eq!(summary.files, 2);
+        assert_eq!(summary.bytes, 10005);
+        assert!(summary.failed.is_empty());
+        assert_eq!(reported.load(Ordering::SeqCst), 2);
+        assert_eq!(dst.content("/data/a").unwrap(), b"hello");
+        assert_eq!(dst.content("/data/sub/b").unwrap(), vec![7u8; 10000]);
+        assert!(dst.content("/other").is_none());
+        assert!(dst.get_children("/data/empty").unwrap().is_empty());
+
+        let summary = copy_tree(&src, &dst, "", &options.skip_existing(true)).unwrap();
+        assert_eq!(summary.skipped, 2);
+        assert_eq!(summary.files, 1);
+    }
+}
@@ -0,0 +1,169 @@
+//! Soft deletion: with a retention set, unlink first copies a file to the trash of the
+//! mount, `.Trash/<time of deletion>/`, purged once older than the retention.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::attr::FileType;
+use crate::ossfs_impl::backend::Backend;
+use std::path::{Path, PathBuf};
+use std::sync::Weak;
+use std::time::{Duration, SystemTime};
+
+pub const TRASH_DIR: &str = ".Trash";
+
+// directories of deletions are named after their time in UTC
+const TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+// interval between purges of expired deletions
+const PURGE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Where `path` of the mount rooted at `root` goes when deleted at `at`.
+pub(crate) fn trash_path(root: &Path, path: &Path, at: SystemTime) -> PathBuf {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let time = chrono::DateTime::<chrono::Utc>::from(at).format(TIME_FORMAT);
+    root.join(TRASH_DIR).join(time.to_string()).join(relative)
+}
+
+/// Whether `path` of the mount rooted at `root` is in the trash.
+pub(crate) fn in_trash(root: &Path, path: &Path) -> bool {
+    path.starts_with(root.join(TRASH_DIR))
+}
+
+/// Removes the deletions made more than `retention` before `now`. Returns how
+/// many were removed.
+pub(crate) fn purge<B: Backend>(
+    backend: &B,
+    root: &Path,
+    retention: Duration,
+    now: SystemTime,
+) -> Result<usize> {
+    let deletions = match backend.get_children(root.join(TRASH_DIR)) {
+        Ok(deletions) => deletions,
+        Err(Error::NotFound(_)) => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut purged = 0;
+    for deletion in deletions {
+        let path = deletion.path();
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => continue,
+        };
+        let deleted = match chrono::DateTime::parse_from_str(
+            &format!("{}+0000", name),
+            &format!("{}%z", TIME_FORMAT),
+        ) {
+            Ok(deleted) => SystemTime::from(deleted),
+            Err(_) => {
+                log::warn!("not a deletion, left in the trash: {:?}", path);
+                continue;
+            }
+        };
+        let expired = now
+            .duration_since(deleted)
+            .map(|age| age > retention)
+            .unwrap_or(false);
+        if expired && deletion.attr().kind == FileType::Directory {
+            log::info!("purging {:?} from the trash", path);
+            remove_tree(backend, &path)?;
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
+fn remove_tree<B: Backend>(backend: &B, path: &Path) -> Result<()> {
+    for child in backend.get_children(path)? {
+        if child.attr().kind == FileType::Directory {
+            remove_tree(backend, &child.path())?;
+        } else {
+            backend.remove(child.path())?;
+        }
+    }
+    match backend.remove_dir(path) {
+        Ok(()) | Err(Error::NotFound(_)) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Purges expired deletions every `PURGE_INTERVAL` until the backend is dropped.
+pub(crate) fn spawn_purger<B>(backend: Weak<B>, root: PathBuf, retention: Duration)
+where
+    B: Backend + Send + Sync + 'static,
+{
+    let spawned = std::thread::Builder::new()
+        .name("ossfs-trash".to_owned())
+        .spawn(move || loop {
+            std::thread::sleep(PURGE_INTERVAL);
+            let backend = match backend.upgrade() {
+                Some(backend) => backend,
+                None => return,
+            };
+            if let Err(e) = purge(&*backend, &root, retention, SystemTime::now()) {
+                log::error!("purge the trash. error: {}", e);
+            }
+        });
+    if let Err(e) = spawned {
+        log::error!("failed to spawn the trash thread. error: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{purge, trash_path, TRASH_DIR};
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::backend::Backend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+    use std::ffi::OsStr;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_trash() {
+        let deleted = UNIX_EPOCH + Duration::from_secs(86400);
+        assert_eq!(
+            trash_path(Path::new("/"), Path::new("/dir/a"), deleted),
+            Path::new("/.Trash/19700102T000000Z/dir/a")
+        );
+
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/dir/a", b"precious");
+        backend.add_dir("/empty");
+        let config = Config {
+            trash_retention: Some(Duration::from_secs(3600)),
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend.clone(), &config);
+        let dir = fs.lookup(ROOT_INODE, OsStr::new("dir")).unwrap().ino;
+        fs.unlink(dir, OsStr::new("a")).unwrap();
+        fs.rmdir(ROOT_INODE, OsStr::new("empty")).unwrap();
+        assert!(fs.lookup(dir, OsStr::new("a")).is_err());
+        assert!(fs.lookup(ROOT_INODE, OsStr::new("empty")).is_err());
+        assert!(backend.content("/dir/a").is_none());
+        let deletions = backend.get_children("/.Trash").unwrap();
+        assert_eq!(deletions.len(), 1);
+        let trashed = deletions[0].path().join("dir/a");
+        assert_eq!(backend.content(&trashed).unwrap(), b"precious");
+
+        // deleting in the trash deletes for good
+        let trash = fs.lookup(ROOT_INODE, OsStr::new(TRASH_DIR)).unwrap().ino;
+        let deletion = fs.readdir(trash, 0, 0).unwrap()[0].inode();
+        let trashed_dir = fs.lookup(deletion, OsStr::new("dir")).unwrap().ino;
+        fs.unlink(trashed_dir, OsStr::new("a")).unwrap();
+        assert!(backend.content(&trashed).is_none());
+        assert_eq!(backend.get_children("/.Trash").unwrap().len(), 1);
+
+        let retention = Duration::from_secs(3600);
+        assert_eq!(
+            purge(&*backend, Path::new("/"), retention, SystemTime::now()).unwrap(),
+            0
+        );
+        let later = SystemTime::now() + Duration::from_secs(7200);
+        assert_eq!(
+            purge(&*backend, Path::new("/"), retention, later).unwrap(),
+            1
+        );
+        assert!(backend.get_children("/.Trash").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+//! Kernel ttls adapting to how often each inode changes: doubled while the inode is
+//! found unchanged, quartered when it changed, within the configured bounds.
+
+use crate::ossfs_impl::attr::FileAttr;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+// inodes tracked at most, all of them start over from the configured ttls past it
+const CAPACITY: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy)]
+struct Seen {
+    size: u64,
+    mtime: SystemTime,
+    ttl: Duration,
+    // when the last reply was sent
+    replied: Instant,
+}
+
+#[derive(Debug)]
+pub(crate) struct AdaptiveTtl {
+    min: Duration,
+    max: Duration,
+    inodes: Mutex<HashMap<u64, Seen>>,
+}
+
+impl AdaptiveTtl {
+    pub fn new(min: Duration, max: Duration) -> AdaptiveTtl {
+        AdaptiveTtl {
+            min,
+            max: std::cmp::max(min, max),
+            inodes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The ttl of a reply with `attr`, `base` for an inode seen for the first time.
+    pub fn of(&self, attr: &FileAttr, base: Duration) -> Duration {
+        let now = Instant::now();
+        let mut inodes = self.inodes.lock().unwrap();
+        let ttl = match inodes.get(&attr.ino) {
+            None => self.bound(base),
+            Some(seen) if seen.size != attr.size || seen.mtime != attr.mtime => {
+                self.bound(seen.ttl / 4)
+            }
+            // still within the ttl of the last reply, not a revalidation
+            Some(seen) if now.duration_since(seen.replied) < seen.ttl => seen.ttl,
+            Some(seen) => self.bound(seen.ttl * 2),
+        };
+        if inodes.len() >= CAPACITY && !inodes.contains_key(&attr.ino) {
+            inodes.clear();
+        }
+        inodes.insert(
+            attr.ino,
+            Seen {
+                size: attr.size,
+                mtime: attr.mtime,
+                ttl,
+                replied: now,
+            },
+        );
+        ttl
+    }
+
+    fn bound(&self, ttl: Duration) -> Duration {
+        std::cmp::min(std::cmp::max(ttl, self.min), self.max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AdaptiveTtl;
+    use crate::ossfs_impl::attr;
+    use crate::ossfs_impl::attr::FileType;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_adaptive_ttl() {
+        let ttl = AdaptiveTtl::new(Duration::from_millis(0), Duration::from_millis(40));
+        let mut attr = attr::synthetic(FileType::RegularFile, 3, SystemTime::UNIX_EPOCH);
+        let base = Duration::from_millis(5);
+        assert_eq!(ttl.of(&attr, base), base);
+        // asked again before it expired
+        assert_eq!(ttl.of(&attr, base), base);
+
+        // unchanged after every expiry, up to the maximum
+        let mut last = base;
+        for _ in 0..5 {
+            std::thread::sleep(last);
+            last = ttl.of(&attr, base);
+        }
+        assert_eq!(last, Duration::from_millis(40));
+
+        attr.size = 4;
+        assert_eq!(ttl.of(&attr, base), Duration::from_millis(10));
+    }
+}
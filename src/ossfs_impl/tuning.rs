@@ -0,0 +1,120 @@
+//! Parameters of the fuse connection the kernel keeps for a mount, written to sysfs
+//! once the mount is up since their defaults suit local disks, not object stores.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::config::Config;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Tuning {
+    pub max_readahead: Option<u32>,
+    pub max_background: Option<u16>,
+    pub congestion_threshold: Option<u16>,
+}
+
+impl Tuning {
+    pub fn from_config(config: &Config) -> Tuning {
+        Tuning {
+            max_readahead: config.max_readahead,
+            max_background: config.max_background,
+            congestion_threshold: config.congestion_threshold,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Tuning::default()
+    }
+
+    /// Fails if the kernel would hold back readahead before it could send as many
+    /// background requests as asked for.
+    pub fn check(&self) -> Result<()> {
+        if let (Some(background), Some(threshold)) =
+            (self.max_background, self.congestion_threshold)
+        {
+            if threshold > background {
+                return Err(Error::Other(format!(
+                    "congestion threshold {} is above max_background {}",
+                    threshold, background
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the parameters to the connection of the mount at `mountpoint`.
+    #[cfg(target_os = "linux")]
+    pub fn apply(&self, mountpoint: &Path) -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let dev = std::fs::metadata(mountpoint)?.dev();
+        let (major, minor) = (major(dev), minor(dev));
+        if let Some(bytes) = self.max_readahead {
+            let path = format!("/sys/class/bdi/{}:{}/read_ahead_kb", major, minor);
+            write(&path, (bytes + 1023) / 1024)?;
+        }
+        // connections are named after the device number, its minor for the
+        // anonymous devices fuse mounts get
+        let connection = format!("/sys/fs/fuse/connections/{}", minor);
+        if let Some(requests) = self.max_background {
+            write(&format!("{}/max_background", connection), requests)?;
+        }
+        if let Some(requests) = self.congestion_threshold {
+            write(&format!("{}/congestion_threshold", connection), requests)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply(&self, mountpoint: &Path) -> Result<()> {
+        Err(Error::Other(format!(
+            "the fuse connection of {:?} can only be tuned on linux",
+            mountpoint
+        )))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn write<T: std::fmt::Display>(path: &str, value: T) -> Result<()> {
+    std::fs::write(path, value.to_string())
+        .map_err(|e| Error::Other(format!("failed to write {} to {}. {}", value, path, e)))
+}
+
+// the encoding of device numbers of glibc
+#[cfg(target_os = "linux")]
+fn major(dev: u64) -> u64 {
+    ((dev >> 8) & 0xfff) | ((dev >> 32) & 0xffff_f000)
+}
+
+#[cfg(target_os = "linux")]
+fn minor(dev: u64) -> u64 {
+    (dev & 0xff) | ((dev >> 12) & 0xffff_ff00)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Tuning;
+
+    #[test]
+    fn test_tuning() {
+        assert!(Tuning::default().is_empty());
+        let tuning = Tuning {
+            max_readahead: Some(8 * 1024 * 1024),
+            max_background: Some(64),
+            congestion_threshold: Some(48),
+        };
+        tuning.check().unwrap();
+        assert!(Tuning {
+            congestion_threshold: Some(96),
+            ..tuning
+        }
+        .check()
+        .is_err());
+        #[cfg(target_os = "linux")]
+        {
+            // 0:52 and 259:65536
+            assert_eq!((super::major(52), super::minor(52)), (0, 52));
+            let dev = (259 << 8) | (65536 & 0xff) | ((65536 & !0xff) << 12);
+            assert_eq!((super::major(dev), super::minor(dev)), (259, 65536));
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Recursive usage of directories, computed by the backend and cached for a while, e.g.
+//! `getfattr --only-values -n user.ossfs.du_bytes /mnt/data/train`.
+
+use crate::ossfs_impl::stat::Usage;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Extended attribute of a directory holding the bytes of the files below it.
+pub const DU_BYTES_XATTR: &str = "user.ossfs.du_bytes";
+
+/// Extended attribute of a directory holding the number of entries below it.
+pub const DU_ENTRIES_XATTR: &str = "user.ossfs.du_entries";
+
+#[derive(Debug)]
+pub struct UsageCache {
+    // by path of the directory, with when they were computed
+    usages: Mutex<HashMap<PathBuf, (Usage, Instant)>>,
+    // directories whose usage is computed in the background
+    computing: Mutex<HashSet<PathBuf>>,
+    // how long a usage is served before it is computed again
+    ttl: Duration,
+}
+
+impl UsageCache {
+    pub fn new(ttl: Duration) -> UsageCache {
+        UsageCache {
+            usages: Mutex::new(HashMap::new()),
+            computing: Mutex::new(HashSet::new()),
+            ttl,
+        }
+    }
+
+    /// Usage of the directory at `path` computed within the ttl.
+    pub fn fresh(&self, path: &Path) -> Option<Usage> {
+        match self.last(path) {
+            Some((usage, true)) => Some(usage),
+            _ => None,
+        }
+    }
+
+    /// Last usage computed of the directory at `path`, with whether it is within
+    /// the ttl.
+    pub fn last(&self, path: &Path) -> Option<(Usage, bool)> {
+        let usages = self.usages.lock().unwrap();
+        usages
+            .get(path)
+            .map(|(usage, computed)| (*usage, computed.elapsed() < self.ttl))
+    }
+
+    pub fn insert(&self, path: PathBuf, usage: Usage) {
+        self.usages
+            .lock()
+            .unwrap()
+            .insert(path, (usage, Instant::now()));
+    }
+
+    /// Claims the background computation of the usage of `path`, false if it is
+    /// computed already.
+    pub fn start(&self, path: &Path) -> bool {
+        self.computing.lock().unwrap().insert(path.to_path_buf())
+    }
+
+    /// Ends the background computation of the usage of `path`, `None` if it failed.
+    pub fn finish(&self, path: &Path, usage: Option<Usage>) {
+        if let Some(usage) = usage {
+            self.insert(path.to_path_buf(), usage);
+        }
+        self.computing.lock().unwrap().remove(path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DU_BYTES_XATTR, DU_ENTRIES_XATTR};
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+    use std::ffi::OsStr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_usage() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/data/a", b"12345");
+        backend.add_file("/data/sub/b", b"123");
+        backend.add_dir("/data/empty");
+        let config = Config {
+            usage_ttl: Duration::from_secs(3600),
+            ..Config::default()
+        };
+        let fs = FileSystem::new(backend.clone(), &config);
+        let data = fs.lookup(ROOT_INODE, OsStr::new("data")).unwrap().ino;
+        let usage = fs.usage(data).unwrap();
+        assert_eq!((usage.bytes, usage.files, usage.dirs), (8, 2, 2));
+        assert_eq!(fs.getxattr(data, OsStr::new(DU_BYTES_XATTR)).unwrap(), b"8");
+        assert_eq!(
+            fs.getxattr(data, OsStr::new(DU_ENTRIES_XATTR)).unwrap(),
+            b"4"
+        );
+
+        // served from the cache until the ttl expires
+        let listings = backend.calls("get_children");
+        backend.add_file("/data/c", b"1");
+        assert_eq!(fs.usage(data).unwrap().bytes, 8);
+        assert_eq!(backend.calls("get_children"), listings);
+        let a = fs.lookup(data, OsStr::new("a")).unwrap().ino;
+        assert!(fs.usage(a).is_err());
+    }
+}
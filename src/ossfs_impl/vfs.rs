@@ -0,0 +1,236 @@
+//! The filesystem as a library: paths are resolved and files read in-process without
+//! mounting it, a `Vfs` may share its `FileSystem` with a mount.
+
+use crate::error::{Error, Result};
+use crate::ossfs_impl::attr::{FileAttr, FileType};
+use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::config::Config;
+use crate::ossfs_impl::filesystem::FileSystem;
+use std::ffi::{OsStr, OsString};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct Vfs<B>
+where
+    B: Backend + std::fmt::Debug + Send + Sync + 'static,
+{
+    fs: Arc<FileSystem<B>>,
+}
+
+/// An entry of a directory listed by `Vfs::read_dir`.
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    name: OsString,
+    attr: FileAttr,
+}
+
+/// A file opened by `Vfs::open`, read with `read_at` or as a `std::io::Read`.
+#[derive(Debug)]
+pub struct File<B>
+where
+    B: Backend + std::fmt::Debug + Send + Sync + 'static,
+{
+    fs: Arc<FileSystem<B>>,
+    attr: FileAttr,
+    fh: u64,
+    position: u64,
+}
+
+impl<B: Backend + std::fmt::Debug + Send + Sync + 'static> Vfs<B> {
+    pub fn new(backend: B, config: &Config) -> Vfs<B> {
+        Vfs::with_filesystem(Arc::new(FileSystem::new(backend, config)))
+    }
+
+    /// Serves the entries of `fs`, e.g. the filesystem of a mount.
+    pub fn with_filesystem(fs: Arc<FileSystem<B>>) -> Vfs<B> {
+        Vfs { fs }
+    }
+
+    pub fn filesystem(&self) -> Arc<FileSystem<B>> {
+        self.fs.clone()
+    }
+
+    /// Attributes of the entry at `path`, relative to the root.
+    pub fn stat<P: AsRef<Path>>(&self, path: P) -> Result<FileAttr> {
+        self.fs.lookup_path(path)
+    }
+
+    /// The entries of the directory at `path`, in the byte order of their names.
+    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> Result<Vec<DirEntry>> {
+        let attr = self.stat(path.as_ref())?;
+        if attr.kind != FileType::Directory {
+            return Err(Error::Fuse(libc::ENOTDIR));
+        }
+        let mut entries = vec![];
+        let mut cookie = 0;
+        // a listing returns at most `readdir_limit` entries
+        loop {
+            let nodes = self.fs.readdir(attr.ino, 0, cookie)?;
+            cookie = match nodes.last() {
+                Some(last) => last.cookie(),
+                None => return Ok(entries),
+            };
+            for node in nodes {
+                let name = node.name();
+                if name.is_empty() {
+                    continue;
+                }
+                entries.push(DirEntry {
+                    name: name.to_os_string(),
+                    attr: node.attr(),
+                });
+            }
+        }
+    }
+
+    /// Opens the file at `path` for reading.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<File<B>> {
+        let attr = self.stat(path.as_ref())?;
+        if attr.kind == FileType::Directory {
+            return Err(Error::Fuse(libc::EISDIR));
+        }
+        Ok(File {
+            fs: self.fs.clone(),
+            attr,
+            fh: self.fs.open_handle(attr.ino),
+            position: 0,
+        })
+    }
+
+    /// The whole content of the file at `path`.
+    pub fn read<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
+        let file = self.open(path)?;
+        let mut data = vec![0; file.attr.size as usize];
+        let mut read = 0;
+        while read < data.len() {
+            match file.read_at(read as u64, &mut data[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        data.truncate(read);
+        Ok(data)
+    }
+}
+
+impl DirEntry {
+    pub fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    pub fn attr(&self) -> &FileAttr {
+        &self.attr
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attr.kind == FileType::Directory
+    }
+}
+
+impl<B: Backend + std::fmt::Debug + Send + Sync + 'static> File<B> {
+    /// Attributes of the file when it was opened.
+    pub fn attr(&self) -> &FileAttr {
+        &self.attr
+    }
+
+    /// Reads up to `buf.len()` bytes at `offset`, 0 at the end of the file.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut content = None;
+        self.fs.read(
+            self.attr.ino,
+            self.fh,
+            false,
+            offset as usize,
+            buf.len(),
+            |result| content = Some(result),
+        );
+        let data = content.unwrap_or_else(|| Ok(vec![]))?;
+        let length = std::cmp::min(data.len(), buf.len());
+        buf[..length].copy_from_slice(&data[..length]);
+        Ok(length)
+    }
+}
+
+impl<B: Backend + std::fmt::Debug + Send + Sync + 'static> Read for File<B> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self
+            .read_at(self.position, buf)
+            .map_err(|e| std::io::Error::from_raw_os_error(e.errno(libc::EIO)))?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<B: Backend + std::fmt::Debug + Send + Sync + 'static> Seek for File<B> {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => offset_by(self.attr.size, offset),
+            SeekFrom::Current(offset) => offset_by(self.position, offset),
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "seek before the start of the file",
+            )),
+        }
+    }
+}
+
+impl<B: Backend + std::fmt::Debug + Send + Sync + 'static> Drop for File<B> {
+    fn drop(&mut self) {
+        self.fs.close_handle(self.fh);
+    }
+}
+
+fn offset_by(position: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        position.checked_add(offset as u64)
+    } else {
+        position.checked_sub(offset.wrapping_neg() as u64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Vfs;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::config::Config;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_vfs() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/data/b", b"hello world");
+        backend.add_file("/data/a/c", b"");
+        let config = Config {
+            readdir_limit: 1,
+            ..Config::default()
+        };
+        let vfs = Vfs::new(backend.clone(), &config);
+        let entries = vfs.read_dir("data").unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert!(entries[0].is_dir());
+        assert_eq!(vfs.stat("data/b").unwrap().size, 11);
+        assert_eq!(vfs.read("/data/b").unwrap(), b"hello world");
+
+        let mut file = vfs.open("data/b").unwrap();
+        file.seek(SeekFrom::Start(6)).unwrap();
+        let mut rest = String::new();
+        file.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "world");
+        assert!(file.seek(SeekFrom::Current(-12)).is_err());
+
+        assert_eq!(vfs.open("data").unwrap_err().errno(0), libc::EISDIR);
+        assert_eq!(vfs.read_dir("data/b").unwrap_err().errno(0), libc::ENOTDIR);
+        assert_eq!(vfs.stat("data/missing").unwrap_err().errno(0), libc::ENOENT);
+    }
+}
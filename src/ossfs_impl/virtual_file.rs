@@ -0,0 +1,162 @@
+//! Read-only files served from callbacks instead of the backend, e.g. a generated
+//! `MANIFEST.json` describing a dataset or `.ossfs/stats`.
+
+use crate::ossfs_impl::attr;
+use crate::ossfs_impl::attr::{FileAttr, FileType};
+use crate::ossfs_impl::node::Node;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+#[derive(Clone)]
+pub struct VirtualFile {
+    generate: Arc<dyn Fn() -> Vec<u8> + Send + Sync>,
+}
+
+impl std::fmt::Debug for VirtualFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("VirtualFile")
+    }
+}
+
+impl VirtualFile {
+    pub fn new<F>(generate: F) -> VirtualFile
+    where
+        F: Fn() -> Vec<u8> + Send + Sync + 'static,
+    {
+        VirtualFile {
+            generate: Arc::new(generate),
+        }
+    }
+
+    pub fn content(&self) -> Vec<u8> {
+        (self.generate)()
+    }
+}
+
+/// The virtual files of a mount, by absolute path.
+#[derive(Debug, Default)]
+pub(crate) struct VirtualFiles {
+    files: RwLock<HashMap<PathBuf, VirtualFile>>,
+}
+
+impl VirtualFiles {
+    pub fn insert(&self, path: PathBuf, file: VirtualFile) {
+        self.files.write().unwrap().insert(path, file);
+    }
+
+    pub fn get(&self, path: &Path) -> Option<VirtualFile> {
+        self.files.read().unwrap().get(path).cloned()
+    }
+
+    /// Whether `path` leads to a virtual file.
+    pub fn is_dir(&self, path: &Path) -> bool {
+        self.files
+            .read()
+            .unwrap()
+            .keys()
+            .any(|file| file != path && file.starts_with(path))
+    }
+
+    /// The node of the virtual entry at `path`, `None` if there is none.
+    pub fn node(&self, path: &Path) -> Option<Node> {
+        let attr = match self.get(path) {
+            Some(file) => attr(FileType::RegularFile, file.content().len() as u64),
+            None if self.is_dir(path) => attr(FileType::Directory, 4096),
+            None => return None,
+        };
+        Some(Node::new(0, 0, path.to_path_buf(), attr))
+    }
+
+    /// Names of the virtual entries right below the directory at `dir`.
+    pub fn children(&self, dir: &Path) -> Vec<OsString> {
+        let mut names: Vec<OsString> = self
+            .files
+            .read()
+            .unwrap()
+            .keys()
+            .filter_map(|file| file.strip_prefix(dir).ok())
+            .filter_map(|relative| relative.components().next())
+            .map(|name| name.as_os_str().to_owned())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+// read only, like their content
+fn attr(kind: FileType, size: u64) -> FileAttr {
+    FileAttr {
+        perm: if kind == FileType::Directory {
+            0o555
+        } else {
+            0o444
+        },
+        ..attr::synthetic(kind, size, SystemTime::now())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::VirtualFile;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::config::Config;
+    use crate::ossfs_impl::filesystem::{FileSystem, ROOT_INODE};
+    use std::ffi::OsStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_virtual_file() {
+        let backend = MockBackend::new();
+        backend.add_file("/data/a", b"1");
+        let config = Config::default();
+        let fs = FileSystem::new(backend, &config);
+        let generated = Arc::new(AtomicUsize::new(0));
+        let counter = generated.clone();
+        fs.add_virtual_file(
+            ".ossfs/stats",
+            VirtualFile::new(move || {
+                format!("{}", counter.fetch_add(1, Ordering::SeqCst)).into_bytes()
+            }),
+        );
+        fs.add_virtual_file(
+            "data/MANIFEST.json",
+            VirtualFile::new(|| b"[\"a\"]".to_vec()),
+        );
+
+        let names = |ino| -> Vec<String> {
+            fs.readdir(ino, 0, 0)
+                .unwrap()
+                .iter()
+                .map(|node| {
+                    node.path()
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect()
+        };
+        let mut root = names(ROOT_INODE);
+        root.sort();
+        assert_eq!(root, vec![".ossfs", "data"]);
+        let data = fs.lookup(ROOT_INODE, OsStr::new("data")).unwrap().ino;
+        let mut children = names(data);
+        children.sort();
+        assert_eq!(children, vec!["MANIFEST.json", "a"]);
+
+        let dir = fs.lookup(ROOT_INODE, OsStr::new(".ossfs")).unwrap().ino;
+        assert_eq!(names(dir), vec!["stats"]);
+        let stats = fs.lookup(dir, OsStr::new("stats")).unwrap();
+        assert_eq!(stats.perm, 0o444);
+        fs.read(stats.ino, 0, false, 0, 4096, |result| {
+            assert_eq!(result.unwrap(), b"1")
+        });
+        assert!(fs.write(stats.ino, 0, b"2").is_err());
+        assert!(fs.unlink(dir, OsStr::new("stats")).is_err());
+    }
+}
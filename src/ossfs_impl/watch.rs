@@ -0,0 +1,211 @@
+//! Changes of the children of watched directories, found by listing them every poll
+//! interval. The kernel is not notified, it sees them once its entry ttl expires.
+
+use crate::error::Result;
+use crate::ossfs_impl::attr::FileAttr;
+use crate::ossfs_impl::backend::Backend;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Created(PathBuf),
+    Modified(PathBuf),
+    Removed(PathBuf),
+}
+
+impl Event {
+    pub fn path(&self) -> &Path {
+        match self {
+            Event::Created(path) | Event::Modified(path) | Event::Removed(path) => path,
+        }
+    }
+
+    fn with_path(&self, path: PathBuf) -> Event {
+        match self {
+            Event::Created(_) => Event::Created(path),
+            Event::Modified(_) => Event::Modified(path),
+            Event::Removed(_) => Event::Removed(path),
+        }
+    }
+}
+
+/// A change found by a poll. `attr` holds the new attributes of created and
+/// modified entries.
+#[derive(Debug, Clone)]
+pub(crate) struct Change {
+    pub event: Event,
+    pub attr: Option<FileAttr>,
+}
+
+type Listing = HashMap<PathBuf, FileAttr>;
+
+#[derive(Debug, Default)]
+struct Watch {
+    subscribers: Vec<Sender<Event>>,
+    // `None` until the first poll
+    listing: Option<Listing>,
+}
+
+/// Watched directories by backend path. Events are sent with paths relative to
+/// `root`, the backend path of the mount root.
+#[derive(Debug)]
+pub(crate) struct Watches {
+    root: PathBuf,
+    watches: Mutex<HashMap<PathBuf, Watch>>,
+}
+
+impl Watches {
+    pub fn new(root: PathBuf) -> Watches {
+        Watches {
+            root,
+            watches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn subscribe(&self, path: PathBuf) -> Receiver<Event> {
+        let (sender, receiver) = channel();
+        self.watches
+            .lock()
+            .unwrap()
+            .entry(path)
+            .or_insert_with(Watch::default)
+            .subscribers
+            .push(sender);
+        receiver
+    }
+
+    /// Number of watched directories, each poll lists all of them.
+    pub fn count(&self) -> usize {
+        self.watches.lock().unwrap().len()
+    }
+
+    /// Lists every watched directory and returns how it changed since the last poll.
+    pub fn poll<B: Backend>(&self, backend: &B) -> Vec<Change> {
+        let paths: Vec<PathBuf> = self.watches.lock().unwrap().keys().cloned().collect();
+        let mut changes = vec![];
+        for path in paths {
+            let listing = match list(backend, &path) {
+                Ok(listing) => listing,
+                Err(e) => {
+                    log::warn!("poll watched directory {:?}. error: {}", path, e);
+                    continue;
+                }
+            };
+            let mut watches = self.watches.lock().unwrap();
+            let watch = match watches.get_mut(&path) {
+                Some(watch) => watch,
+                None => continue,
+            };
+            if let Some(ref previous) = watch.listing {
+                changes.extend(diff(previous, &listing));
+            }
+            watch.listing = Some(listing);
+        }
+        changes
+    }
+
+    /// Sends `changes` to the subscribers of the parent directories, and drops the
+    /// watches nobody listens to anymore.
+    pub fn notify(&self, changes: &[Change]) {
+        let mut watches = self.watches.lock().unwrap();
+        for change in changes {
+            let parent = match change.event.path().parent() {
+                Some(parent) => parent,
+                None => continue,
+            };
+            let watch = match watches.get_mut(parent) {
+                Some(watch) => watch,
+                None => continue,
+            };
+            let relative = change
+                .event
+                .path()
+                .strip_prefix(&self.root)
+                .unwrap_or_else(|_| change.event.path())
+                .to_path_buf();
+            let event = change.event.with_path(relative);
+            watch
+                .subscribers
+                .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        }
+        watches.retain(|_, watch| !watch.subscribers.is_empty());
+    }
+}
+
+fn list<B: Backend>(backend: &B, path: &Path) -> Result<Listing> {
+    Ok(backend
+        .get_children(path)?
+        .into_iter()
+        .map(|child| (child.path(), child.attr()))
+        .collect())
+}
+
+fn diff(previous: &Listing, current: &Listing) -> Vec<Change> {
+    let mut changes = vec![];
+    for (path, attr) in current.iter() {
+        let event = match previous.get(path) {
+            None => Event::Created(path.clone()),
+            Some(old)
+                if old.size != attr.size || old.mtime != attr.mtime || old.kind != attr.kind =>
+            {
+                Event::Modified(path.clone())
+            }
+            Some(_) => continue,
+        };
+        changes.push(Change {
+            event,
+            attr: Some(*attr),
+        });
+    }
+    for path in previous.keys() {
+        if !current.contains_key(path) {
+            changes.push(Change {
+                event: Event::Removed(path.clone()),
+                attr: None,
+            });
+        }
+    }
+    changes.sort_by(|a, b| a.event.path().cmp(b.event.path()));
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Event, Watches};
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_poll() {
+        let backend = MockBackend::new();
+        backend.add_file("/dir/a", b"1");
+        backend.add_file("/dir/b", b"2");
+        let watches = Watches::new(PathBuf::from("/"));
+        let events = watches.subscribe(PathBuf::from("/dir"));
+        assert!(watches.poll(&backend).is_empty());
+
+        backend.add_file("/dir/a", b"changed");
+        backend.remove("/dir/b");
+        backend.add_file("/dir/c", b"3");
+        backend.add_file("/dir/sub/d", b"not watched");
+        let changes = watches.poll(&backend);
+        watches.notify(&changes);
+        let received: Vec<Event> = events.try_iter().collect();
+        assert_eq!(
+            received,
+            vec![
+                Event::Modified(PathBuf::from("dir/a")),
+                Event::Removed(PathBuf::from("dir/b")),
+                Event::Created(PathBuf::from("dir/c")),
+                Event::Created(PathBuf::from("dir/sub")),
+            ]
+        );
+
+        drop(events);
+        watches.notify(&changes);
+        assert!(watches.watches.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,831 @@
+//! Write-back caching of file contents: writes change an in-memory copy of the file,
+//! uploaded whole by flush, fsync, release or in the background above a dirty mark.
+
+use crate::error::Result;
+use crate::ossfs_impl::backend::Backend;
+use crate::ossfs_impl::budget::{Budget, Request};
+use crate::ossfs_impl::idempotency;
+use crate::ossfs_impl::journal::Journal;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+const LOAD_CHUNK: usize = 4 * 1024 * 1024;
+
+// bytes of a page of a copy
+const PAGE_SIZE: u64 = 64 * 1024;
+
+// copies of released files whose upload failed kept at most
+const MAX_FAILED_RELEASES: usize = 64;
+
+// interval between checks of a writer blocked above the high-water mark
+const THROTTLE_INTERVAL: Duration = Duration::from_millis(100);
+
+// content of a file in pages, the pages missing are zeros. The pages are shared
+// with the clones, e.g. an upload in flight, until they are written.
+#[derive(Debug, Default, Clone)]
+struct Pages {
+    len: u64,
+    // by index, each takes `PAGE_SIZE` bytes
+    pages: BTreeMap<u64, Arc<Vec<u8>>>,
+}
+
+impl Pages {
+    fn from_vec(data: Vec<u8>) -> Pages {
+        let mut pages = Pages::default();
+        pages.write(0, &data);
+        pages
+    }
+
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    // bytes of the content the pages hold
+    fn resident(&self) -> u64 {
+        self.pages
+            .keys()
+            .map(|index| std::cmp::min(PAGE_SIZE, self.len - index * PAGE_SIZE))
+            .sum()
+    }
+
+    fn read(&self, offset: u64, size: usize) -> Vec<u8> {
+        let start = std::cmp::min(offset, self.len);
+        let end = std::cmp::min(start + size as u64, self.len);
+        let mut data = vec![0; (end - start) as usize];
+        self.read_into(start, &mut data);
+        data
+    }
+
+    // fills `data` with the content at `offset`, returns the bytes filled
+    fn read_into(&self, offset: u64, data: &mut [u8]) -> usize {
+        let start = std::cmp::min(offset, self.len);
+        let end = std::cmp::min(start + data.len() as u64, self.len);
+        for byte in data[..(end - start) as usize].iter_mut() {
+            *byte = 0;
+        }
+        let first = start / PAGE_SIZE;
+        for (index, page) in self.pages.range(first..) {
+            let page_start = index * PAGE_SIZE;
+            if page_start >= end {
+                break;
+            }
+            let from = std::cmp::max(start, page_start);
+            let to = std::cmp::min(end, page_start + PAGE_SIZE);
+            data[(from - start) as usize..(to - start) as usize]
+                .copy_from_slice(&page[(from - page_start) as usize..(to - page_start) as usize]);
+        }
+        (end - start) as usize
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        let end = offset + data.len() as u64;
+        let mut at = offset;
+        while at < end {
+            let index = at / PAGE_SIZE;
+            let page_start = index * PAGE_SIZE;
+            let to = std::cmp::min(end, page_start + PAGE_SIZE);
+            let page = self
+                .pages
+                .entry(index)
+                .or_insert_with(|| Arc::new(vec![0; PAGE_SIZE as usize]));
+            Arc::make_mut(page)[(at - page_start) as usize..(to - page_start) as usize]
+                .copy_from_slice(&data[(at - offset) as usize..(to - offset) as usize]);
+            at = to;
+        }
+        self.len = std::cmp::max(self.len, end);
+    }
+
+    fn truncate(&mut self, size: u64) {
+        if size < self.len {
+            let kept = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+            let dropped: Vec<u64> = self.pages.range(kept..).map(|(index, _)| *index).collect();
+            for index in dropped {
+                self.pages.remove(&index);
+            }
+            // what was cut off reads as zeros if the file grows again
+            if let Some(page) = self.pages.get_mut(&(size / PAGE_SIZE)) {
+                for byte in Arc::make_mut(page)[(size % PAGE_SIZE) as usize..].iter_mut() {
+                    *byte = 0;
+                }
+            }
+        }
+        self.len = size;
+    }
+
+    // reads the content from the start, the holes are only made zeros as they are read
+    fn reader(&self) -> PagesReader {
+        PagesReader {
+            pages: self.clone(),
+            offset: 0,
+        }
+    }
+}
+
+struct PagesReader {
+    pages: Pages,
+    offset: u64,
+}
+
+impl Read for PagesReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.pages.read_into(self.offset, buf);
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+#[derive(Debug)]
+struct Buffer {
+    path: PathBuf,
+    data: Pages,
+    // incremented by every change, the buffer is dirty until `uploaded` catches up
+    version: u64,
+    uploaded: u64,
+    // a background upload is in flight
+    uploading: bool,
+    // uploads being put, foreground flushes included
+    putting: usize,
+    // error of the last upload, cleared by the next successful one
+    error: Option<String>,
+    // the file was released, the copy is dropped once it is put
+    released: bool,
+}
+
+impl Buffer {
+    fn state(&self) -> SyncState {
+        if self.putting > 0 || self.uploading {
+            SyncState::Uploading
+        } else if self.version == self.uploaded {
+            SyncState::Clean
+        } else if let Some(ref e) = self.error {
+            SyncState::Error(e.clone())
+        } else {
+            SyncState::Dirty
+        }
+    }
+
+    fn dirty_bytes(&self) -> u64 {
+        if self.version == self.uploaded {
+            0
+        } else {
+            self.data.resident()
+        }
+    }
+
+    fn droppable(&self) -> bool {
+        self.released && self.dirty_bytes() == 0 && !self.uploading && self.putting == 0
+    }
+}
+
+/// Whether what was written to a file reached the backend.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncState {
+    /// The backend has the content of the file.
+    Clean,
+    /// Changes are waiting for a flush, a release or the high-water mark.
+    Dirty,
+    /// Changes are being uploaded.
+    Uploading,
+    /// The last upload of the changes failed, with this error.
+    Error(String),
+}
+
+impl std::fmt::Display for SyncState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SyncState::Clean => write!(f, "clean"),
+            SyncState::Dirty => write!(f, "dirty"),
+            SyncState::Uploading => write!(f, "uploading"),
+            SyncState::Error(e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+/// Files whose changes did not reach the backend yet, by state.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Pending {
+    pub dirty: usize,
+    pub uploading: usize,
+    pub failed: usize,
+}
+
+impl std::fmt::Display for Pending {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} dirty, {} uploading, {} failed",
+            self.dirty, self.uploading, self.failed
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Slots {
+    free: Mutex<usize>,
+    freed: Condvar,
+}
+
+struct Slot<'a>(&'a Slots);
+
+impl<'a> Drop for Slot<'a> {
+    fn drop(&mut self) {
+        *self.0.free.lock().unwrap() += 1;
+        self.0.freed.notify_one();
+    }
+}
+
+impl Slots {
+    fn acquire(&self) -> Slot<'_> {
+        let mut free = self.free.lock().unwrap();
+        while *free == 0 {
+            free = self.freed.wait(free).unwrap();
+        }
+        *free -= 1;
+        Slot(self)
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    buffers: Mutex<HashMap<u64, Buffer>>,
+    // notified when an upload ends, with the lock of `buffers`
+    settled: Condvar,
+    // released files whose upload failed, the oldest first
+    failed: Mutex<VecDeque<u64>>,
+    // sum of the dirty bytes of the buffers
+    dirty: Mutex<u64>,
+    cleaned: Condvar,
+    // storage classes asked for files, used by their uploads
+    classes: Mutex<HashMap<u64, String>>,
+    slots: Slots,
+    budget: Arc<Budget>,
+    // attempts of a failed upload after the first one
+    retries: usize,
+    // mirrors the dirty copies on disk, `None` loses them on a crash
+    journal: Option<Journal>,
+}
+
+// a change of the copy of a file
+#[derive(Clone, Copy)]
+enum Edit<'a> {
+    Write(u64, &'a [u8]),
+    Truncate(u64),
+}
+
+#[derive(Debug)]
+pub(crate) struct WriteBack {
+    inner: Arc<Inner>,
+    // dirty bytes above which writers block, `None` never blocks them
+    high_water: Option<u64>,
+    pool: Mutex<threadpool::ThreadPool>,
+}
+
+impl WriteBack {
+    pub fn new(
+        high_water: Option<u64>,
+        uploads: usize,
+        retries: usize,
+        budget: Arc<Budget>,
+        journal: Option<Journal>,
+    ) -> WriteBack {
+        let uploads = std::cmp::max(uploads, 1);
+        WriteBack {
+            inner: Arc::new(Inner {
+                buffers: Mutex::new(HashMap::new()),
+                settled: Condvar::new(),
+                failed: Mutex::new(VecDeque::new()),
+                dirty: Mutex::new(0),
+                cleaned: Condvar::new(),
+                classes: Mutex::new(HashMap::new()),
+                slots: Slots {
+                    free: Mutex::new(uploads),
+                    freed: Condvar::new(),
+                },
+                budget,
+                retries,
+                journal,
+            }),
+            high_water,
+            pool: Mutex::new(threadpool::ThreadPool::with_name(
+                "ossfs-upload".to_owned(),
+                uploads,
+            )),
+        }
+    }
+
+    pub fn dirty_bytes(&self) -> u64 {
+        *self.inner.dirty.lock().unwrap()
+    }
+
+    /// Reads up to `size` bytes at `offset` of the copy of the file `ino`, `None` if
+    /// it has none. While there is a copy it is newer than the backend's content.
+    pub fn read(&self, ino: u64, offset: u64, size: usize) -> Option<Vec<u8>> {
+        let buffers = self.inner.buffers.lock().unwrap();
+        Some(buffers.get(&ino)?.data.read(offset, size))
+    }
+
+    /// Writes `data` at `offset` of the file `ino`, whose content in the backend is
+    /// at `path` and takes `size` bytes. Returns the new size of the file.
+    pub fn write<B>(
+        &self,
+        backend: &Arc<B>,
+        ino: u64,
+        path: &Path,
+        size: u64,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<u64>
+    where
+        B: Backend + Send + Sync + 'static,
+    {
+        self.throttle(backend);
+        // nothing to keep when the whole content is overwritten
+        let keep = offset > 0 || (data.len() as u64) < size;
+        self.change(backend, ino, path, keep, Edit::Write(offset, data))
+    }
+
+    /// Cuts or zero-extends the file `ino` to `new_size` bytes.
+    pub fn truncate<B>(&self, backend: &Arc<B>, ino: u64, path: &Path, new_size: u64) -> Result<u64>
+    where
+        B: Backend + Send + Sync + 'static,
+    {
+        self.throttle(backend);
+        self.change(backend, ino, path, new_size > 0, Edit::Truncate(new_size))
+    }
+
+    /// Puts the file `ino` to the backend if it changed since its last upload.
+    /// Returns whether it was put.
+    pub fn flush<B: Backend>(&self, backend: &B, ino: u64) -> Result<bool> {
+        upload(&self.inner, backend, ino)
+    }
+
+    /// Flushes the file `ino` and drops its copy once it is clean. A copy failing to
+    /// upload is kept for a later flush, up to `MAX_FAILED_RELEASES` of them.
+    pub fn release<B: Backend>(&self, backend: &B, ino: u64) -> Result<bool> {
+        if let Some(buffer) = self.inner.buffers.lock().unwrap().get_mut(&ino) {
+            buffer.released = true;
+        }
+        let result = self.flush(backend, ino);
+        drop_released(&mut self.inner.buffers.lock().unwrap(), ino);
+        if result.is_err() {
+            self.keep_failed(ino);
+        }
+        result
+    }
+
+    /// Uploads the file `ino` in the storage class `class` from now on, `None` for
+    /// the default class of the backend.
+    pub fn set_storage_class(&self, ino: u64, class: Option<String>) {
+        let mut classes = self.inner.classes.lock().unwrap();
+        match class {
+            Some(class) => classes.insert(ino, class),
+            None => classes.remove(&ino),
+        };
+    }
+
+    pub fn state(&self, ino: u64) -> SyncState {
+        match self.inner.buffers.lock().unwrap().get(&ino) {
+            Some(buffer) => buffer.state(),
+            None => SyncState::Clean,
+        }
+    }
+
+    pub fn pending(&self) -> Pending {
+        let mut pending = Pending::default();
+        for buffer in self.inner.buffers.lock().unwrap().values() {
+            match buffer.state() {
+                SyncState::Clean => {}
+                SyncState::Dirty => pending.dirty += 1,
+                SyncState::Uploading => pending.uploading += 1,
+                SyncState::Error(_) => pending.failed += 1,
+            }
+        }
+        pending
+    }
+
+    /// Whether the file `ino` has a copy, which is newer than the backend's content.
+    pub fn has_copy(&self, ino: u64) -> bool {
+        self.inner.buffers.lock().unwrap().contains_key(&ino)
+    }
+
+    /// Inodes of the files with a copy.
+    pub fn copies(&self) -> Vec<u64> {
+        self.inner.buffers.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Files whose changes did not reach the backend yet with their paths and
+    /// sizes, the smallest first.
+    pub fn unflushed(&self) -> Vec<(u64, PathBuf, u64)> {
+        let mut files: Vec<(u64, PathBuf, u64)> = self
+            .inner
+            .buffers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, buffer)| buffer.state() != SyncState::Clean)
+            .map(|(ino, buffer)| (*ino, buffer.path.clone(), buffer.data.len()))
+            .collect();
+        files.sort_by_key(|file| file.2);
+        files
+    }
+
+    pub fn storage_class(&self, ino: u64) -> Option<String> {
+        self.inner.classes.lock().unwrap().get(&ino).cloned()
+    }
+
+    /// Uploads the files a crash of an earlier mount left in the journal. Returns
+    /// their paths.
+    pub fn recover<B: Backend>(&self, backend: &B) -> Vec<PathBuf> {
+        match self.inner.journal {
+            Some(ref journal) => journal.recover(backend),
+            None => vec![],
+        }
+    }
+
+    /// Drops the copy of the deleted file `ino` without uploading it.
+    pub fn forget(&self, ino: u64) {
+        self.inner.classes.lock().unwrap().remove(&ino);
+        let removed = self.inner.buffers.lock().unwrap().remove(&ino);
+        if let Some(ref journal) = self.inner.journal {
+            journal.commit(ino);
+        }
+        if let Some(buffer) = removed {
+            let mut dirty = self.inner.dirty.lock().unwrap();
+            *dirty -= buffer.dirty_bytes();
+            self.inner.cleaned.notify_all();
+        }
+    }
+
+    // remembers the released file `ino` failed to upload, dropping the copies of
+    // the oldest ones beyond `MAX_FAILED_RELEASES`
+    fn keep_failed(&self, ino: u64) {
+        let mut dropped = vec![];
+        {
+            let mut failed = self.inner.failed.lock().unwrap();
+            let mut buffers = self.inner.buffers.lock().unwrap();
+            // copies put or written since are not failed releases anymore
+            failed.retain(|failed| {
+                *failed != ino
+                    && buffers
+                        .get(failed)
+                        .map(|buffer| buffer.released)
+                        .unwrap_or(false)
+            });
+            failed.push_back(ino);
+            while failed.len() > MAX_FAILED_RELEASES {
+                let oldest = failed.pop_front().unwrap();
+                let idle = buffers
+                    .get(&oldest)
+                    .map(|buffer| !buffer.uploading && buffer.putting == 0)
+                    .unwrap_or(false);
+                if idle {
+                    dropped.extend(buffers.remove(&oldest));
+                }
+            }
+        }
+        for buffer in dropped {
+            log::error!(
+                "dropping the copy of {:?}, released with {} bytes failing to upload",
+                buffer.path,
+                buffer.dirty_bytes()
+            );
+            let mut dirty = self.inner.dirty.lock().unwrap();
+            *dirty -= buffer.dirty_bytes();
+            self.inner.cleaned.notify_all();
+        }
+    }
+
+    // applies `edit` to the copy of the file, loading it first if there is none and
+    // `keep` asks for the current content, and to its entry in the journal
+    fn change<B>(
+        &self,
+        backend: &Arc<B>,
+        ino: u64,
+        path: &Path,
+        keep: bool,
+        edit: Edit,
+    ) -> Result<u64>
+    where
+        B: Backend,
+    {
+        let loaded = if keep && !self.inner.buffers.lock().unwrap().contains_key(&ino) {
+            Some(load(&self.inner.budget, &**backend, path)?)
+        } else {
+            None
+        };
+        let mut buffers = self.inner.buffers.lock().unwrap();
+        let buffer = buffers.entry(ino).or_insert_with(|| Buffer {
+            path: path.to_path_buf(),
+            data: Pages::from_vec(loaded.unwrap_or_default()),
+            version: 0,
+            uploaded: 0,
+            uploading: false,
+            putting: 0,
+            error: None,
+            released: false,
+        });
+        buffer.released = false;
+        let before = buffer.dirty_bytes();
+        if let Some(ref journal) = self.inner.journal {
+            let journaled = if buffer.version == buffer.uploaded {
+                // a clean copy has no entry
+                journal.begin(ino, path, &mut buffer.data.reader())
+            } else {
+                Ok(())
+            }
+            .and_then(|_| match edit {
+                Edit::Write(offset, data) => journal.write(ino, offset, data),
+                Edit::Truncate(size) => journal.truncate(ino, size),
+            });
+            if let Err(e) = journaled {
+                log::error!("failed to journal a change of {:?}. {}", path, e);
+                // a copy just loaded would stand for the content of the file
+                if buffer.version == 0 {
+                    buffers.remove(&ino);
+                }
+                return Err(e);
+            }
+        }
+        match edit {
+            Edit::Write(offset, data) => buffer.data.write(offset, data),
+            Edit::Truncate(size) => buffer.data.truncate(size),
+        }
+        buffer.version += 1;
+        let after = buffer.dirty_bytes();
+        let size = buffer.data.len();
+        let mut dirty = self.inner.dirty.lock().unwrap();
+        *dirty = *dirty + after - before;
+        Ok(size)
+    }
+
+    // blocks while the dirty bytes are above the high-water mark, uploading the dirty
+    // files in the background meanwhile
+    fn throttle<B>(&self, backend: &Arc<B>)
+    where
+        B: Backend + Send + Sync + 'static,
+    {
+        let high_water = match self.high_water {
+            Some(high_water) => high_water,
+            None => return,
+        };
+        let mut dirty = self.inner.dirty.lock().unwrap();
+        while *dirty > high_water {
+            drop(dirty);
+            log::debug!("above {} dirty bytes, waiting for uploads", high_water);
+            self.upload_dirty(backend);
+            dirty = self.inner.dirty.lock().unwrap();
+            dirty = self
+                .inner
+                .cleaned
+                .wait_timeout(dirty, THROTTLE_INTERVAL)
+                .unwrap()
+                .0;
+        }
+    }
+
+    fn upload_dirty<B>(&self, backend: &Arc<B>)
+    where
+        B: Backend + Send + Sync + 'static,
+    {
+        let inodes: Vec<u64> = {
+            let mut buffers = self.inner.buffers.lock().unwrap();
+            buffers
+                .iter_mut()
+                .filter(|(_, buffer)| buffer.dirty_bytes() > 0 && !buffer.uploading)
+                .map(|(ino, buffer)| {
+                    buffer.uploading = true;
+                    *ino
+                })
+                .collect()
+        };
+        let pool = self.pool.lock().unwrap();
+        for ino in inodes {
+            let inner = self.inner.clone();
+            let backend = backend.clone();
+            pool.execute(move || {
+                if let Err(e) = upload(&inner, &*backend, ino) {
+                    log::error!("background upload of inode {}. error: {}", ino, e);
+                }
+                let mut buffers = inner.buffers.lock().unwrap();
+                if let Some(buffer) = buffers.get_mut(&ino) {
+                    buffer.uploading = false;
+                }
+                drop_released(&mut buffers, ino);
+            });
+        }
+    }
+}
+
+fn load<B: Backend>(budget: &Budget, backend: &B, path: &Path) -> Result<Vec<u8>> {
+    let mut data = vec![];
+    loop {
+        budget.acquire(Request::Get);
+        let chunk = backend.read(path, data.len() as u64, LOAD_CHUNK)?;
+        if chunk.is_empty() {
+            return Ok(data);
+        }
+        data.extend_from_slice(&chunk);
+    }
+}
+
+// drops the copy of the released file `ino` once it is clean
+fn drop_released(buffers: &mut HashMap<u64, Buffer>, ino: u64) {
+    if buffers.get(&ino).map(Buffer::droppable).unwrap_or(false) {
+        buffers.remove(&ino);
+    }
+}
+
+fn upload<B: Backend>(inner: &Inner, backend: &B, ino: u64) -> Result<bool> {
+    let (path, data, version) = {
+        let mut buffers = inner.buffers.lock().unwrap();
+        // the upload in flight may put what this one would
+        let mut waited = false;
+        while buffers
+            .get(&ino)
+            .map(|buffer| buffer.putting > 0)
+            .unwrap_or(false)
+        {
+            buffers = inner.settled.wait(buffers).unwrap();
+            waited = true;
+        }
+        match buffers.get_mut(&ino) {
+            Some(buffer) if buffer.dirty_bytes() > 0 => {
+                buffer.putting += 1;
+                (buffer.path.clone(), buffer.data.clone(), buffer.version)
+            }
+            // put by the upload waited for
+            Some(_) if waited => return Ok(true),
+            _ => return Ok(false),
+        }
+    };
+    let put = {
+        let _slot = inner.slots.acquire();
+        let class = inner.classes.lock().unwrap().get(&ino).cloned();
+        let class = class.as_ref().map(|class| class.as_str());
+        idempotency::retry(&path, inner.retries, |token, retry| {
+            inner.budget.acquire(Request::Put);
+            let body = || Box::new(data.reader()) as Box<dyn Read + Send>;
+            backend.put_streamed(&path, data.len(), &body, class, token, retry)
+        })
+    };
+    let mut buffers = inner.buffers.lock().unwrap();
+    if let Some(buffer) = buffers.get_mut(&ino) {
+        buffer.putting -= 1;
+        buffer.error = put.as_ref().err().map(|e| e.to_string());
+    }
+    inner.settled.notify_all();
+    put?;
+    if let Some(buffer) = buffers.get_mut(&ino) {
+        // changed again meanwhile, those changes still have to be uploaded
+        if buffer.version == version {
+            let before = buffer.dirty_bytes();
+            buffer.uploaded = version;
+            if let Some(ref journal) = inner.journal {
+                journal.commit(ino);
+            }
+            let mut dirty = inner.dirty.lock().unwrap();
+            *dirty -= before;
+            inner.cleaned.notify_all();
+        }
+    }
+    drop_released(&mut buffers, ino);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Pages, Pending, SyncState, WriteBack, MAX_FAILED_RELEASES, PAGE_SIZE};
+    use crate::error::Error;
+    use crate::ossfs_impl::backend::mock::MockBackend;
+    use crate::ossfs_impl::budget::Budget;
+    use std::io::Read;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_write_back() {
+        let backend = Arc::new(MockBackend::new());
+        backend.add_file("/a", b"hello world");
+        let writeback = WriteBack::new(Some(8), 1, 0, Arc::new(Budget::new(None)), None);
+        let a = Path::new("/a");
+        assert_eq!(
+            writeback.write(&backend, 2, a, 11, 6, b"there").unwrap(),
+            11
+        );
+        assert_eq!(writeback.dirty_bytes(), 11);
+        assert_eq!(writeback.state(2), SyncState::Dirty);
+        assert_eq!(backend.content("/a").unwrap(), b"hello world");
+        backend.fail_next("put", Error::Fuse(libc::EIO));
+        assert!(writeback.flush(&*backend, 2).is_err());
+        assert!(writeback.state(2).to_string().starts_with("error: "));
+        assert_eq!(
+            writeback.pending(),
+            Pending {
+                failed: 1,
+                ..Pending::default()
+            }
+        );
+        writeback.flush(&*backend, 2).unwrap();
+        assert_eq!(backend.content("/a").unwrap(), b"hello there");
+        assert_eq!(writeback.dirty_bytes(), 0);
+        assert_eq!(writeback.state(2), SyncState::Clean);
+
+        // above the high-water mark the next writer waits for a background upload
+        backend.set_latency("put", Duration::from_millis(100));
+        let b = Path::new("/b");
+        writeback
+            .write(&backend, 3, b, 0, 0, b"0123456789")
+            .unwrap();
+        assert_eq!(writeback.write(&backend, 2, a, 11, 11, b"!").unwrap(), 12);
+        assert_eq!(backend.content("/b").unwrap(), b"0123456789");
+        assert_eq!(writeback.dirty_bytes(), 12);
+
+        assert_eq!(writeback.truncate(&backend, 2, a, 5).unwrap(), 5);
+        writeback.release(&*backend, 2).unwrap();
+        assert_eq!(backend.content("/a").unwrap(), b"hello");
+        assert!(!writeback.inner.buffers.lock().unwrap().contains_key(&2));
+    }
+
+    #[test]
+    fn test_pages() {
+        let mut pages = Pages::from_vec(b"hello".to_vec());
+        // a hole of a terabyte takes no page
+        pages.write(1 << 40, b"end");
+        assert_eq!(pages.len(), (1 << 40) + 3);
+        assert_eq!(pages.resident(), PAGE_SIZE + 3);
+        assert_eq!(pages.read(3, 4), b"lo\0\0");
+        assert_eq!(pages.read((1 << 40) - 1, 16), b"\0end");
+
+        pages.truncate(2);
+        pages.truncate(PAGE_SIZE * 4);
+        assert_eq!(pages.resident(), PAGE_SIZE);
+        assert_eq!(pages.read(0, 4), b"he\0\0");
+        pages.write(PAGE_SIZE - 1, b"ab");
+        assert_eq!(pages.read(PAGE_SIZE - 2, 4), b"\0ab\0");
+        let mut content = vec![];
+        pages.reader().read_to_end(&mut content).unwrap();
+        assert_eq!(content.len() as u64, PAGE_SIZE * 4);
+        assert_eq!(
+            &content[PAGE_SIZE as usize - 2..PAGE_SIZE as usize + 2],
+            b"\0ab\0"
+        );
+    }
+
+    #[test]
+    fn test_flush_waits_for_upload() {
+        let backend = Arc::new(MockBackend::new());
+        let writeback = Arc::new(WriteBack::new(
+            None,
+            2,
+            0,
+            Arc::new(Budget::new(None)),
+            None,
+        ));
+        let a = Path::new("/a");
+        writeback.write(&backend, 2, a, 0, 0, b"hello").unwrap();
+        // reads see what no upload put yet
+        assert_eq!(writeback.read(2, 1, 3).unwrap(), b"ell");
+        assert!(backend.content("/a").is_none());
+
+        backend.set_latency("put", Duration::from_millis(200));
+        let flushes: Vec<_> = (0..2)
+            .map(|_| {
+                let writeback = writeback.clone();
+                let backend = backend.clone();
+                std::thread::spawn(move || writeback.flush(&*backend, 2).unwrap())
+            })
+            .collect();
+        for flush in flushes {
+            assert!(flush.join().unwrap());
+        }
+        assert_eq!(backend.calls("put"), 1);
+        assert_eq!(backend.content("/a").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_failed_release() {
+        let backend = Arc::new(MockBackend::new());
+        let writeback = WriteBack::new(None, 1, 0, Arc::new(Budget::new(None)), None);
+        for ino in 0..MAX_FAILED_RELEASES as u64 + 1 {
+            let path = format!("/{}", ino);
+            writeback
+                .write(&backend, ino, Path::new(&path), 0, 0, b"data")
+                .unwrap();
+            backend.fail_next("put", Error::Fuse(libc::EIO));
+            assert!(writeback.release(&*backend, ino).is_err());
+        }
+        // the oldest copy was dropped
+        assert!(!writeback.has_copy(0));
+        assert_eq!(writeback.copies().len(), MAX_FAILED_RELEASES);
+        assert_eq!(writeback.dirty_bytes(), 4 * MAX_FAILED_RELEASES as u64);
+
+        // put by the next flush, and dropped since it was released
+        assert!(writeback.flush(&*backend, 1).unwrap());
+        assert!(!writeback.has_copy(1));
+        assert_eq!(backend.content("/1").unwrap(), b"data");
+    }
+}
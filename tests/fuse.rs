@@ -0,0 +1,180 @@
+//! Mounts a `MockBackend` into a temporary directory and drives it through the
+//! kernel. Needs access to /dev/fuse, run with `cargo test --features fuse-tests`.
+#![cfg(feature = "fuse-tests")]
+
+use ossfs::{Error, Fuse, MockBackend};
+use std::ffi::OsStr;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+static NEXT_MOUNT: AtomicUsize = AtomicUsize::new(0);
+
+struct Mount {
+    backend: Arc<MockBackend>,
+    mountpoint: PathBuf,
+    session: Option<fuse::BackgroundSession<'static>>,
+}
+
+impl Mount {
+    fn new(backend: MockBackend) -> Mount {
+        let backend = Arc::new(backend);
+        let mountpoint = std::env::temp_dir().join(format!(
+            "ossfs-test-{}-{}",
+            std::process::id(),
+            NEXT_MOUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&mountpoint).unwrap();
+        let fs = Fuse::builder(backend.clone()).build();
+        let options = ["-o", "fsname=ossfs-test"]
+            .iter()
+            .map(|o| o.as_ref())
+            .collect::<Vec<&OsStr>>();
+        let session = unsafe { fuse::spawn_mount(fs, &mountpoint, &options) }.unwrap();
+        Mount {
+            backend,
+            mountpoint,
+            session: Some(session),
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.mountpoint.join(name)
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        // dropping the session unmounts the filesystem
+        self.session.take();
+        let _ = std::fs::remove_dir(&self.mountpoint);
+    }
+}
+
+#[test]
+fn test_lookup_and_read() {
+    let backend = MockBackend::new();
+    backend.add_file("/dir/hello", b"hello world");
+    let mount = Mount::new(backend);
+
+    let meta = std::fs::metadata(mount.path("dir/hello")).unwrap();
+    assert!(meta.is_file());
+    assert_eq!(meta.len(), 11);
+    assert!(std::fs::metadata(mount.path("dir")).unwrap().is_dir());
+
+    let mut content = String::new();
+    std::fs::File::open(mount.path("dir/hello"))
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "hello world");
+
+    let err = std::fs::metadata(mount.path("missing")).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+}
+
+#[test]
+fn test_readdir() {
+    let backend = MockBackend::new();
+    for i in 0..200 {
+        backend.add_file(format!("/many/{:03}", i), b"");
+    }
+    backend.add_dir("/many/sub");
+    let mount = Mount::new(backend);
+
+    let mut names: Vec<String> = std::fs::read_dir(mount.path("many"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    let mut expected: Vec<String> = (0..200).map(|i| format!("{:03}", i)).collect();
+    expected.push("sub".to_owned());
+    assert_eq!(names, expected);
+    // a second listing is served locally
+    let calls = mount.backend.calls("get_children");
+    assert_eq!(std::fs::read_dir(mount.path("many")).unwrap().count(), 201);
+    assert_eq!(mount.backend.calls("get_children"), calls);
+}
+
+#[test]
+fn test_readdir_long_names() {
+    // a few of them fill the buffer of a reply, none is lost at the boundaries
+    let backend = MockBackend::new();
+    for i in 0..300 {
+        backend.add_file(format!("/long/{:03}{}", i, "n".repeat(200 + i % 50)), b"");
+    }
+    let mount = Mount::new(backend);
+
+    let mut names: Vec<String> = std::fs::read_dir(mount.path("long"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    let expected: Vec<String> = (0..300)
+        .map(|i| format!("{:03}{}", i, "n".repeat(200 + i % 50)))
+        .collect();
+    assert_eq!(names, expected);
+}
+
+#[test]
+fn test_create_and_write() {
+    let mount = Mount::new(MockBackend::new());
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(mount.path("new"))
+        .unwrap();
+    assert_eq!(mount.backend.content("/new"), Some(vec![]));
+    std::fs::create_dir(mount.path("newdir")).unwrap();
+    assert!(std::fs::metadata(mount.path("newdir")).unwrap().is_dir());
+
+    // writes are not supported by the backends yet
+    assert!(file
+        .write_all(b"data")
+        .and_then(|_| file.sync_all())
+        .is_err());
+}
+
+#[test]
+fn test_create_existing() {
+    let backend = MockBackend::new();
+    backend.add_file("/existing", b"kept");
+    let mount = Mount::new(backend);
+
+    let err = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(mount.path("existing"))
+        .unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EEXIST));
+    // without O_EXCL the file is opened as it is
+    let mut content = String::new();
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(mount.path("existing"))
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "kept");
+}
+
+#[test]
+fn test_backend_errors() {
+    let backend = MockBackend::new();
+    backend.add_file("/throttled", b"");
+    let mount = Mount::new(backend);
+
+    mount
+        .backend
+        .fail_next("get_node", Error::Throttled("test".to_owned()));
+    let err = std::fs::metadata(mount.path("throttled")).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EAGAIN));
+    // the scripted error is consumed, the retry succeeds
+    assert!(std::fs::metadata(mount.path("throttled"))
+        .unwrap()
+        .is_file());
+}
@@ -0,0 +1,114 @@
+//! Runs the conformance suite against a real S3 endpoint, started with
+//! `docker-compose -f test/minio/docker-compose.yml up`, with `--features s3-tests`.
+#![cfg(feature = "s3-tests")]
+
+use ossfs::{Backend, S3Backend};
+use rusoto_core::credential::StaticProvider;
+use rusoto_core::request::HttpClient;
+use rusoto_core::Region;
+use rusoto_s3::{CreateBucketRequest, PutObjectRequest, S3Client, S3};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_BUCKET: AtomicUsize = AtomicUsize::new(0);
+
+fn env(name: &str, default: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| default.to_owned())
+}
+
+fn endpoint() -> String {
+    env("OSSFS_S3_ENDPOINT", "http://127.0.0.1:9001")
+}
+
+fn access_key() -> String {
+    env("OSSFS_S3_ACCESS_KEY", "admin")
+}
+
+fn secret_key() -> String {
+    env("OSSFS_S3_SECRET_KEY", "password")
+}
+
+fn client() -> S3Client {
+    S3Client::new_with(
+        HttpClient::new().unwrap(),
+        StaticProvider::new_minimal(access_key(), secret_key()),
+        Region::Custom {
+            name: "minio".to_owned(),
+            endpoint: endpoint(),
+        },
+    )
+}
+
+/// Creates a fresh bucket holding `files` and a backend on top of it.
+fn setup(files: &[(&str, &[u8])]) -> S3Backend {
+    let bucket = format!(
+        "ossfs-test-{}-{}",
+        std::process::id(),
+        NEXT_BUCKET.fetch_add(1, Ordering::SeqCst)
+    );
+    let client = client();
+    client
+        .create_bucket(CreateBucketRequest {
+            bucket: bucket.clone(),
+            ..CreateBucketRequest::default()
+        })
+        .sync()
+        .unwrap();
+    for (key, data) in files {
+        client
+            .put_object(PutObjectRequest {
+                bucket: bucket.clone(),
+                key: key.to_string(),
+                body: Some(data.to_vec().into()),
+                ..PutObjectRequest::default()
+            })
+            .sync()
+            .unwrap();
+    }
+    let backend = S3Backend::new(endpoint(), bucket, access_key(), secret_key());
+    backend.check().unwrap();
+    backend
+}
+
+ossfs::backend_conformance!(conformance, setup);
+
+#[test]
+fn test_large_listing() {
+    let names: Vec<String> = (0..2500).map(|i| format!("large/{:05}", i)).collect();
+    let files: Vec<(&str, &[u8])> = names.iter().map(|name| (name.as_str(), &b""[..])).collect();
+    let backend = setup(&files);
+    let mut listed: Vec<String> = backend
+        .get_children("large")
+        .unwrap()
+        .iter()
+        .map(|node| node.path().to_string_lossy().into_owned())
+        .collect();
+    listed.sort();
+    assert_eq!(listed, names);
+}
+
+#[test]
+fn test_ranged_reads() {
+    let data: Vec<u8> = (0..5 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let backend = setup(&[("object", &data[..])]);
+    let len = data.len() as u64;
+    for &(offset, size) in &[
+        (0, 1),
+        (0, 128 * 1024),
+        (1024 * 1024 - 1, 2),
+        (len - 1, 1),
+        (len - 100, 4096),
+        (len, 10),
+        (len + 4096, 10),
+    ] {
+        let read = backend.read("object", offset, size).unwrap();
+        let start = std::cmp::min(offset, len) as usize;
+        let end = std::cmp::min(offset + size as u64, len) as usize;
+        assert_eq!(
+            read,
+            &data[start..end],
+            "offset: {}, size: {}",
+            offset,
+            size
+        );
+    }
+}
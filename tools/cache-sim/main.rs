@@ -0,0 +1,122 @@
+use clap::{App, Arg};
+use ossfs::simulate::{self, Access, Eviction, Policy, Unit};
+use ossfs::trace;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+fn main() {
+    let matches = App::new("cache-sim")
+        .version("1.0")
+        .author("divinerapier")
+        .about("projects the hit rates of caches replaying an access trace")
+        .arg(
+            Arg::with_name("trace")
+                .required(true)
+                .short("t")
+                .long("trace")
+                .value_name("TRACE")
+                .help("Sets the trace, recorded by a mount with an access trace or a read per line as path, offset and size separated by tabs")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("capacity")
+                .required(true)
+                .short("c")
+                .long("capacity")
+                .value_name("BYTES")
+                .help("Sets the sizes of the caches simulated, separated by commas")
+                .takes_value(true)
+                .use_delimiter(true)
+                .validator(is_u64),
+        )
+        .arg(
+            Arg::with_name("block_size")
+                .short("b")
+                .long("block-size")
+                .value_name("BYTES")
+                .help("Caches blocks of this size instead of whole files")
+                .takes_value(true)
+                .validator(is_u64),
+        )
+        .arg(
+            Arg::with_name("fifo")
+                .long("fifo")
+                .help("Evicts the first fetched entries instead of the least recently read"),
+        )
+        .arg(
+            Arg::with_name("epochs")
+                .short("e")
+                .long("epochs")
+                .value_name("EPOCHS")
+                .help("Sets the number of times the trace is replayed, shuffled after the first")
+                .takes_value(true)
+                .default_value("1")
+                .validator(is_u64),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Sets the seed of the shuffles")
+                .takes_value(true)
+                .default_value("1")
+                .validator(is_u64),
+        )
+        .get_matches();
+
+    let trace = read_trace(matches.value_of("trace").unwrap());
+    let unit = match matches.value_of("block_size") {
+        Some(size) => Unit::Blocks(size.parse().unwrap()),
+        None => Unit::Files,
+    };
+    let eviction = if matches.is_present("fifo") {
+        Eviction::Fifo
+    } else {
+        Eviction::Lru
+    };
+    let epochs: usize = matches.value_of("epochs").unwrap().parse().unwrap();
+    let seed: u64 = matches.value_of("seed").unwrap().parse().unwrap();
+    for capacity in matches.values_of("capacity").unwrap() {
+        let policy = Policy {
+            unit,
+            eviction,
+            capacity: capacity.parse().unwrap(),
+        };
+        let report = simulate::simulate(&trace, policy, epochs, seed);
+        println!("capacity: {}, {}", capacity, report);
+    }
+}
+
+fn read_trace(path: &str) -> Vec<Access> {
+    let file = std::fs::File::open(path).expect(&format!("path: {}", path));
+    // recorded by a mount, or written by hand
+    if let Ok(records) = trace::read(&file) {
+        return trace::reads(&records);
+    }
+    let file = std::fs::File::open(path).expect(&format!("path: {}", path));
+    let mut trace = vec![];
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line.unwrap();
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 3 {
+            eprintln!("skipping malformed line: {}", line);
+            continue;
+        }
+        match (fields[1].parse(), fields[2].parse()) {
+            (Ok(offset), Ok(size)) => trace.push(Access {
+                path: PathBuf::from(fields[0]),
+                offset,
+                size,
+            }),
+            _ => eprintln!("skipping malformed line: {}", line),
+        }
+    }
+    trace
+}
+
+fn is_u64(arg: String) -> Result<(), String> {
+    match arg.parse::<u64>() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("{:?}", e)),
+    }
+}
@@ -0,0 +1,72 @@
+use clap::{App, Arg};
+use std::io::Write;
+
+fn main() {
+    let matches = App::new("export")
+        .version("1.0")
+        .author("divinerapier")
+        .about("exports a tree of a backend into a tar archive, without mounting it")
+        .arg(
+            Arg::with_name("backend")
+                .required(true)
+                .short("b")
+                .long("backend")
+                .value_name("URI")
+                .help("backend, e.g. s3://bucket, seaweedfs://filer:8888/bucket or file:///path")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("prefix")
+                .short("p")
+                .long("prefix")
+                .value_name("PREFIX")
+                .help("only exports this directory")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("ARCHIVE")
+                .help("Sets the archive written, - for the standard output")
+                .takes_value(true)
+                .default_value("-"),
+        )
+        .get_matches();
+
+    let backend =
+        match ossfs::BackendRegistry::default().build(matches.value_of("backend").unwrap()) {
+            Ok(backend) => backend,
+            Err(e) => {
+                eprintln!("failed to connect to the backend. {}", e);
+                std::process::exit(1);
+            }
+        };
+    let prefix = matches.value_of("prefix").unwrap_or("");
+    let output = matches.value_of("output").unwrap();
+    let out: Box<dyn Write> = if output == "-" {
+        Box::new(std::io::stdout())
+    } else {
+        Box::new(std::fs::File::create(output).expect(&format!("path: {}", output)))
+    };
+    let result = ossfs::export::export(&backend, prefix, out, &mut |progress| {
+        eprintln!(
+            "{}/{} files, {}/{} bytes: {:?}",
+            progress.files_done,
+            progress.files_total,
+            progress.bytes_done,
+            progress.bytes_total,
+            progress.path
+        );
+    });
+    match result {
+        Ok(summary) => eprintln!(
+            "exported {} files and {} directories, {} bytes",
+            summary.files, summary.directories, summary.bytes
+        ),
+        Err(e) => {
+            eprintln!("failed to export. {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -5,6 +5,7 @@ fn main() {
         .version("1.0")
         .author("divinerapier")
         .about("check files' md5")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("source")
                 .required(true)
@@ -23,12 +24,83 @@ fn main() {
                 .help("target of mountpoint")
                 .takes_value(true),
         )
+        .subcommand(
+            SubCommand::with_name("manifest")
+                .about("prints the manifest of a backend, without mounting it")
+                .arg(
+                    Arg::with_name("backend")
+                        .required(true)
+                        .short("b")
+                        .long("backend")
+                        .value_name("URI")
+                        .help("backend, e.g. s3://bucket, seaweedfs://filer:8888/bucket or file:///path")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("prefix")
+                        .short("p")
+                        .long("prefix")
+                        .value_name("PREFIX")
+                        .help("only lists the files under this directory")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("no-checksum")
+                        .long("no-checksum")
+                        .help("skips reading the files, the md5 column is -"),
+                )
+                .arg(
+                    Arg::with_name("inventory")
+                        .long("inventory")
+                        .value_name("BUCKET")
+                        .help("prints the csv of an s3 inventory report of this bucket instead")
+                        .takes_value(true),
+                ),
+        )
         .get_matches();
+    if let Some(matches) = matches.subcommand_matches("manifest") {
+        manifest(
+            matches.value_of("backend").unwrap(),
+            matches.value_of("prefix").unwrap_or(""),
+            !matches.is_present("no-checksum"),
+            matches.value_of("inventory"),
+            32,
+        );
+        return;
+    }
     let source = matches.value_of("source").unwrap();
     let target = matches.value_of("target").unwrap();
     check(source.to_owned(), target.to_owned(), 32);
 }
 
+fn manifest(
+    backend: &str,
+    prefix: &str,
+    checksum: bool,
+    inventory: Option<&str>,
+    concurrency: usize,
+) {
+    let backend = match ossfs::BackendRegistry::default().build(backend) {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("failed to connect to the backend. {}", e);
+            std::process::exit(1);
+        }
+    };
+    let stdout = std::io::stdout();
+    let result =
+        ossfs::manifest::generate(&backend, prefix, checksum, concurrency).and_then(|entries| {
+            match inventory {
+                Some(bucket) => ossfs::manifest::write_inventory(&entries, bucket, stdout.lock()),
+                None => ossfs::manifest::write(&entries, stdout.lock()),
+            }
+        });
+    if let Err(e) = result {
+        eprintln!("failed to generate the manifest. {}", e);
+        std::process::exit(1);
+    }
+}
+
 fn check(source: String, target: String, concurrency: usize) {
     let begin_at = std::time::SystemTime::now();
     // let entries = std::fs::read_dir(path).unwrap();
@@ -1,4 +1,5 @@
 use clap::{App, Arg};
+use ossfs::manifest::{self, Entry};
 use std::io::Write;
 
 fn main() {
@@ -24,6 +25,12 @@ fn main() {
                 .help("Set the output path")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("manifest")
+                .short("m")
+                .long("manifest")
+                .help("Writes a manifest with the size and modification time of the files, keyed relative to the directory, which a mount loads instead of listing"),
+        )
         .get_matches();
 
     let directory = matches.value_of("directory").expect("missing directory");
@@ -40,6 +47,27 @@ fn main() {
 
     let mut writer = std::io::BufWriter::new(file);
 
+    if matches.is_present("manifest") {
+        let mut entries = vec![];
+        for entry in walkdir::WalkDir::new(&directory) {
+            let entry: walkdir::DirEntry = entry.unwrap();
+            let metadata = entry.metadata().unwrap();
+            if metadata.is_dir() {
+                continue;
+            }
+            let key = entry.path().strip_prefix(&directory).unwrap();
+            entries.push(Entry {
+                key: key.to_str().unwrap().to_owned(),
+                size: metadata.len(),
+                mtime: metadata.modified().unwrap(),
+                md5: None,
+            });
+        }
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        manifest::write(&entries, writer).unwrap();
+        return;
+    }
+
     for entry in walkdir::WalkDir::new(&directory) {
         let entry: walkdir::DirEntry = entry.unwrap();
         if entry.metadata().unwrap().is_dir() {